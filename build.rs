@@ -0,0 +1,34 @@
+//! Records the size and checksum of every embedded asset, so that the binary can tell if it shipped a broken one.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+use sha2::{Digest, Sha256};
+
+/// Every file embedded with `include_bytes!`, by name and path. Keep in sync with `src/assets.rs`.
+const ASSETS: &[(&str, &str)] = &[("htmx.min.js", "src/htmx.min.js")];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let mut manifest = String::from("&[\n");
+    for (name, path) in ASSETS {
+        println!("cargo:rerun-if-changed={path}");
+        let bytes =
+            fs::read(path).unwrap_or_else(|err| panic!("Unable to read asset {path}: {err}"));
+        let checksum = Sha256::digest(&bytes)
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                write!(hex, "{byte:02x}").unwrap();
+                hex
+            });
+        writeln!(
+            manifest,
+            "    ManifestEntry {{ name: {name:?}, size: {}, sha256: {checksum:?} }},",
+            bytes.len()
+        )
+        .unwrap();
+    }
+    manifest.push(']');
+    let out_dir = env::var("OUT_DIR").expect("Cargo should set OUT_DIR.");
+    fs::write(Path::new(&out_dir).join("assets_manifest.rs"), manifest)
+        .expect("Unable to write the assets manifest.");
+}
@@ -0,0 +1,94 @@
+//! Serves a custom activity alongside the built-in ones, without touching the CLI.
+//!
+//! ```sh
+//! cargo run --example hello_activity
+//! cargo run --example hello_activity -- ssh HOSTNAME IDENTITY_FILE
+//! ```
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use axum::{routing::get, Router};
+use htmx_ssh_games::{
+    connection::ConnectionTimeouts,
+    entrypoint::{local_server_entrypoint, ssh_entrypoint, LocalAddress, SshOptions, TunnelConfig},
+    handoff::Drain,
+    http::{
+        landing::{self, Activity},
+        registry::{self, ActivityContext},
+        ROUTER,
+    },
+    known_hosts::{user_known_hosts, KnownHosts},
+    ssh::{Credentials, HostKeyPolicy},
+    tunnel::{TunnelState, TunnelStatusCell, DEFAULT_MAINTENANCE_REASON},
+};
+use maud::html;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    registry::register_builtins();
+    registry::register("hello", "Hello - Says hi.", |_context| async {
+        Ok(Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    html! { h1 { "Hello!" } }
+                }),
+            )
+            .route(
+                "/summary",
+                get(|| async {
+                    html! { p { "Saying hi" } }
+                }),
+            ))
+    });
+    let context = ActivityContext::default();
+    let mut activities = vec![];
+    for name in ["checkboxes", "hello"] {
+        activities.push(Activity {
+            slug: name,
+            title: registry::description(name).unwrap(),
+            router: registry::build(name, context.clone()).await?,
+        });
+    }
+    ROUTER.set(landing::mount(activities)).unwrap();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.as_slice() {
+        [] => {
+            local_server_entrypoint(
+                &LocalAddress::Tcp {
+                    hostname: String::from("localhost"),
+                    port: 5023,
+                },
+                None,
+                Drain::default(),
+                ConnectionTimeouts::default(),
+                context.clock,
+            )
+            .await
+        }
+        [mode, hostname, identity_file] if mode == "ssh" => {
+            let ssh = SshOptions::new(
+                hostname,
+                Credentials::from_identity_file(Path::new(identity_file), None).await?,
+                KnownHosts::new(
+                    user_known_hosts().into_iter().collect(),
+                    HostKeyPolicy::Strict,
+                ),
+            );
+            ssh_entrypoint(
+                ssh,
+                TunnelConfig::default(),
+                TunnelStatusCell::new(
+                    TunnelState::Connecting,
+                    vec![String::from(DEFAULT_MAINTENANCE_REASON)],
+                ),
+                context.clock,
+                Drain::default(),
+                ConnectionTimeouts::default(),
+            )
+            .await
+        }
+        _ => None.with_context(|| "Usage: hello_activity [ssh HOSTNAME IDENTITY_FILE]"),
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tracing::info;
+
+/// Frees memory from a subsystem until it fits within the given budget (in bytes), oldest entries first.
+/// Returns the approximate number of bytes still in use afterwards.
+pub type EvictFn = Box<dyn Fn(usize) -> usize + Send + Sync>;
+
+/// Approximate memory usage of every long-lived subsystem, with optional soft caps.
+#[derive(Clone, Default)]
+pub struct MemoryAccounting {
+    soft_caps: Arc<HashMap<String, usize>>,
+    gauges: Arc<Mutex<BTreeMap<String, Arc<GaugeEntry>>>>,
+}
+
+struct GaugeEntry {
+    bytes: AtomicUsize,
+    evictions: AtomicU64,
+    soft_cap: Option<usize>,
+    evict: Option<EvictFn>,
+}
+
+/// Handle for a subsystem to report its approximate size.
+#[derive(Clone)]
+pub struct Gauge {
+    name: Arc<str>,
+    entry: Arc<GaugeEntry>,
+}
+
+/// Point-in-time values of a single gauge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GaugeSnapshot {
+    pub name: String,
+    pub bytes: usize,
+    pub soft_cap: Option<usize>,
+    pub evictions: u64,
+}
+
+impl MemoryAccounting {
+    /// Creates the registry, with soft caps (in bytes) keyed by subsystem name.
+    pub fn new(soft_caps: HashMap<String, usize>) -> Self {
+        MemoryAccounting {
+            soft_caps: Arc::new(soft_caps),
+            gauges: Arc::default(),
+        }
+    }
+
+    /// Registers a subsystem. If a soft cap has been configured for it, `evict` is called whenever it's exceeded.
+    pub fn register(&self, name: &str, evict: Option<EvictFn>) -> Gauge {
+        let entry = Arc::new(GaugeEntry {
+            bytes: AtomicUsize::new(0),
+            evictions: AtomicU64::new(0),
+            soft_cap: self.soft_caps.get(name).copied(),
+            evict,
+        });
+        self.gauges
+            .lock()
+            .unwrap()
+            .insert(String::from(name), Arc::clone(&entry));
+        Gauge {
+            name: Arc::from(name),
+            entry,
+        }
+    }
+
+    /// Current values of every registered gauge, sorted by name.
+    pub fn snapshot(&self) -> Vec<GaugeSnapshot> {
+        self.gauges
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| GaugeSnapshot {
+                name: name.clone(),
+                bytes: entry.bytes.load(Ordering::Relaxed),
+                soft_cap: entry.soft_cap,
+                evictions: entry.evictions.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Renders every gauge in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut output = String::from(
+            "# HELP htmx_ssh_games_memory_bytes Approximate memory used by each subsystem.\n\
+             # TYPE htmx_ssh_games_memory_bytes gauge\n",
+        );
+        for gauge in &snapshot {
+            output.push_str(&format!(
+                "htmx_ssh_games_memory_bytes{{subsystem=\"{}\"}} {}\n",
+                gauge.name, gauge.bytes
+            ));
+        }
+        output.push_str(
+            "# HELP htmx_ssh_games_memory_soft_cap_bytes Soft cap configured for each subsystem.\n\
+             # TYPE htmx_ssh_games_memory_soft_cap_bytes gauge\n",
+        );
+        for gauge in &snapshot {
+            if let Some(soft_cap) = gauge.soft_cap {
+                output.push_str(&format!(
+                    "htmx_ssh_games_memory_soft_cap_bytes{{subsystem=\"{}\"}} {}\n",
+                    gauge.name, soft_cap
+                ));
+            }
+        }
+        output.push_str(
+            "# HELP htmx_ssh_games_memory_evictions_total Times each subsystem exceeded its soft cap.\n\
+             # TYPE htmx_ssh_games_memory_evictions_total counter\n",
+        );
+        for gauge in &snapshot {
+            output.push_str(&format!(
+                "htmx_ssh_games_memory_evictions_total{{subsystem=\"{}\"}} {}\n",
+                gauge.name, gauge.evictions
+            ));
+        }
+        output
+    }
+}
+
+impl Gauge {
+    /// Updates the approximate size of the subsystem, evicting entries if it's over its soft cap.
+    ///
+    /// Callers must not hold any lock that the eviction callback takes.
+    pub fn set(&self, bytes: usize) {
+        self.entry.bytes.store(bytes, Ordering::Relaxed);
+        let (Some(soft_cap), Some(evict)) = (self.entry.soft_cap, &self.entry.evict) else {
+            return;
+        };
+        if bytes > soft_cap {
+            let remaining = evict(soft_cap);
+            self.entry.bytes.store(remaining, Ordering::Relaxed);
+            self.entry.evictions.fetch_add(1, Ordering::Relaxed);
+            info!(
+                subsystem = &*self.name,
+                before = bytes,
+                after = remaining,
+                "Evicted entries over soft cap."
+            );
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.entry.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// A byte count formatted for humans, such as `18.0 MB`.
+pub struct HumanBytes(pub usize);
+
+impl Display for HumanBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+        if self.0 < 1000 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64;
+        let mut unit = UNITS[0];
+        for next_unit in UNITS {
+            value /= 1000.0;
+            unit = next_unit;
+            if value < 1000.0 {
+                break;
+            }
+        }
+        write!(f, "{value:.1} {unit}")
+    }
+}
+
+/// Parses a soft cap given as `SUBSYSTEM=BYTES`, with an optional `K`, `M` or `G` suffix.
+pub fn parse_soft_cap(value: &str) -> Result<(String, usize), String> {
+    let (name, bytes) = value
+        .split_once('=')
+        .ok_or_else(|| String::from("expected SUBSYSTEM=BYTES"))?;
+    let bytes = bytes.trim();
+    let (digits, multiplier) = match bytes.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&bytes[..bytes.len() - 1], 1_000),
+        Some('M') => (&bytes[..bytes.len() - 1], 1_000_000),
+        Some('G') => (&bytes[..bytes.len() - 1], 1_000_000_000),
+        _ => (bytes, 1),
+    };
+    let bytes = digits
+        .parse::<usize>()
+        .map_err(|e| format!("invalid byte count: {e}"))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| String::from("byte count is too large"))?;
+    Ok((String::from(name.trim()), bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn it_evicts_when_over_the_soft_cap() {
+        let accounting = MemoryAccounting::new(HashMap::from([(String::from("fake"), 100)]));
+        let entries = Arc::new(Mutex::new(VecDeque::<usize>::new()));
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let gauge = accounting.register("fake", {
+            let entries = Arc::clone(&entries);
+            let evicted = Arc::clone(&evicted);
+            Some(Box::new(move |budget| {
+                let mut entries = entries.lock().unwrap();
+                while entries.iter().sum::<usize>() > budget {
+                    evicted.lock().unwrap().push(entries.pop_front().unwrap());
+                }
+                entries.iter().sum()
+            }))
+        });
+        for size in [30, 40, 20] {
+            entries.lock().unwrap().push_back(size);
+            gauge.set(entries.lock().unwrap().iter().sum());
+        }
+        assert_eq!(gauge.get(), 90);
+        assert!(evicted.lock().unwrap().is_empty());
+        entries.lock().unwrap().push_back(50);
+        gauge.set(140);
+        assert_eq!(*evicted.lock().unwrap(), vec![30, 40]);
+        assert_eq!(gauge.get(), 70);
+        assert_eq!(
+            accounting.snapshot(),
+            vec![GaugeSnapshot {
+                name: String::from("fake"),
+                bytes: 70,
+                soft_cap: Some(100),
+                evictions: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn it_never_evicts_without_a_soft_cap() {
+        let accounting = MemoryAccounting::default();
+        let gauge = accounting.register(
+            "fake",
+            Some(Box::new(|_| panic!("Evicted without a soft cap."))),
+        );
+        gauge.set(usize::MAX);
+        assert_eq!(gauge.get(), usize::MAX);
+    }
+
+    #[test]
+    fn it_renders_prometheus_metrics() {
+        let accounting = MemoryAccounting::new(HashMap::from([(String::from("cursors"), 2048)]));
+        accounting.register("cursors", None).set(1234);
+        accounting.register("chat", None).set(56);
+        let metrics = accounting.prometheus();
+        assert!(metrics.contains("htmx_ssh_games_memory_bytes{subsystem=\"chat\"} 56\n"));
+        assert!(metrics.contains("htmx_ssh_games_memory_bytes{subsystem=\"cursors\"} 1234\n"));
+        assert!(
+            metrics.contains("htmx_ssh_games_memory_soft_cap_bytes{subsystem=\"cursors\"} 2048\n")
+        );
+        assert!(!metrics.contains("htmx_ssh_games_memory_soft_cap_bytes{subsystem=\"chat\"}"));
+    }
+
+    #[test]
+    fn it_formats_and_parses_sizes() {
+        assert_eq!(HumanBytes(999).to_string(), "999 B");
+        assert_eq!(HumanBytes(40_000).to_string(), "40.0 KB");
+        assert_eq!(HumanBytes(18_000_000).to_string(), "18.0 MB");
+        assert_eq!(
+            parse_soft_cap("replays=18M"),
+            Ok((String::from("replays"), 18_000_000))
+        );
+        assert_eq!(
+            parse_soft_cap("chat=4096"),
+            Ok((String::from("chat"), 4096))
+        );
+        assert!(parse_soft_cap("chat").is_err());
+        assert!(parse_soft_cap("chat=lots").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Agent forwarding for the session channel, so that a command run with `--request-pty` can authenticate onward with
+//! our local ssh-agent.
+//!
+//! russh only hands us the id of an `auth-agent@openssh.com` channel, not a stream, so the agent protocol is relayed
+//! message by message from the client handler: each request is passed on to the local agent, and its reply is written
+//! back to the channel before the next one is read.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+};
+
+/// Largest agent message that is relayed either way, like OpenSSH's `AGENT_MAX_LEN`.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// One forwarded agent channel, with its own connection to the local agent.
+pub struct AgentRelay {
+    agent: UnixStream,
+    /// What the server sent that doesn't make up a whole message yet.
+    pending: Vec<u8>,
+}
+
+impl AgentRelay {
+    /// Connects to the local agent listening on `socket`.
+    pub async fn connect(socket: &Path) -> Result<Self> {
+        let agent = UnixStream::connect(socket).await.with_context(|| {
+            format!(
+                "Unable to connect to the ssh-agent at {}.",
+                socket.display()
+            )
+        })?;
+        Ok(Self::new(agent))
+    }
+
+    fn new(agent: UnixStream) -> Self {
+        AgentRelay {
+            agent,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Takes data that the server sent over the channel, and returns the agent's replies to the requests that it
+    /// completes, each with its length prefix.
+    pub async fn relay(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.pending.extend_from_slice(data);
+        let mut replies = Vec::new();
+        while let Some(len) = message_len(&self.pending)? {
+            if self.pending.len() < len {
+                break;
+            }
+            let request = self.pending.drain(..len).collect::<Vec<_>>();
+            self.agent
+                .write_all(&request)
+                .await
+                .with_context(|| "Unable to send a request to the ssh-agent.")?;
+            replies.push(self.read_reply().await?);
+        }
+        Ok(replies)
+    }
+
+    async fn read_reply(&mut self) -> Result<Vec<u8>> {
+        let mut reply = vec![0; 4];
+        self.agent
+            .read_exact(&mut reply)
+            .await
+            .with_context(|| "The ssh-agent didn't reply.")?;
+        let Some(len) = message_len(&reply)? else {
+            unreachable!("The length prefix was read.");
+        };
+        reply.resize(len, 0);
+        self.agent
+            .read_exact(&mut reply[4..])
+            .await
+            .with_context(|| "The ssh-agent's reply was cut short.")?;
+        Ok(reply)
+    }
+}
+
+/// The length of the agent message at the start of `buffer`, including its length prefix, once the prefix is there.
+fn message_len(buffer: &[u8]) -> Result<Option<usize>> {
+    let Some(prefix) = buffer.first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let len = u32::from_be_bytes(*prefix) as usize;
+    if len > MAX_MESSAGE_LEN {
+        bail!("Refusing to relay an agent message of {len} bytes.");
+    }
+    Ok(Some(len + 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `SSH_AGENTC_REQUEST_IDENTITIES`.
+    const REQUEST_IDENTITIES: [u8; 5] = [0, 0, 0, 1, 11];
+    /// `SSH_AGENT_IDENTITIES_ANSWER`, without any identity.
+    const NO_IDENTITIES: [u8; 9] = [0, 0, 0, 5, 12, 0, 0, 0, 0];
+
+    /// An agent that answers every request with [`NO_IDENTITIES`], and records the requests.
+    fn spawn_agent(mut stream: UnixStream) -> tokio::task::JoinHandle<Vec<Vec<u8>>> {
+        tokio::spawn(async move {
+            let mut requests = Vec::new();
+            let mut prefix = [0; 4];
+            while stream.read_exact(&mut prefix).await.is_ok() {
+                let mut request = vec![0; u32::from_be_bytes(prefix) as usize];
+                stream.read_exact(&mut request).await.unwrap();
+                requests.push(request);
+                stream.write_all(&NO_IDENTITIES).await.unwrap();
+            }
+            requests
+        })
+    }
+
+    #[tokio::test]
+    async fn requests_are_relayed_once_complete() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let agent = spawn_agent(theirs);
+        let mut relay = AgentRelay::new(ours);
+
+        assert!(relay
+            .relay(&REQUEST_IDENTITIES[..3])
+            .await
+            .unwrap()
+            .is_empty());
+        let mut data = REQUEST_IDENTITIES[3..].to_vec();
+        data.extend_from_slice(&REQUEST_IDENTITIES);
+        assert_eq!(
+            relay.relay(&data).await.unwrap(),
+            [NO_IDENTITIES, NO_IDENTITIES]
+        );
+
+        drop(relay);
+        assert_eq!(agent.await.unwrap(), [[11], [11]]);
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_refused() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let agent = spawn_agent(theirs);
+        let mut relay = AgentRelay::new(ours);
+
+        let error = relay.relay(&[0, 0x10, 0, 0, 11]).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to relay an agent message of 1048576 bytes."
+        );
+        drop(relay);
+        assert!(agent.await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! Files embedded into the binary, checked against the manifest that `build.rs` records, so that a broken build
+//! fails on startup instead of shipping a broken site.
+
+use std::{
+    fmt::{self, Display, Write},
+    ops::RangeInclusive,
+};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+/// A file embedded into the binary.
+#[derive(Debug)]
+pub struct Asset {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub bytes: &'static [u8],
+    /// Sizes that a healthy copy of this asset may have, to catch truncated or replaced files.
+    pub expected_size: RangeInclusive<usize>,
+}
+
+/// What `build.rs` recorded about an asset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub name: &'static str,
+    pub size: usize,
+    /// Lowercase hex.
+    pub sha256: &'static str,
+}
+
+pub static HTMX: Asset = Asset {
+    name: "htmx.min.js",
+    version: "2.0.2",
+    bytes: include_bytes!("htmx.min.js"),
+    expected_size: 40_000..=60_000,
+};
+
+/// Every embedded asset. Add new ones to `build.rs` too.
+pub static ASSETS: &[&Asset] = &[&HTMX];
+
+/// The manifest written by `build.rs`.
+pub static MANIFEST: &[ManifestEntry] = include!(concat!(env!("OUT_DIR"), "/assets_manifest.rs"));
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssetProblem {
+    NotInManifest {
+        name: &'static str,
+    },
+    UnexpectedSize {
+        name: &'static str,
+        size: usize,
+        expected: RangeInclusive<usize>,
+    },
+    ChecksumMismatch {
+        name: &'static str,
+        expected: &'static str,
+        actual: String,
+    },
+}
+
+impl Display for AssetProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetProblem::NotInManifest { name } => {
+                write!(f, "{name} is missing from the build manifest.")
+            }
+            AssetProblem::UnexpectedSize {
+                name,
+                size,
+                expected,
+            } => write!(
+                f,
+                "{name} is {size} bytes, expected {} to {} bytes.",
+                expected.start(),
+                expected.end()
+            ),
+            AssetProblem::ChecksumMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{name} has checksum {actual}, but the build recorded {expected}."
+            ),
+        }
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+}
+
+/// Every problem with `assets` according to `manifest`.
+pub fn validate(assets: &[&Asset], manifest: &[ManifestEntry]) -> Vec<AssetProblem> {
+    let mut problems = vec![];
+    for asset in assets {
+        let size = asset.bytes.len();
+        if !asset.expected_size.contains(&size) {
+            problems.push(AssetProblem::UnexpectedSize {
+                name: asset.name,
+                size,
+                expected: asset.expected_size.clone(),
+            });
+        }
+        let Some(entry) = manifest.iter().find(|entry| entry.name == asset.name) else {
+            problems.push(AssetProblem::NotInManifest { name: asset.name });
+            continue;
+        };
+        let actual = sha256_hex(asset.bytes);
+        if actual != entry.sha256 {
+            problems.push(AssetProblem::ChecksumMismatch {
+                name: asset.name,
+                expected: entry.sha256,
+                actual,
+            });
+        }
+    }
+    problems
+}
+
+/// Validates every embedded asset, logging their versions.
+pub fn check_embedded_assets() -> Result<()> {
+    let problems = validate(ASSETS, MANIFEST);
+    if !problems.is_empty() {
+        let problems = problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        return Err(anyhow!("Embedded assets are broken: {problems}"));
+    }
+    for asset in ASSETS {
+        info!(
+            name = asset.name,
+            version = asset.version,
+            size = asset.bytes.len(),
+            etag = etag(asset),
+            "Embedded asset is valid."
+        );
+    }
+    Ok(())
+}
+
+/// A strong `ETag` for an asset, from its checksum in the manifest.
+pub fn etag(asset: &Asset) -> String {
+    let checksum = MANIFEST
+        .iter()
+        .find(|entry| entry.name == asset.name)
+        .map_or("unknown", |entry| entry.sha256);
+    format!("\"{}\"", &checksum[..checksum.len().min(16)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_build_manifest_matches() {
+        assert_eq!(validate(ASSETS, MANIFEST), vec![]);
+        assert!(check_embedded_assets().is_ok());
+        assert_eq!(etag(&HTMX).len(), 18);
+    }
+
+    #[test]
+    fn it_reports_the_broken_asset() {
+        let mut manifest = MANIFEST.to_vec();
+        manifest[0].sha256 = "0000";
+        let problems = validate(ASSETS, &manifest);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            AssetProblem::ChecksumMismatch {
+                name: "htmx.min.js",
+                expected: "0000",
+                ..
+            }
+        ));
+        assert_eq!(
+            validate(ASSETS, &[]),
+            vec![AssetProblem::NotInManifest {
+                name: "htmx.min.js"
+            }]
+        );
+        let truncated = Asset {
+            name: HTMX.name,
+            version: HTMX.version,
+            bytes: &HTMX.bytes[..1000],
+            expected_size: HTMX.expected_size.clone(),
+        };
+        let problems = validate(&[&truncated], MANIFEST);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].to_string().contains("is 1000 bytes"));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::oneshot, time::Instant};
+
+/// Source of time for every time-based piece of logic (timers, expiries, backoffs).
+///
+/// Production code uses [`Clock::tokio`]. Tests can use [`Clock::manual`] and move time forward explicitly with
+/// [`ManualClock::advance`], instead of waiting in real time.
+#[derive(Clone, Debug)]
+pub struct Clock(ClockSource);
+
+#[derive(Clone, Debug)]
+enum ClockSource {
+    Tokio,
+    Manual(ManualClock),
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::tokio()
+    }
+}
+
+impl Clock {
+    /// A clock backed by `tokio::time`.
+    pub fn tokio() -> Self {
+        Clock(ClockSource::Tokio)
+    }
+
+    /// A clock which only moves forward when told to, along with the handle to move it.
+    pub fn manual() -> (Self, ManualClock) {
+        let manual = ManualClock::default();
+        (Clock(ClockSource::Manual(manual.clone())), manual)
+    }
+
+    pub fn now(&self) -> Instant {
+        match &self.0 {
+            ClockSource::Tokio => Instant::now(),
+            ClockSource::Manual(manual) => manual.now(),
+        }
+    }
+
+    /// Time elapsed since an earlier instant from this clock.
+    pub fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    /// Waits until the given deadline.
+    pub fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        match &self.0 {
+            ClockSource::Tokio => Box::pin(tokio::time::sleep_until(deadline)),
+            ClockSource::Manual(manual) => Box::pin(manual.sleep_until(deadline)),
+        }
+    }
+
+    pub fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+/// Handle to a manually-driven [`Clock`].
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<ManualClockState>>);
+
+#[derive(Debug)]
+struct ManualClockState {
+    now: Instant,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock(Arc::new(Mutex::new(ManualClockState {
+            now: Instant::now(),
+            sleepers: vec![],
+        })))
+    }
+}
+
+impl ManualClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        let receiver = {
+            let mut state = self.0.lock().unwrap();
+            if deadline <= state.now {
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+                state.sleepers.push((deadline, sender));
+                Some(receiver)
+            }
+        };
+        async move {
+            if let Some(receiver) = receiver {
+                let _ = receiver.await;
+            }
+        }
+    }
+
+    /// Moves time forward, waking up every sleeper whose deadline has passed.
+    ///
+    /// Woken tasks still need to be polled by the runtime, so tests should yield before asserting on their effects.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.0.lock().unwrap();
+        state.now += duration;
+        let now = state.now;
+        let (woken, sleeping) = state
+            .sleepers
+            .drain(..)
+            .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        state.sleepers = sleeping;
+        drop(state);
+        for (_, sender) in woken {
+            let _ = sender.send(());
+        }
+    }
+
+    /// How many tasks are waiting on this clock.
+    pub fn sleepers(&self) -> usize {
+        self.0.lock().unwrap().sleepers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn manual_sleeps_wake_up_in_order() {
+        let (clock, manual) = Clock::manual();
+        let start = clock.now();
+        let woken = Arc::new(Mutex::new(vec![]));
+        for secs in [30, 10, 20] {
+            let clock = clock.clone();
+            let woken = Arc::clone(&woken);
+            tokio::spawn(async move {
+                clock.sleep(Duration::from_secs(secs)).await;
+                woken.lock().unwrap().push(secs);
+            });
+        }
+        tokio::task::yield_now().await;
+        assert_eq!(manual.sleepers(), 3);
+        manual.advance(Duration::from_secs(15));
+        tokio::task::yield_now().await;
+        assert_eq!(*woken.lock().unwrap(), vec![10]);
+        assert_eq!(clock.elapsed(start), Duration::from_secs(15));
+        manual.advance(Duration::from_secs(15));
+        for _ in 0..3 {
+            tokio::task::yield_now().await;
+        }
+        let mut woken = woken.lock().unwrap().clone();
+        woken.sort();
+        assert_eq!(woken, vec![10, 20, 30]);
+        assert_eq!(manual.sleepers(), 0);
+    }
+
+    #[tokio::test]
+    async fn past_deadlines_resolve_immediately() {
+        let (clock, manual) = Clock::manual();
+        let deadline = clock.now() + Duration::from_secs(1);
+        manual.advance(Duration::from_secs(2));
+        clock.sleep_until(deadline).await;
+        clock.sleep(Duration::ZERO).await;
+        assert_eq!(manual.sleepers(), 0);
+    }
+}
@@ -0,0 +1,481 @@
+//! Serves the router over a single connection, whether it came through the tunnel or from a local listener.
+//!
+//! Clients that stop talking are cut off, so that they don't hold a channel open forever: a request's headers must
+//! arrive within the header read timeout, and a connection with no traffic either way for the idle timeout is closed.
+//! Such as keep-alive connections that browsers hold open after the player navigates away. A connection isn't idle
+//! while a response is in flight, even if its body is quiet for a while.
+
+use std::{
+    convert::Infallible,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    Router,
+};
+use hyper::{
+    body::{Frame, Incoming, SizeHint},
+    service::service_fn,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+    time::Instant,
+};
+use tower::Service;
+use tracing::{debug, warn};
+
+use crate::{clock::Clock, handoff::Drain, tunnel::TunnelStats};
+
+/// How long a client can take to send a request's headers, unless overridden with `--header-read-timeout`.
+pub const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a connection can go without traffic, unless overridden with `--idle-timeout`.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    pub header_read: Duration,
+    pub idle: Duration,
+}
+
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        ConnectionTimeouts {
+            header_read: DEFAULT_HEADER_READ_TIMEOUT,
+            idle: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// Serves `router` over `io` until the client is done, goes idle, or `drain` hands off. On handoff or once idle, the
+/// connection is shut down gracefully: the requests in flight are finished and the connection is closed, so that the
+/// client opens a new one (to the next instance, on handoff).
+///
+/// Handlers can extract `peer` as a `ConnectInfo<SocketAddr>`, as with Axum's own server. With `stats`, the connection,
+/// its requests and the bytes either way are counted there.
+///
+/// To make Axum behave with streaming, we must turn it into a Tower service first. And to handle `io` as a stream, we
+/// use a utility from `hyper_util` that turns an AsyncRead/Write stream into a `hyper` IO object.
+///
+/// See also: [axum/examples/serve-with-hyper](https://github.com/tokio-rs/axum/blob/main/examples/serve-with-hyper/src/main.rs)
+pub async fn serve_connection<I>(
+    io: I,
+    peer: SocketAddr,
+    router: Router,
+    timeouts: ConnectionTimeouts,
+    drain: Drain,
+    clock: Clock,
+    stats: Option<Arc<TunnelStats>>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let last_activity = Arc::new(Mutex::new(clock.now()));
+    if let Some(stats) = &stats {
+        stats.connection();
+    }
+    let io = IdleIo {
+        io,
+        clock: clock.clone(),
+        last_activity: Arc::clone(&last_activity),
+        stats: stats.clone(),
+    };
+    let requests = Arc::new(AtomicU64::new(0));
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let responses_done = Arc::new(Notify::new());
+    let router = router.into_service();
+    let hyper_service = service_fn({
+        let requests = Arc::clone(&requests);
+        let in_flight = Arc::clone(&in_flight);
+        let responses_done = Arc::clone(&responses_done);
+        let last_activity = Arc::clone(&last_activity);
+        let clock = clock.clone();
+        move |mut req: Request<Incoming>| {
+            requests.fetch_add(1, Ordering::Relaxed);
+            if let Some(stats) = &stats {
+                stats.request();
+            }
+            let guard = InFlight {
+                in_flight: Arc::clone(&in_flight),
+                done: Arc::clone(&responses_done),
+                last_activity: Arc::clone(&last_activity),
+                clock: clock.clone(),
+            };
+            guard.in_flight.fetch_add(1, Ordering::Relaxed);
+            req.extensions_mut().insert(ConnectInfo(peer));
+            let response = router.clone().call(req);
+            async move {
+                let response = response.await?;
+                Ok::<_, Infallible>(response.map(|body| {
+                    Body::new(InFlightBody {
+                        body,
+                        _in_flight: guard,
+                    })
+                }))
+            }
+        }
+    });
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(timeouts.header_read);
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(io), hyper_service);
+    tokio::pin!(connection);
+    let idle = async {
+        loop {
+            let done = responses_done.notified();
+            tokio::pin!(done);
+            done.as_mut().enable();
+            if in_flight.load(Ordering::Relaxed) > 0 {
+                done.await;
+                continue;
+            }
+            let deadline = *last_activity.lock().unwrap() + timeouts.idle;
+            if clock.now() >= deadline {
+                break;
+            }
+            clock.sleep_until(deadline).await;
+        }
+    };
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        () = drain.handoff_requested() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+        () = idle => {
+            debug!(idle_timeout = ?timeouts.idle, "Closing an idle connection.");
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    // Such as a malformed request, which only concerns this connection.
+    if let Err(err) = result {
+        warn!(err = %err, "Connection closed with an error.");
+    }
+    debug!(
+        requests = requests.load(Ordering::Relaxed),
+        "Connection closed."
+    );
+}
+
+/// Marks a request as in flight until its response body is done, so that the connection isn't considered idle meanwhile.
+struct InFlight {
+    in_flight: Arc<AtomicUsize>,
+    done: Arc<Notify>,
+    last_activity: Arc<Mutex<Instant>>,
+    clock: Clock,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        // The idle timeout starts over once the last response is done.
+        *self.last_activity.lock().unwrap() = self.clock.now();
+        if self.in_flight.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.done.notify_waiters();
+        }
+    }
+}
+
+/// A response body that holds its request's [`InFlight`] marker until it's done or dropped.
+struct InFlightBody {
+    body: Body,
+    _in_flight: InFlight,
+}
+
+impl hyper::body::Body for InFlightBody {
+    type Data = <Body as hyper::body::Body>::Data;
+    type Error = <Body as hyper::body::Body>::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Records when data last went through `io`, in either direction, and counts it into `stats`.
+struct IdleIo<I> {
+    io: I,
+    clock: Clock,
+    last_activity: Arc<Mutex<Instant>>,
+    stats: Option<Arc<TunnelStats>>,
+}
+
+impl<I> IdleIo<I> {
+    fn record<T>(&self, poll: &Poll<io::Result<T>>, transferred: bool) {
+        if transferred && matches!(poll, Poll::Ready(Ok(_))) {
+            *self.last_activity.lock().unwrap() = self.clock.now();
+        }
+    }
+
+    fn record_read(&self, bytes: usize) {
+        if let Some(stats) = &self.stats {
+            stats.received(bytes);
+        }
+    }
+
+    fn record_written(&self, poll: &Poll<io::Result<usize>>) {
+        if let (Some(stats), Poll::Ready(Ok(written))) = (&self.stats, poll) {
+            stats.sent(*written);
+        }
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for IdleIo<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.io).poll_read(cx, buf);
+        self.record(&poll, buf.filled().len() > filled);
+        self.record_read(buf.filled().len() - filled);
+        poll
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for IdleIo<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_write(cx, buf);
+        let written = matches!(poll, Poll::Ready(Ok(written)) if written > 0);
+        self.record(&poll, written);
+        self.record_written(&poll);
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_write_vectored(cx, bufs);
+        let written = matches!(poll, Poll::Ready(Ok(written)) if written > 0);
+        self.record(&poll, written);
+        self.record_written(&poll);
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    const PEER: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST), 49_152);
+
+    #[tokio::test]
+    async fn handlers_get_the_peer_address() {
+        let (mut client, server) = duplex(4096);
+        let router = Router::new().route(
+            "/",
+            get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.to_string() }),
+        );
+        tokio::spawn(serve_connection(
+            server,
+            PEER,
+            router,
+            ConnectionTimeouts::default(),
+            Drain::default(),
+            Clock::default(),
+            None,
+        ));
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response).ends_with("[::1]:49152"));
+    }
+
+    #[tokio::test]
+    async fn traffic_is_counted() {
+        let (mut client, server) = duplex(4096);
+        let router = Router::new().route("/", get(|| async { "Hello!" }));
+        let stats = Arc::new(TunnelStats::default());
+        let served = tokio::spawn(serve_connection(
+            server,
+            PEER,
+            router,
+            ConnectionTimeouts::default(),
+            Drain::default(),
+            Clock::default(),
+            Some(Arc::clone(&stats)),
+        ));
+        let requests = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                         GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        client.write_all(requests).await.unwrap();
+        let mut responses = Vec::new();
+        client.read_to_end(&mut responses).await.unwrap();
+        served.await.unwrap();
+        let totals = stats.totals();
+        assert_eq!(totals.connections, 1);
+        assert_eq!(totals.requests, 2);
+        assert_eq!(totals.bytes_received, requests.len() as u64);
+        assert_eq!(totals.bytes_sent, responses.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_only_close_their_connection() {
+        let (mut client, server) = duplex(4096);
+        let served = tokio::spawn(serve_connection(
+            server,
+            PEER,
+            Router::new(),
+            ConnectionTimeouts::default(),
+            Drain::default(),
+            Clock::default(),
+            None,
+        ));
+        client.write_all(b"NOT HTTP\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 400"));
+        served.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn idle_connections_are_closed() {
+        let (clock, manual) = Clock::manual();
+        let (mut client, server) = duplex(4096);
+        let router = Router::new().route("/", get(|| async { "Hello!" }));
+        let timeouts = ConnectionTimeouts {
+            header_read: Duration::from_secs(3600),
+            idle: Duration::from_secs(60),
+        };
+        let served = tokio::spawn(serve_connection(
+            server,
+            PEER,
+            router,
+            timeouts,
+            Drain::default(),
+            clock,
+            None,
+        ));
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = [0; 1024];
+        let read = client.read(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response[..read]).ends_with("Hello!"));
+        while manual.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        manual.advance(Duration::from_secs(59));
+        tokio::task::yield_now().await;
+        assert!(!served.is_finished());
+
+        manual.advance(Duration::from_secs(1));
+        served.await.unwrap();
+        assert_eq!(client.read(&mut response).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn streaming_responses_are_not_idle() {
+        let (clock, manual) = Clock::manual();
+        let (mut client, server) = duplex(4096);
+        let (chunks, receiver) = tokio::sync::mpsc::channel::<Result<&str, Infallible>>(1);
+        let receiver = Arc::new(Mutex::new(Some(receiver)));
+        let router =
+            Router::new().route(
+                "/",
+                get(move || {
+                    let receiver = receiver.lock().unwrap().take().unwrap();
+                    async move {
+                        Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(receiver))
+                    }
+                }),
+            );
+        let timeouts = ConnectionTimeouts {
+            header_read: Duration::from_secs(3600),
+            idle: Duration::from_secs(60),
+        };
+        let served = tokio::spawn(serve_connection(
+            server,
+            PEER,
+            router,
+            timeouts,
+            Drain::default(),
+            clock,
+            None,
+        ));
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        chunks.send(Ok("first")).await.unwrap();
+        let mut response = [0; 1024];
+        let read = client.read(&mut response).await.unwrap();
+        assert!(String::from_utf8_lossy(&response[..read]).contains("first"));
+        manual.advance(Duration::from_secs(120));
+        tokio::task::yield_now().await;
+        assert!(!served.is_finished());
+
+        chunks.send(Ok("second")).await.unwrap();
+        drop(chunks);
+        let mut body = String::new();
+        while !body.ends_with("0\r\n\r\n") {
+            let read = client.read(&mut response).await.unwrap();
+            body.push_str(&String::from_utf8_lossy(&response[..read]));
+        }
+        assert!(body.contains("second"));
+        while manual.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        manual.advance(Duration::from_secs(59));
+        tokio::task::yield_now().await;
+        assert!(!served.is_finished());
+
+        manual.advance(Duration::from_secs(1));
+        served.await.unwrap();
+        assert_eq!(client.read(&mut response).await.unwrap(), 0);
+    }
+}
@@ -1,83 +1,2114 @@
-use std::{iter, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    fmt::{self, Display},
+    fs::Permissions,
+    future::Future,
+    io, iter,
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
-use axum::Router;
-use russh::{client, keys::decode_secret_key};
-use tokio::{fs, net::TcpListener};
-use tracing::{debug, error, info};
+use axum::{Extension, Router};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio_util::either::Either;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::{http::ROUTER, ssh::TcpForwardSession};
+use crate::{
+    clock::Clock,
+    connection::{serve_connection, ConnectionTimeouts},
+    handoff::Drain,
+    http::{secure_context::TlsConnection, ROUTER},
+    known_hosts::KnownHosts,
+    ssh::{
+        AuthMethod, Credentials, Destination, ForwardingRefused, JumpHost, ProtocolSettings,
+        RemoteOutput, StaleConnection, TcpForwardSession,
+    },
+    tls::TlsCertificate,
+    tunnel::{Retries, RetryPolicy, TrafficTotals, TunnelState, TunnelStatusCell},
+};
 
 /* Local server entrypoint */
 
-/// Spins up a local Axum server for development.
-pub async fn local_server_entrypoint(hostname: &str, port: u16) -> Result<()> {
-    let listener = TcpListener::bind((hostname, port))
+/// Where the local server listens.
+#[derive(Clone, Debug)]
+pub enum LocalAddress {
+    Tcp {
+        hostname: String,
+        port: u16,
+    },
+    /// A Unix domain socket at this path, as for a reverse proxy on the same machine.
+    Unix(PathBuf),
+}
+
+impl Display for LocalAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalAddress::Tcp { hostname, port } => write!(f, "{hostname}:{port}"),
+            LocalAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A bound listener of either kind, which unlinks its socket file once dropped.
+enum LocalListener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl LocalListener {
+    async fn bind(address: &LocalAddress) -> Result<Self> {
+        match address {
+            LocalAddress::Tcp { hostname, port } => Ok(LocalListener::Tcp(
+                TcpListener::bind((hostname.as_str(), *port))
+                    .await
+                    .with_context(|| "Failed to bind TCP listener")?,
+            )),
+            LocalAddress::Unix(path) => {
+                remove_stale_socket(path).await?;
+                let listener = bind_unix_socket(path).await?;
+                Ok(LocalListener::Unix(listener, path.clone()))
+            }
+        }
+    }
+}
+
+impl Drop for LocalListener {
+    fn drop(&mut self) {
+        if let LocalListener::Unix(_, path) = self {
+            if let Err(e) = std::fs::remove_file(&*path) {
+                warn!(error = %e, path = %path.display(), "Unable to remove the Unix socket.");
+            }
+        }
+    }
+}
+
+/// Lets the owner and group of the Unix socket connect, such as a reverse proxy in the same group.
+const SOCKET_MODE: u32 = 0o660;
+
+/// Binds a Unix socket at `path` with [`SOCKET_MODE`]. The socket is made in a directory next to it that only we can
+/// enter, and only moved into place once its mode is set, since binding uses the process umask, which may let anyone
+/// connect in the meantime.
+async fn bind_unix_socket(path: &Path) -> Result<UnixListener> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} isn't a valid socket path.", path.display()))?;
+    let staging = path.with_file_name(format!(
+        ".{}.{:08x}",
+        file_name.to_string_lossy(),
+        rand::random::<u32>()
+    ));
+    tokio::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
         .await
-        .with_context(|| "Failed to bind TCP listener")?;
-    println!("Listening on http://{}:{}", hostname, port);
-    axum::serve(
-        listener,
-        Router::clone(
-            ROUTER
-                .get()
-                .with_context(|| "Router hasn't been initialized.")?,
-        ),
-    )
-    .await
-    .with_context(|| "Server has closed.")
+        .with_context(|| format!("Unable to create {}", staging.display()))?;
+    let staged = staging.join("socket");
+    let bound = async {
+        let listener = UnixListener::bind(&staged)
+            .with_context(|| format!("Failed to bind Unix socket {}", path.display()))?;
+        tokio::fs::set_permissions(&staged, Permissions::from_mode(SOCKET_MODE))
+            .await
+            .with_context(|| format!("Unable to set the mode of Unix socket {}", path.display()))?;
+        tokio::fs::rename(&staged, path)
+            .await
+            .with_context(|| format!("Unable to move Unix socket into {}", path.display()))?;
+        Ok(listener)
+    }
+    .await;
+    if bound.is_err() {
+        let _ = tokio::fs::remove_file(&staged).await;
+    }
+    if let Err(e) = tokio::fs::remove_dir(&staging).await {
+        warn!(error = %e, path = %staging.display(), "Unable to remove the staging directory of the Unix socket.");
+    }
+    bound
+}
+
+/// Removes a socket file at `path` that was left behind by a server that didn't shut down cleanly. Fails if the file
+/// isn't a socket, or if a server is still listening on it.
+async fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Unable to check {}", path.display()));
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        anyhow::bail!(
+            "{} already exists and isn't a socket; refusing to replace it.",
+            path.display()
+        );
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        anyhow::bail!("Another server is already listening on {}.", path.display());
+    }
+    info!(path = %path.display(), "Removing a stale Unix socket.");
+    tokio::fs::remove_file(path)
+        .await
+        .with_context(|| format!("Unable to remove stale Unix socket {}", path.display()))
+}
+
+/// Spins up a local Axum server for development, to serve a LAN directly, or behind a reverse proxy on a Unix socket.
+/// With `tls`, it's served over HTTPS, and clients have as long as the header read timeout to finish their handshake.
+///
+/// A Unix socket is made with mode 0660, replacing one that was left behind, and is removed again on shutdown. Its
+/// clients show up with the unspecified address and port 0.
+///
+/// Returns once a shutdown is requested through `drain`, after letting open connections finish their requests.
+pub async fn local_server_entrypoint(
+    address: &LocalAddress,
+    tls: Option<TlsCertificate>,
+    drain: Drain,
+    timeouts: ConnectionTimeouts,
+    clock: Clock,
+) -> Result<()> {
+    let listener = LocalListener::bind(address).await?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    match address {
+        LocalAddress::Tcp { .. } => println!("Listening on {scheme}://{address}"),
+        LocalAddress::Unix(_) => println!("Listening on {address} ({scheme})"),
+    }
+    let router = Router::clone(
+        ROUTER
+            .get()
+            .with_context(|| "Router hasn't been initialized.")?,
+    );
+    // HTTP/1.1 requests don't say their scheme, so pages need to be told that they're served over HTTPS.
+    let tls_router = router.clone().layer(Extension(TlsConnection));
+    loop {
+        let accepted = tokio::select! {
+            accepted = async {
+                match &listener {
+                    LocalListener::Tcp(listener) => listener
+                        .accept()
+                        .await
+                        .map(|(stream, peer)| (Either::Left(stream), peer)),
+                    LocalListener::Unix(listener, _) => listener.accept().await.map(|(stream, _)| {
+                        (Either::Right(stream), SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+                    }),
+                }
+            } => accepted,
+            () = drain.handoff_requested() => break,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            // Such as running out of file descriptors, which may pass once some connections close.
+            Err(e) => {
+                warn!(error = %e, "Unable to accept a connection.");
+                clock.sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Ok(guard) = drain.begin_connection() else {
+            continue;
+        };
+        let (drain, clock) = (drain.clone(), clock.clone());
+        let (router, acceptor) = match &tls {
+            Some(tls) => (tls_router.clone(), Some(tls.acceptor())),
+            None => (router.clone(), None),
+        };
+        tokio::spawn(
+            async move {
+                let _guard = guard;
+                let Some(acceptor) = acceptor else {
+                    return serve_connection(stream, peer, router, timeouts, drain, clock, None)
+                        .await;
+                };
+                let stream = tokio::select! {
+                    accepted = acceptor.accept(stream) => match accepted {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            debug!(error = %e, "TLS handshake failed.");
+                            return;
+                        }
+                    },
+                    () = clock.sleep(timeouts.header_read) => {
+                        debug!("TLS handshake timed out.");
+                        return;
+                    }
+                };
+                serve_connection(stream, peer, router, timeouts, drain, clock, None).await
+            }
+            .instrument(info_span!("connection", peer = %peer)),
+        );
+    }
+    // New clients are turned away by the missing socket, rather than left waiting in the backlog.
+    drop(listener);
+    drain.drained(drain.timeout(), &clock).await;
+    Ok(())
 }
 
 /* SSH entrypoint */
 
+/// Where and how to log in to an SSH server, for the SSH entrypoints.
+#[derive(Clone)]
+pub struct SshOptions {
+    pub host: String,
+    pub port: u16,
+    /// A bastion that `host` is only reachable through, if any.
+    pub jump_host: Option<JumpHost>,
+    pub login_name: String,
+    pub credentials: Credentials,
+    pub known_hosts: KnownHosts,
+    pub protocol: ProtocolSettings,
+}
+
+impl SshOptions {
+    /// Port 22 of `host`, without a login name or a jump host, and with the default protocol settings.
+    pub fn new(host: impl Into<String>, credentials: Credentials, known_hosts: KnownHosts) -> Self {
+        SshOptions {
+            host: host.into(),
+            port: 22,
+            jump_host: None,
+            login_name: String::new(),
+            credentials,
+            known_hosts,
+            protocol: ProtocolSettings::default(),
+        }
+    }
+}
+
+/// What [`ssh_entrypoint`] forwards, and how it keeps the tunnel up.
+#[derive(Clone, Debug)]
+pub struct TunnelConfig {
+    /// Remote hostname to bind to, where empty means every address.
+    pub remote_host: String,
+    /// Remote port to bind to. With 0, the server picks one.
+    pub remote_port: u16,
+    /// Unix socket on the server to listen on instead of the remote port.
+    pub remote_socket: Option<String>,
+    /// Remote ports to try in turn when the server refuses `remote_port`.
+    pub fallback_ports: Vec<u16>,
+    /// Command to run in a pseudo-terminal on the session channel.
+    pub request_pty: Option<String>,
+    /// The ssh-agent socket to relay agent forwarding to, which the session channel asks for.
+    pub forward_agent: Option<PathBuf>,
+    pub remote_output: RemoteOutput,
+    /// How long to wait before asking again for a remote port that's taken, instead of reconnecting.
+    pub bind_retry: Option<Duration>,
+    pub retry: RetryPolicy,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        TunnelConfig {
+            remote_host: String::new(),
+            remote_port: 80,
+            remote_socket: None,
+            fallback_ports: Vec::new(),
+            request_pty: None,
+            forward_agent: None,
+            remote_output: RemoteOutput::detect(),
+            bind_retry: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
 /// Begins remote port forwarding (reverse tunneling) with Russh to serve an Axum application.
+///
+/// Returns once `drain` is handed off, after letting go of the remote port and waiting for open connections. With
+/// `bind_retry`, waits for the remote port to be free instead of reconnecting, as when taking over from an instance
+/// that is handing off. With keepalives in the protocol settings, dead connections are noticed and reconnected.
+///
+/// Connecting is retried as `retry` says, including the first time. Errors that retrying can't fix, like a refused
+/// key, are returned right away. With a jump host, the SSH server is reached through it, and every reconnection goes
+/// through a new session to it, whichever of the two connections was lost.
+///
+/// When the server refuses `remote_port`, each of the `fallback_ports` is tried in turn. A session that ends before
+/// forwarding, as when they're all refused, counts as a failed attempt just like a failed connection, so that the
+/// server isn't hammered. A session that ends after forwarding counts as a restart instead.
+///
+/// With `remote_socket`, the server listens on that Unix socket instead of the remote port, and lets go of it again
+/// before every disconnect, so that stale sockets don't pile up on the server.
+///
+/// With `forward_agent`, the session channel asks for agent forwarding, which is relayed to the ssh-agent on that
+/// socket.
 pub async fn ssh_entrypoint(
-    host: &str,
-    port: u16,
-    login_name: &str,
-    identity_file: PathBuf,
-    remote_host: &str,
-    remote_port: u16,
-    request_pty: Option<String>,
+    ssh: SshOptions,
+    tunnel: TunnelConfig,
+    status: TunnelStatusCell,
+    clock: Clock,
+    drain: Drain,
+    timeouts: ConnectionTimeouts,
 ) -> Result<()> {
-    let secret_key = fs::read_to_string(identity_file)
-        .await
-        .with_context(|| "Failed to open secret key")?;
-    let secret_key =
-        Arc::new(decode_secret_key(&secret_key, None).with_context(|| "Invalid secret key")?);
-    let config = Arc::new(client::Config {
-        ..Default::default()
-    });
+    let TunnelConfig {
+        remote_host,
+        remote_port,
+        remote_socket,
+        fallback_ports,
+        request_pty,
+        forward_agent,
+        remote_output,
+        bind_retry,
+        retry,
+    } = tunnel;
+    let (remote_host, remote_socket) = (remote_host.as_str(), remote_socket.as_deref());
+    let config = Arc::new(ssh.protocol.config());
+    let known_hosts = Arc::new(ssh.known_hosts);
+    let mut retries = Retries::new(retry);
+    status.set_state(TunnelState::Connecting);
     loop {
-        let mut reconnect_attempt = 0;
-        let mut session = TcpForwardSession::connect(
-            host,
-            port,
-            login_name,
+        let connecting = TcpForwardSession::connect(
+            &ssh.host,
+            ssh.port,
+            ssh.jump_host.as_ref(),
+            &ssh.login_name,
             Arc::clone(&config),
-            Arc::clone(&secret_key),
-            iter::from_fn(move || {
-                reconnect_attempt += 1;
-                if reconnect_attempt <= 5 {
-                    Some(Duration::from_secs(2 * reconnect_attempt))
+            &ssh.credentials,
+            Arc::clone(&known_hosts),
+            status.clone(),
+            drain.clone(),
+            timeouts,
+            clock.clone(),
+            &mut retries,
+        );
+        // Without a session, there's no port to let go of.
+        let mut session = tokio::select! {
+            result = connecting => result.with_context(|| "Connection failed.")?,
+            () = drain.handoff_requested() => {
+                info!("Stopped connecting.");
+                return Ok(());
+            }
+        };
+        status.connected();
+        // What to let go of at handoff, which is only known once the server has picked a port for a `remote_port` of 0.
+        let mut bound_port = remote_port;
+        let forwarding = tokio::select! {
+            result = async {
+                match remote_socket {
+                    Some(remote_socket) => {
+                        session.start_socket_forwarding(remote_socket).await?;
+                        status.forwarding_socket(remote_socket);
+                    }
+                    None => {
+                        bound_port = session
+                            .start_forwarding(remote_host, remote_port, &fallback_ports, bind_retry, &clock)
+                            .await?;
+                        status.forwarding(bound_port);
+                    }
+                }
+                retries.forwarding();
+                session
+                    .run(request_pty.as_deref(), forward_agent.as_deref(), remote_output)
+                    .await
+            } => Some(result),
+            () = drain.handoff_requested() => None,
+        };
+        let mut refused = None;
+        match forwarding {
+            Some(Err(e)) if e.is::<ForwardingRefused>() => refused = Some(e),
+            Some(Err(e)) if e.is::<StaleConnection>() => {
+                warn!(error = %e, "The connection went stale, reconnecting.")
+            }
+            Some(Err(e)) => error!(error = ?e, "TCP forward session failed."),
+            Some(Ok(_)) => info!("Connection closed."),
+            None => {
+                let stopped = match remote_socket {
+                    Some(remote_socket) => session.cancel_socket_forwarding(remote_socket).await,
+                    None => session.cancel_forwarding(remote_host, bound_port).await,
+                };
+                if let Err(e) = stopped {
+                    warn!(error = ?e, "Unable to let go of the remote port.");
+                }
+                info!(
+                    connections = drain.connections(),
+                    "Let go of the remote port, waiting for open connections."
+                );
+                drain.drained(drain.timeout(), &clock).await;
+                if let Err(e) = session.close().await {
+                    debug!(error = ?e, "Graceful disconnect failed.")
+                }
+                if drain.is_shutting_down() {
+                    info!("Closed the tunnel.");
                 } else {
-                    None
+                    info!("Handed off the tunnel.");
                 }
-            }),
-        )
-        .await
-        .with_context(|| "Connection failed.")?;
-        match session
-            .start_forwarding(remote_host, remote_port, request_pty.as_deref())
-            .await
-        {
-            Err(e) => error!(error = ?e, "TCP forward session failed."),
-            _ => info!("Connection closed."),
+                return Ok(());
+            }
         }
         debug!("Attempting graceful disconnect.");
+        if let Some(remote_socket) = remote_socket {
+            if let Err(e) = session.cancel_socket_forwarding(remote_socket).await {
+                debug!(error = ?e, "Unable to let go of the remote socket.")
+            }
+        }
         if let Err(e) = session.close().await {
             debug!(error = ?e, "Graceful disconnect failed.")
         }
-        debug!("Restarting connection.");
+        let policy = status.connection_lost();
+        let delay = match retries.session_ended(policy) {
+            Ok(delay) => delay,
+            Err(e) => {
+                return Err(match refused {
+                    Some(refused) => refused.context("Gave up on forwarding a remote port."),
+                    None => e,
+                })
+            }
+        };
+        if let Some(refused) = refused {
+            warn!(error = %refused, retry_in = ?delay, "No remote port was forwarded.");
+        }
+        debug!(policy = ?policy, retry_in = ?delay, "Restarting connection.");
+        if let Some(delay) = delay {
+            tokio::select! {
+                () = clock.sleep(delay) => (),
+                () = drain.handoff_requested() => {
+                    info!("Stopped connecting.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/* Tunnel test entrypoint */
+
+/// Proxies the connections to a local listener on `listen` to `destination` through the SSH server, like `ssh -L`,
+/// until `stop` resolves. This checks the login, keys and network path of a tunnel without the HTTP server in the way.
+///
+/// Connecting isn't retried, so that the first error is the one reported. Returns what was proxied, which is logged
+/// too.
+pub async fn tunnel_test_entrypoint(
+    ssh: SshOptions,
+    listen: &str,
+    destination: &Destination,
+    stop: impl Future<Output = ()>,
+) -> Result<TrafficTotals> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind TCP listener on {listen}"))?;
+    let mut session = TcpForwardSession::connect(
+        &ssh.host,
+        ssh.port,
+        ssh.jump_host.as_ref(),
+        &ssh.login_name,
+        Arc::new(ssh.protocol.config()),
+        &ssh.credentials,
+        Arc::new(ssh.known_hosts),
+        TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+        Drain::default(),
+        ConnectionTimeouts::default(),
+        Clock::tokio(),
+        iter::empty(),
+    )
+    .await
+    .with_context(|| "Connection failed.")?;
+    info!(
+        listen = %listener.local_addr()?,
+        destination = %destination,
+        "Forwarding local connections through the SSH server."
+    );
+    let forwarded = session.forward_local(listener, destination, stop).await;
+    match session.close().await {
+        Ok(()) => info!("Disconnected."),
+        Err(e) => warn!(error = ?e, "Graceful disconnect failed."),
+    }
+    let totals = forwarded?;
+    info!(
+        connections = totals.connections,
+        received = totals.bytes_received,
+        sent = totals.bytes_sent,
+        "Proxied through the tunnel."
+    );
+    Ok(totals)
+}
+
+/* Connection check entrypoint */
+
+/// What [`check_connection_entrypoint`] found out about an SSH server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionReport {
+    pub host: String,
+    pub port: u16,
+    /// The server's version string, such as `SSH-2.0-OpenSSH_9.6`.
+    pub server_version: String,
+    pub auth_method: AuthMethod,
+    pub remote_host: String,
+    /// The remote port that the server agreed to forward, or 0 if it didn't say which one it assigned.
+    pub remote_port: u16,
+}
+
+impl Display for ConnectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Connected to {}:{}", self.host, self.port)?;
+        writeln!(f, "Server version: {}", self.server_version)?;
+        writeln!(f, "Authenticated with {}", self.auth_method)?;
+        let remote_host = if self.remote_host.is_empty() {
+            "*"
+        } else {
+            &self.remote_host
+        };
+        if self.remote_port == 0 {
+            writeln!(
+                f,
+                "Forwarding allowed on {remote_host}, on a port that the server didn't tell"
+            )
+        } else {
+            writeln!(
+                f,
+                "Forwarding allowed on {remote_host}:{}",
+                self.remote_port
+            )
+        }
+    }
+}
+
+/// Connects and logs in to the SSH server, has it forward the remote port and lets go of it right away, without
+/// serving anything. This checks a deployment's settings before running it for real.
+///
+/// Nothing is retried, so that the first error is the one reported, and neither is a refused port waited for.
+pub async fn check_connection_entrypoint(
+    ssh: SshOptions,
+    remote_host: &str,
+    remote_port: u16,
+    fallback_ports: &[u16],
+) -> Result<ConnectionReport> {
+    let clock = Clock::tokio();
+    let mut session = TcpForwardSession::connect(
+        &ssh.host,
+        ssh.port,
+        ssh.jump_host.as_ref(),
+        &ssh.login_name,
+        Arc::new(ssh.protocol.config()),
+        &ssh.credentials,
+        Arc::new(ssh.known_hosts),
+        TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+        Drain::default(),
+        ConnectionTimeouts::default(),
+        clock.clone(),
+        iter::empty(),
+    )
+    .await
+    .with_context(|| "Connection failed.")?;
+    let bound_port = session
+        .start_forwarding(remote_host, remote_port, fallback_ports, None, &clock)
+        .await?;
+    session
+        .cancel_forwarding(remote_host, bound_port)
+        .await
+        .with_context(|| "Unable to let go of the remote port.")?;
+    let server_version = session.server_version().await?;
+    if let Err(e) = session.close().await {
+        debug!(error = ?e, "Graceful disconnect failed.")
+    }
+    Ok(ConnectionReport {
+        host: ssh.host,
+        port: ssh.port,
+        server_version,
+        auth_method: session.auth_method(),
+        remote_host: String::from(remote_host),
+        remote_port: bound_port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use async_trait::async_trait;
+    use axum::routing::get;
+    use russh::{
+        cipher, kex,
+        keys::{
+            agent::{client::AgentClient, server::serve},
+            key::{KeyPair, PublicKey},
+            known_hosts::learn_known_hosts_path,
+            PublicKeyBase64,
+        },
+        mac,
+        server::{self, Auth, Msg, Session},
+        Channel, ChannelId, CryptoVec, Disconnect,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream, UnixListener,
+        },
+        sync::Notify,
+        task::JoinHandle,
+        time::Instant,
+    };
+    use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
+    use tokio_stream::wrappers::UnixListenerStream;
+
+    use super::*;
+    use crate::{
+        http::secure_context::SecureContext,
+        ssh::{AuthRefused, BackoffConfig, HostKeyPolicy, Keepalive},
+        tls,
+        tunnel::{ReconnectPolicy, TunnelState},
+    };
+
+    /// What happened to the forwarded port, by session number.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    enum PortEvent {
+        Bound(usize),
+        Refused(usize),
+        Released(usize),
+    }
+
+    /// What the tunnel server picks when asked for port 0.
+    const ASSIGNED_PORT: u32 = 49_152;
+
+    /// A tunnel server with a single public port, which it forwards to whichever session bound it, like sshd or sish.
+    #[derive(Clone, Default)]
+    struct TunnelServer {
+        owner: Arc<Mutex<Option<(usize, server::Handle)>>>,
+        /// The Unix socket that the owner asked for instead of the port, if any.
+        socket: Arc<Mutex<Option<String>>>,
+        events: Arc<Mutex<Vec<(Instant, PortEvent)>>>,
+        sessions: Arc<AtomicUsize>,
+        /// The only key allowed in, if any. Otherwise, every key is.
+        authorized_key: Option<PublicKey>,
+        /// Ports that are never forwarded, as with `PermitListen`.
+        refused_ports: Vec<u32>,
+        /// The password that keyboard-interactive authentication asks for, if it's offered.
+        password: Option<&'static str>,
+        /// What the client's agent replied when asked for its identities over a forwarded agent channel.
+        agent_replies: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl TunnelServer {
+        /// Serves SSH and the public port, and returns their addresses.
+        async fn spawn(&self) -> (SocketAddr, SocketAddr) {
+            self.spawn_on(TcpListener::bind("127.0.0.1:0").await.unwrap())
+                .await
+        }
+
+        /// Serves SSH on `ssh`, and the public port.
+        async fn spawn_on(&self, ssh: TcpListener) -> (SocketAddr, SocketAddr) {
+            let config = Arc::new(server::Config {
+                keys: vec![KeyPair::generate_ed25519()],
+                ..Default::default()
+            });
+            let ssh_addr = ssh.local_addr().unwrap();
+            let tunnel = self.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = ssh.accept().await {
+                    let handler = TunnelSession {
+                        id: tunnel.sessions.fetch_add(1, Ordering::SeqCst),
+                        tunnel: tunnel.clone(),
+                        port: None,
+                        agent_channel: None,
+                    };
+                    server::run_stream(config.clone(), stream, handler)
+                        .await
+                        .unwrap();
+                }
+            });
+            let public = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let public_addr = public.local_addr().unwrap();
+            let tunnel = self.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, peer)) = public.accept().await {
+                    // Nobody holds the port: the connection is refused.
+                    let Some((_, handle)) = tunnel.owner.lock().unwrap().clone() else {
+                        continue;
+                    };
+                    let socket = tunnel.socket.lock().unwrap().clone();
+                    tokio::spawn(async move {
+                        let channel = match socket {
+                            Some(socket) => handle.channel_open_forwarded_streamlocal(socket).await,
+                            None => {
+                                handle
+                                    .channel_open_forwarded_tcpip(
+                                        "localhost",
+                                        80,
+                                        peer.ip().to_string(),
+                                        peer.port().into(),
+                                    )
+                                    .await
+                            }
+                        }
+                        .unwrap();
+                        let _ =
+                            tokio::io::copy_bidirectional(&mut stream, &mut channel.into_stream())
+                                .await;
+                    });
+                }
+            });
+            (ssh_addr, public_addr)
+        }
+
+        fn record(&self, event: PortEvent) {
+            self.events.lock().unwrap().push((Instant::now(), event));
+        }
+
+        fn when(&self, event: PortEvent) -> Option<Instant> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(_, recorded)| *recorded == event)
+                .map(|&(instant, _)| instant)
+        }
+
+        async fn wait_for(&self, event: PortEvent) -> Instant {
+            for _ in 0..500 {
+                if let Some(instant) = self.when(event) {
+                    return instant;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("Timed out waiting for {event:?}");
+        }
+    }
+
+    struct TunnelSession {
+        id: usize,
+        tunnel: TunnelServer,
+        /// The port this session bound, as the server reported it.
+        port: Option<u32>,
+        agent_channel: Option<ChannelId>,
+    }
+
+    impl Drop for TunnelSession {
+        fn drop(&mut self) {
+            let mut owner = self.tunnel.owner.lock().unwrap();
+            if matches!(*owner, Some((id, _)) if id == self.id) {
+                *owner = None;
+                *self.tunnel.socket.lock().unwrap() = None;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl server::Handler for TunnelSession {
+        type Error = anyhow::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _user: &str,
+            public_key: &PublicKey,
+        ) -> Result<Auth, Self::Error> {
+            match &self.tunnel.authorized_key {
+                Some(authorized_key) if authorized_key != public_key => Ok(Auth::Reject {
+                    proceed_with_methods: None,
+                }),
+                _ => Ok(Auth::Accept),
+            }
+        }
+
+        async fn auth_keyboard_interactive(
+            &mut self,
+            _user: &str,
+            _submethods: &str,
+            response: Option<server::Response<'async_trait>>,
+        ) -> Result<Auth, Self::Error> {
+            let reject = Auth::Reject {
+                proceed_with_methods: None,
+            };
+            let Some(password) = self.tunnel.password else {
+                return Ok(reject);
+            };
+            match response {
+                None => Ok(Auth::Partial {
+                    name: "".into(),
+                    instructions: "".into(),
+                    prompts: vec![("Password: ".into(), false)].into(),
+                }),
+                Some(response) => match response.into_iter().next() {
+                    Some(answer) if answer == password.as_bytes() => Ok(Auth::Accept),
+                    _ => Ok(reject),
+                },
+            }
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<Msg>,
+            _session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        /// Asks the client's agent for its identities, like `ssh-add -l` would on the server.
+        async fn agent_request(
+            &mut self,
+            _channel: ChannelId,
+            session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            self.agent_channel = Some(session.channel_open_agent()?);
+            Ok(true)
+        }
+
+        async fn channel_open_confirmation(
+            &mut self,
+            id: ChannelId,
+            _max_packet_size: u32,
+            _window_size: u32,
+            session: &mut Session,
+        ) -> Result<(), Self::Error> {
+            if self.agent_channel == Some(id) {
+                // SSH_AGENTC_REQUEST_IDENTITIES
+                session.data(id, CryptoVec::from_slice(&[0, 0, 0, 1, 11]));
+            }
+            Ok(())
+        }
+
+        async fn data(
+            &mut self,
+            channel: ChannelId,
+            data: &[u8],
+            _session: &mut Session,
+        ) -> Result<(), Self::Error> {
+            if self.agent_channel == Some(channel) {
+                self.tunnel
+                    .agent_replies
+                    .lock()
+                    .unwrap()
+                    .extend_from_slice(data);
+            }
+            Ok(())
+        }
+
+        async fn tcpip_forward(
+            &mut self,
+            _address: &str,
+            port: &mut u32,
+            session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            let mut owner = self.tunnel.owner.lock().unwrap();
+            if owner.is_some() || self.tunnel.refused_ports.contains(port) {
+                self.tunnel.record(PortEvent::Refused(self.id));
+                return Ok(false);
+            }
+            if *port == 0 {
+                *port = ASSIGNED_PORT;
+            }
+            self.port = Some(*port);
+            *owner = Some((self.id, session.handle()));
+            self.tunnel.record(PortEvent::Bound(self.id));
+            Ok(true)
+        }
+
+        async fn cancel_tcpip_forward(
+            &mut self,
+            _address: &str,
+            port: u32,
+            _session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            let mut owner = self.tunnel.owner.lock().unwrap();
+            if !matches!(*owner, Some((id, _)) if id == self.id) || self.port != Some(port) {
+                return Ok(false);
+            }
+            *owner = None;
+            self.tunnel.record(PortEvent::Released(self.id));
+            Ok(true)
+        }
+
+        async fn streamlocal_forward(
+            &mut self,
+            socket_path: &str,
+            session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            let mut owner = self.tunnel.owner.lock().unwrap();
+            if owner.is_some() {
+                self.tunnel.record(PortEvent::Refused(self.id));
+                return Ok(false);
+            }
+            *self.tunnel.socket.lock().unwrap() = Some(String::from(socket_path));
+            *owner = Some((self.id, session.handle()));
+            self.tunnel.record(PortEvent::Bound(self.id));
+            Ok(true)
+        }
+
+        async fn cancel_streamlocal_forward(
+            &mut self,
+            socket_path: &str,
+            _session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            let mut owner = self.tunnel.owner.lock().unwrap();
+            let mut socket = self.tunnel.socket.lock().unwrap();
+            if !matches!(*owner, Some((id, _)) if id == self.id)
+                || socket.as_deref() != Some(socket_path)
+            {
+                return Ok(false);
+            }
+            *owner = None;
+            *socket = None;
+            self.tunnel.record(PortEvent::Released(self.id));
+            Ok(true)
+        }
+    }
+
+    /// A bastion that lets every key in, and opens `direct-tcpip` channels to anywhere.
+    #[derive(Clone, Default)]
+    struct Bastion {
+        sessions: Arc<Mutex<Vec<server::Handle>>>,
+    }
+
+    impl Bastion {
+        async fn spawn(&self) -> SocketAddr {
+            let config = Arc::new(server::Config {
+                keys: vec![KeyPair::generate_ed25519()],
+                ..Default::default()
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let bastion = self.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let session = server::run_stream(config.clone(), stream, bastion.clone())
+                        .await
+                        .unwrap();
+                    bastion.sessions.lock().unwrap().push(session.handle());
+                    tokio::spawn(session);
+                }
+            });
+            addr
+        }
+
+        async fn wait_for_sessions(&self, count: usize) {
+            for _ in 0..500 {
+                if self.sessions.lock().unwrap().len() >= count {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("Timed out waiting for {count} sessions");
+        }
+    }
+
+    #[async_trait]
+    impl server::Handler for Bastion {
+        type Error = anyhow::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _user: &str,
+            _public_key: &PublicKey,
+        ) -> Result<Auth, Self::Error> {
+            Ok(Auth::Accept)
+        }
+
+        async fn channel_open_direct_tcpip(
+            &mut self,
+            channel: Channel<Msg>,
+            host_to_connect: &str,
+            port_to_connect: u32,
+            _originator_address: &str,
+            _originator_port: u32,
+            _session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            let mut stream = TcpStream::connect((host_to_connect, port_to_connect as u16)).await?;
+            tokio::spawn(async move {
+                let _ =
+                    tokio::io::copy_bidirectional(&mut stream, &mut channel.into_stream()).await;
+            });
+            Ok(true)
+        }
+    }
+
+    /// Logs in to the SSH server at `addr` as `player`.
+    fn player(addr: SocketAddr, credentials: Credentials, known_hosts: KnownHosts) -> SshOptions {
+        SshOptions {
+            port: addr.port(),
+            login_name: String::from("player"),
+            ..SshOptions::new(addr.ip().to_string(), credentials, known_hosts)
+        }
+    }
+
+    fn spawn_instance(
+        ssh_addr: SocketAddr,
+        credentials: Credentials,
+        known_hosts: KnownHosts,
+        drain: Drain,
+        bind_retry: Option<Duration>,
+    ) -> JoinHandle<Result<()>> {
+        tokio::spawn(async move {
+            ssh_entrypoint(
+                player(ssh_addr, credentials, known_hosts),
+                TunnelConfig {
+                    remote_host: String::from("localhost"),
+                    remote_output: RemoteOutput::Log,
+                    bind_retry,
+                    ..Default::default()
+                },
+                TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+                Clock::tokio(),
+                drain,
+                ConnectionTimeouts::default(),
+            )
+            .await
+        })
+    }
+
+    /// Forwards TCP connections to `target`, until the connections opened before the returned count go silent, like
+    /// a network that drops packets without closing anything.
+    async fn spawn_proxy(target: SocketAddr) -> (SocketAddr, Arc<AtomicUsize>) {
+        async fn pipe(
+            mut from: OwnedReadHalf,
+            mut to: OwnedWriteHalf,
+            id: usize,
+            silenced: Arc<AtomicUsize>,
+        ) {
+            let mut buffer = [0; 4096];
+            while let Ok(read @ 1..) = from.read(&mut buffer).await {
+                if id < silenced.load(Ordering::SeqCst) {
+                    // Holds on to both ends, so that nothing gets closed.
+                    std::future::pending::<()>().await;
+                }
+                if to.write_all(&buffer[..read]).await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let silenced = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let silenced = Arc::clone(&silenced);
+            async move {
+                for id in 0.. {
+                    let Ok((client, _)) = listener.accept().await else {
+                        break;
+                    };
+                    let server = TcpStream::connect(target).await.unwrap();
+                    let (client_read, client_write) = client.into_split();
+                    let (server_read, server_write) = server.into_split();
+                    tokio::spawn(pipe(client_read, server_write, id, Arc::clone(&silenced)));
+                    tokio::spawn(pipe(server_read, client_write, id, Arc::clone(&silenced)));
+                }
+            }
+        });
+        (addr, silenced)
+    }
+
+    /// Requests to `/slow` so far, which take 300ms each.
+    static SLOW_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Sets up the router that every test here shares, since it can only be set once.
+    fn init_router() {
+        ROUTER.get_or_init(|| {
+            Router::new()
+                .route("/", get(|| async { "Hello!" }))
+                .route(
+                    "/slow",
+                    get(|| async {
+                        SLOW_REQUESTS.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        "Done."
+                    }),
+                )
+                .route(
+                    "/secure",
+                    get(|SecureContext(secure): SecureContext| async move { secure.to_string() }),
+                )
+        });
+    }
+
+    async fn fetch(public_addr: SocketAddr, path: &str) -> String {
+        reqwest::get(format!("http://{public_addr}{path}"))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unix_sockets_are_replaced_and_removed() {
+        init_router();
+        let socket = std::env::temp_dir().join(format!("{}-local-server.sock", std::process::id()));
+        // Left behind by a server that didn't get to clean up.
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        let address = LocalAddress::Unix(socket.clone());
+        let drain = Drain::default();
+        let server = tokio::spawn({
+            let drain = drain.clone();
+            async move {
+                local_server_entrypoint(
+                    &address,
+                    None,
+                    drain,
+                    ConnectionTimeouts::default(),
+                    Clock::default(),
+                )
+                .await
+            }
+        });
+        let mut stream = loop {
+            // The stale socket refuses connections until it's replaced.
+            match UnixStream::connect(&socket).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("Hello!"));
+
+        // A second server leaves the live socket alone.
+        let error = LocalListener::bind(&LocalAddress::Unix(socket.clone()))
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{error:#}").contains("already listening"));
+
+        drain.request_shutdown();
+        server.await.unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+
+    /// Serves the local server on a Unix socket, and asks it whether `/secure` is a secure context.
+    async fn ask_if_secure(name: &str, tls: Option<TlsCertificate>) -> String {
+        init_router();
+        let socket = std::env::temp_dir().join(format!("{}-{name}.sock", std::process::id()));
+        let drain = Drain::default();
+        let server = tokio::spawn({
+            let address = LocalAddress::Unix(socket.clone());
+            let drain = drain.clone();
+            let tls = tls.clone();
+            async move {
+                local_server_entrypoint(
+                    &address,
+                    tls,
+                    drain,
+                    ConnectionTimeouts::default(),
+                    Clock::default(),
+                )
+                .await
+            }
+        });
+        let stream = loop {
+            match UnixStream::connect(&socket).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        // Over HTTP/1.1, the request line has no scheme.
+        let request = b"GET /secure HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let mut response = String::new();
+        if tls.is_some() {
+            let client = tls::tests::client_config(tls::tests::CERT_A);
+            let mut stream = TlsConnector::from(Arc::new(client))
+                .connect(ServerName::try_from("localhost").unwrap(), stream)
+                .await
+                .unwrap();
+            stream.write_all(request).await.unwrap();
+            stream.read_to_string(&mut response).await.unwrap();
+        } else {
+            let mut stream = stream;
+            stream.write_all(request).await.unwrap();
+            stream.read_to_string(&mut response).await.unwrap();
+        }
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        drain.request_shutdown();
+        server.await.unwrap().unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn only_tls_connections_are_secure_contexts() {
+        let cert_path = tls::tests::temp_file("secure-cert.pem", tls::tests::CERT_A);
+        let key_path = tls::tests::temp_file("secure-key.pem", tls::tests::KEY_A);
+        let tls = TlsCertificate::load(cert_path.clone(), key_path.clone())
+            .await
+            .unwrap();
+        assert!(ask_if_secure("tls-server", Some(tls))
+            .await
+            .ends_with("true"));
+        assert!(ask_if_secure("plain-server", None).await.ends_with("false"));
+        std::fs::remove_file(cert_path).unwrap();
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unix_sockets_only_show_up_with_their_final_mode() {
+        let directory = std::env::temp_dir().join(format!("{}-socket-mode", std::process::id()));
+        std::fs::create_dir(&directory).unwrap();
+        let socket = directory.join("games.sock");
+        let listener = LocalListener::bind(&LocalAddress::Unix(socket.clone()))
+            .await
+            .unwrap();
+        let metadata = std::fs::symlink_metadata(&socket).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, SOCKET_MODE);
+        // Nothing else is left behind, such as the directory where it was made.
+        let entries = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, ["games.sock"]);
+        UnixStream::connect(&socket).await.unwrap();
+
+        drop(listener);
+        assert!(!socket.exists());
+        std::fs::remove_dir(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_handoff_only_drops_the_port_for_a_retry_interval() {
+        const RETRY_INTERVAL: Duration = Duration::from_millis(200);
+        /// Leeway for the round trips of the retried request.
+        const SLACK: Duration = Duration::from_millis(150);
+        init_router();
+        let credentials = Credentials::Key(Arc::new(KeyPair::generate_ed25519()));
+        // The first instance records the server's key, and the second one checks it.
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-handoff-known-hosts", std::process::id()));
+        let known_hosts = KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew);
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, public_addr) = tunnel.spawn().await;
+
+        let old_drain = Drain::default();
+        let old = spawn_instance(
+            ssh_addr,
+            credentials.clone(),
+            known_hosts,
+            old_drain.clone(),
+            None,
+        );
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+        assert_eq!(fetch(public_addr, "/").await, "Hello!");
+
+        // The new instance keeps asking for the port while the old one holds it.
+        let new = spawn_instance(
+            ssh_addr,
+            credentials,
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::Strict),
+            Drain::default(),
+            Some(RETRY_INTERVAL),
+        );
+        tunnel.wait_for(PortEvent::Refused(1)).await;
+        let slow = tokio::spawn(async move { fetch(public_addr, "/slow").await });
+        while SLOW_REQUESTS.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        old_drain.request_handoff();
+        let released = tunnel.wait_for(PortEvent::Released(0)).await;
+        let bound = tunnel.wait_for(PortEvent::Bound(1)).await;
+        assert!(
+            bound - released <= RETRY_INTERVAL + SLACK,
+            "The port was free for {:?}",
+            bound - released
+        );
+        // The request that was in flight during the handoff still gets its response.
+        assert_eq!(slow.await.unwrap(), "Done.");
+        old.await.unwrap().unwrap();
+        assert_eq!(fetch(public_addr, "/").await, "Hello!");
+        assert!(!new.is_finished());
+        new.abort();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn changed_host_keys_are_refused_without_retrying() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-host-key-known-hosts", std::process::id()));
+        let other_key = KeyPair::generate_ed25519().clone_public_key().unwrap();
+        learn_known_hosts_path(
+            &ssh_addr.ip().to_string(),
+            ssh_addr.port(),
+            &other_key,
+            &known_hosts_file,
+        )
+        .unwrap();
+
+        let instance = spawn_instance(
+            ssh_addr,
+            Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew),
+            Drain::default(),
+            None,
+        );
+        let error = tokio::time::timeout(Duration::from_secs(5), instance)
+            .await
+            .expect("Connecting was retried")
+            .unwrap()
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("has changed"));
+        assert_eq!(tunnel.sessions.load(Ordering::SeqCst), 1);
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn agent_identities_are_tried_until_one_is_accepted() {
+        let authorized_key = KeyPair::generate_ed25519();
+        let tunnel = TunnelServer {
+            authorized_key: Some(authorized_key.clone_public_key().unwrap()),
+            ..Default::default()
+        };
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-agent-known-hosts", std::process::id()));
+        let socket = std::env::temp_dir().join(format!("{}-agent.sock", std::process::id()));
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(serve(UnixListenerStream::new(listener), ()));
+        let mut agent = AgentClient::connect_uds(&socket).await.unwrap();
+        agent
+            .add_identity(&KeyPair::generate_ed25519(), &[])
+            .await
+            .unwrap();
+
+        let refused = spawn_instance(
+            ssh_addr,
+            Credentials::Agent(socket.clone()),
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew),
+            Drain::default(),
+            None,
+        );
+        let error = refused.await.unwrap().unwrap_err();
+        let refused = error.downcast_ref::<AuthRefused>().unwrap();
+        assert_eq!(refused.attempts.len(), 1);
+        assert!(refused.attempts[0].offered.ends_with("from the ssh-agent"));
+
+        // The agent is asked again on the next connection.
+        agent.add_identity(&authorized_key, &[]).await.unwrap();
+        agent
+            .add_identity(&KeyPair::generate_ed25519(), &[])
+            .await
+            .unwrap();
+        let accepted = spawn_instance(
+            ssh_addr,
+            Credentials::Agent(socket.clone()),
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::Strict),
+            Drain::default(),
+            None,
+        );
+        tunnel.wait_for(PortEvent::Bound(1)).await;
+        accepted.abort();
+        std::fs::remove_file(socket).unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refused_keys_fall_back_to_keyboard_interactive() {
+        let tunnel = TunnelServer {
+            authorized_key: Some(KeyPair::generate_ed25519().clone_public_key().unwrap()),
+            password: Some("hunter2"),
+            ..Default::default()
+        };
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file = std::env::temp_dir().join(format!(
+            "{}-keyboard-interactive-known-hosts",
+            std::process::id()
+        ));
+        let response_file =
+            std::env::temp_dir().join(format!("{}-auth-responses", std::process::id()));
+        let credentials = Credentials::Key(Arc::new(KeyPair::generate_ed25519()))
+            .with_keyboard_interactive(Some(response_file.clone()));
+
+        std::fs::write(&response_file, "hunter3\n").unwrap();
+        let refused = spawn_instance(
+            ssh_addr,
+            credentials.clone(),
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew),
+            Drain::default(),
+            None,
+        );
+        let error = refused.await.unwrap().unwrap_err();
+        let methods = error
+            .downcast_ref::<AuthRefused>()
+            .unwrap()
+            .attempts
+            .iter()
+            .map(|attempt| attempt.method)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            [AuthMethod::PublicKey, AuthMethod::KeyboardInteractive],
+            "{error:#}"
+        );
+
+        // The responses are read again on the next connection. Each attempt takes two: russh only answers prompts
+        // when keyboard-interactive is the first method tried.
+        std::fs::write(&response_file, "hunter2\n").unwrap();
+        let accepted = spawn_instance(
+            ssh_addr,
+            credentials,
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::Strict),
+            Drain::default(),
+            None,
+        );
+        tunnel.wait_for(PortEvent::Bound(3)).await;
+        accepted.abort();
+        std::fs::remove_file(response_file).unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_lets_go_of_the_port() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-shutdown-known-hosts", std::process::id()));
+        let drain = Drain::default();
+        let instance = spawn_instance(
+            ssh_addr,
+            Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew),
+            drain.clone(),
+            None,
+        );
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+        drain.request_shutdown();
+        tunnel.wait_for(PortEvent::Released(0)).await;
+        instance.await.unwrap().unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_assigned_port_is_reported_and_released() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-assigned-known-hosts", std::process::id()));
+        let clock = Clock::tokio();
+        let mut session = TcpForwardSession::connect(
+            &ssh_addr.ip().to_string(),
+            ssh_addr.port(),
+            None,
+            "player",
+            Arc::new(ProtocolSettings::default().config()),
+            &Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            Arc::new(KnownHosts::new(
+                vec![known_hosts_file.clone()],
+                HostKeyPolicy::AcceptNew,
+            )),
+            TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+            Drain::default(),
+            ConnectionTimeouts::default(),
+            clock.clone(),
+            ReconnectPolicy::Eager.delays(BackoffConfig::default()),
+        )
+        .await
+        .unwrap();
+        let port = session
+            .start_forwarding("localhost", 0, &[], None, &clock)
+            .await
+            .unwrap();
+        assert_eq!(u32::from(port), ASSIGNED_PORT);
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+        // The server only lets go of the port it assigned, not of port 0.
+        assert!(session.cancel_forwarding("localhost", 0).await.is_err());
+        session.cancel_forwarding("localhost", port).await.unwrap();
+        tunnel.wait_for(PortEvent::Released(0)).await;
+        session.close().await.unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn remote_sockets_are_served_and_let_go_of_at_handoff() {
+        init_router();
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, public_addr) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-socket-known-hosts", std::process::id()));
+        let drain = Drain::default();
+        let status = TunnelStatusCell::new(TunnelState::Connecting, vec![]);
+        let instance = tokio::spawn({
+            let known_hosts =
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew);
+            let (drain, status) = (drain.clone(), status.clone());
+            async move {
+                ssh_entrypoint(
+                    player(
+                        ssh_addr,
+                        Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                        known_hosts,
+                    ),
+                    TunnelConfig {
+                        remote_socket: Some(String::from("/run/apps/games.sock")),
+                        remote_output: RemoteOutput::Log,
+                        ..Default::default()
+                    },
+                    status,
+                    Clock::tokio(),
+                    drain,
+                    ConnectionTimeouts::default(),
+                )
+                .await
+            }
+        });
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+        assert_eq!(
+            tunnel.socket.lock().unwrap().as_deref(),
+            Some("/run/apps/games.sock")
+        );
+        assert_eq!(fetch(public_addr, "/").await, "Hello!");
+        let reported = status.get();
+        assert_eq!(
+            reported.remote_socket.as_deref(),
+            Some("/run/apps/games.sock")
+        );
+        assert_eq!(reported.remote_port, None);
+
+        drain.request_handoff();
+        tunnel.wait_for(PortEvent::Released(0)).await;
+        assert_eq!(*tunnel.socket.lock().unwrap(), None);
+        instance.await.unwrap().unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn checking_the_connection_lets_go_of_the_port() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-check-known-hosts", std::process::id()));
+        let report = check_connection_entrypoint(
+            player(
+                ssh_addr,
+                Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew),
+            ),
+            "",
+            0,
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(u32::from(report.remote_port), ASSIGNED_PORT);
+        assert_eq!(report.auth_method, AuthMethod::PublicKey);
+        assert!(report.server_version.starts_with("SSH-2.0-"), "{report}");
+        assert!(report.to_string().contains(&format!("*:{ASSIGNED_PORT}")));
+        tunnel.wait_for(PortEvent::Released(0)).await;
+
+        // A refused port is reported rather than waited for.
+        let tunnel = TunnelServer {
+            refused_ports: vec![80],
+            ..TunnelServer::default()
+        };
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let refused = check_connection_entrypoint(
+            player(
+                ssh_addr,
+                Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew),
+            ),
+            "",
+            80,
+            &[],
+        )
+        .await
+        .unwrap_err();
+        assert!(refused.is::<ForwardingRefused>(), "{refused:?}");
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn preferred_algorithms_are_negotiated() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-algorithms-known-hosts", std::process::id()));
+        let protocol = ProtocolSettings {
+            kex_algorithms: vec![kex::DH_G14_SHA256],
+            ciphers: vec![cipher::AES_128_CTR],
+            macs: vec![mac::HMAC_SHA256],
+            ..Default::default()
+        };
+        let clock = Clock::tokio();
+        let mut session = TcpForwardSession::connect(
+            &ssh_addr.ip().to_string(),
+            ssh_addr.port(),
+            None,
+            "player",
+            Arc::new(protocol.config()),
+            &Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            Arc::new(KnownHosts::new(
+                vec![known_hosts_file.clone()],
+                HostKeyPolicy::AcceptNew,
+            )),
+            TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+            Drain::default(),
+            ConnectionTimeouts::default(),
+            clock.clone(),
+            ReconnectPolicy::Eager.delays(BackoffConfig::default()),
+        )
+        .await
+        .unwrap();
+        session
+            .start_forwarding("localhost", 80, &[], None, &clock)
+            .await
+            .unwrap();
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+        session.close().await.unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_agent_is_forwarded_to_the_session() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-forward-agent-known-hosts", std::process::id()));
+        let socket =
+            std::env::temp_dir().join(format!("{}-agent-forwarding.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(serve(UnixListenerStream::new(listener), ()));
+        let identity = KeyPair::generate_ed25519();
+        AgentClient::connect_uds(&socket)
+            .await
+            .unwrap()
+            .add_identity(&identity, &[])
+            .await
+            .unwrap();
+
+        let clock = Clock::tokio();
+        let mut session = TcpForwardSession::connect(
+            &ssh_addr.ip().to_string(),
+            ssh_addr.port(),
+            None,
+            "player",
+            Arc::new(ProtocolSettings::default().config()),
+            &Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            Arc::new(KnownHosts::new(
+                vec![known_hosts_file.clone()],
+                HostKeyPolicy::AcceptNew,
+            )),
+            TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+            Drain::default(),
+            ConnectionTimeouts::default(),
+            clock.clone(),
+            ReconnectPolicy::Eager.delays(BackoffConfig::default()),
+        )
+        .await
+        .unwrap();
+        session
+            .start_forwarding("localhost", 80, &[], None, &clock)
+            .await
+            .unwrap();
+        let forwarded = socket.clone();
+        let running =
+            tokio::spawn(
+                async move { session.run(None, Some(&forwarded), RemoteOutput::Log).await },
+            );
+
+        // SSH_AGENT_IDENTITIES_ANSWER, with the one identity.
+        let mut reply = Vec::new();
+        for _ in 0..500 {
+            reply = tunnel.agent_replies.lock().unwrap().clone();
+            if reply.len() > 9 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(reply[4..9], [12, 0, 0, 0, 1]);
+        let public_key = identity.clone_public_key().unwrap().public_key_bytes();
+        assert_eq!(reply[9..13], (public_key.len() as u32).to_be_bytes());
+        assert_eq!(reply[13..13 + public_key.len()], public_key);
+        running.abort();
+        std::fs::remove_file(socket).unwrap();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refused_ports_fall_back_then_give_up() {
+        let tunnel = TunnelServer {
+            refused_ports: vec![80, 8000],
+            ..Default::default()
+        };
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-fallback-known-hosts", std::process::id()));
+        let (clock, manual) = Clock::manual();
+        let mut session = TcpForwardSession::connect(
+            &ssh_addr.ip().to_string(),
+            ssh_addr.port(),
+            None,
+            "player",
+            Arc::new(ProtocolSettings::default().config()),
+            &Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            Arc::new(KnownHosts::new(
+                vec![known_hosts_file.clone()],
+                HostKeyPolicy::AcceptNew,
+            )),
+            TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+            Drain::default(),
+            ConnectionTimeouts::default(),
+            clock.clone(),
+            ReconnectPolicy::Eager.delays(BackoffConfig::default()),
+        )
+        .await
+        .unwrap();
+        let error = session
+            .start_forwarding("localhost", 80, &[8000], None, &clock)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<ForwardingRefused>()
+                .unwrap()
+                .remote_ports,
+            [80, 8000]
+        );
+        let port = session
+            .start_forwarding("localhost", 80, &[8000, 8001], None, &clock)
+            .await
+            .unwrap();
+        assert_eq!(port, 8001);
+        session.close().await.unwrap();
+
+        // Without any port to fall back to, the server isn't asked again until the backoff says so.
+        let instance = tokio::spawn({
+            let known_hosts =
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::Strict);
+            async move {
+                ssh_entrypoint(
+                    player(
+                        ssh_addr,
+                        Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                        known_hosts,
+                    ),
+                    TunnelConfig {
+                        remote_host: String::from("localhost"),
+                        remote_output: RemoteOutput::Log,
+                        retry: RetryPolicy {
+                            backoff: BackoffConfig {
+                                max_attempts: 2,
+                                ..Default::default()
+                            },
+                            max_session_restarts: None,
+                        },
+                        ..Default::default()
+                    },
+                    TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+                    clock,
+                    Drain::default(),
+                    ConnectionTimeouts::default(),
+                )
+                .await
+            }
+        });
+        for sessions in [2, 3] {
+            while manual.sleepers() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            assert_eq!(tunnel.sessions.load(Ordering::SeqCst), sessions);
+            manual.advance(BackoffConfig::default().max_delay);
+        }
+        let error = instance.await.unwrap().unwrap_err();
+        assert!(format!("{error:#}").contains("Gave up on forwarding"));
+        assert_eq!(tunnel.sessions.load(Ordering::SeqCst), 4);
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dead_connections_are_noticed_by_keepalives() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let (proxy_addr, silenced) = spawn_proxy(ssh_addr).await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-keepalive-known-hosts", std::process::id()));
+        let instance = tokio::spawn({
+            let known_hosts =
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew);
+            async move {
+                ssh_entrypoint(
+                    SshOptions {
+                        protocol: ProtocolSettings {
+                            keepalive: Some(Keepalive {
+                                interval: Duration::from_millis(100),
+                                max_missed: 2,
+                            }),
+                            ..Default::default()
+                        },
+                        ..player(
+                            proxy_addr,
+                            Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                            known_hosts,
+                        )
+                    },
+                    TunnelConfig {
+                        remote_host: String::from("localhost"),
+                        remote_output: RemoteOutput::Log,
+                        ..Default::default()
+                    },
+                    TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+                    Clock::tokio(),
+                    Drain::default(),
+                    ConnectionTimeouts::default(),
+                )
+                .await
+            }
+        });
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+
+        silenced.store(1, Ordering::SeqCst);
+        // The server never noticed, so it still gives the port to the first session, but the client did reconnect.
+        tunnel.wait_for(PortEvent::Refused(1)).await;
+        assert!(!instance.is_finished());
+        instance.abort();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn losing_the_jump_host_reconnects_through_it() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let bastion = Bastion::default();
+        let bastion_addr = bastion.spawn().await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-jump-known-hosts", std::process::id()));
+        let instance = tokio::spawn({
+            let known_hosts =
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew);
+            async move {
+                ssh_entrypoint(
+                    SshOptions {
+                        jump_host: Some(JumpHost {
+                            login_name: Some(String::from("admin")),
+                            host: bastion_addr.ip().to_string(),
+                            port: bastion_addr.port(),
+                        }),
+                        ..player(
+                            ssh_addr,
+                            Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                            known_hosts,
+                        )
+                    },
+                    TunnelConfig {
+                        remote_host: String::from("localhost"),
+                        remote_output: RemoteOutput::Log,
+                        // Until the server notices that the first session is gone.
+                        bind_retry: Some(Duration::from_millis(10)),
+                        ..Default::default()
+                    },
+                    TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+                    Clock::tokio(),
+                    Drain::default(),
+                    ConnectionTimeouts::default(),
+                )
+                .await
+            }
+        });
+        tunnel.wait_for(PortEvent::Bound(0)).await;
+        bastion.wait_for_sessions(1).await;
+
+        let first = bastion.sessions.lock().unwrap()[0].clone();
+        first
+            .disconnect(Disconnect::ByApplication, String::new(), String::new())
+            .await
+            .unwrap();
+        // Both hops are connected again, and the bastion's key was recorded along with the server's.
+        bastion.wait_for_sessions(2).await;
+        tunnel.wait_for(PortEvent::Bound(1)).await;
+        assert!(!instance.is_finished());
+        let known_hosts = std::fs::read_to_string(&known_hosts_file).unwrap();
+        assert!(known_hosts.contains(&bastion_addr.port().to_string()));
+        instance.abort();
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_connections_are_proxied_through_direct_tcpip() {
+        let bastion = Bastion::default();
+        let bastion_addr = bastion.spawn().await;
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let destination = Destination::parse(&echo.local_addr().unwrap().to_string()).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = echo.accept().await.unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-local-forward-known-hosts", std::process::id()));
+        // Only there to find a free port for the listener.
+        let listen = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let stop = Arc::new(Notify::new());
+        let instance = tokio::spawn({
+            let known_hosts =
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew);
+            let stop = Arc::clone(&stop);
+            async move {
+                tunnel_test_entrypoint(
+                    player(
+                        bastion_addr,
+                        Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                        known_hosts,
+                    ),
+                    &listen.to_string(),
+                    &destination,
+                    stop.notified(),
+                )
+                .await
+            }
+        });
+        bastion.wait_for_sessions(1).await;
+
+        let mut client = TcpStream::connect(listen).await.unwrap();
+        client.write_all(b"Hello!").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, b"Hello!");
+        stop.notify_one();
+        let totals = instance.await.unwrap().unwrap();
+        assert_eq!(totals.connections, 1);
+        assert_eq!(totals.bytes_received, 6);
+        assert_eq!(totals.bytes_sent, 6);
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unanswered_keepalives_end_the_session_as_stale() {
+        let tunnel = TunnelServer::default();
+        let (ssh_addr, _) = tunnel.spawn().await;
+        let (proxy_addr, silenced) = spawn_proxy(ssh_addr).await;
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-stale-known-hosts", std::process::id()));
+        let protocol = ProtocolSettings {
+            keepalive: Some(Keepalive {
+                interval: Duration::from_millis(100),
+                max_missed: 2,
+            }),
+            ..Default::default()
+        };
+        let clock = Clock::tokio();
+        let mut session = TcpForwardSession::connect(
+            &proxy_addr.ip().to_string(),
+            proxy_addr.port(),
+            None,
+            "player",
+            Arc::new(protocol.config()),
+            &Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+            Arc::new(KnownHosts::new(
+                vec![known_hosts_file.clone()],
+                HostKeyPolicy::AcceptNew,
+            )),
+            TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+            Drain::default(),
+            ConnectionTimeouts::default(),
+            clock.clone(),
+            ReconnectPolicy::Eager.delays(BackoffConfig::default()),
+        )
+        .await
+        .unwrap();
+        session
+            .start_forwarding("localhost", 80, &[], None, &clock)
+            .await
+            .unwrap();
+
+        silenced.store(1, Ordering::SeqCst);
+        let error = tokio::time::timeout(
+            Duration::from_secs(5),
+            session.run(None, None, RemoteOutput::Log),
+        )
+        .await
+        .expect("The session never ended")
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<StaleConnection>(),
+            Some(&StaleConnection::KeepalivesUnanswered),
+            "{error:#}"
+        );
+        std::fs::remove_file(known_hosts_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_first_connection_is_retried_until_the_server_is_up() {
+        let (clock, manual) = Clock::manual();
+        // The server hangs up on every connection until it's up.
+        let ssh = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ssh_addr = ssh.local_addr().unwrap();
+        let up = Arc::new(Notify::new());
+        let down = tokio::spawn({
+            let up = Arc::clone(&up);
+            async move {
+                loop {
+                    tokio::select! {
+                        accepted = ssh.accept() => drop(accepted),
+                        () = up.notified() => return ssh,
+                    }
+                }
+            }
+        });
+        let known_hosts_file =
+            std::env::temp_dir().join(format!("{}-retry-known-hosts", std::process::id()));
+        let connect = |max_attempts| {
+            let known_hosts =
+                KnownHosts::new(vec![known_hosts_file.clone()], HostKeyPolicy::AcceptNew);
+            let clock = clock.clone();
+            tokio::spawn(async move {
+                ssh_entrypoint(
+                    player(
+                        ssh_addr,
+                        Credentials::Key(Arc::new(KeyPair::generate_ed25519())),
+                        known_hosts,
+                    ),
+                    TunnelConfig {
+                        remote_host: String::from("localhost"),
+                        remote_output: RemoteOutput::Log,
+                        retry: RetryPolicy {
+                            backoff: BackoffConfig {
+                                max_attempts,
+                                ..Default::default()
+                            },
+                            max_session_restarts: None,
+                        },
+                        ..Default::default()
+                    },
+                    TunnelStatusCell::new(TunnelState::Connecting, vec![]),
+                    clock,
+                    Drain::default(),
+                    ConnectionTimeouts::default(),
+                )
+                .await
+            })
+        };
+        let retry = || async {
+            while manual.sleepers() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            manual.advance(BackoffConfig::default().max_delay);
+        };
+
+        let gives_up = connect(3);
+        let instance = connect(0);
+        // Well past the eager retries.
+        for _ in 0..10 {
+            retry().await;
+        }
+        let error = gives_up.await.unwrap().unwrap_err();
+        assert!(format!("{error:#}").contains("Gave up"));
+        assert!(!instance.is_finished());
+
+        up.notify_one();
+        let tunnel = TunnelServer::default();
+        tunnel.spawn_on(down.await.unwrap()).await;
+        // Whichever attempt comes next gets through, even one that was already waiting to be accepted.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while tunnel.when(PortEvent::Bound(0)).is_none() {
+                manual.advance(BackoffConfig::default().max_delay);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Never connected");
+        instance.abort();
+        std::fs::remove_file(known_hosts_file).unwrap();
     }
 }
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+/// How [`format_duration`] renders a duration. Every style truncates to whole seconds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DurationStyle {
+    /// Like a stopwatch: `4:05`, or `1:02:03` from an hour on.
+    Clock,
+    /// Like a stopwatch that always shows hours: `0:04:05`. Keeps long countdowns from changing shape.
+    HourClock,
+    /// Short and unpadded, for tables: `4m05s`, `1h02m`, or `42s`.
+    Compact,
+    /// For sentences: `4m 05s`, `1h 02m`, or `42s`.
+    Human,
+}
+
+pub fn format_duration(duration: Duration, style: DurationStyle) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / HOUR, secs % HOUR / MINUTE, secs % MINUTE);
+    match style {
+        DurationStyle::Clock if hours == 0 => format!("{minutes}:{seconds:02}"),
+        DurationStyle::Clock | DurationStyle::HourClock => {
+            format!("{hours}:{minutes:02}:{seconds:02}")
+        }
+        DurationStyle::Compact | DurationStyle::Human => {
+            let separator = if style == DurationStyle::Human {
+                " "
+            } else {
+                ""
+            };
+            if hours > 0 {
+                format!("{hours}h{separator}{minutes:02}m")
+            } else if minutes > 0 {
+                format!("{minutes}m{separator}{seconds:02}s")
+            } else {
+                format!("{seconds}s")
+            }
+        }
+    }
+}
+
+/// Parses a duration with an s, m, h or d suffix, such as `90m`, for command-line flags.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let multiplier = match value.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('s') => 1,
+        Some('m') => MINUTE,
+        Some('h') => HOUR,
+        Some('d') => 24 * HOUR,
+        _ => return Err(String::from("duration needs a unit: s, m, h or d")),
+    };
+    let seconds = value[..value.len() - 1]
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid duration: {e}"))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| String::from("duration is too large"))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_styles(duration: Duration) -> [String; 4] {
+        [
+            DurationStyle::Clock,
+            DurationStyle::HourClock,
+            DurationStyle::Compact,
+            DurationStyle::Human,
+        ]
+        .map(|style| format_duration(duration, style))
+    }
+
+    #[test]
+    fn it_formats_the_hour_boundary() {
+        assert_eq!(
+            all_styles(Duration::from_secs(HOUR - 1)),
+            ["59:59", "0:59:59", "59m59s", "59m 59s"]
+        );
+        assert_eq!(
+            all_styles(Duration::from_secs(HOUR)),
+            ["1:00:00", "1:00:00", "1h00m", "1h 00m"]
+        );
+        assert_eq!(
+            all_styles(Duration::from_secs(HOUR + 2 * MINUTE + 3)),
+            ["1:02:03", "1:02:03", "1h02m", "1h 02m"]
+        );
+        assert_eq!(
+            all_styles(Duration::from_secs(26 * HOUR)),
+            ["26:00:00", "26:00:00", "26h00m", "26h 00m"]
+        );
+    }
+
+    #[test]
+    fn it_formats_zero_and_sub_second_durations() {
+        let zero = ["0:00", "0:00:00", "0s", "0s"];
+        assert_eq!(all_styles(Duration::ZERO), zero);
+        assert_eq!(all_styles(Duration::from_millis(999)), zero);
+        assert_eq!(
+            all_styles(Duration::from_millis(65_500)),
+            ["1:05", "0:01:05", "1m05s", "1m 05s"]
+        );
+    }
+
+    #[test]
+    fn it_parses_durations_with_units() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration(" 90M "), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(
+            parse_duration("2d"),
+            Ok(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("90").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-1h").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Handing the tunnel off to a new instance without dropping the remote port for more than a moment.
+//!
+//! The new instance is started first with `--wait-for-port`, and keeps asking for the remote port. On SIGUSR2, the
+//! old instance gives up the port, refuses any connection that still reaches it, lets open connections finish their
+//! in-flight requests, persists its state with the [`ShutdownHooks`], and exits.
+//!
+//! SIGINT and SIGTERM shut down the same way, except that nobody takes over the port, so open connections only get a
+//! few seconds.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{watch, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::{clock::Clock, tasks::TaskRegistry};
+
+/// How often `--wait-for-port` asks for the remote port again while another instance holds it.
+pub const BIND_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest that a handoff waits for open connections before exiting anyway.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest that a shutdown waits for open connections before exiting anyway.
+pub const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Copy, Clone, Debug, Default)]
+struct DrainState {
+    handing_off: bool,
+    shutting_down: bool,
+    connections: usize,
+}
+
+/// Counts the connections being served through the tunnel, so that a handoff can wait for them, and so that there
+/// aren't too many at once. Shared by every clone.
+#[derive(Clone, Debug)]
+pub struct Drain {
+    state: Arc<watch::Sender<DrainState>>,
+    /// One per connection that can still be opened, if there's a limit.
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Drain {
+            state: Arc::new(watch::channel(DrainState::default()).0),
+            permits: None,
+        }
+    }
+}
+
+/// Why [`Drain::begin_connection`] refused a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionRefused {
+    HandingOff,
+    /// As many connections as allowed are already open.
+    AtCapacity,
+}
+
+impl Drain {
+    /// Refuses connections beyond the first `max_connections` open at once.
+    pub fn with_max_connections(max_connections: usize) -> Self {
+        Drain {
+            permits: Some(Arc::new(Semaphore::new(max_connections))),
+            ..Default::default()
+        }
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.borrow().connections
+    }
+
+    pub fn is_handing_off(&self) -> bool {
+        self.state.borrow().handing_off
+    }
+
+    /// Stops accepting new connections, and asks open ones to finish their requests and close.
+    pub fn request_handoff(&self) {
+        self.state
+            .send_if_modified(|state| !std::mem::replace(&mut state.handing_off, true));
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.borrow().shutting_down
+    }
+
+    /// Hands off to nobody: same as [`Drain::request_handoff`], but open connections get less time to finish.
+    pub fn request_shutdown(&self) {
+        self.state.send_if_modified(|state| {
+            let changed = !state.shutting_down;
+            state.handing_off = true;
+            state.shutting_down = true;
+            changed
+        });
+    }
+
+    /// How long to wait for open connections once handing off or shutting down.
+    pub fn timeout(&self) -> Duration {
+        if self.is_shutting_down() {
+            SHUTDOWN_DRAIN_TIMEOUT
+        } else {
+            DRAIN_TIMEOUT
+        }
+    }
+
+    /// Waits until a handoff or a shutdown is requested.
+    pub async fn handoff_requested(&self) {
+        let mut receiver = self.state.subscribe();
+        let _ = receiver.wait_for(|state| state.handing_off).await;
+    }
+
+    /// Counts a connection as open until the guard is dropped, unless handing off or at capacity.
+    pub fn begin_connection(&self) -> Result<ConnectionGuard, ConnectionRefused> {
+        if self.is_handing_off() {
+            return Err(ConnectionRefused::HandingOff);
+        }
+        let permit = match &self.permits {
+            Some(permits) => Some(
+                Arc::clone(permits)
+                    .try_acquire_owned()
+                    .map_err(|_| ConnectionRefused::AtCapacity)?,
+            ),
+            None => None,
+        };
+        let mut guard = Err(ConnectionRefused::HandingOff);
+        self.state.send_if_modified(|state| {
+            if state.handing_off {
+                return false;
+            }
+            state.connections += 1;
+            guard = Ok(ConnectionGuard {
+                drain: self.clone(),
+                _permit: permit,
+            });
+            true
+        });
+        guard
+    }
+
+    /// Waits up to `timeout` for every connection to close. Returns how many were still open.
+    pub async fn drained(&self, timeout: Duration, clock: &Clock) -> usize {
+        let mut receiver = self.state.subscribe();
+        tokio::select! {
+            _ = receiver.wait_for(|state| state.connections == 0) => 0,
+            () = clock.sleep(timeout) => {
+                let connections = self.connections();
+                warn!(connections, "Gave up waiting for open connections.");
+                connections
+            }
+        }
+    }
+}
+
+/// An open connection, from [`Drain::begin_connection`].
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    drain: Drain,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.drain.state.send_modify(|state| state.connections -= 1);
+    }
+}
+
+/// Starts a handoff once SIGUSR2 is received.
+pub fn spawn_handoff_on_sigusr2(drain: Drain, tasks: &TaskRegistry) -> Result<JoinHandle<()>> {
+    let mut user_defined =
+        signal(SignalKind::user_defined2()).with_context(|| "Unable to listen for SIGUSR2")?;
+    Ok(tasks.spawn("handoff on SIGUSR2", async move {
+        if user_defined.recv().await.is_some() {
+            info!("Received SIGUSR2, handing off the tunnel.");
+            drain.request_handoff();
+        }
+    }))
+}
+
+/// Shuts down once SIGINT or SIGTERM is received.
+pub fn spawn_shutdown_on_signal(drain: Drain, tasks: &TaskRegistry) -> Result<JoinHandle<()>> {
+    let mut interrupt =
+        signal(SignalKind::interrupt()).with_context(|| "Unable to listen for SIGINT")?;
+    let mut terminate =
+        signal(SignalKind::terminate()).with_context(|| "Unable to listen for SIGTERM")?;
+    Ok(tasks.spawn("shutdown on SIGINT or SIGTERM", async move {
+        let signal = tokio::select! {
+            Some(()) = interrupt.recv() => "SIGINT",
+            Some(()) = terminate.recv() => "SIGTERM",
+            else => return,
+        };
+        info!(signal, "Shutting down.");
+        drain.request_shutdown();
+    }))
+}
+
+/// Persists some state before the process exits.
+pub type ShutdownHook = Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// What activities need to do before the process exits, after a handoff or Ctrl+C.
+#[derive(Clone, Default)]
+pub struct ShutdownHooks(Arc<Mutex<Vec<ShutdownHook>>>);
+
+impl ShutdownHooks {
+    pub fn register(&self, hook: ShutdownHook) {
+        self.0.lock().unwrap().push(hook);
+    }
+
+    /// Runs every hook, one at a time, in the order that they were registered.
+    pub async fn run(&self) {
+        let hooks = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|hook| hook())
+            .collect::<Vec<_>>();
+        for hook in hooks {
+            hook.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn handoffs_wait_for_open_connections() {
+        let (clock, _) = Clock::manual();
+        let drain = Drain::default();
+        let first = drain.begin_connection().unwrap();
+        let second = drain.begin_connection().unwrap();
+        assert_eq!(drain.connections(), 2);
+        drain.request_handoff();
+        assert!(drain.is_handing_off());
+        assert_eq!(
+            drain.begin_connection().unwrap_err(),
+            ConnectionRefused::HandingOff
+        );
+        let drained = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drained(DRAIN_TIMEOUT, &clock).await }
+        });
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!drained.is_finished());
+        drop(second);
+        assert_eq!(drained.await.unwrap(), 0);
+        assert_eq!(drain.connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn handoffs_give_up_after_the_timeout() {
+        let (clock, manual) = Clock::manual();
+        let drain = Drain::default();
+        let _stuck = drain.begin_connection().unwrap();
+        drain.request_handoff();
+        let drained = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drained(DRAIN_TIMEOUT, &clock).await }
+        });
+        while manual.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        manual.advance(DRAIN_TIMEOUT);
+        assert_eq!(drained.await.unwrap(), 1);
+    }
+
+    #[test]
+    fn connections_are_refused_at_capacity() {
+        let drain = Drain::with_max_connections(2);
+        let first = drain.begin_connection().unwrap();
+        let _second = drain.begin_connection().unwrap();
+        assert_eq!(
+            drain.begin_connection().unwrap_err(),
+            ConnectionRefused::AtCapacity
+        );
+        assert_eq!(drain.connections(), 2);
+        drop(first);
+        assert!(drain.begin_connection().is_ok());
+    }
+
+    #[test]
+    fn shutdowns_wait_less_than_handoffs() {
+        let drain = Drain::default();
+        assert_eq!(drain.timeout(), DRAIN_TIMEOUT);
+        drain.request_handoff();
+        assert!(!drain.is_shutting_down());
+        drain.request_shutdown();
+        assert!(drain.is_handing_off());
+        assert_eq!(
+            drain.begin_connection().unwrap_err(),
+            ConnectionRefused::HandingOff
+        );
+        assert_eq!(drain.timeout(), SHUTDOWN_DRAIN_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn shutdown_hooks_run_in_order() {
+        let hooks = ShutdownHooks::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        for expected in 0..3 {
+            let calls = calls.clone();
+            hooks.register(Box::new(move || {
+                let calls = calls.clone();
+                Box::pin(async move {
+                    assert_eq!(calls.fetch_add(1, Ordering::SeqCst), expected);
+                })
+            }));
+        }
+        hooks.run().await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
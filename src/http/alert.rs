@@ -0,0 +1,451 @@
+//! A message from the operators at the top of every activity's page, such as "Tunnel flaky tonight, saves may lag",
+//! set and cleared through `/admin/banner` without redeploying.
+//!
+//! Pages pick up changes through the polling that they already do: every poll response carries the banner as an
+//! out-of-band swap.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Router,
+};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{
+    clock::Clock,
+    format::parse_duration,
+    http::{
+        audit::{self, Audited},
+        form::StrictForm,
+    },
+    storage::DataDir,
+};
+
+/// Longest banner message, in characters.
+pub const MAX_BANNER_LENGTH: usize = 200;
+
+/// How long banners are shown for when the operator doesn't say, unless overridden with `--banner-duration`.
+pub const DEFAULT_BANNER_DURATION: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Where the banner is kept in the data dir, so that restarts keep showing it.
+pub const BANNER_FILE: &str = "banner.json";
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Banner {
+    message: String,
+    severity: Severity,
+    expires_at: Instant,
+}
+
+/// [`Banner`] as persisted, with the expiry as a Unix timestamp so that it survives restarts.
+#[derive(Serialize, Deserialize)]
+struct PersistedBanner {
+    message: String,
+    severity: Severity,
+    expires_at: u64,
+}
+
+/// The operators' banner, if any. Shared by every clone.
+#[derive(Clone)]
+pub struct AlertBanner {
+    current: Arc<Mutex<Option<Banner>>>,
+    data_dir: Option<DataDir>,
+    /// How long a banner is shown for, unless set with its own duration.
+    default_duration: Duration,
+    clock: Clock,
+}
+
+impl AlertBanner {
+    pub fn new(default_duration: Duration, clock: Clock) -> Self {
+        AlertBanner {
+            current: Arc::default(),
+            data_dir: None,
+            default_duration,
+            clock,
+        }
+    }
+
+    /// Picks up the banner from the data dir, unless it expired in the meantime, and persists any changes there.
+    pub async fn load(data_dir: Option<DataDir>, default_duration: Duration, clock: Clock) -> Self {
+        let mut banner = AlertBanner::new(default_duration, clock);
+        let Some(data_dir) = data_dir else {
+            return banner;
+        };
+        match data_dir
+            .read_json::<Option<PersistedBanner>>(BANNER_FILE)
+            .await
+        {
+            Ok(Some(Some(persisted))) => {
+                let expires_at = UNIX_EPOCH + Duration::from_secs(persisted.expires_at);
+                if let Ok(left) = expires_at.duration_since(SystemTime::now()) {
+                    *banner.current.lock().unwrap() = Some(Banner {
+                        message: persisted.message,
+                        severity: persisted.severity,
+                        expires_at: banner.clock.now() + left,
+                    });
+                }
+            }
+            Ok(_) => (),
+            Err(e) => warn!(error = ?e, "Unable to load the banner."),
+        }
+        banner.data_dir = Some(data_dir);
+        banner
+    }
+
+    /// Shows `message` on every page until `duration` (or the default duration) is up. Returns why the message
+    /// can't be shown, if it can't.
+    pub async fn set(
+        &self,
+        message: &str,
+        severity: Severity,
+        duration: Option<Duration>,
+    ) -> Result<(), String> {
+        let message = validate_message(message)?;
+        let duration = duration.unwrap_or(self.default_duration);
+        *self.current.lock().unwrap() = Some(Banner {
+            message: String::from(message),
+            severity,
+            expires_at: self.clock.now() + duration,
+        });
+        self.persist(Some(PersistedBanner {
+            message: String::from(message),
+            severity,
+            expires_at: (SystemTime::now() + duration)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }))
+        .await;
+        Ok(())
+    }
+
+    pub async fn clear(&self) {
+        *self.current.lock().unwrap() = None;
+        self.persist(None).await;
+    }
+
+    async fn persist(&self, banner: Option<PersistedBanner>) {
+        if let Some(data_dir) = &self.data_dir {
+            if let Err(e) = data_dir.write_json(BANNER_FILE, &banner).await {
+                warn!(error = ?e, "Unable to persist the banner.");
+            }
+        }
+    }
+
+    /// The message and its severity, unless there's none or it expired.
+    pub fn current(&self) -> Option<(String, Severity)> {
+        let mut current = self.current.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|banner| banner.expires_at <= self.clock.now())
+        {
+            *current = None;
+        }
+        current
+            .as_ref()
+            .map(|banner| (banner.message.clone(), banner.severity))
+    }
+}
+
+/// Styles for the banner, for every page that shows it.
+/// Trims `message`, or returns why it can't be shown.
+pub fn validate_message(message: &str) -> Result<&str, String> {
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(String::from("The message is empty."));
+    }
+    if message.chars().count() > MAX_BANNER_LENGTH {
+        return Err(format!(
+            "The message is longer than {MAX_BANNER_LENGTH} characters."
+        ));
+    }
+    if message.chars().any(char::is_control) {
+        return Err(String::from("The message has control characters."));
+    }
+    Ok(message)
+}
+
+pub static STYLE: &str = r#"
+.alert-banner {
+    padding: 8px;
+    border-left: 6px solid;
+}
+.alert-info {
+    background-color: #def;
+    border-color: #48c;
+}
+.alert-warning {
+    background-color: #fd6;
+    border-color: #c80;
+}
+.alert-critical {
+    background-color: #fcc;
+    border-color: #c22;
+}
+"#;
+
+/// The banner's placeholder, for the top of a page. It stays empty while there's no banner, and is left out
+/// altogether without an [`AlertBanner`].
+pub fn banner(alert: Option<&AlertBanner>) -> Markup {
+    render(alert, false)
+}
+
+/// The banner as an out-of-band swap, for responses that pages poll.
+pub fn banner_update(alert: Option<&AlertBanner>) -> Markup {
+    render(alert, true)
+}
+
+fn render(alert: Option<&AlertBanner>, out_of_band: bool) -> Markup {
+    let Some(alert) = alert else {
+        return html! {};
+    };
+    let current = alert.current();
+    html! {
+        #alert-banner hx-swap-oob=[out_of_band.then_some("true")] {
+            @if let Some((message, severity)) = current {
+                p class=(format!("alert-banner alert-{}", severity.as_str())) role="alert" {
+                    (message)
+                }
+            }
+        }
+    }
+}
+
+/// Adds `/admin/banner`, and makes the banner available to the pages.
+pub fn with_alert_banner(router: Router, alert: AlertBanner) -> Router {
+    router
+        .merge(
+            Router::new()
+                .route("/admin/banner", post(set_banner).delete(clear_banner))
+                .with_state(alert.clone()),
+        )
+        .layer(Extension(alert))
+}
+
+#[derive(Deserialize, Debug)]
+struct BannerPayload {
+    message: String,
+    #[serde(default)]
+    severity: Severity,
+    /// How long to show the banner for, with an s, m, h or d suffix.
+    duration: Option<String>,
+}
+
+/// Parses the optional `duration` of the form, where empty means the default duration.
+fn parse_banner_duration(value: Option<&str>) -> Result<Option<Duration>, String> {
+    match value.filter(|value| !value.is_empty()) {
+        Some(value) => match parse_duration(value)? {
+            duration if duration.is_zero() => {
+                Err(String::from("The duration must be longer than zero."))
+            }
+            duration => Ok(Some(duration)),
+        },
+        None => Ok(None),
+    }
+}
+
+async fn set_banner(
+    audit: Audited,
+    State(alert): State<AlertBanner>,
+    StrictForm(payload): StrictForm<BannerPayload>,
+) -> Response {
+    let checked = parse_banner_duration(payload.duration.as_deref())
+        .and_then(|duration| validate_message(&payload.message).map(|_| duration));
+    let parameters = json!({
+        "message": payload.message,
+        "severity": payload.severity.as_str(),
+        "duration": payload.duration,
+    });
+    let outcome = checked.as_ref().err().map_or(audit::OK, String::as_str);
+    if let Err(response) = audit.record("set_banner", parameters, outcome).await {
+        return response;
+    }
+    let result = match checked {
+        Ok(duration) => {
+            alert
+                .set(&payload.message, payload.severity, duration)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => banner(Some(&alert)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+async fn clear_banner(audit: Audited, State(alert): State<AlertBanner>) -> Response {
+    if let Err(response) = audit.record("clear_banner", json!({}), audit::OK).await {
+        return response;
+    }
+    alert.clear().await;
+    banner(Some(&alert)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request, http::header::CONTENT_TYPE};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::identity::Identity;
+
+    async fn send(alert: &AlertBanner, method: &str, body: &str) -> (StatusCode, String) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/admin/banner")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(String::from(body)))
+            .unwrap();
+        request.extensions_mut().insert(Identity::Named {
+            name: String::from("operator"),
+            is_admin: true,
+        });
+        let response = with_alert_banner(Router::new(), alert.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn banners_are_set_escaped_and_deleted() {
+        let (clock, _) = Clock::manual();
+        let alert = AlertBanner::new(DEFAULT_BANNER_DURATION, clock);
+        assert_eq!(
+            banner(Some(&alert)).into_string(),
+            r#"<div id="alert-banner"></div>"#
+        );
+        let (status, body) = send(
+            &alert,
+            "POST",
+            "message=Tunnel+flaky+tonight%2C+<b>saves</b>+may+lag&severity=warning",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("alert-warning"), "{body}");
+        assert!(
+            body.contains("Tunnel flaky tonight, &lt;b&gt;saves&lt;/b&gt; may lag"),
+            "{body}"
+        );
+        assert!(banner_update(Some(&alert))
+            .into_string()
+            .contains(r#"hx-swap-oob="true""#));
+
+        let (status, _) = send(&alert, "POST", &format!("message={}", "a".repeat(201))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&alert, "POST", "message=+").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&alert, "POST", "message=Hi&duration=soon").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        // Rejected messages leave the previous banner alone.
+        assert!(alert.current().is_some());
+
+        let (status, body) = send(&alert, "DELETE", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"<div id="alert-banner"></div>"#);
+        assert_eq!(alert.current(), None);
+    }
+
+    #[tokio::test]
+    async fn banners_expire() {
+        let (clock, manual) = Clock::manual();
+        let alert = AlertBanner::new(DEFAULT_BANNER_DURATION, clock);
+        send(&alert, "POST", "message=Short&duration=10m").await;
+        manual.advance(Duration::from_secs(9 * 60));
+        assert_eq!(
+            alert.current(),
+            Some((String::from("Short"), Severity::Info))
+        );
+        manual.advance(Duration::from_secs(60));
+        assert_eq!(alert.current(), None);
+
+        send(&alert, "POST", "message=Long").await;
+        manual.advance(DEFAULT_BANNER_DURATION - Duration::from_secs(1));
+        assert!(alert.current().is_some());
+        manual.advance(Duration::from_secs(1));
+        assert_eq!(alert.current(), None);
+    }
+
+    #[tokio::test]
+    async fn banners_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("{}-banner", std::process::id()));
+        let data_dir = DataDir::open(path.clone()).await.unwrap();
+        let alert = AlertBanner::load(
+            Some(data_dir.clone()),
+            DEFAULT_BANNER_DURATION,
+            Clock::tokio(),
+        )
+        .await;
+        assert_eq!(alert.current(), None);
+        alert
+            .set("Saves may lag", Severity::Critical, None)
+            .await
+            .unwrap();
+        let restarted = AlertBanner::load(
+            Some(data_dir.clone()),
+            DEFAULT_BANNER_DURATION,
+            Clock::tokio(),
+        )
+        .await;
+        assert_eq!(
+            restarted.current(),
+            Some((String::from("Saves may lag"), Severity::Critical))
+        );
+        restarted.clear().await;
+        let restarted =
+            AlertBanner::load(Some(data_dir), DEFAULT_BANNER_DURATION, Clock::tokio()).await;
+        assert_eq!(restarted.current(), None);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_operators_set_banners() {
+        let alert = AlertBanner::new(DEFAULT_BANNER_DURATION, Clock::tokio());
+        let response = with_alert_banner(Router::new(), alert.clone())
+            .oneshot(
+                Request::post("/admin/banner")
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from("message=Hi"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(alert.current(), None);
+    }
+}
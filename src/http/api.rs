@@ -0,0 +1,268 @@
+//! Conventions for the JSON routes under `/api`, which shouldn't ever answer with an HTML or plain text error.
+//!
+//! Every error has the shape `{ "error": { "code": "...", "message": "..." } }`. Handlers should return
+//! [`ApiError`] and use [`ApiPath`] and [`ApiJson`] instead of the plain extractors, so that rejections follow it
+//! too. Anything else that slips through, such as unknown routes or errors from shared middleware, is rewritten by
+//! [`with_json_errors`].
+
+use axum::{
+    extract::{rejection::JsonRejection, rejection::PathRejection, Request},
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use axum_macros::{FromRequest, FromRequestParts};
+use serde::Serialize;
+
+/// Largest error body from other layers that gets carried over into the JSON message.
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+/// An error from an `/api` route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// Stable, machine-readable reason, such as `not_found`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// An error with the default code for its status, for errors that didn't come with their own.
+    pub fn from_status(status: StatusCode, message: impl Into<String>) -> Self {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+            StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+            StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+            status if status.is_server_error() => "internal_error",
+            _ => "error",
+        };
+        ApiError::new(status, code, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+            },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_path", rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        let code = match rejection.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+            _ => "invalid_body",
+        };
+        ApiError::new(rejection.status(), code, rejection.body_text())
+    }
+}
+
+/// [`Path`](axum::extract::Path), rejecting with an [`ApiError`].
+#[derive(FromRequestParts, Debug)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct ApiPath<T>(pub T);
+
+/// [`Json`], rejecting with an [`ApiError`].
+#[derive(FromRequest, Debug)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// Makes every error response from `router` follow the API's error shape, including unknown routes and errors from
+/// layers that don't know about it. Responses that are already JSON are left alone.
+pub fn with_json_errors<S: Clone + Send + Sync + 'static>(router: Router<S>) -> Router<S> {
+    router
+        .fallback(|| async { ApiError::from_status(StatusCode::NOT_FOUND, "No such API route.") })
+        .layer(middleware::from_fn(json_errors))
+}
+
+async fn json_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+    let message = match axum::body::to_bytes(response.into_body(), MAX_MESSAGE_SIZE).await {
+        Ok(body) if !body.is_empty() => String::from_utf8_lossy(&body).into_owned(),
+        _ => String::from(status.canonical_reason().unwrap_or("Error")),
+    };
+    ApiError::from_status(status, message).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::{DefaultBodyLimit, Path},
+        routing::{get, post},
+    };
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize)]
+    struct Payload {
+        value: u32,
+    }
+
+    async fn fragment(Path(id): Path<u32>) -> String {
+        id.to_string()
+    }
+
+    fn router() -> Router {
+        let api = Router::new()
+            .route(
+                "/items/:id",
+                get(|ApiPath(id): ApiPath<u32>| async move { Json(id) }),
+            )
+            .route(
+                "/items",
+                post(|ApiJson(payload): ApiJson<Payload>| async move { Json(payload.value) }),
+            )
+            .route(
+                "/teapot",
+                get(|| async { (StatusCode::IM_A_TEAPOT, "Short and stout") }),
+            )
+            .layer(DefaultBodyLimit::max(16));
+        Router::new()
+            .route("/items/:id", get(fragment))
+            .nest("/api", with_json_errors(api))
+    }
+
+    async fn send(request: Request) -> (StatusCode, String, String) {
+        let response = router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|value| String::from(value.to_str().unwrap()))
+            .unwrap_or_default();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn error_code(body: &str) -> String {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert!(json["error"]["message"].is_string());
+        String::from(json["error"]["code"].as_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_json() {
+        for (request, status, code) in [
+            (
+                Request::get("/api/items/abc").body(Body::empty()).unwrap(),
+                StatusCode::BAD_REQUEST,
+                "invalid_path",
+            ),
+            (
+                Request::get("/api/missing").body(Body::empty()).unwrap(),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                Request::delete("/api/items/1").body(Body::empty()).unwrap(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+            ),
+            (
+                Request::post("/api/items")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"value":"x"}"#))
+                    .unwrap(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_body",
+            ),
+            (
+                Request::post("/api/items")
+                    .body(Body::from(r#"{"value": 1}"#))
+                    .unwrap(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+            ),
+            (
+                Request::post("/api/items")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(format!(r#"{{"value": {}}}"#, "1".repeat(32))))
+                    .unwrap(),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+            ),
+            (
+                Request::get("/api/teapot").body(Body::empty()).unwrap(),
+                StatusCode::IM_A_TEAPOT,
+                "error",
+            ),
+        ] {
+            let (actual_status, content_type, body) = send(request).await;
+            assert_eq!(actual_status, status, "{body}");
+            assert_eq!(content_type, "application/json");
+            assert_eq!(error_code(&body), code);
+        }
+        let (status, content_type, body) =
+            send(Request::get("/api/items/7").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, "7");
+    }
+
+    #[tokio::test]
+    async fn other_routes_keep_their_errors() {
+        let (status, content_type, _) =
+            send(Request::get("/items/abc").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(content_type.starts_with("text/plain"));
+        let (status, content_type, _) =
+            send(Request::get("/missing").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "");
+    }
+}
@@ -0,0 +1,485 @@
+//! A record of everything that operators do, written before it's done.
+//!
+//! Every entry is appended to a file per day in the data dir, which `--retain-max-age audit=...` can prune. If the
+//! entry can't be written, the action is refused, so that nothing happens without a trace.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Query, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+use maud::{html, Markup, DOCTYPE};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    http::identity::Admin,
+    schedule::TimeZone,
+    storage::{AppendWriter, ArtifactKind, DataDir},
+    tasks::TaskRegistry,
+};
+
+/// Audit log files, for `--retain-max-age` and `--retain-max-size`.
+pub const AUDIT_ARTIFACT: ArtifactKind = ArtifactKind {
+    name: "audit",
+    prefix: "audit_",
+};
+
+/// How many of the latest entries are kept in memory for `/admin/audit`.
+pub const RECENT_ENTRIES: usize = 500;
+
+/// Outcome of an action that went ahead.
+pub const OK: &str = "ok";
+
+/// A single action taken by an operator.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub operator: String,
+    pub action: String,
+    pub parameters: serde_json::Value,
+    /// [`OK`], or why the action was refused.
+    pub outcome: String,
+}
+
+/// Where entries are written, along with the latest ones. Without a data dir, entries are only kept in memory.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    writer: Option<AppendWriter>,
+    /// Where the day of each file starts, from `--timezone`.
+    timezone: TimeZone,
+    recent: Arc<Mutex<VecDeque<AuditEntry>>>,
+}
+
+/// Name of the file that entries at `time` go to, by local date in `timezone`.
+fn file_name(time: SystemTime, timezone: &TimeZone) -> String {
+    let (year, month, day) = timezone.local_date(time);
+    format!(
+        "{}{year:04}-{month:02}-{day:02}.jsonl",
+        AUDIT_ARTIFACT.prefix
+    )
+}
+
+impl AuditLog {
+    /// Starts writing to the data dir if there is one, and reads back the latest entries from it.
+    pub async fn load(data_dir: Option<DataDir>, timezone: TimeZone, tasks: &TaskRegistry) -> Self {
+        let Some(data_dir) = data_dir else {
+            return AuditLog {
+                timezone,
+                ..AuditLog::default()
+            };
+        };
+        let log = AuditLog {
+            writer: Some(AppendWriter::spawn(data_dir.clone(), tasks)),
+            timezone,
+            recent: Arc::default(),
+        };
+        match read_latest(&data_dir).await {
+            Ok(entries) => *log.recent.lock().unwrap() = entries,
+            Err(e) => warn!(error = ?e, "Unable to read the audit log."),
+        }
+        log
+    }
+
+    /// Writes the entry, returning an error if it couldn't be persisted.
+    pub async fn record(&self, entry: AuditEntry) -> Result<()> {
+        if let Some(writer) = &self.writer {
+            let time = UNIX_EPOCH + std::time::Duration::from_secs(entry.timestamp);
+            writer
+                .append_json_line(&file_name(time, &self.timezone), &entry)
+                .await?;
+        }
+        info!(
+            target: "audit",
+            operator = entry.operator,
+            action = entry.action,
+            parameters = %entry.parameters,
+            outcome = entry.outcome,
+            "Admin action."
+        );
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= RECENT_ENTRIES {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+        Ok(())
+    }
+
+    /// The latest entries, oldest first.
+    pub fn recent(&self) -> Vec<AuditEntry> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// The last [`RECENT_ENTRIES`] entries across the newest audit files.
+async fn read_latest(data_dir: &DataDir) -> Result<VecDeque<AuditEntry>> {
+    let names = data_dir.list(AUDIT_ARTIFACT).await?;
+    let mut recent = VecDeque::new();
+    // Dates sort the same as their names.
+    for name in names.iter().rev().filter(|name| name.ends_with(".jsonl")) {
+        let lines = data_dir.read_json_lines::<AuditEntry>(name).await?;
+        for entry in lines.into_iter().rev() {
+            if recent.len() >= RECENT_ENTRIES {
+                return Ok(recent);
+            }
+            recent.push_front(entry);
+        }
+    }
+    Ok(recent)
+}
+
+/// An operator's request, which must be written to the audit log before acting on it. Rejects everyone else with
+/// 403 Forbidden, like [`Admin`].
+pub struct Audited {
+    pub operator: String,
+    log: Option<AuditLog>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Audited
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Admin(operator) = Admin::from_request_parts(parts, state).await?;
+        Ok(Audited {
+            operator,
+            log: parts.extensions.get::<AuditLog>().cloned(),
+        })
+    }
+}
+
+impl Audited {
+    /// Writes what the operator is about to do. If this fails, the caller must give up with the returned response.
+    pub async fn record(
+        &self,
+        action: &str,
+        parameters: serde_json::Value,
+        outcome: &str,
+    ) -> Result<(), Response> {
+        let Some(log) = &self.log else {
+            return Ok(());
+        };
+        let entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            operator: self.operator.clone(),
+            action: String::from(action),
+            parameters,
+            outcome: String::from(outcome),
+        };
+        log.record(entry).await.map_err(|e| {
+            warn!(error = ?e, action, "Unable to write the audit log, refusing the action.");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Unable to write the audit log, so nothing was changed.",
+            )
+                .into_response()
+        })
+    }
+}
+
+/// Adds the operator-only `GET /admin/audit`, and makes the log available to [`Audited`] handlers.
+pub fn with_audit_log(router: Router, log: AuditLog) -> Router {
+    router
+        .merge(
+            Router::new()
+                .route("/admin/audit", get(audit_page))
+                .with_state(log.clone()),
+        )
+        .layer(Extension(log))
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AuditFilter {
+    operator: Option<String>,
+    action: Option<String>,
+}
+
+/// The latest entries, newest first, optionally only from one operator or for one action.
+async fn audit_page(
+    _admin: Admin,
+    State(log): State<AuditLog>,
+    Query(filter): Query<AuditFilter>,
+) -> Markup {
+    let matches = |wanted: &Option<String>, value: &str| {
+        wanted
+            .as_deref()
+            .filter(|wanted| !wanted.is_empty())
+            .is_none_or(|wanted| wanted == value)
+    };
+    let entries = log
+        .recent()
+        .into_iter()
+        .rev()
+        .filter(|entry| matches(&filter.operator, &entry.operator))
+        .filter(|entry| matches(&filter.action, &entry.action))
+        .collect::<Vec<_>>();
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            title { "Audit log" }
+        }
+        body {
+            h1 { "Audit log" }
+            form method="get" {
+                label { "Operator " input type="text" name="operator" value=[filter.operator.as_deref()]; }
+                " "
+                label { "Action " input type="text" name="action" value=[filter.action.as_deref()]; }
+                " "
+                button { "Filter" }
+            }
+            @if entries.is_empty() {
+                p { "No entries." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "Time" }
+                            th { "Operator" }
+                            th { "Action" }
+                            th { "Parameters" }
+                            th { "Outcome" }
+                        }
+                    }
+                    tbody {
+                        @for entry in &entries {
+                            tr {
+                                td { (httpdate::fmt_http_date(UNIX_EPOCH + std::time::Duration::from_secs(entry.timestamp))) }
+                                td { (entry.operator) }
+                                td { (entry.action) }
+                                td { code { (entry.parameters) } }
+                                td { (entry.outcome) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request, http::header::CONTENT_TYPE, routing::post};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        clock::Clock,
+        http::{
+            alert::{with_alert_banner, AlertBanner, DEFAULT_BANNER_DURATION},
+            identity::Identity,
+            maintenance::{with_maintenance_mode, MaintenanceMode},
+        },
+        schedule::tests::utc,
+        storage::tests::temp_data_dir,
+    };
+
+    fn router(log: &AuditLog, maintenance: &MaintenanceMode) -> Router {
+        let router = with_alert_banner(
+            Router::new(),
+            AlertBanner::new(DEFAULT_BANNER_DURATION, Clock::tokio()),
+        );
+        let router = with_maintenance_mode(router, maintenance.clone());
+        with_audit_log(router, log.clone())
+    }
+
+    async fn send(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        body: &str,
+        operator: &str,
+    ) -> (StatusCode, String) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(String::from(body)))
+            .unwrap();
+        request.extensions_mut().insert(Identity::Named {
+            name: String::from(operator),
+            is_admin: true,
+        });
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn admin_actions_are_recorded_in_order() {
+        let data_dir = temp_data_dir("audit").await;
+        let log = AuditLog::load(
+            Some(data_dir.clone()),
+            TimeZone::default(),
+            &TaskRegistry::default(),
+        )
+        .await;
+        let maintenance = MaintenanceMode::default();
+        let router = router(&log, &maintenance);
+        send(
+            &router,
+            "POST",
+            "/admin/banner",
+            "message=Saves+may+lag&severity=warning",
+            "alice",
+        )
+        .await;
+        send(&router, "POST", "/admin/banner", "message=+", "alice").await;
+        send(&router, "POST", "/admin/maintenance", "mode=on", "bob").await;
+        send(&router, "DELETE", "/admin/banner", "", "bob").await;
+
+        let expected = [
+            (
+                "alice",
+                "set_banner",
+                json!({"message": "Saves may lag", "severity": "warning", "duration": null}),
+                OK,
+            ),
+            (
+                "alice",
+                "set_banner",
+                json!({"message": " ", "severity": "info", "duration": null}),
+                "The message is empty.",
+            ),
+            ("bob", "maintenance", json!({"mode": "on"}), OK),
+            ("bob", "clear_banner", json!({}), OK),
+        ];
+        let written = data_dir
+            .read_json_lines::<AuditEntry>(&file_name(SystemTime::now(), &TimeZone::default()))
+            .await
+            .unwrap();
+        for entries in [written, log.recent()] {
+            assert_eq!(entries.len(), expected.len());
+            for (entry, (operator, action, parameters, outcome)) in entries.iter().zip(&expected) {
+                assert_eq!(entry.operator, *operator);
+                assert_eq!(entry.action, *action);
+                assert_eq!(entry.parameters, *parameters);
+                assert_eq!(entry.outcome, *outcome);
+            }
+            assert!(entries
+                .windows(2)
+                .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        }
+
+        let (status, body) = send(&router, "GET", "/admin/audit?operator=bob", "", "carol").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<td>maintenance</td>"), "{body}");
+        assert!(!body.contains("<td>alice</td>"), "{body}");
+        // Newest first.
+        assert!(body.find("clear_banner").unwrap() < body.find("<td>maintenance</td>").unwrap());
+        let (_, body) = send(
+            &router,
+            "GET",
+            "/admin/audit?action=set_banner",
+            "",
+            "carol",
+        )
+        .await;
+        assert_eq!(body.matches("<td>set_banner</td>").count(), 2);
+        assert!(body.contains("The message is empty."));
+
+        // Entries are read back after a restart.
+        let restarted = AuditLog::load(
+            Some(data_dir),
+            TimeZone::default(),
+            &TaskRegistry::default(),
+        )
+        .await;
+        assert_eq!(restarted.recent(), log.recent());
+    }
+
+    #[tokio::test]
+    async fn actions_are_refused_if_the_entry_cant_be_written() {
+        let data_dir = temp_data_dir("audit-fail").await;
+        // A directory where today's file should be makes every append fail.
+        std::fs::create_dir(data_dir.file(&file_name(SystemTime::now(), &TimeZone::default())))
+            .unwrap();
+        let log = AuditLog::load(
+            Some(data_dir),
+            TimeZone::default(),
+            &TaskRegistry::default(),
+        )
+        .await;
+        let maintenance = MaintenanceMode::default();
+        let router = router(&log, &maintenance);
+        let (status, _) = send(&router, "POST", "/admin/maintenance", "mode=on", "alice").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!maintenance.is_on());
+        assert!(log.recent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn files_follow_the_local_day() {
+        let data_dir = temp_data_dir("audit-timezone").await;
+        let log = AuditLog::load(
+            Some(data_dir.clone()),
+            TimeZone::load("America/Sao_Paulo").unwrap(),
+            &TaskRegistry::default(),
+        )
+        .await;
+        // 01:00 UTC is still the day before in UTC-3.
+        let entry = AuditEntry {
+            timestamp: utc(2024, 6, 2, 1)
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            operator: String::from("alice"),
+            action: String::from("maintenance"),
+            parameters: json!({"mode": "on"}),
+            outcome: String::from(OK),
+        };
+        log.record(entry.clone()).await.unwrap();
+        assert_eq!(
+            data_dir
+                .read_json_lines::<AuditEntry>("audit_2024-06-01.jsonl")
+                .await
+                .unwrap(),
+            vec![entry]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_operators_are_audited() {
+        let log = AuditLog::default();
+        let router = Router::new()
+            .route(
+                "/act",
+                post(|audit: Audited| async move {
+                    audit.record("act", json!({}), OK).await.map(|()| "done")
+                }),
+            )
+            .layer(Extension(log.clone()));
+        let response = router
+            .clone()
+            .oneshot(Request::post("/act").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(log.recent().is_empty());
+        let (status, _) = send(&router, "POST", "/act", "", "alice").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(log.recent()[0].operator, "alice");
+    }
+}
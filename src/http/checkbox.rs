@@ -1,32 +1,247 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::{Path as FilePath, PathBuf},
+    sync::{Arc, Mutex},
+};
 
+use anyhow::{bail, Context, Result};
 use axum::{
     extract::{Path, State},
+    response::{IntoResponse, Response},
     routing::{delete, get, put},
-    Router,
+    Extension, Router,
 };
-use bitvec::{array::BitArray, order::Lsb0, BitArr};
+use bitvec::{order::Lsb0, BitArr};
+use clap::Args;
 use hyper::StatusCode;
-use maud::{html, Markup, DOCTYPE};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+
+use super::{
+    alert::{self, AlertBanner},
+    custom_assets::CustomAssets,
+    identity::Identity,
+    landing::{base_href, MountPath, SUMMARY_CACHE_CONTROL},
+    maintenance::{self, MaintenanceMode},
+    registry, tunnel_status,
+};
+use crate::nonogram::image::{image_to_cells, DEFAULT_THRESHOLD};
+use crate::tunnel::TunnelStatusCell;
+
+type Checkboxes = BitArr!(for CHECKBOX_WIDTH*CHECKBOX_HEIGHT, in usize, Lsb0);
 
 #[derive(Clone)]
 struct AppState {
-    checkboxes: Arc<Mutex<BitArr!(for CHECKBOX_WIDTH*CHECKBOX_HEIGHT, in usize, Lsb0)>>,
+    board: CheckboxBoard,
+}
+
+pub const CHECKBOX_WIDTH: usize = 20;
+pub const CHECKBOX_HEIGHT: usize = 20;
+
+/// The checkboxes, shared between the HTTP handlers and the terminal UI of `serve-ssh`.
+#[derive(Clone, Default)]
+pub struct CheckboxBoard(Arc<Mutex<Board>>);
+
+#[derive(Default)]
+struct Board {
+    checkboxes: Checkboxes,
+    /// Cells that players can't toggle, set from a locked seed.
+    locked: Checkboxes,
+}
+
+/// A checkbox as stored on the board.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub checked: bool,
+    /// Whether it was seeded as locked.
+    pub locked: bool,
+}
+
+impl CheckboxBoard {
+    /// Replaces the whole board with the seed image of `config`, or empties it.
+    pub async fn load_seed(&self, config: &CheckboxConfig) -> Result<()> {
+        let seed = match config.seed_image() {
+            Some(path) => Some(CheckboxSeed::from_image(path, config.seed_locked()).await?),
+            None => None,
+        };
+        self.seed(seed);
+        Ok(())
+    }
+
+    /// Replaces the whole board with `seed`, or empties it.
+    fn seed(&self, seed: Option<CheckboxSeed>) {
+        let (checkboxes, locked) = match seed {
+            None => (Checkboxes::ZERO, Checkboxes::ZERO),
+            Some(seed) if seed.locked => (seed.checkboxes, seed.checkboxes),
+            Some(seed) => (seed.checkboxes, Checkboxes::ZERO),
+        };
+        *self.0.lock().unwrap() = Board { checkboxes, locked };
+    }
+
+    /// Every cell, row by row.
+    pub fn cells(&self) -> Vec<Cell> {
+        let board = self.0.lock().unwrap();
+        board.checkboxes[..CHECKBOX_WIDTH * CHECKBOX_HEIGHT]
+            .iter()
+            .by_vals()
+            .zip(board.locked.iter().by_vals())
+            .map(|(checked, locked)| Cell { checked, locked })
+            .collect()
+    }
+
+    /// How many checkboxes are checked.
+    pub fn checked(&self) -> usize {
+        self.0.lock().unwrap().checkboxes[..CHECKBOX_WIDTH * CHECKBOX_HEIGHT].count_ones()
+    }
+
+    /// The cell `id`, if there's one.
+    pub fn get(&self, id: usize) -> Option<Cell> {
+        let board = self.0.lock().unwrap();
+        (id < CHECKBOX_WIDTH * CHECKBOX_HEIGHT).then(|| Cell {
+            checked: board.checkboxes[id],
+            locked: board.locked[id],
+        })
+    }
+
+    /// Checks or unchecks the cell `id` unless it's locked, and returns it as it ends up.
+    pub fn set(&self, id: usize, checked: bool) -> Option<Cell> {
+        let mut board = self.0.lock().unwrap();
+        if id >= CHECKBOX_WIDTH * CHECKBOX_HEIGHT {
+            return None;
+        }
+        if !board.locked[id] {
+            board.checkboxes.set(id, checked);
+        }
+        Some(Cell {
+            checked: board.checkboxes[id],
+            locked: board.locked[id],
+        })
+    }
+}
+
+/// Initial contents of the board, as set by `--seed-image`.
+#[derive(Clone)]
+pub struct CheckboxSeed {
+    checkboxes: Checkboxes,
+    /// Whether the seeded cells are read-only.
+    locked: bool,
+}
+
+impl CheckboxSeed {
+    /// Downscales and thresholds an image to the board dimensions.
+    pub async fn from_image(path: &FilePath, locked: bool) -> Result<Self> {
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        Self::from_image_bytes(&bytes, locked)
+    }
+
+    fn from_image_bytes(bytes: &[u8], locked: bool) -> Result<Self> {
+        let cells = image_to_cells(bytes, CHECKBOX_WIDTH, CHECKBOX_HEIGHT, DEFAULT_THRESHOLD)?;
+        let mut checkboxes = Checkboxes::ZERO;
+        checkboxes[..CHECKBOX_WIDTH * CHECKBOX_HEIGHT].copy_from_bitslice(&cells);
+        Ok(CheckboxSeed { checkboxes, locked })
+    }
+}
+
+/// Validated settings for the checkboxes activity. Start from [`CheckboxConfig::builder`], or from the command line
+/// with [`CheckboxArgs`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckboxConfig {
+    seed_image: Option<PathBuf>,
+    seed_locked: bool,
+}
+
+impl CheckboxConfig {
+    pub fn builder() -> CheckboxConfigBuilder {
+        CheckboxConfigBuilder::default()
+    }
+
+    /// Image to pre-seed the board with.
+    pub fn seed_image(&self) -> Option<&FilePath> {
+        self.seed_image.as_deref()
+    }
+
+    /// Whether the seeded cells are read-only.
+    pub fn seed_locked(&self) -> bool {
+        self.seed_locked
+    }
+}
+
+/// Builds a [`CheckboxConfig`], checking that its settings make sense together.
+#[derive(Clone, Debug, Default)]
+pub struct CheckboxConfigBuilder {
+    seed_image: Option<PathBuf>,
+    seed_locked: bool,
+}
+
+impl CheckboxConfigBuilder {
+    pub fn seed_image(mut self, path: Option<PathBuf>) -> Self {
+        self.seed_image = path;
+        self
+    }
+
+    pub fn seed_locked(mut self, locked: bool) -> Self {
+        self.seed_locked = locked;
+        self
+    }
+
+    pub fn build(self) -> Result<CheckboxConfig> {
+        if self.seed_locked && self.seed_image.is_none() {
+            bail!("Invalid checkboxes config: seed_locked requires a seed_image.");
+        }
+        Ok(CheckboxConfig {
+            seed_image: self.seed_image,
+            seed_locked: self.seed_locked,
+        })
+    }
+}
+
+/// Command-line flags for the checkboxes activity, flattened into the binary's arguments.
+#[derive(Args, Clone, Debug)]
+pub struct CheckboxArgs {
+    /// Image to pre-seed the checkboxes board with. It is downscaled to the board, and dark pixels become checked.
+    #[arg(long, global = true, value_name = "FILE")]
+    seed_image: Option<PathBuf>,
+
+    /// Make the cells seeded from `--seed-image` read-only.
+    #[arg(long, global = true, requires = "seed_image")]
+    seed_locked: bool,
+}
+
+impl TryFrom<CheckboxArgs> for CheckboxConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(args: CheckboxArgs) -> Result<Self> {
+        CheckboxConfig::builder()
+            .seed_image(args.seed_image)
+            .seed_locked(args.seed_locked)
+            .build()
+    }
 }
 
-const CHECKBOX_WIDTH: usize = 20;
-const CHECKBOX_HEIGHT: usize = 20;
+/// Builds the router around `board`, loading the seed image into it if there is one.
+pub async fn build_router(config: CheckboxConfig, board: CheckboxBoard) -> Result<Router> {
+    board.load_seed(&config).await?;
+    Ok(router(AppState { board }))
+}
 
-/// A lazily-created Router, to be used by the SSH client tunnels.
-pub fn get_router() -> Router {
+/// Registers this activity as `checkboxes`, configured from
+/// [`ActivityContext::checkboxes`](registry::ActivityContext::checkboxes).
+pub fn register() {
+    registry::register(
+        "checkboxes",
+        "400 Checkboxes - A barebones clone of One Million Checkboxes.",
+        |context| build_router(context.checkboxes, context.checkbox_board),
+    );
+}
+
+fn router(state: AppState) -> Router {
     Router::new()
         .route("/", get(index))
         .route("/checkboxes", get(all_checkboxes))
+        .route("/summary", get(summary))
         .route("/checkbox/:id", put(mark_checkbox))
         .route("/checkbox/:id", delete(unmark_checkbox))
-        .with_state(AppState {
-            checkboxes: Arc::new(Mutex::new(BitArray::ZERO)),
-        })
+        .with_state(state)
 }
 
 fn style() -> &'static str {
@@ -44,81 +259,410 @@ li {
     width: 20px;
     height: 20px;
 }
+input.locked {
+    cursor: not-allowed;
+}
 "#
 }
 
-fn head() -> Markup {
+/// Everything that the page shows outside of the polled board.
+struct IndexView<'a> {
+    base: &'a str,
+    /// Extra `<head>` contents from `--extra-css` and `--extra-js`.
+    custom_head: Option<Markup>,
+    /// Banners and overlays at the top of the page.
+    banners: Markup,
+    footer: Markup,
+}
+
+fn render_index(view: &IndexView) -> Markup {
     html! {
         (DOCTYPE)
         head {
             meta charset="utf-8";
+            base href=(view.base);
             title { (CHECKBOX_WIDTH*CHECKBOX_HEIGHT) " Checkboxes" }
             script src="https://unpkg.com/htmx.org@2.0.2" integrity="sha384-Y7hw+L/jvKeWIRRkqWYfPcvVxHzVzn5REgzbawhxAuQGwX1XWe70vji+VSeHOThJ" crossorigin="anonymous" {}
             style { (style()) }
+            style { (PreEscaped(alert::STYLE)) }
+            style { (PreEscaped(maintenance::STYLE)) }
+            @if let Some(custom_head) = &view.custom_head {
+                (custom_head)
+            }
+        }
+        body {
+            (view.banners)
+            h1 { (CHECKBOX_WIDTH*CHECKBOX_HEIGHT) " Checkboxes" }
+            div hx-get="checkboxes" hx-trigger="load" hx-swap="outerHTML" {}
+            (view.footer)
         }
     }
 }
 
-async fn index() -> Markup {
+/// A single checkbox as players see it.
+#[derive(Copy, Clone, Debug)]
+struct CellView {
+    id: usize,
+    checked: bool,
+    /// Whether it can't be toggled right now, either because it was seeded as locked or during maintenance.
+    locked: bool,
+}
+
+fn render_cell(cell: CellView) -> Markup {
+    let CellView {
+        id,
+        checked,
+        locked,
+    } = cell;
     html! {
-        (head())
-        body {
-            h1 { (CHECKBOX_WIDTH*CHECKBOX_HEIGHT) " Checkboxes" }
-            div hx-get="/checkboxes" hx-trigger="load" hx-swap="outerHTML" {}
+        @if locked {
+            input id=(format!("cb-{}", id)) .locked type="checkbox" checked[checked] disabled {}
+        } @else if checked {
+            input id=(format!("cb-{}", id)) type="checkbox" hx-delete=(format!("checkbox/{}", id)) hx-trigger="click" checked {}
+        } @else {
+            input id=(format!("cb-{}", id)) type="checkbox" hx-put=(format!("checkbox/{}", id)) hx-trigger="click" {}
         }
     }
 }
 
-async fn all_checkboxes(State(state): State<AppState>) -> Markup {
+/// The whole polled board.
+fn render_board(cells: &[CellView]) -> Markup {
     html! {
-        ul hx-get="/checkboxes" hx-trigger="every 3s" style=(format!("grid-template-columns: repeat({}, minmax(0, 1fr));", CHECKBOX_WIDTH)) hx-swap="outerHTML" {
-            @for (id, checkbox) in state.checkboxes.lock().unwrap()[..CHECKBOX_WIDTH*CHECKBOX_HEIGHT].iter().by_vals().enumerate() {
-                li {
-                    @if checkbox {
-                        (checked(id))
-                    } @else {
-                        (unchecked(id))
-                    }
-                }
+        ul hx-get="checkboxes" hx-trigger="every 3s" style=(format!("grid-template-columns: repeat({}, minmax(0, 1fr));", CHECKBOX_WIDTH)) hx-swap="outerHTML" {
+            @for &cell in cells {
+                li { (render_cell(cell)) }
             }
         }
     }
 }
 
-fn checked(id: usize) -> Markup {
-    html! {
-        input id=(format!("cb-{}", id)) type="checkbox" hx-delete=(format!("/checkbox/{}", id)) hx-trigger="click" checked {}
-    }
+async fn index(
+    custom_assets: Option<Extension<CustomAssets>>,
+    tunnel: Option<Extension<TunnelStatusCell>>,
+    alert: Option<Extension<AlertBanner>>,
+    maintenance: Option<Extension<MaintenanceMode>>,
+    mount: Option<Extension<MountPath>>,
+    identity: Identity,
+) -> Markup {
+    let tunnel = tunnel.as_ref().map(|Extension(status)| status);
+    render_index(&IndexView {
+        base: base_href(mount.as_ref()),
+        custom_head: custom_assets.map(|Extension(custom_assets)| custom_assets.head()),
+        banners: html! {
+            (alert::banner(alert.as_ref().map(|Extension(alert)| alert)))
+            (tunnel_status::banner(tunnel))
+            (maintenance::overlay(maintenance.as_ref().map(|Extension(mode)| mode)))
+        },
+        footer: tunnel_status::operator_footer(tunnel, &identity),
+    })
 }
 
-fn unchecked(id: usize) -> Markup {
+async fn all_checkboxes(
+    State(state): State<AppState>,
+    alert: Option<Extension<AlertBanner>>,
+    maintenance: Option<Extension<MaintenanceMode>>,
+) -> Markup {
+    let cells = state
+        .board
+        .cells()
+        .into_iter()
+        .enumerate()
+        .map(|(id, Cell { checked, locked })| CellView {
+            id,
+            checked,
+            locked,
+        })
+        .collect::<Vec<_>>();
     html! {
-        input id=(format!("cb-{}", id)) type="checkbox" hx-put=(format!("/checkbox/{}", id)) hx-trigger="click" {}
+        (alert::banner_update(alert.as_ref().map(|Extension(alert)| alert)))
+        (maintenance::overlay_update(maintenance.as_ref().map(|Extension(mode)| mode)))
+        (render_board(&cells))
     }
 }
 
+/// How many checkboxes are checked, for the landing page.
+async fn summary(State(state): State<AppState>) -> impl IntoResponse {
+    let checked = state.board.checked();
+    (
+        [SUMMARY_CACHE_CONTROL],
+        html! {
+            p { (checked) " of " (CHECKBOX_WIDTH * CHECKBOX_HEIGHT) " checked" }
+        },
+    )
+}
+
 async fn mark_checkbox(
     State(state): State<AppState>,
     Path(id): Path<usize>,
-) -> Result<Markup, StatusCode> {
-    match state.checkboxes.lock().unwrap().get_mut(id) {
-        None => Err(StatusCode::NOT_FOUND),
-        Some(mut checkbox) => {
-            *checkbox = true;
-            Ok(checked(id))
-        }
-    }
+    maintenance: Option<Extension<MaintenanceMode>>,
+) -> Response {
+    toggle(&state, id, true, maintenance.as_ref())
 }
 
 async fn unmark_checkbox(
     State(state): State<AppState>,
     Path(id): Path<usize>,
-) -> Result<Markup, StatusCode> {
-    match state.checkboxes.lock().unwrap().get_mut(id) {
-        None => Err(StatusCode::NOT_FOUND),
-        Some(mut checkbox) => {
-            *checkbox = false;
-            Ok(unchecked(id))
+    maintenance: Option<Extension<MaintenanceMode>>,
+) -> Response {
+    toggle(&state, id, false, maintenance.as_ref())
+}
+
+/// Shared logic for checking and unchecking a checkbox.
+fn toggle(
+    state: &AppState,
+    id: usize,
+    checked: bool,
+    maintenance: Option<&Extension<MaintenanceMode>>,
+) -> Response {
+    let Some(cell) = state.board.get(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if is_frozen(maintenance) {
+        return maintenance::rejection(render_cell(CellView {
+            id,
+            checked: cell.checked,
+            locked: true,
+        }));
+    }
+    let Some(Cell { checked, locked }) = state.board.set(id, checked) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    render_cell(CellView {
+        id,
+        checked,
+        locked,
+    })
+    .into_response()
+}
+
+fn is_frozen(maintenance: Option<&Extension<MaintenanceMode>>) -> bool {
+    maintenance.is_some_and(|Extension(mode)| mode.is_on())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::Clock, http::snapshot::assert_snapshot, nonogram::image::tests::fixture_png,
+    };
+    use axum::{body::Body, extract::Request};
+    use tower::ServiceExt;
+
+    /// A 20x20 stencil with a filled 10x10 square in the top-left corner.
+    fn stencil() -> Vec<u8> {
+        let filled = format!("{}{}", "#".repeat(10), ".".repeat(10));
+        let empty = ".".repeat(20);
+        let rows = (0..20)
+            .map(|row| {
+                if row < 10 {
+                    filled.as_str()
+                } else {
+                    empty.as_str()
+                }
+            })
+            .collect::<Vec<_>>();
+        fixture_png(&rows)
+    }
+
+    /// The router around a fresh board, seeded from an image that's already in memory.
+    fn seeded_router(seed: CheckboxSeed) -> Router {
+        let board = CheckboxBoard::default();
+        board.seed(Some(seed));
+        router(AppState { board })
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str) -> (StatusCode, String) {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        checkboxes: CheckboxArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<CheckboxConfig> {
+        let cli = <Cli as clap::Parser>::try_parse_from(["checkboxes"].iter().chain(args))?;
+        CheckboxConfig::try_from(cli.checkboxes)
+    }
+
+    #[tokio::test]
+    async fn the_default_config_is_an_empty_board() {
+        let config = CheckboxConfig::default();
+        assert_eq!(config.seed_image(), None);
+        assert!(!config.seed_locked());
+        assert_eq!(parse(&[]).unwrap(), config);
+        let router = build_router(config, CheckboxBoard::default())
+            .await
+            .unwrap();
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert_eq!(body.matches("checked").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn the_banner_is_shown_and_polled() {
+        let alert = AlertBanner::new(alert::DEFAULT_BANNER_DURATION, Clock::tokio());
+        alert
+            .set("Saves may lag", alert::Severity::Warning, None)
+            .await
+            .unwrap();
+        let router = build_router(CheckboxConfig::default(), CheckboxBoard::default())
+            .await
+            .unwrap()
+            .layer(Extension(alert.clone()));
+        let (_, body) = send(&router, "GET", "/").await;
+        assert!(body.contains("Saves may lag"), "{body}");
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert!(body.contains(r#"hx-swap-oob="true""#), "{body}");
+        assert!(body.contains("Saves may lag"), "{body}");
+        alert.clear().await;
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert!(!body.contains("Saves may lag"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn maintenance_freezes_the_checkboxes() {
+        let mode = MaintenanceMode::default();
+        let router = build_router(CheckboxConfig::default(), CheckboxBoard::default())
+            .await
+            .unwrap()
+            .layer(Extension(mode.clone()));
+        mode.set(true).await;
+        let (status, body) = send(&router, "PUT", "/checkbox/3").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body.contains(r#"class="locked" id="cb-3""#), "{body}");
+        assert!(body.contains(maintenance::NOTICE), "{body}");
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert!(body.contains("maintenance-overlay"), "{body}");
+        assert!(!body.contains(r#"id="cb-3" type="checkbox" hx-delete"#));
+
+        mode.set(false).await;
+        let (status, _) = send(&router, "PUT", "/checkbox/3").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert!(!body.contains(maintenance::NOTICE), "{body}");
+        assert!(body.contains(r#"id="cb-3" type="checkbox" hx-delete"#));
+    }
+
+    #[test]
+    fn the_views_match_their_snapshots() {
+        assert_snapshot(
+            "checkboxes_index",
+            render_index(&IndexView {
+                base: "/checkboxes/",
+                custom_head: None,
+                banners: html! { p .banner { "Banner" } },
+                footer: html! { footer { "Footer" } },
+            }),
+        );
+        let cells = [(false, false), (true, false), (false, true), (true, true)]
+            .into_iter()
+            .enumerate()
+            .map(|(id, (checked, locked))| CellView {
+                id,
+                checked,
+                locked,
+            })
+            .collect::<Vec<_>>();
+        for (cell, name) in cells
+            .iter()
+            .zip(["unchecked", "checked", "locked", "locked_checked"])
+        {
+            assert_snapshot(&format!("checkboxes_cell_{name}"), render_cell(*cell));
         }
+        assert_snapshot("checkboxes_board", render_board(&cells));
+    }
+
+    #[test]
+    fn locked_seeds_need_an_image() {
+        let error = CheckboxConfig::builder()
+            .seed_locked(true)
+            .build()
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("seed_locked requires a seed_image"));
+        assert!(parse(&["--seed-locked"]).is_err());
+    }
+
+    #[test]
+    fn configs_round_trip_through_the_command_line() {
+        for (image, locked) in [
+            (None, false),
+            (Some("seed.png"), false),
+            (Some("seed.png"), true),
+        ] {
+            let config = CheckboxConfig::builder()
+                .seed_image(image.map(PathBuf::from))
+                .seed_locked(locked)
+                .build()
+                .unwrap();
+            let mut args = vec![];
+            if let Some(path) = config.seed_image() {
+                args.push(format!("--seed-image={}", path.display()));
+            }
+            if config.seed_locked() {
+                args.push(String::from("--seed-locked"));
+            }
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            assert_eq!(parse(&args).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn it_seeds_the_board_from_an_image() {
+        let seed = CheckboxSeed::from_image_bytes(&stencil(), false).unwrap();
+        for row in 0..CHECKBOX_HEIGHT {
+            for column in 0..CHECKBOX_WIDTH {
+                assert_eq!(
+                    seed.checkboxes[row * CHECKBOX_WIDTH + column],
+                    row < 10 && column < 10,
+                    "row {row}, column {column}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unlocked_seeds_can_be_toggled() {
+        let router = seeded_router(CheckboxSeed::from_image_bytes(&stencil(), false).unwrap());
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert_eq!(body.matches("checked").count(), 100);
+        let (status, body) = send(&router, "DELETE", "/checkbox/0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains("checked"));
+        let (_, body) = send(&router, "GET", "/summary").await;
+        assert!(body.contains("99 of 400 checked"));
+    }
+
+    #[tokio::test]
+    async fn locked_seeds_reject_toggles() {
+        let router = seeded_router(CheckboxSeed::from_image_bytes(&stencil(), true).unwrap());
+        let (status, body) = send(&router, "DELETE", "/checkbox/0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"class="locked""#));
+        assert!(body.contains("checked"));
+        let (_, body) = send(&router, "GET", "/checkboxes").await;
+        assert_eq!(body.matches(r#"class="locked""#).count(), 100);
+        assert_eq!(body.matches("checked").count(), 100);
+        // Cells outside of the stencil are still free.
+        let (_, body) = send(&router, "PUT", "/checkbox/399").await;
+        assert!(!body.contains("locked"));
+        assert!(body.contains("checked"));
     }
 }
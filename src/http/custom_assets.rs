@@ -0,0 +1,297 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    extract::State,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+use maud::{html, Markup};
+use tokio::{
+    fs,
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::tasks::TaskRegistry;
+
+/// Largest custom stylesheet or script that we're willing to serve, in bytes.
+pub const MAX_CUSTOM_ASSET_SIZE: u64 = 256 * 1024;
+
+/// A custom stylesheet or script loaded from disk.
+#[derive(Clone, Debug)]
+struct CustomAsset {
+    contents: String,
+    etag: String,
+}
+
+impl CustomAsset {
+    async fn load(path: &Path) -> Result<Self> {
+        let size = fs::metadata(path)
+            .await
+            .with_context(|| format!("Unable to read {}", path.display()))?
+            .len();
+        if size > MAX_CUSTOM_ASSET_SIZE {
+            return Err(anyhow!(
+                "{} is too large ({size} > {MAX_CUSTOM_ASSET_SIZE} bytes)",
+                path.display()
+            ));
+        }
+        let contents = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        Ok(CustomAsset {
+            contents,
+            etag: format!("\"{:016x}\"", hasher.finish()),
+        })
+    }
+}
+
+/// Operator-provided stylesheet and script, injected into the running activity's page.
+///
+/// Files are read at startup and whenever [`CustomAssets::reload`] is called (such as on SIGHUP).
+#[derive(Clone, Debug, Default)]
+pub struct CustomAssets {
+    css_path: Option<PathBuf>,
+    js_path: Option<PathBuf>,
+    css: Arc<RwLock<Option<CustomAsset>>>,
+    js: Arc<RwLock<Option<CustomAsset>>>,
+}
+
+impl CustomAssets {
+    /// Loads the given files. Read failures are logged and the corresponding asset is skipped.
+    pub async fn load(css_path: Option<PathBuf>, js_path: Option<PathBuf>) -> Self {
+        let assets = CustomAssets {
+            css_path,
+            js_path,
+            ..Default::default()
+        };
+        assets.reload().await;
+        assets
+    }
+
+    /// Whether any custom files were configured.
+    pub fn is_enabled(&self) -> bool {
+        self.css_path.is_some() || self.js_path.is_some()
+    }
+
+    /// Reads the files from disk again. If a file can't be read, the previous version is kept.
+    pub async fn reload(&self) {
+        for (path, asset) in [(&self.css_path, &self.css), (&self.js_path, &self.js)] {
+            let Some(path) = path else {
+                continue;
+            };
+            match CustomAsset::load(path).await {
+                Ok(loaded) => {
+                    info!(path = %path.display(), etag = loaded.etag, "Loaded custom asset.");
+                    *asset.write().unwrap() = Some(loaded);
+                }
+                Err(e) => warn!(error = ?e, "Unable to load custom asset, skipping."),
+            }
+        }
+    }
+
+    /// Reloads the files every time that the process receives SIGHUP.
+    pub fn spawn_reload_on_sighup(&self, tasks: &TaskRegistry) -> Result<JoinHandle<()>> {
+        let mut hangup =
+            signal(SignalKind::hangup()).with_context(|| "Unable to listen for SIGHUP")?;
+        let assets = self.clone();
+        Ok(tasks.spawn("custom asset reloads", async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading custom assets.");
+                assets.reload().await;
+            }
+        }))
+    }
+
+    /// Tags to include in the `<head>` of an activity's page, referencing whichever assets are loaded.
+    pub fn head(&self) -> Markup {
+        let css = self
+            .css
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|css| css.etag.clone());
+        let js = self.js.read().unwrap().as_ref().map(|js| js.etag.clone());
+        html! {
+            @if let Some(etag) = css {
+                link rel="stylesheet" href=(format!("/custom.css?v={}", etag.trim_matches('"'))) {}
+            }
+            @if let Some(etag) = js {
+                script src=(format!("/custom.js?v={}", etag.trim_matches('"'))) {}
+            }
+        }
+    }
+}
+
+/// Adds the `/custom.css` and `/custom.js` routes, and makes the assets available to the activity's handlers.
+pub fn with_custom_assets(router: Router, assets: CustomAssets) -> Router {
+    if !assets.is_enabled() {
+        return router;
+    }
+    router
+        .merge(
+            Router::new()
+                .route("/custom.css", get(custom_css))
+                .route("/custom.js", get(custom_js))
+                .with_state(assets.clone()),
+        )
+        .layer(Extension(assets))
+}
+
+async fn custom_css(State(assets): State<CustomAssets>, headers: HeaderMap) -> Response {
+    let css = assets.css.read().unwrap().clone();
+    serve_asset(css, "text/css; charset=utf-8", &headers)
+}
+
+async fn custom_js(State(assets): State<CustomAssets>, headers: HeaderMap) -> Response {
+    let js = assets.js.read().unwrap().clone();
+    serve_asset(js, "text/javascript; charset=utf-8", &headers)
+}
+
+fn serve_asset(
+    asset: Option<CustomAsset>,
+    content_type: &'static str,
+    headers: &HeaderMap,
+) -> Response {
+    let Some(asset) = asset else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let cache_headers = [
+        (ETAG, asset.etag.clone()),
+        (CACHE_CONTROL, String::from("no-cache")),
+    ];
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|etag| etag.as_bytes() == asset.etag.as_bytes())
+    {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (
+        [(CONTENT_TYPE, String::from(content_type))],
+        cache_headers,
+        asset.contents,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, time::Duration};
+
+    use super::*;
+    use axum::{body::Body, extract::Request};
+    use tokio::time::sleep;
+    use tower::ServiceExt;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    async fn get_asset(router: Router, uri: &str, etag: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_serves_only_the_provided_assets() {
+        let css_path = temp_file("present.css", "body { color: red; }");
+        let assets = CustomAssets::load(Some(css_path), None).await;
+        let router = with_custom_assets(Router::new(), assets.clone());
+        let response = get_asset(router.clone(), "/custom.css", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/css; charset=utf-8");
+        let response = get_asset(router, "/custom.js", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let head = assets.head().into_string();
+        assert!(head.contains("/custom.css?v="));
+        assert!(!head.contains("/custom.js"));
+    }
+
+    #[tokio::test]
+    async fn it_skips_missing_and_oversized_files() {
+        let big_path = temp_file("big.js", &"x".repeat(MAX_CUSTOM_ASSET_SIZE as usize + 1));
+        let assets = CustomAssets::load(
+            Some(PathBuf::from("/nonexistent/custom.css")),
+            Some(big_path),
+        )
+        .await;
+        assert!(assets.is_enabled());
+        assert_eq!(assets.head().into_string(), "");
+        let router = with_custom_assets(Router::new(), assets);
+        let response = get_asset(router, "/custom.js", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_does_nothing_without_flags() {
+        let assets = CustomAssets::load(None, None).await;
+        assert!(!assets.is_enabled());
+        let router = with_custom_assets(Router::new(), assets);
+        let response = get_asset(router, "/custom.css", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_honors_etags() {
+        let js_path = temp_file("etag.js", "console.log('hi');");
+        let router =
+            with_custom_assets(Router::new(), CustomAssets::load(None, Some(js_path)).await);
+        let response = get_asset(router.clone(), "/custom.js", None).await;
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        let etag = response.headers()[ETAG].to_str().unwrap().to_owned();
+        let response = get_asset(router.clone(), "/custom.js", Some(&etag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let response = get_asset(router, "/custom.js", Some("\"stale\"")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_reloads_on_sighup() {
+        let css_path = temp_file("sighup.css", "body { color: red; }");
+        let assets = CustomAssets::load(Some(css_path.clone()), None).await;
+        let before = assets.head().into_string();
+        assets
+            .spawn_reload_on_sighup(&TaskRegistry::default())
+            .unwrap();
+        std::fs::write(&css_path, "body { color: blue; }").unwrap();
+        Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        for _ in 0..50 {
+            if assets.head().into_string() != before {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert_ne!(assets.head().into_string(), before);
+        assert_eq!(
+            assets.css.read().unwrap().as_ref().unwrap().contents,
+            "body { color: blue; }"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Which sites may frame the embeddable pages, from `--embed-origin`. Framing is denied unless an origin is listed.
+
+use axum::http::{
+    header::{CONTENT_SECURITY_POLICY, X_FRAME_OPTIONS},
+    HeaderMap, HeaderValue,
+};
+
+/// Origins allowed to frame embeddable pages, such as `https://example.com`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EmbedOrigins(Vec<String>);
+
+/// Parses an origin for `--embed-origin`, as an `http` or `https` scheme and a host, with an optional port and
+/// leading `*.` wildcard.
+pub fn parse_embed_origin(value: &str) -> Result<String, String> {
+    let value = value.trim().trim_end_matches('/');
+    let host = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"))
+        .ok_or_else(|| String::from("expected an http:// or https:// origin"))?;
+    let host = host.strip_prefix("*.").unwrap_or(host);
+    let (name, port) = host.split_once(':').unwrap_or((host, ""));
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return Err(String::from("expected a host name without a path"));
+    }
+    if !port.is_empty() && port.parse::<u16>().is_err() {
+        return Err(String::from("invalid port"));
+    }
+    Ok(value.to_ascii_lowercase())
+}
+
+impl EmbedOrigins {
+    pub fn new(origins: Vec<String>) -> Self {
+        let mut origins = origins;
+        origins.sort();
+        origins.dedup();
+        EmbedOrigins(origins)
+    }
+
+    /// Headers for an embeddable page, so that only the listed origins may frame it.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if self.0.is_empty() {
+            headers.insert(
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("frame-ancestors 'none'"),
+            );
+            // For browsers without CSP support.
+            headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        } else {
+            let policy = format!("frame-ancestors {}", self.0.join(" "));
+            // Origins were validated when parsed.
+            headers.insert(
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(&policy).unwrap(),
+            );
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_origins() {
+        assert_eq!(
+            parse_embed_origin("https://Example.com/"),
+            Ok(String::from("https://example.com"))
+        );
+        assert_eq!(
+            parse_embed_origin("http://localhost:8080"),
+            Ok(String::from("http://localhost:8080"))
+        );
+        assert_eq!(
+            parse_embed_origin("https://*.example.com"),
+            Ok(String::from("https://*.example.com"))
+        );
+        assert!(parse_embed_origin("example.com").is_err());
+        assert!(parse_embed_origin("https://example.com/page").is_err());
+        assert!(parse_embed_origin("https://example.com:http").is_err());
+        assert!(parse_embed_origin("https://").is_err());
+        assert!(parse_embed_origin("https://a.com 'unsafe-inline'").is_err());
+    }
+
+    #[test]
+    fn it_denies_framing_by_default() {
+        let headers = EmbedOrigins::default().headers();
+        assert_eq!(headers[CONTENT_SECURITY_POLICY], "frame-ancestors 'none'");
+        assert_eq!(headers[X_FRAME_OPTIONS], "DENY");
+
+        let headers = EmbedOrigins::new(vec![
+            String::from("https://b.example"),
+            String::from("https://a.example"),
+            String::from("https://b.example"),
+        ])
+        .headers();
+        assert_eq!(
+            headers[CONTENT_SECURITY_POLICY],
+            "frame-ancestors https://a.example https://b.example"
+        );
+        assert!(!headers.contains_key(X_FRAME_OPTIONS));
+    }
+}
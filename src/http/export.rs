@@ -0,0 +1,145 @@
+//! Streamed responses for heavy exports, so that they are never built fully in memory, and a cap on how many of
+//! them run at once.
+
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::debug;
+
+/// How many exports may stream at once by default.
+pub const MAX_CONCURRENT_EXPORTS: usize = 2;
+
+/// What to send in `Retry-After` when every export slot is taken, in seconds.
+pub const EXPORT_RETRY_AFTER_SECS: u64 = 5;
+
+/// Size of the chunks sent to the client.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many chunks may wait for a slow client before the worker blocks.
+const BUFFERED_CHUNKS: usize = 4;
+
+/// Limits how many exports are streaming at once.
+#[derive(Clone, Debug)]
+pub struct ExportLimiter(Arc<Semaphore>);
+
+/// A slot from [`ExportLimiter`], held until its export is done streaming.
+#[derive(Debug)]
+pub struct ExportPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Default for ExportLimiter {
+    fn default() -> Self {
+        ExportLimiter::new(MAX_CONCURRENT_EXPORTS)
+    }
+}
+
+impl ExportLimiter {
+    pub fn new(max_concurrent: usize) -> Self {
+        ExportLimiter(Arc::new(Semaphore::new(max_concurrent)))
+    }
+
+    /// Takes a slot, unless every one is taken.
+    pub fn try_start(&self) -> Option<ExportPermit> {
+        Arc::clone(&self.0)
+            .try_acquire_owned()
+            .ok()
+            .map(|permit| ExportPermit { _permit: permit })
+    }
+}
+
+/// Sends whatever is written into it to a response body, one chunk at a time.
+struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client went away."))
+    }
+}
+
+/// Streams whatever `write` writes from a blocking worker, holding `permit` until it's done or the client goes
+/// away.
+pub fn stream_blocking<F>(permit: ExportPermit, content_type: &'static str, write: F) -> Response
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let mut writer = ChannelWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        if let Err(err) = write(&mut writer).and_then(|()| writer.flush()) {
+            debug!(?err, "Export stopped early.");
+            let _ = sender.blocking_send(Err(err));
+        }
+    });
+    (
+        [(CONTENT_TYPE, content_type)],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_streams_in_chunks() {
+        let limiter = ExportLimiter::new(1);
+        let response = stream_blocking(limiter.try_start().unwrap(), "text/plain", |writer| {
+            for _ in 0..CHUNK_SIZE {
+                writer.write_all(b"ab")?;
+            }
+            Ok(())
+        });
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 2 * CHUNK_SIZE);
+        assert!(body.starts_with(b"abab"));
+        // The worker let go of its slot before the end of the body.
+        assert!(limiter.try_start().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_caps_concurrent_exports() {
+        let limiter = ExportLimiter::new(2);
+        let first = limiter.try_start().unwrap();
+        let _second = limiter.try_start().unwrap();
+        assert!(limiter.try_start().is_none());
+        drop(first);
+        assert!(limiter.try_start().is_some());
+    }
+}
@@ -0,0 +1,187 @@
+//! Form extractors that log malformed payloads, instead of rejecting them without a trace.
+//!
+//! Payloads built by `hx-vals` expressions may be garbage before the page script is ready (eg. `id: NaN`). Use
+//! [`StrictForm`] where the client should hear about it, and [`LenientForm`] where a bad payload should just be
+//! skipped, such as frequent background requests.
+
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, Mutex},
+};
+
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{rejection::FormRejection, FromRequest, MatchedPath, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Form,
+};
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+/// Largest part of a malformed body that gets logged.
+const MAX_LOGGED_BODY: usize = 256;
+
+/// How many malformed form payloads each route received.
+static REJECTED_FORMS: LazyLock<Mutex<BTreeMap<String, u64>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Counts of malformed form payloads by route, for `/metrics`.
+pub fn rejected_forms() -> Vec<(String, u64)> {
+    REJECTED_FORMS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(route, count)| (route.clone(), *count))
+        .collect()
+}
+
+/// Parses the form, logging and counting it if it's malformed.
+async fn parse<T, S>(request: Request, state: &S) -> Result<T, FormRejection>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| String::from(path.as_str()))
+        .unwrap_or_else(|| String::from(request.uri().path()));
+    let (parts, body) = request.into_parts();
+    let bytes = Bytes::from_request(Request::from_parts(parts.clone(), body), state)
+        .await
+        .unwrap_or_default();
+    let request = Request::from_parts(parts, Body::from(bytes.clone()));
+    match Form::<T>::from_request(request, state).await {
+        Ok(Form(value)) => Ok(value),
+        Err(rejection) => {
+            debug!(
+                route,
+                body = %String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_LOGGED_BODY)]),
+                len = bytes.len(),
+                error = rejection.body_text(),
+                "Malformed form payload."
+            );
+            *REJECTED_FORMS.lock().unwrap().entry(route).or_default() += 1;
+            Err(rejection)
+        }
+    }
+}
+
+/// [`Form`] which rejects malformed payloads like it does, but logs them first.
+#[derive(Debug)]
+pub struct StrictForm<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for StrictForm<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = FormRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        parse(request, state).await.map(StrictForm)
+    }
+}
+
+/// [`Form`] which answers malformed payloads with an empty 204 No Content, after logging them, so that the page
+/// leaves things as they were.
+#[derive(Debug)]
+pub struct LenientForm<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for LenientForm<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        parse(request, state)
+            .await
+            .map(LenientForm)
+            .map_err(|_| StatusCode::NO_CONTENT.into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::header::CONTENT_TYPE, routing::post, Router};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize)]
+    struct Payload {
+        id: u64,
+    }
+
+    async fn send(uri: &str, body: &'static str) -> (StatusCode, String) {
+        let router =
+            Router::new()
+                .route(
+                    "/strict",
+                    post(|StrictForm(payload): StrictForm<Payload>| async move {
+                        payload.id.to_string()
+                    }),
+                )
+                .route(
+                    "/lenient",
+                    post(|LenientForm(payload): LenientForm<Payload>| async move {
+                        payload.id.to_string()
+                    }),
+                );
+        let response = router
+            .oneshot(
+                Request::post(uri)
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn rejections(route: &str) -> u64 {
+        rejected_forms()
+            .into_iter()
+            .find(|(name, _)| name == route)
+            .map_or(0, |(_, count)| count)
+    }
+
+    #[tokio::test]
+    async fn it_applies_each_policy() {
+        assert_eq!(
+            send("/strict", "id=4").await,
+            (StatusCode::OK, String::from("4"))
+        );
+        assert_eq!(
+            send("/lenient", "id=5").await,
+            (StatusCode::OK, String::from("5"))
+        );
+        assert_eq!(rejections("/strict"), 0);
+
+        let (status, body) = send("/strict", "id=NaN").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains("id"));
+        assert_eq!(rejections("/strict"), 1);
+
+        assert_eq!(
+            send("/lenient", "id=NaN").await,
+            (StatusCode::NO_CONTENT, String::new())
+        );
+        assert_eq!(
+            send("/lenient", "mouseX=1").await,
+            (StatusCode::NO_CONTENT, String::new())
+        );
+        assert_eq!(rejections("/lenient"), 2);
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, HeaderName, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::unwrap_infallible;
+
+/// Longest display name (in characters) that we accept from the identity header.
+pub const MAX_IDENTITY_LENGTH: usize = 64;
+
+/// Settings for adopting identities injected by a tunnel frontend (sish with auth, Cloudflare Access, oauth2-proxy...).
+#[derive(Clone, Debug, Default)]
+pub struct IdentityConfig {
+    /// Header containing the player's name, such as `X-Forwarded-User`. Identities are disabled when unset.
+    pub header: Option<HeaderName>,
+    /// Names which are allowed to perform administrative actions.
+    pub admin_users: Vec<String>,
+    /// Reject requests lacking a valid identity header with 401, instead of treating them as anonymous.
+    pub require_identity: bool,
+}
+
+/// Who is making a request, as told by the tunnel frontend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Identity {
+    Anonymous,
+    Named { name: String, is_admin: bool },
+}
+
+impl Identity {
+    /// The display name of this player, if any.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Identity::Anonymous => None,
+            Identity::Named { name, .. } => Some(name),
+        }
+    }
+
+    /// Whether this player has been listed in `--admin-users`.
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Identity::Named { is_admin: true, .. })
+    }
+
+    /// A stable ID derived from the player's name, to be used instead of the anonymous client-generated IDs.
+    ///
+    /// It's the start of the name's SHA-256, so that it stays the same across builds, unlike `DefaultHasher`.
+    pub fn stable_id(&self) -> Option<u64> {
+        self.name().map(|name| {
+            let digest = Sha256::digest(name.as_bytes());
+            u64::from_be_bytes(digest[..8].try_into().unwrap())
+        })
+    }
+}
+
+/// Handlers can always extract an `Identity`; it defaults to anonymous when the identity layer isn't installed.
+#[async_trait]
+impl<S> FromRequestParts<S> for Identity
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Identity>()
+            .cloned()
+            .unwrap_or(Identity::Anonymous))
+    }
+}
+
+/// The name of an operator listed in `--admin-users`. Extracting it rejects everyone else with 403 Forbidden.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Admin(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let identity = unwrap_infallible(Identity::from_request_parts(parts, state).await);
+        match identity {
+            Identity::Named {
+                name,
+                is_admin: true,
+            } => Ok(Admin(name)),
+            _ => Err(StatusCode::FORBIDDEN),
+        }
+    }
+}
+
+/// Wraps the router with the identity middleware. This is a no-op if no identity header has been configured.
+pub fn with_identity(router: Router, config: IdentityConfig) -> Router {
+    if config.header.is_none() {
+        return router;
+    }
+    router.layer(middleware::from_fn_with_state(
+        Arc::new(config),
+        identity_middleware,
+    ))
+}
+
+async fn identity_middleware(
+    State(config): State<Arc<IdentityConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let identity = config
+        .header
+        .as_ref()
+        .and_then(|header| request.headers().get(header))
+        .and_then(|value| match value.to_str() {
+            Ok(value) => validate_name(value),
+            Err(_) => None,
+        })
+        .map(|name| Identity::Named {
+            is_admin: config.admin_users.iter().any(|admin| admin == name),
+            name: String::from(name),
+        });
+    let identity = match identity {
+        Some(identity) => identity,
+        None if config.require_identity => {
+            debug!("Rejecting request without a valid identity header.");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        None => Identity::Anonymous,
+    };
+    request.extensions_mut().insert(identity);
+    next.run(request).await
+}
+
+/// Returns the trimmed name if it's non-empty, short enough, and free of control characters.
+fn validate_name(value: &str) -> Option<&str> {
+    let name = value.trim();
+    if name.is_empty()
+        || name.chars().count() > MAX_IDENTITY_LENGTH
+        || name.chars().any(char::is_control)
+    {
+        debug!(len = value.len(), "Invalid identity header value.");
+        return None;
+    }
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use tower::ServiceExt;
+
+    async fn whoami(identity: Identity) -> String {
+        match identity {
+            Identity::Anonymous => String::from("anonymous"),
+            Identity::Named { name, is_admin } => format!("{name} admin={is_admin}"),
+        }
+    }
+
+    fn router(require_identity: bool) -> Router {
+        with_identity(
+            Router::new().route("/", get(whoami)),
+            IdentityConfig {
+                header: Some(HeaderName::from_static("x-forwarded-user")),
+                admin_users: vec![String::from("operator")],
+                require_identity,
+            },
+        )
+    }
+
+    async fn request(router: Router, user: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::builder().uri("/");
+        if let Some(user) = user {
+            request = request.header("X-Forwarded-User", user);
+        }
+        let response = router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn it_adopts_the_header_identity() {
+        let (status, body) = request(router(false), Some("  alice ")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "alice admin=false");
+        let (_, body) = request(router(false), Some("operator")).await;
+        assert_eq!(body, "operator admin=true");
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_anonymous() {
+        let (status, body) = request(router(false), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "anonymous");
+        let long_name = "a".repeat(MAX_IDENTITY_LENGTH + 1);
+        let (status, body) = request(router(false), Some(&long_name)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "anonymous");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_missing_identity_when_required() {
+        let (status, _) = request(router(true), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = request(router(true), Some("   ")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = request(router(true), Some("bob")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "bob admin=false");
+    }
+
+    #[tokio::test]
+    async fn it_only_lets_admins_through() {
+        async fn admin_only(Admin(name): Admin) -> String {
+            name
+        }
+        let router = |require_identity| {
+            with_identity(
+                Router::new().route("/", get(admin_only)),
+                IdentityConfig {
+                    header: Some(HeaderName::from_static("x-forwarded-user")),
+                    admin_users: vec![String::from("operator")],
+                    require_identity,
+                },
+            )
+        };
+        let (status, body) = request(router(false), Some("operator")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "operator");
+        let (status, _) = request(router(false), Some("alice")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = request(router(false), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn it_derives_stable_ids_from_names() {
+        let alice = Identity::Named {
+            name: String::from("alice"),
+            is_admin: false,
+        };
+        assert_eq!(alice.stable_id(), Some(0x2bd8_06c9_7f0e_00af));
+        assert_ne!(
+            alice.stable_id(),
+            Identity::Named {
+                name: String::from("bob"),
+                is_admin: false
+            }
+            .stable_id()
+        );
+        assert_eq!(Identity::Anonymous.stable_id(), None);
+    }
+}
@@ -0,0 +1,217 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::header::CACHE_CONTROL,
+    response::Redirect,
+    routing::get,
+    Extension, Router,
+};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use tower::ServiceExt;
+use tracing::warn;
+
+use super::{
+    alert::{self, AlertBanner},
+    custom_assets::CustomAssets,
+    identity::Identity,
+    tunnel_status,
+};
+use crate::tunnel::TunnelStatusCell;
+
+/// How long browsers may reuse an activity's `/summary` fragment.
+pub const SUMMARY_CACHE_CONTROL: (axum::http::HeaderName, &str) = (CACHE_CONTROL, "max-age=5");
+
+/// Largest summary fragment that gets inlined into the landing page.
+const MAX_SUMMARY_SIZE: usize = 16 * 1024;
+
+/// An activity router, along with what the landing page needs to know about it.
+pub struct Activity {
+    /// Path segment that the activity is mounted under, when there are several.
+    pub slug: &'static str,
+    pub title: &'static str,
+    /// Must serve the activity's page at `/`, and a short fragment at `/summary`.
+    pub router: Router,
+}
+
+/// Where an activity is mounted, such as `/multipaint/`. Pages use it as their `<base>`, so that every URL in them
+/// must be relative.
+#[derive(Clone, Debug)]
+pub struct MountPath(pub String);
+
+/// The `href` for an activity page's `<base>` element.
+pub fn base_href(mount: Option<&Extension<MountPath>>) -> &str {
+    mount.map_or("/", |Extension(MountPath(path))| path.as_str())
+}
+
+/// Serves a single activity at `/` as-is. Several activities are nested under their slugs instead, with a landing
+/// page at `/` linking to all of them.
+pub fn mount(mut activities: Vec<Activity>) -> Router {
+    if activities.len() == 1 {
+        return activities.pop().unwrap().router;
+    }
+    let cards = activities
+        .iter()
+        .map(|activity| (activity.slug, activity.title, activity.router.clone()))
+        .collect::<Vec<_>>();
+    activities.into_iter().fold(
+        Router::new()
+            .route("/", get(landing))
+            .with_state(cards.into()),
+        |router, activity| {
+            let path = format!("/{}", activity.slug);
+            let mount_path = MountPath(format!("{path}/"));
+            router
+                .nest(&path, activity.router.layer(Extension(mount_path)))
+                .route(&format!("{path}/"), get(Redirect::permanent(&path)))
+        },
+    )
+}
+
+type Cards = std::sync::Arc<[(&'static str, &'static str, Router)]>;
+
+/// Fetches an activity's summary fragment, so that the landing page is useful before htmx loads.
+async fn summary(router: Router) -> Markup {
+    let request = Request::get("/summary").body(Body::empty()).unwrap();
+    let response = match router.oneshot(request).await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            warn!(status = %response.status(), "Unable to render activity summary.");
+            return html! {};
+        }
+        Err(e) => match e {},
+    };
+    match axum::body::to_bytes(response.into_body(), MAX_SUMMARY_SIZE).await {
+        Ok(body) => PreEscaped(String::from_utf8_lossy(&body).into_owned()),
+        Err(e) => {
+            warn!(error = ?e, "Unable to read activity summary.");
+            html! {}
+        }
+    }
+}
+
+async fn landing(
+    State(cards): State<Cards>,
+    custom_assets: Option<Extension<CustomAssets>>,
+    tunnel: Option<Extension<TunnelStatusCell>>,
+    alert: Option<Extension<AlertBanner>>,
+    identity: Identity,
+) -> Markup {
+    let tunnel = tunnel.as_ref().map(|Extension(status)| status);
+    let mut summaries = Vec::with_capacity(cards.len());
+    for (_, _, router) in cards.iter() {
+        summaries.push(summary(router.clone()).await);
+    }
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            title { "htmx SSH games" }
+            script src="https://unpkg.com/htmx.org@2.0.2" integrity="sha384-Y7hw+L/jvKeWIRRkqWYfPcvVxHzVzn5REgzbawhxAuQGwX1XWe70vji+VSeHOThJ" crossorigin="anonymous" {}
+            style { (PreEscaped(STYLE)) }
+            style { (PreEscaped(alert::STYLE)) }
+            @if let Some(Extension(custom_assets)) = custom_assets {
+                (custom_assets.head())
+            }
+        }
+        body {
+            (alert::banner(alert.as_ref().map(|Extension(alert)| alert)))
+            (tunnel_status::banner(tunnel))
+            h1 { "htmx SSH games" }
+            main .activities {
+                @for ((slug, title, _), summary) in cards.iter().zip(summaries) {
+                    section .activity-card {
+                        h2 {
+                            a href=(format!("/{slug}")) { (title) }
+                        }
+                        div hx-get=(format!("/{slug}/summary")) hx-trigger="every 5s" {
+                            (summary)
+                        }
+                    }
+                }
+            }
+            (tunnel_status::operator_footer(tunnel, &identity))
+        }
+    }
+}
+
+static STYLE: &str = r#"
+.activities {
+    display: flex;
+    flex-wrap: wrap;
+    gap: 16px;
+}
+.activity-card {
+    padding: 12px 16px;
+    border: 1pt solid #888;
+    border-radius: 6px;
+    min-width: 240px;
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    fn mock_activity(slug: &'static str, title: &'static str, summary: &'static str) -> Activity {
+        Activity {
+            slug,
+            title,
+            router: Router::new()
+                .route(
+                    "/",
+                    get(|mount: Option<Extension<MountPath>>| async move {
+                        String::from(base_href(mount.as_ref()))
+                    }),
+                )
+                .route("/summary", get(move || async move { summary })),
+        }
+    }
+
+    async fn get_body(router: &Router, uri: &str) -> (StatusCode, String) {
+        let response = router
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn it_lists_every_activity_with_its_summary() {
+        let router = mount(vec![
+            mock_activity("checkboxes", "400 Checkboxes", "<p>12 checked</p>"),
+            mock_activity(
+                "multipaint",
+                "Multipaint by Numbers",
+                "<p>Puzzle: Heart</p>",
+            ),
+        ]);
+        let (status, body) = get_body(&router, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"<a href="/checkboxes">400 Checkboxes</a>"#));
+        assert!(body.contains(r#"<a href="/multipaint">Multipaint by Numbers</a>"#));
+        assert!(body.contains("<p>12 checked</p>"));
+        assert!(body.contains("<p>Puzzle: Heart</p>"));
+        assert!(body.contains(r#"hx-get="/multipaint/summary""#));
+        let (_, body) = get_body(&router, "/multipaint").await;
+        assert_eq!(body, "/multipaint/");
+        let (status, _) = get_body(&router, "/multipaint/").await;
+        assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
+        let (_, body) = get_body(&router, "/checkboxes/summary").await;
+        assert_eq!(body, "<p>12 checked</p>");
+    }
+
+    #[tokio::test]
+    async fn a_single_activity_stays_at_the_root() {
+        let router = mount(vec![mock_activity("checkboxes", "400 Checkboxes", "")]);
+        let (_, body) = get_body(&router, "/").await;
+        assert_eq!(body, "/");
+        let (status, _) = get_body(&router, "/checkboxes").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,279 @@
+//! A switch that freezes every board without stopping the process, such as before a risky migration.
+
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Router,
+};
+use maud::{html, Markup};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::{
+    http::{
+        audit::{self, Audited},
+        form::StrictForm,
+    },
+    storage::DataDir,
+};
+
+/// Data dir file with whether maintenance mode is on, so that it survives restarts.
+pub const MAINTENANCE_FILE: &str = "maintenance.json";
+
+/// What players are told while the boards are frozen.
+pub const NOTICE: &str = "The board is frozen for maintenance. Please try again later.";
+
+/// Called with the new state whenever maintenance mode is switched.
+pub type MaintenanceHook = Box<dyn Fn(bool) + Send + Sync>;
+
+/// Whether maintenance mode is on, along with whoever needs to know when it changes. Shared by every clone.
+#[derive(Clone)]
+pub struct MaintenanceMode {
+    enabled: Arc<watch::Sender<bool>>,
+    hooks: Arc<Mutex<Vec<MaintenanceHook>>>,
+    data_dir: Option<DataDir>,
+}
+
+impl Default for MaintenanceMode {
+    fn default() -> Self {
+        MaintenanceMode {
+            enabled: Arc::new(watch::Sender::new(false)),
+            hooks: Arc::default(),
+            data_dir: None,
+        }
+    }
+}
+
+impl MaintenanceMode {
+    /// Restores the mode from the data dir if there is one, and persists every later change to it.
+    pub async fn load(data_dir: Option<DataDir>) -> Self {
+        let mut mode = MaintenanceMode::default();
+        let Some(data_dir) = data_dir else {
+            return mode;
+        };
+        match data_dir.read_json::<bool>(MAINTENANCE_FILE).await {
+            Ok(Some(enabled)) => {
+                if enabled {
+                    warn!("Maintenance mode is still on from the previous run.");
+                }
+                mode.enabled.send_replace(enabled);
+            }
+            Ok(None) => (),
+            Err(e) => warn!(error = ?e, "Unable to load the maintenance mode."),
+        }
+        mode.data_dir = Some(data_dir);
+        mode
+    }
+
+    pub fn is_on(&self) -> bool {
+        *self.enabled.borrow()
+    }
+
+    /// Calls `hook` on every later switch. Hooks run while switching, so they must not switch the mode themselves.
+    pub fn on_change(&self, hook: MaintenanceHook) {
+        self.hooks.lock().unwrap().push(hook);
+    }
+
+    /// Switches maintenance mode on or off, and tells the hooks about it. Returns whether anything changed.
+    pub async fn set(&self, enabled: bool) -> bool {
+        if !self
+            .enabled
+            .send_if_modified(|current| mem::replace(current, enabled) != enabled)
+        {
+            return false;
+        }
+        for hook in self.hooks.lock().unwrap().iter() {
+            hook(enabled);
+        }
+        if let Some(data_dir) = &self.data_dir {
+            if let Err(e) = data_dir.write_json(MAINTENANCE_FILE, &enabled).await {
+                warn!(error = ?e, "Unable to persist the maintenance mode.");
+            }
+        }
+        true
+    }
+
+    /// Returns right away if maintenance mode is off, or as soon as it's switched off.
+    pub async fn wait_until_off(&self) {
+        let _ = self.enabled.subscribe().wait_for(|enabled| !enabled).await;
+    }
+}
+
+/// Answers a change to the board while it's frozen, with the cell as it was.
+pub fn rejection(cell: Markup) -> Response {
+    (
+        StatusCode::CONFLICT,
+        html! {
+            (cell)
+            p .maintenance-notice role="status" { (NOTICE) }
+        },
+    )
+        .into_response()
+}
+
+pub static STYLE: &str = r#"
+.maintenance-overlay {
+    position: fixed;
+    inset: 0;
+    z-index: 100;
+    display: flex;
+    align-items: center;
+    justify-content: center;
+    background-color: rgba(255, 255, 255, 0.8);
+    font-size: 1.5em;
+}
+"#;
+
+/// Covers the page while maintenance mode is on. Empty if there's no maintenance mode to check.
+pub fn overlay(mode: Option<&MaintenanceMode>) -> Markup {
+    render(mode, false)
+}
+
+/// Same as [`overlay`], but swapped out of band by poll responses.
+pub fn overlay_update(mode: Option<&MaintenanceMode>) -> Markup {
+    render(mode, true)
+}
+
+fn render(mode: Option<&MaintenanceMode>, out_of_band: bool) -> Markup {
+    let Some(mode) = mode else {
+        return html! {};
+    };
+    html! {
+        #maintenance-overlay hx-swap-oob=[out_of_band.then_some("true")] {
+            @if mode.is_on() {
+                .maintenance-overlay role="status" { p { (NOTICE) } }
+            }
+        }
+    }
+}
+
+/// Adds the operator-only `POST /admin/maintenance`, and makes the mode available to the pages and to `/status`.
+pub fn with_maintenance_mode(router: Router, mode: MaintenanceMode) -> Router {
+    router
+        .merge(
+            Router::new()
+                .route("/admin/maintenance", post(switch))
+                .with_state(mode.clone()),
+        )
+        .layer(Extension(mode))
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Deserialize, Debug)]
+struct SwitchPayload {
+    mode: Switch,
+}
+
+async fn switch(
+    audit: Audited,
+    State(mode): State<MaintenanceMode>,
+    StrictForm(payload): StrictForm<SwitchPayload>,
+) -> Response {
+    let enabled = payload.mode == Switch::On;
+    let parameters = json!({ "mode": if enabled { "on" } else { "off" } });
+    if let Err(response) = audit.record("maintenance", parameters, audit::OK).await {
+        return response;
+    }
+    mode.set(enabled).await;
+    html! {
+        p { "Maintenance mode is " (if enabled { "on" } else { "off" }) "." }
+    }
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{body::Body, extract::Request, http::header::CONTENT_TYPE};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::identity::Identity;
+
+    async fn send(mode: &MaintenanceMode, body: &str, admin: bool) -> StatusCode {
+        let mut request = Request::post("/admin/maintenance")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(String::from(body)))
+            .unwrap();
+        if admin {
+            request.extensions_mut().insert(Identity::Named {
+                name: String::from("operator"),
+                is_admin: true,
+            });
+        }
+        with_maintenance_mode(Router::new(), mode.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn operators_switch_it_and_hooks_follow() {
+        let mode = MaintenanceMode::default();
+        let switches = Arc::new(AtomicUsize::new(0));
+        mode.on_change(Box::new({
+            let switches = Arc::clone(&switches);
+            move |_| {
+                switches.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+        assert_eq!(send(&mode, "mode=on", false).await, StatusCode::FORBIDDEN);
+        assert!(!mode.is_on());
+        assert_eq!(
+            send(&mode, "mode=sideways", true).await,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        assert_eq!(send(&mode, "mode=on", true).await, StatusCode::OK);
+        assert!(mode.is_on());
+        assert!(overlay(Some(&mode)).into_string().contains(NOTICE));
+        // Switching to the same mode again doesn't bother the hooks.
+        send(&mode, "mode=on", true).await;
+        assert_eq!(switches.load(Ordering::SeqCst), 1);
+
+        let waiter = tokio::spawn({
+            let mode = mode.clone();
+            async move { mode.wait_until_off().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        send(&mode, "mode=off", true).await;
+        waiter.await.unwrap();
+        assert_eq!(switches.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            overlay_update(Some(&mode)).into_string(),
+            r#"<div id="maintenance-overlay" hx-swap-oob="true"></div>"#
+        );
+        assert_eq!(overlay(None).into_string(), "");
+    }
+
+    #[tokio::test]
+    async fn it_survives_restarts() {
+        let path = std::env::temp_dir().join(format!("{}-maintenance", std::process::id()));
+        let data_dir = DataDir::open(path.clone()).await.unwrap();
+        let mode = MaintenanceMode::load(Some(data_dir.clone())).await;
+        assert!(!mode.is_on());
+        mode.set(true).await;
+        assert!(MaintenanceMode::load(Some(data_dir.clone())).await.is_on());
+        mode.set(false).await;
+        assert!(!MaintenanceMode::load(Some(data_dir)).await.is_on());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+use maud::{html, Markup, DOCTYPE};
+
+use crate::{
+    accounting::{HumanBytes, MemoryAccounting},
+    http::{form::rejected_forms, identity::Admin, shedding::LoadShedder},
+    nonogram::funnel::PuzzleFunnel,
+    supervisor::task_panics,
+    tunnel::TunnelStatusCell,
+};
+
+/// Renders a section of the `/admin/status` page, as label and value pairs.
+pub type StatusFn = Box<dyn Fn() -> Vec<(&'static str, String)> + Send + Sync>;
+
+/// Extra sections of the `/admin/status` page, which activities can register to show their own state.
+#[derive(Clone, Default)]
+pub struct StatusSections(Arc<Mutex<BTreeMap<String, StatusFn>>>);
+
+impl StatusSections {
+    /// Adds a section under the given heading, replacing any previous section with the same heading.
+    pub fn register(&self, heading: &str, section: StatusFn) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(String::from(heading), section);
+    }
+
+    fn render(&self) -> Vec<(String, Vec<(&'static str, String)>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(heading, section)| (heading.clone(), section()))
+            .collect()
+    }
+}
+
+/// Adds `/metrics` (Prometheus text format) and the operator-only `/admin/status` page.
+///
+/// Puzzle funnel counters are included if a [`PuzzleFunnel`] extension is layered over the router, and tunnel traffic
+/// counters if a [`TunnelStatusCell`] is.
+pub fn with_memory_metrics(
+    router: Router,
+    accounting: MemoryAccounting,
+    sections: StatusSections,
+) -> Router {
+    router.merge(
+        Router::new()
+            .route("/metrics", get(metrics))
+            .route("/admin/status", get(status_page))
+            .with_state((accounting, sections)),
+    )
+}
+
+async fn metrics(
+    State((accounting, _)): State<(MemoryAccounting, StatusSections)>,
+    shedder: LoadShedder,
+    funnel: Option<Extension<PuzzleFunnel>>,
+    tunnel: Option<Extension<TunnelStatusCell>>,
+) -> Response {
+    let mut output = accounting.prometheus();
+    output.push_str(
+        "# HELP htmx_ssh_games_rejected_forms_total Malformed form payloads received by each route.\n\
+         # TYPE htmx_ssh_games_rejected_forms_total counter\n",
+    );
+    for (route, count) in rejected_forms() {
+        output.push_str(&format!(
+            "htmx_ssh_games_rejected_forms_total{{route=\"{route}\"}} {count}\n"
+        ));
+    }
+    output.push_str(&format!(
+        "# HELP htmx_ssh_games_load_shedding Whether optional work is being shed.\n\
+         # TYPE htmx_ssh_games_load_shedding gauge\n\
+         htmx_ssh_games_load_shedding {}\n\
+         # HELP htmx_ssh_games_shed_requests_total Requests answered cheaply while shedding load.\n\
+         # TYPE htmx_ssh_games_shed_requests_total counter\n\
+         htmx_ssh_games_shed_requests_total {}\n\
+         # HELP htmx_ssh_games_route_latency_seconds Moving average of each route's latency.\n\
+         # TYPE htmx_ssh_games_route_latency_seconds gauge\n",
+        u8::from(shedder.is_shedding()),
+        shedder.shed_total()
+    ));
+    for (route, latency) in shedder.averages() {
+        output.push_str(&format!(
+            "htmx_ssh_games_route_latency_seconds{{route=\"{route}\"}} {:.6}\n",
+            latency.as_secs_f64()
+        ));
+    }
+    output.push_str(
+        "# HELP htmx_ssh_games_task_panics_total Background tasks that panicked, and were restarted unless they \
+         panicked too often.\n\
+         # TYPE htmx_ssh_games_task_panics_total counter\n",
+    );
+    for (task, count) in task_panics() {
+        output.push_str(&format!(
+            "htmx_ssh_games_task_panics_total{{task=\"{task}\"}} {count}\n"
+        ));
+    }
+    if let Some(Extension(funnel)) = funnel {
+        output.push_str(&funnel.prometheus());
+    }
+    if let Some(traffic) = tunnel.and_then(|Extension(tunnel)| tunnel.get().traffic) {
+        output.push_str(&format!(
+            "# HELP htmx_ssh_games_tunnel_connections_total Connections forwarded through the tunnel.\n\
+             # TYPE htmx_ssh_games_tunnel_connections_total counter\n\
+             htmx_ssh_games_tunnel_connections_total {}\n\
+             # HELP htmx_ssh_games_tunnel_requests_total HTTP requests served through the tunnel.\n\
+             # TYPE htmx_ssh_games_tunnel_requests_total counter\n\
+             htmx_ssh_games_tunnel_requests_total {}\n\
+             # HELP htmx_ssh_games_tunnel_bytes_total Bytes forwarded through the tunnel.\n\
+             # TYPE htmx_ssh_games_tunnel_bytes_total counter\n\
+             htmx_ssh_games_tunnel_bytes_total{{direction=\"received\"}} {}\n\
+             htmx_ssh_games_tunnel_bytes_total{{direction=\"sent\"}} {}\n",
+            traffic.connections, traffic.requests, traffic.bytes_received, traffic.bytes_sent
+        ));
+    }
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        output,
+    )
+        .into_response()
+}
+
+async fn status_page(
+    _admin: Admin,
+    State((accounting, sections)): State<(MemoryAccounting, StatusSections)>,
+) -> Markup {
+    let gauges = accounting.snapshot();
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            title { "Status" }
+        }
+        body {
+            h1 { "Memory usage" }
+            table {
+                thead {
+                    tr {
+                        th { "Subsystem" }
+                        th { "Size" }
+                        th { "Soft cap" }
+                        th { "Evictions" }
+                    }
+                }
+                tbody {
+                    @for gauge in gauges {
+                        tr {
+                            td { (gauge.name) }
+                            td { (HumanBytes(gauge.bytes)) }
+                            td {
+                                @match gauge.soft_cap {
+                                    Some(soft_cap) => (HumanBytes(soft_cap)),
+                                    None => "-",
+                                }
+                            }
+                            td { (gauge.evictions) }
+                        }
+                    }
+                }
+            }
+            @for (heading, rows) in sections.render() {
+                h2 { (heading) }
+                table {
+                    tbody {
+                        @for (label, value) in rows {
+                            tr {
+                                th { (label) }
+                                td { (value) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::identity::Identity;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    async fn get(accounting: &MemoryAccounting, request: Request) -> (StatusCode, String) {
+        let sections = StatusSections::default();
+        sections.register(
+            "Rotation",
+            Box::new(|| vec![("Puzzles left", String::from("3 of 5"))]),
+        );
+        let response = with_memory_metrics(Router::new(), accounting.clone(), sections)
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn it_exposes_gauges() {
+        let accounting = MemoryAccounting::default();
+        accounting.register("replays", None).set(18_000_000);
+        let (status, body) = get(
+            &accounting,
+            Request::get("/metrics").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("htmx_ssh_games_memory_bytes{subsystem=\"replays\"} 18000000"));
+
+        let (status, _) = get(
+            &accounting,
+            Request::get("/admin/status").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let mut request = Request::get("/admin/status").body(Body::empty()).unwrap();
+        request.extensions_mut().insert(Identity::Named {
+            name: String::from("operator"),
+            is_admin: true,
+        });
+        let (status, body) = get(&accounting, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<td>replays</td><td>18.0 MB</td>"));
+        assert!(body.contains("<h2>Rotation</h2>"));
+        assert!(body.contains("<th>Puzzles left</th><td>3 of 5</td>"));
+    }
+}
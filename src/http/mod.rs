@@ -2,8 +2,26 @@ use std::sync::OnceLock;
 
 use axum::Router;
 
+pub mod alert;
+pub mod api;
+pub mod audit;
 pub mod checkbox;
+pub mod custom_assets;
+pub mod embed;
+pub mod export;
+pub mod form;
+pub mod identity;
+pub mod landing;
+pub mod maintenance;
+pub mod metrics;
 pub mod multipaint_by_numbers;
+pub mod registry;
+pub mod secure_context;
+pub mod shedding;
+#[cfg(test)]
+pub mod snapshot;
+pub mod trigger;
+pub mod tunnel_status;
 
 /// A lazily-created Router, to be used by the SSH client tunnels or directly by the HTTP server.
 pub static ROUTER: OnceLock<Router> = OnceLock::new();
@@ -0,0 +1,519 @@
+use std::time::Duration;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{
+    history::{CellDiff, DiffError},
+    is_sanctioned, load_puzzle,
+    preview::{build_variants, render_variants},
+    recovery::cell_char,
+    replay::{reconstruct, ReplayEvent},
+    AppState, CursorId, SanctionKind,
+};
+use crate::{
+    format::{format_duration, DurationStyle},
+    http::{
+        api::ApiError,
+        audit::{self, Audited},
+        form::StrictForm,
+        identity::Admin,
+        landing::{base_href, MountPath},
+    },
+    nonogram::{image::DEFAULT_THRESHOLD, PuzzleSource},
+};
+
+/// How long a muted cursor stays muted.
+pub(super) const MUTE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// How long a kicked cursor ID stays rejected.
+const KICK_DURATION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+enum CursorAction {
+    Mute,
+    Unmute,
+    Kick,
+}
+
+/// Operator-only routes. Every handler extracts [`Admin`], so everyone else gets 403 Forbidden.
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/cursors", get(cursors_page))
+        .route("/admin/cursors/table", get(cursors_table))
+        .route("/admin/cursors/:id/:action", post(cursor_action))
+        .route("/admin/queue/:source/:id", post(queue_puzzle))
+        .route("/admin/diff", get(diff))
+        .route("/admin/replay", get(replay))
+        .route("/admin/puzzle", get(puzzle_page))
+        .route("/admin/puzzle/preview", post(preview_puzzle))
+        .route("/admin/puzzle/confirm", post(confirm_puzzle))
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct QueueParams {
+    /// Play the puzzle even if it was played recently.
+    #[serde(default)]
+    force: bool,
+}
+
+/// Queues a puzzle to be played after the current one.
+async fn queue_puzzle(
+    audit: Audited,
+    State(state): State<AppState>,
+    Path((source, id)): Path<(PuzzleSource, u32)>,
+    Query(params): Query<QueueParams>,
+) -> Result<Markup, Response> {
+    let parameters = json!({
+        "source": source,
+        "puzzle": id,
+        "force": params.force,
+    });
+    audit.record("queue_puzzle", parameters, audit::OK).await?;
+    let queued = state
+        .rotation
+        .lock()
+        .unwrap()
+        .enqueue((source, id), params.force);
+    Ok(html! {
+        @if queued {
+            "Queued " (source) " puzzle #" (id) "."
+        } @else {
+            (source) " puzzle #" (id) " is already queued."
+        }
+    })
+}
+
+/// Posts the picked file as the raw request body, since there's no multipart support on the server.
+static UPLOAD_SCRIPT: &str = r#"
+document.getElementById("puzzle-upload").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const form = event.target;
+  const params = new URLSearchParams({ threshold: form.threshold.value });
+  if (form.title.value) {
+    params.set("title", form.title.value);
+  }
+  const response = await fetch(`admin/puzzle/preview?${params}`, {
+    method: "POST",
+    body: form.image.files[0],
+  });
+  const result = document.getElementById("puzzle-upload-result");
+  result.innerHTML = await response.text();
+  htmx.process(result);
+});
+"#;
+
+async fn puzzle_page(_admin: Admin, mount: Option<Extension<MountPath>>) -> Markup {
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            base href=(base_href(mount.as_ref()));
+            title { "Upload a puzzle - Multipaint by Numbers" }
+            script src="htmx.js" {}
+        }
+        body {
+            h1 { "Upload a puzzle" }
+            form #puzzle-upload {
+                label { "Image " input type="file" name="image" accept="image/png, image/gif, image/bmp" required; }
+                label { "Title " input type="text" name="title"; }
+                label { "Threshold " input type="number" name="threshold" min="1" max="255" value=(DEFAULT_THRESHOLD); }
+                button { "Preview" }
+            }
+            #puzzle-upload-result {}
+            script { (PreEscaped(UPLOAD_SCRIPT)) }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct PreviewParams {
+    /// Pixels darker than this become filled cells.
+    threshold: Option<u8>,
+    title: Option<String>,
+}
+
+/// Converts an uploaded image at every preview size, without playing any of them yet.
+async fn preview_puzzle(
+    audit: Audited,
+    State(state): State<AppState>,
+    Query(params): Query<PreviewParams>,
+    body: Bytes,
+) -> Response {
+    let threshold = params.threshold.unwrap_or(DEFAULT_THRESHOLD);
+    let result = build_variants(&body, threshold, params.title.as_deref());
+    let parameters = json!({
+        "bytes": body.len(),
+        "threshold": threshold,
+        "title": params.title,
+    });
+    let outcome = match &result {
+        Ok(_) => String::from(audit::OK),
+        Err(e) => format!("Unable to use this image: {e}"),
+    };
+    if let Err(response) = audit.record("preview_puzzle", parameters, &outcome).await {
+        return response;
+    }
+    let variants = match result {
+        Ok(variants) => variants,
+        Err(e) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                html! { p { "Unable to use this image: " (e) } },
+            )
+                .into_response()
+        }
+    };
+    let now = state.clock.now();
+    let variants = variants
+        .into_iter()
+        .map(|variant| {
+            let token = variant
+                .puzzle
+                .clone()
+                .map(|puzzle| state.previews.insert(puzzle, now));
+            (variant, token)
+        })
+        .collect::<Vec<_>>();
+    render_variants(&variants).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct ConfirmPayload {
+    token: String,
+}
+
+/// Plays a previewed puzzle right away, replacing the current one.
+async fn confirm_puzzle(
+    audit: Audited,
+    State(state): State<AppState>,
+    StrictForm(payload): StrictForm<ConfirmPayload>,
+) -> Response {
+    let outcome = match state.previews.contains(&payload.token, state.clock.now()) {
+        true => audit::OK,
+        false => "expired",
+    };
+    let parameters = json!({ "token": payload.token });
+    if let Err(response) = audit.record("confirm_puzzle", parameters, outcome).await {
+        return response;
+    }
+    let Some(puzzle) = state.previews.take(&payload.token, state.clock.now()) else {
+        return (
+            StatusCode::GONE,
+            html! { p { "This preview expired. Upload the image again." } },
+        )
+            .into_response();
+    };
+    let (rows, columns) = (puzzle.rows.len(), puzzle.columns.len());
+    state.rotation.lock().unwrap().play_custom();
+    load_puzzle(&state, puzzle, None);
+    html! { "Now playing the " (columns) "x" (rows) " puzzle." }.into_response()
+}
+
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum DiffFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(Deserialize, Debug)]
+struct DiffParams {
+    from: u64,
+    to: u64,
+    #[serde(default)]
+    format: DiffFormat,
+}
+
+#[derive(Serialize)]
+struct DiffBody {
+    from: u64,
+    to: u64,
+    cells: Vec<DiffCell>,
+}
+
+#[derive(Serialize)]
+struct DiffCell {
+    row: usize,
+    column: usize,
+    before: &'static str,
+    after: &'static str,
+    /// Cursor ID of whoever changed the cell last, if known.
+    session: Option<u64>,
+}
+
+/// Shows which cells of the current puzzle changed between two revisions, and who changed them.
+async fn diff(
+    _admin: Admin,
+    State(state): State<AppState>,
+    Query(params): Query<DiffParams>,
+) -> Response {
+    let (result, columns) = {
+        let nonogram = state.nonogram.lock().unwrap();
+        let columns = state.puzzle.borrow().columns.len();
+        (
+            nonogram
+                .history
+                .diff(params.from, params.to, nonogram.revision),
+            columns,
+        )
+    };
+    let cells = match result {
+        Ok(cells) => cells,
+        Err(e) => {
+            let (status, code) = match e {
+                DiffError::Truncated { .. } => (StatusCode::GONE, "history_truncated"),
+                DiffError::InvalidRange { .. } => (StatusCode::BAD_REQUEST, "invalid_range"),
+            };
+            return match params.format {
+                DiffFormat::Json => ApiError::new(status, code, e.to_string()).into_response(),
+                DiffFormat::Html => (status, html! { p { (e) } }).into_response(),
+            };
+        }
+    };
+    match params.format {
+        DiffFormat::Json => Json(DiffBody {
+            from: params.from,
+            to: params.to,
+            cells: cells
+                .iter()
+                .map(|diff| DiffCell {
+                    row: diff.cell / columns,
+                    column: diff.cell % columns,
+                    before: diff.before.as_str(),
+                    after: diff.after.as_str(),
+                    session: diff.session.map(|id| id.0),
+                })
+                .collect(),
+        })
+        .into_response(),
+        DiffFormat::Html => render_diff(&state, &params, &cells, columns).into_response(),
+    }
+}
+
+fn render_diff(
+    state: &AppState,
+    params: &DiffParams,
+    cells: &[CellDiff],
+    columns: usize,
+) -> Markup {
+    let cursors = state.cursors.lock().unwrap();
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            title { "Board diff - Multipaint by Numbers" }
+        }
+        body {
+            h1 { "Changes from revision " (params.from) " to " (params.to) }
+            @if cells.is_empty() {
+                p { "No cells changed." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "Row" }
+                            th { "Column" }
+                            th { "Before" }
+                            th { "After" }
+                            th { "Changed by" }
+                        }
+                    }
+                    tbody {
+                        @for diff in cells {
+                            tr {
+                                td { (diff.cell / columns) }
+                                td { (diff.cell % columns) }
+                                td { (diff.before.as_str()) }
+                                td { (diff.after.as_str()) }
+                                td {
+                                    @match diff.session {
+                                        Some(id) => {
+                                            (cursors
+                                                .get(&id)
+                                                .and_then(|cursor| cursor.name.clone())
+                                                .unwrap_or_else(|| format!("#{}", id.0)))
+                                        }
+                                        None => "Unknown",
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ReplayBody {
+    generation: u64,
+    rows: usize,
+    columns: usize,
+    events: Vec<ReplayCell>,
+    /// The board after every event, row by row, as in board snapshots.
+    board: String,
+}
+
+#[derive(Serialize)]
+struct ReplayCell {
+    /// Milliseconds since the puzzle started.
+    at: u64,
+    row: usize,
+    column: usize,
+    state: &'static str,
+}
+
+/// Every change to the current puzzle so far, expanded from its compacted replay.
+async fn replay(_admin: Admin, State(state): State<AppState>) -> Json<ReplayBody> {
+    let (generation, replay) = {
+        let nonogram = state.nonogram.lock().unwrap();
+        (nonogram.generation, nonogram.replay.clone())
+    };
+    let (rows, columns) = {
+        let puzzle = state.puzzle.borrow();
+        (puzzle.rows.len(), puzzle.columns.len())
+    };
+    let events = replay.expand();
+    Json(ReplayBody {
+        generation,
+        rows,
+        columns,
+        board: reconstruct(&events, rows * columns)
+            .into_iter()
+            .map(cell_char)
+            .collect(),
+        events: events
+            .into_iter()
+            .map(|ReplayEvent { at, cell, state }| ReplayCell {
+                at: at.as_millis() as u64,
+                row: cell / columns,
+                column: cell % columns,
+                state: state.as_str(),
+            })
+            .collect(),
+    })
+}
+
+async fn cursors_page(_admin: Admin, mount: Option<Extension<MountPath>>) -> Markup {
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            base href=(base_href(mount.as_ref()));
+            title { "Cursors - Multipaint by Numbers" }
+            script src="htmx.js" {}
+        }
+        body {
+            h1 { "Active cursors" }
+            #admin-cursors hx-get="admin/cursors/table" hx-trigger="load, every 5s" {}
+        }
+    }
+}
+
+async fn cursors_table(_admin: Admin, State(state): State<AppState>) -> Markup {
+    render_cursors_table(&state)
+}
+
+async fn cursor_action(
+    audit: Audited,
+    State(state): State<AppState>,
+    Path((id, action)): Path<(u64, CursorAction)>,
+) -> Result<Markup, Response> {
+    let cursor_id = CursorId(id);
+    let parameters = json!({ "cursor": id, "action": action });
+    audit.record("cursor_action", parameters, audit::OK).await?;
+    let now = state.clock.now();
+    {
+        let mut sanctions = state.sanctions.lock().unwrap();
+        match action {
+            CursorAction::Mute => {
+                sanctions.insert((cursor_id, SanctionKind::Muted), now + MUTE_DURATION);
+            }
+            // A kick stands until it expires.
+            CursorAction::Unmute => {
+                sanctions.remove(&(cursor_id, SanctionKind::Muted));
+            }
+            CursorAction::Kick => {
+                sanctions.insert((cursor_id, SanctionKind::Kicked), now + KICK_DURATION);
+            }
+        }
+    }
+    if let CursorAction::Kick = action {
+        state.cursors.lock().unwrap().remove(&cursor_id);
+    }
+    Ok(render_cursors_table(&state))
+}
+
+fn render_cursors_table(state: &AppState) -> Markup {
+    let mut rows = state
+        .cursors
+        .lock()
+        .unwrap()
+        .values()
+        .map(|cursor| {
+            (
+                cursor.id,
+                cursor.name.clone(),
+                cursor.color,
+                state.clock.elapsed(cursor.modified_at),
+                cursor.actions,
+            )
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|&(_, _, _, idle, _)| idle);
+    html! {
+        table {
+            thead {
+                tr {
+                    th { "Color" }
+                    th { "Name" }
+                    th { "Last activity" }
+                    th { "Actions" }
+                    th { "Status" }
+                    th {}
+                }
+            }
+            tbody {
+                @for (id, name, color, idle, actions) in rows {
+                    @let muted = is_sanctioned(state, id, SanctionKind::Muted);
+                    @let kicked = is_sanctioned(state, id, SanctionKind::Kicked);
+                    tr {
+                        td style=(format!("background-color: rgb({}, {}, {});", color[0], color[1], color[2])) {}
+                        td { (name.unwrap_or_else(|| format!("#{}", id.0))) }
+                        td { (format_duration(idle, DurationStyle::Human)) " ago" }
+                        td { (actions) }
+                        td {
+                            @match (muted, kicked) {
+                                (true, true) => "Muted, kicked",
+                                (true, false) => "Muted",
+                                (false, true) => "Kicked",
+                                (false, false) => "Active",
+                            }
+                        }
+                        td {
+                            @if muted {
+                                button hx-post=(format!("admin/cursors/{}/unmute", id.0)) hx-target="#admin-cursors" { "Unmute" }
+                            } @else {
+                                button hx-post=(format!("admin/cursors/{}/mute", id.0)) hx-target="#admin-cursors" { "Mute" }
+                            }
+                            button hx-post=(format!("admin/cursors/{}/kick", id.0)) hx-target="#admin-cursors" { "Kick" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::io::Write;
+
+use axum::{
+    extract::State,
+    http::{
+        header::{
+            ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            RETRY_AFTER, VARY,
+        },
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use flate2::{write::GzEncoder, Compression};
+use image::{codecs::png::PngEncoder, ExtendedColorType, ImageEncoder};
+use serde::Serialize;
+
+use super::{
+    compact::{CompactBoard, CompactCell},
+    recovery::cell_char,
+    AppState, CellRef, CheckboxState, NonogramState, VERSION,
+};
+use crate::http::{
+    api::{with_json_errors, ApiError, ApiPath},
+    export::{stream_blocking, EXPORT_RETRY_AFTER_SECS},
+};
+
+/// Side of each cell in `GET /api/board.png`, in pixels.
+const PNG_CELL_SIZE: usize = 8;
+
+/// Read-only JSON routes, nested under `/api`.
+pub(super) fn router() -> Router<AppState> {
+    with_json_errors(
+        Router::new()
+            .route("/board", get(board))
+            .route("/board.snapshot", get(board_snapshot))
+            .route("/board.png", get(board_png))
+            .route("/cell/:row/:column", get(cell)),
+    )
+}
+
+#[derive(Serialize)]
+struct Board {
+    puzzle: u32,
+    rows: usize,
+    columns: usize,
+    revision: u64,
+    state: &'static str,
+    /// One character per cell, in row-major order: `.` for empty, `x` for flagged, and `#` for marked.
+    cells: String,
+}
+
+#[derive(Serialize)]
+struct Cell {
+    row: usize,
+    column: usize,
+    state: &'static str,
+}
+
+fn state_name(state: NonogramState) -> &'static str {
+    match state {
+        NonogramState::Unsolved => "unsolved",
+        NonogramState::Solved(_) => "solved",
+        NonogramState::Failed => "failed",
+    }
+}
+
+async fn board(State(state): State<AppState>) -> Json<Board> {
+    let nonogram = state.nonogram.lock().unwrap();
+    let puzzle = state.puzzle.borrow();
+    Json(Board {
+        puzzle: puzzle.id,
+        rows: puzzle.rows.len(),
+        columns: puzzle.columns.len(),
+        revision: nonogram.revision,
+        state: state_name(nonogram.state),
+        cells: nonogram.checkboxes.iter().copied().map(cell_char).collect(),
+    })
+}
+
+/// Whether the client's `If-None-Match` already has `etag`.
+pub(super) fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        })
+}
+
+/// The whole board as a [`CompactBoard`], with an `ETag` so that pollers only download it when it changed.
+async fn board_snapshot(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let (etag, board) = {
+        let nonogram = state.nonogram.lock().unwrap();
+        // Revisions start over on restarts without a data dir, so tell processes apart too.
+        let etag = format!("\"{:08x}-{}\"", *VERSION, nonogram.revision);
+        if is_fresh(&headers, &etag) {
+            return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+        }
+        let puzzle = state.puzzle.borrow();
+        let board = CompactBoard::new(
+            puzzle.id,
+            (puzzle.rows.len(), puzzle.columns.len()),
+            nonogram.revision,
+            state_name(nonogram.state),
+            nonogram.checkboxes.iter().map(|&cell| match cell {
+                CheckboxState::Empty => CompactCell::Empty,
+                CheckboxState::Flagged => CompactCell::Flagged,
+                CheckboxState::Marked => CompactCell::Marked,
+            }),
+        );
+        (etag, board)
+    };
+    let body = serde_json::to_vec(&board).expect("Board should serialize to JSON.");
+    let response_headers = [
+        (ETAG, etag),
+        (CACHE_CONTROL, String::from("no-cache")),
+        (VARY, String::from(ACCEPT_ENCODING.as_str())),
+    ];
+    let accepts_gzip = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| encoding.split(';').next().unwrap_or_default().trim() == "gzip");
+    if accepts_gzip {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        if let Ok(gzipped) = encoder.write_all(&body).and_then(|()| encoder.finish()) {
+            return (
+                response_headers,
+                [
+                    (CONTENT_TYPE, "application/json"),
+                    (CONTENT_ENCODING, "gzip"),
+                ],
+                gzipped,
+            )
+                .into_response();
+        }
+    }
+    (response_headers, [(CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// The board as a grayscale PNG, streamed from a blocking worker.
+async fn board_png(State(state): State<AppState>) -> Response {
+    let Some(permit) = state.exports.try_start() else {
+        return (
+            [(RETRY_AFTER, EXPORT_RETRY_AFTER_SECS.to_string())],
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "exports_busy",
+                "Too many exports in progress, try again later.",
+            ),
+        )
+            .into_response();
+    };
+    let (columns, checkboxes) = {
+        let nonogram = state.nonogram.lock().unwrap();
+        (
+            state.puzzle.borrow().columns.len(),
+            nonogram.checkboxes.clone(),
+        )
+    };
+    let rows = checkboxes.len() / columns.max(1);
+    stream_blocking(permit, "image/png", move |writer| {
+        let (width, height) = (columns * PNG_CELL_SIZE, rows * PNG_CELL_SIZE);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in checkboxes.chunks(columns.max(1)) {
+            let line = row
+                .iter()
+                .flat_map(|&cell| {
+                    let luma = match cell {
+                        CheckboxState::Empty => 255,
+                        CheckboxState::Flagged => 192,
+                        CheckboxState::Marked => 0,
+                    };
+                    [luma; PNG_CELL_SIZE]
+                })
+                .collect::<Vec<u8>>();
+            for _ in 0..PNG_CELL_SIZE {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        PngEncoder::new(writer)
+            .write_image(&pixels, width as u32, height as u32, ExtendedColorType::L8)
+            .map_err(std::io::Error::other)
+    })
+}
+
+async fn cell(
+    State(state): State<AppState>,
+    ApiPath((row, column)): ApiPath<(usize, usize)>,
+) -> Result<Json<Cell>, ApiError> {
+    let nonogram = state.nonogram.lock().unwrap();
+    let puzzle = state.puzzle.borrow();
+    let id = CellRef::Coordinates { row, column }
+        .resolve(&puzzle)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "cell_out_of_range",
+                format!(
+                    "Cell ({row}, {column}) is out of range: row must be within 0..{} and column within 0..{}.",
+                    puzzle.rows.len(),
+                    puzzle.columns.len()
+                ),
+            )
+        })?;
+    Ok(Json(Cell {
+        row,
+        column,
+        state: nonogram.checkboxes[id].as_str(),
+    }))
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+use bitvec::{order::Lsb0, slice::BitSlice};
+use maud::{html, Markup};
+
+use super::CellChange;
+
+/// How the players went about the current puzzle, for a short summary once it's solved.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlayStats {
+    /// Every cell that was flagged at some point. Only compared against the solution once the puzzle is over, so
+    /// that nothing about it leaks during play.
+    flagged: BTreeSet<usize>,
+    /// Correct marks in a row so far, since the last reversal.
+    streak: usize,
+    longest_streak: usize,
+}
+
+impl PlayStats {
+    /// Records an accepted change to a cell.
+    pub fn record(&mut self, id: usize, change: CellChange, solution: &BitSlice<usize, Lsb0>) {
+        match change {
+            CellChange::Flag => {
+                self.flagged.insert(id);
+            }
+            CellChange::Mark if solution[id] => {
+                self.streak += 1;
+                self.longest_streak = self.longest_streak.max(self.streak);
+            }
+            CellChange::Mark | CellChange::Unmark => self.streak = 0,
+            CellChange::Unflag => (),
+        }
+    }
+
+    /// How many of the flagged cells turned out to be part of the solution.
+    pub fn flags_on_painted_cells(&self, solution: &BitSlice<usize, Lsb0>) -> usize {
+        self.flagged
+            .iter()
+            .filter(|&&id| solution.get(id).is_some_and(|cell| *cell))
+            .count()
+    }
+
+    /// A few friendly numbers about the solve.
+    pub fn summary(&self, solution: &BitSlice<usize, Lsb0>) -> Markup {
+        let flags = self.flags_on_painted_cells(solution);
+        html! {
+            p .coaching {
+                @match flags {
+                    0 => "No flags were on painted cells.",
+                    1 => "1 flag was on a painted cell.",
+                    flags => (flags) " flags were on painted cells.",
+                }
+                @if self.longest_streak > 0 {
+                    " Longest streak: " (self.longest_streak) " correct marks in a row."
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitvec::bitvec;
+
+    #[test]
+    fn it_replays_a_scripted_solve() {
+        // A 3x3 plus sign.
+        let solution = bitvec![0, 1, 0, 1, 1, 1, 0, 1, 0];
+        let mut stats = PlayStats::default();
+        for (id, change) in [
+            (0, CellChange::Flag),
+            (1, CellChange::Flag),
+            (1, CellChange::Unflag),
+            (1, CellChange::Mark),
+            (3, CellChange::Mark),
+            (0, CellChange::Unflag),
+            (0, CellChange::Mark),
+            (0, CellChange::Unmark),
+            (4, CellChange::Flag),
+            (4, CellChange::Unflag),
+            (4, CellChange::Mark),
+            (5, CellChange::Mark),
+            (7, CellChange::Mark),
+        ] {
+            stats.record(id, change, &solution);
+        }
+        assert_eq!(stats.flags_on_painted_cells(&solution), 2);
+        assert_eq!(stats.longest_streak, 3);
+        let summary = stats.summary(&solution).into_string();
+        assert!(summary.contains("2 flags were on painted cells."));
+        assert!(summary.contains("Longest streak: 3 correct marks in a row."));
+    }
+
+    #[test]
+    fn it_starts_from_scratch() {
+        let solution = bitvec![1, 0];
+        let stats = PlayStats::default();
+        assert_eq!(stats.flags_on_painted_cells(&solution), 0);
+        assert_eq!(
+            stats.summary(&solution).into_string(),
+            r#"<p class="coaching">No flags were on painted cells.</p>"#
+        );
+    }
+}
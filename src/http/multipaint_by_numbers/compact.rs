@@ -0,0 +1,119 @@
+//! Compact encoding of the whole Multipaint board, for clients that poll it often, such as info screens.
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bitvec::{order::Lsb0, vec::BitVec};
+use serde::{Deserialize, Serialize};
+
+/// State of a single cell, as decoded from a [`CompactBoard`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompactCell {
+    Empty,
+    Flagged,
+    Marked,
+}
+
+/// The whole board at a given revision, as served by `GET /api/board.snapshot`.
+///
+/// Cells are numbered in row-major order. Each of `marked` and `flagged` is a bitmask with one bit per cell, where
+/// cell `i` is bit `i % 8` (least significant first) of byte `i / 8`, encoded as padded standard base64. A cell is
+/// never set in both. Use [`CompactBoard::cells`] to decode them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CompactBoard {
+    pub puzzle: u32,
+    pub rows: usize,
+    pub columns: usize,
+    pub revision: u64,
+    /// One of `unsolved`, `solved`, or `failed`.
+    pub state: String,
+    pub marked: String,
+    pub flagged: String,
+}
+
+impl CompactBoard {
+    /// Encodes the bitmasks for `cells`, which must have `rows * columns` entries.
+    pub fn new(
+        puzzle: u32,
+        (rows, columns): (usize, usize),
+        revision: u64,
+        state: &str,
+        cells: impl IntoIterator<Item = CompactCell>,
+    ) -> Self {
+        let mut marked = BitVec::<u8, Lsb0>::with_capacity(rows * columns);
+        let mut flagged = BitVec::<u8, Lsb0>::with_capacity(rows * columns);
+        for cell in cells {
+            marked.push(cell == CompactCell::Marked);
+            flagged.push(cell == CompactCell::Flagged);
+        }
+        CompactBoard {
+            puzzle,
+            rows,
+            columns,
+            revision,
+            state: String::from(state),
+            marked: STANDARD.encode(marked.as_raw_slice()),
+            flagged: STANDARD.encode(flagged.as_raw_slice()),
+        }
+    }
+
+    /// Decodes every cell, in row-major order.
+    pub fn cells(&self) -> Result<Vec<CompactCell>> {
+        let len = self.rows * self.columns;
+        let decode = |name: &str, mask: &str| -> Result<BitVec<u8, Lsb0>> {
+            let bytes = STANDARD
+                .decode(mask)
+                .with_context(|| format!("Invalid base64 in '{name}'"))?;
+            if bytes.len() != len.div_ceil(8) {
+                return Err(anyhow!(
+                    "Expected {} bytes in '{name}' for {len} cells, got {}.",
+                    len.div_ceil(8),
+                    bytes.len()
+                ));
+            }
+            Ok(BitVec::from_vec(bytes))
+        };
+        let marked = decode("marked", &self.marked)?;
+        let flagged = decode("flagged", &self.flagged)?;
+        (0..len)
+            .map(|id| match (marked[id], flagged[id]) {
+                (false, false) => Ok(CompactCell::Empty),
+                (false, true) => Ok(CompactCell::Flagged),
+                (true, false) => Ok(CompactCell::Marked),
+                (true, true) => Err(anyhow!("Cell {id} is both marked and flagged.")),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_the_cells() {
+        let cells = (0..25 * 25)
+            .map(|id| match id % 7 {
+                0 | 3 => CompactCell::Marked,
+                5 => CompactCell::Flagged,
+                _ => CompactCell::Empty,
+            })
+            .collect::<Vec<_>>();
+        let board = CompactBoard::new(7, (25, 25), 42, "unsolved", cells.iter().copied());
+        assert_eq!(board.cells().unwrap(), cells);
+        assert!(serde_json::to_string(&board).unwrap().len() < 2048);
+        let decoded: CompactBoard =
+            serde_json::from_str(&serde_json::to_string(&board).unwrap()).unwrap();
+        assert_eq!(decoded, board);
+    }
+
+    #[test]
+    fn it_rejects_broken_masks() {
+        let mut board = CompactBoard::new(1, (3, 3), 0, "unsolved", [CompactCell::Marked; 9]);
+        board.flagged.clone_from(&board.marked);
+        assert!(board.cells().is_err());
+        board.flagged = String::from("AA==");
+        assert!(board.cells().is_err());
+        board.flagged = String::from("not base64");
+        assert!(board.cells().is_err());
+    }
+}
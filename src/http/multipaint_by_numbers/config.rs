@@ -0,0 +1,370 @@
+//! Multipaint's own settings, as opposed to the services shared by every activity in
+//! [`ActivityContext`](crate::http::registry::ActivityContext).
+//!
+//! New options go on [`MultipaintConfigBuilder`] and [`MultipaintArgs`], so that they are validated in one place.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use super::rotation::{RefillStrategy, DEFAULT_RECENTLY_PLAYED};
+use crate::format::{format_duration, parse_duration, DurationStyle};
+
+/// How soon after a player's change to a cell another change by them is recorded as the same action, unless
+/// overridden with `--coalesce-window`.
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(300);
+
+/// Bounds on how long players get for each puzzle, which otherwise only depends on its size.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PuzzleDurations {
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl PuzzleDurations {
+    pub fn clamp(&self, duration: Duration) -> Duration {
+        let duration = self.min.map_or(duration, |min| duration.max(min));
+        self.max.map_or(duration, |max| duration.min(max))
+    }
+}
+
+/// Validated settings for Multipaint. Start from [`MultipaintConfig::builder`], or from the command line with
+/// [`MultipaintArgs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultipaintConfig {
+    recently_played: usize,
+    refill_strategy: RefillStrategy,
+    event_log: bool,
+    durations: PuzzleDurations,
+    manual_submit: bool,
+    coalesce_window: Duration,
+}
+
+impl Default for MultipaintConfig {
+    fn default() -> Self {
+        MultipaintConfig::builder()
+            .build()
+            .expect("the default config is valid")
+    }
+}
+
+impl MultipaintConfig {
+    pub fn builder() -> MultipaintConfigBuilder {
+        MultipaintConfigBuilder::default()
+    }
+
+    /// How many recently played puzzles are skipped when picking the next one.
+    pub fn recently_played(&self) -> usize {
+        self.recently_played
+    }
+
+    /// What to do once the shuffled puzzle list runs out.
+    pub fn refill_strategy(&self) -> RefillStrategy {
+        self.refill_strategy
+    }
+
+    /// Whether every change to the board is logged in the data dir.
+    pub fn event_log(&self) -> bool {
+        self.event_log
+    }
+
+    pub fn durations(&self) -> PuzzleDurations {
+        self.durations
+    }
+
+    /// Whether complete boards wait for someone to check the solution, instead of being solved right away.
+    pub fn manual_submit(&self) -> bool {
+        self.manual_submit
+    }
+
+    /// Changes to a cell by the same player within this long of their previous one are recorded as a single
+    /// action in the history and replay. Zero records every change on its own.
+    pub fn coalesce_window(&self) -> Duration {
+        self.coalesce_window
+    }
+}
+
+/// Builds a [`MultipaintConfig`], checking that its settings make sense together.
+#[derive(Clone, Debug)]
+pub struct MultipaintConfigBuilder {
+    recently_played: usize,
+    refill_strategy: RefillStrategy,
+    event_log: bool,
+    min_puzzle_time: Option<Duration>,
+    max_puzzle_time: Option<Duration>,
+    manual_submit: bool,
+    coalesce_window: Duration,
+}
+
+impl Default for MultipaintConfigBuilder {
+    fn default() -> Self {
+        MultipaintConfigBuilder {
+            recently_played: DEFAULT_RECENTLY_PLAYED,
+            refill_strategy: RefillStrategy::default(),
+            event_log: false,
+            min_puzzle_time: None,
+            max_puzzle_time: None,
+            manual_submit: false,
+            coalesce_window: DEFAULT_COALESCE_WINDOW,
+        }
+    }
+}
+
+impl MultipaintConfigBuilder {
+    pub fn recently_played(mut self, count: usize) -> Self {
+        self.recently_played = count;
+        self
+    }
+
+    pub fn refill_strategy(mut self, strategy: RefillStrategy) -> Self {
+        self.refill_strategy = strategy;
+        self
+    }
+
+    /// Only takes effect with a data dir.
+    pub fn event_log(mut self, enabled: bool) -> Self {
+        self.event_log = enabled;
+        self
+    }
+
+    pub fn min_puzzle_time(mut self, duration: Option<Duration>) -> Self {
+        self.min_puzzle_time = duration;
+        self
+    }
+
+    pub fn max_puzzle_time(mut self, duration: Option<Duration>) -> Self {
+        self.max_puzzle_time = duration;
+        self
+    }
+
+    pub fn manual_submit(mut self, enabled: bool) -> Self {
+        self.manual_submit = enabled;
+        self
+    }
+
+    pub fn coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    pub fn build(self) -> Result<MultipaintConfig> {
+        for (field, duration) in [
+            ("min_puzzle_time", self.min_puzzle_time),
+            ("max_puzzle_time", self.max_puzzle_time),
+        ] {
+            if duration == Some(Duration::ZERO) {
+                bail!("Invalid Multipaint config: {field} must be longer than zero.");
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_puzzle_time, self.max_puzzle_time) {
+            if min > max {
+                bail!(
+                    "Invalid Multipaint config: min_puzzle_time ({}) is longer than max_puzzle_time ({}).",
+                    format_duration(min, DurationStyle::Compact),
+                    format_duration(max, DurationStyle::Compact)
+                );
+            }
+        }
+        Ok(MultipaintConfig {
+            recently_played: self.recently_played,
+            refill_strategy: self.refill_strategy,
+            event_log: self.event_log,
+            durations: PuzzleDurations {
+                min: self.min_puzzle_time,
+                max: self.max_puzzle_time,
+            },
+            manual_submit: self.manual_submit,
+            coalesce_window: self.coalesce_window,
+        })
+    }
+}
+
+/// Command-line flags for Multipaint, flattened into the binary's arguments.
+#[derive(Args, Clone, Debug)]
+pub struct MultipaintArgs {
+    /// How many recently played puzzles to skip when picking the next one, unless an operator forces them.
+    /// Remembered across restarts with `--data-dir`.
+    #[arg(long, global = true, value_name = "COUNT", default_value_t = DEFAULT_RECENTLY_PLAYED)]
+    recently_played: usize,
+
+    /// What to do once every puzzle in Multipaint's shuffled list has been played.
+    #[arg(long, global = true, value_enum, default_value_t = RefillStrategy::default())]
+    refill_strategy: RefillStrategy,
+
+    /// Also log every change to the Multipaint board in `--data-dir`, so that a crash doesn't lose the moves made
+    /// since the last snapshot.
+    #[arg(long, global = true, requires = "data_dir")]
+    event_log: bool,
+
+    /// Give players at least this long for each Multipaint puzzle, with an s, m, h or d suffix.
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration)]
+    min_puzzle_time: Option<Duration>,
+
+    /// Give players at most this long for each Multipaint puzzle, with an s, m, h or d suffix.
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration)]
+    max_puzzle_time: Option<Duration>,
+
+    /// Don't solve Multipaint puzzles as soon as the board is right. Players check the solution with a button
+    /// instead, and only learn how many cells are wrong.
+    #[arg(long, global = true)]
+    manual_submit: bool,
+
+    /// Record changes to a Multipaint cell made by the same player within this many milliseconds of each other as
+    /// a single action in the history and replay, such as when dragging or double-clicking. 0 records every change.
+    #[arg(long, global = true, value_name = "MS", default_value_t = DEFAULT_COALESCE_WINDOW.as_millis() as u64)]
+    coalesce_window: u64,
+}
+
+impl TryFrom<MultipaintArgs> for MultipaintConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(args: MultipaintArgs) -> Result<Self> {
+        MultipaintConfig::builder()
+            .recently_played(args.recently_played)
+            .refill_strategy(args.refill_strategy)
+            .event_log(args.event_log)
+            .min_puzzle_time(args.min_puzzle_time)
+            .max_puzzle_time(args.max_puzzle_time)
+            .manual_submit(args.manual_submit)
+            .coalesce_window(Duration::from_millis(args.coalesce_window))
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::{Parser, ValueEnum};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        #[command(flatten)]
+        multipaint: MultipaintArgs,
+    }
+
+    /// The flags that configure `config`.
+    fn to_args(config: &MultipaintConfig) -> Vec<String> {
+        let mut args = vec![
+            String::from("--data-dir=data"),
+            format!("--recently-played={}", config.recently_played),
+            format!(
+                "--refill-strategy={}",
+                config
+                    .refill_strategy
+                    .to_possible_value()
+                    .unwrap()
+                    .get_name()
+            ),
+        ];
+        if config.event_log {
+            args.push(String::from("--event-log"));
+        }
+        if let Some(min) = config.durations.min {
+            args.push(format!("--min-puzzle-time={}s", min.as_secs()));
+        }
+        if let Some(max) = config.durations.max {
+            args.push(format!("--max-puzzle-time={}s", max.as_secs()));
+        }
+        if config.manual_submit {
+            args.push(String::from("--manual-submit"));
+        }
+        args.push(format!(
+            "--coalesce-window={}",
+            config.coalesce_window.as_millis()
+        ));
+        args
+    }
+
+    fn parse(args: &[&str]) -> Result<MultipaintConfig> {
+        let cli = Cli::try_parse_from(["multipaint"].iter().chain(args))?;
+        MultipaintConfig::try_from(cli.multipaint)
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        let config = MultipaintConfig::default();
+        assert_eq!(config.recently_played(), DEFAULT_RECENTLY_PLAYED);
+        assert_eq!(config.refill_strategy(), RefillStrategy::default());
+        assert!(!config.event_log());
+        assert_eq!(config.durations(), PuzzleDurations::default());
+        assert!(!config.manual_submit());
+        assert_eq!(config.coalesce_window(), DEFAULT_COALESCE_WINDOW);
+        assert_eq!(parse(&[]).unwrap(), config);
+    }
+
+    #[test]
+    fn invalid_configs_name_their_fields() {
+        let error = MultipaintConfig::builder()
+            .min_puzzle_time(Some(Duration::from_secs(20 * 60)))
+            .max_puzzle_time(Some(Duration::from_secs(10 * 60)))
+            .build()
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("min_puzzle_time (20m00s)"), "{error}");
+        assert!(error.contains("max_puzzle_time (10m00s)"), "{error}");
+        let error = parse(&["--max-puzzle-time=0s"]).unwrap_err().to_string();
+        assert!(error.contains("max_puzzle_time"), "{error}");
+        assert!(parse(&["--min-puzzle-time=5m", "--max-puzzle-time=5m"]).is_ok());
+    }
+
+    #[test]
+    fn puzzle_durations_are_clamped() {
+        let durations = PuzzleDurations {
+            min: Some(Duration::from_secs(60)),
+            max: Some(Duration::from_secs(600)),
+        };
+        assert_eq!(
+            durations.clamp(Duration::from_secs(5)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            durations.clamp(Duration::from_secs(367)),
+            Duration::from_secs(367)
+        );
+        assert_eq!(
+            durations.clamp(Duration::from_secs(1277)),
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            PuzzleDurations::default().clamp(Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn arbitrary_configs_round_trip_through_the_command_line() {
+        let mut rng = StdRng::seed_from_u64(741);
+        let strategies = RefillStrategy::value_variants();
+        for _ in 0..200 {
+            let min = rng
+                .gen_bool(0.5)
+                .then(|| Duration::from_secs(rng.gen_range(1..10_000)));
+            let max = rng
+                .gen_bool(0.5)
+                .then(|| Duration::from_secs(rng.gen_range(1..10_000)));
+            let Ok(config) = MultipaintConfig::builder()
+                .recently_played(rng.gen_range(0..1000))
+                .refill_strategy(strategies[rng.gen_range(0..strategies.len())])
+                .event_log(rng.gen())
+                .min_puzzle_time(min)
+                .max_puzzle_time(max)
+                .manual_submit(rng.gen())
+                .coalesce_window(Duration::from_millis(rng.gen_range(0..2000)))
+                .build()
+            else {
+                assert!(min > max);
+                continue;
+            };
+            let args = to_args(&config);
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            assert_eq!(parse(&args).unwrap(), config, "{args:?}");
+        }
+    }
+}
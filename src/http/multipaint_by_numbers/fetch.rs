@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use tracing::{debug, info};
+
+use super::rotation::{PuzzleKey, Rotation};
+use crate::{
+    clock::Clock,
+    nonogram::{
+        funnel::{FunnelEvent, PuzzleFunnel},
+        nonogrammed::{self, NonogrammedPuzzle, NONOGRAMMED_PUZZLE_LIST},
+        rejection::{RejectionLog, RejectionReason, RejectionRecord},
+        throttle::{Breakers, Throttled},
+        upstream::UpstreamClient,
+        webpbn::{self, WEBPBN_PUZZLE_LIST},
+        PuzzleSource, UpstreamUrls,
+    },
+    random::Random,
+};
+
+/// A fetch that every caller asking for the same puzzle awaits, instead of fetching it again.
+type InFlightFetch = Shared<BoxFuture<'static, Result<NonogrammedPuzzle, Arc<anyhow::Error>>>>;
+
+/// Takes a fetch out of the in-flight map once it's over, even if it panicked.
+struct InFlightGuard {
+    in_flight: Arc<Mutex<HashMap<PuzzleKey, InFlightFetch>>>,
+    key: PuzzleKey,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Fetches puzzles from the upstreams, backing off from the ones that throttle us.
+#[derive(Clone)]
+pub struct PuzzleFetcher {
+    upstreams: Arc<UpstreamUrls>,
+    client: UpstreamClient,
+    rejections: RejectionLog,
+    breakers: Breakers,
+    clock: Clock,
+    random: Random,
+    funnel: PuzzleFunnel,
+    /// Fetches that haven't finished yet. Results are only shared while in flight, so errors aren't cached.
+    in_flight: Arc<Mutex<HashMap<PuzzleKey, InFlightFetch>>>,
+    /// Called before every fetch, to stand in for bugs in the fetcher.
+    #[cfg(test)]
+    fault: Option<Arc<dyn Fn(PuzzleKey) + Send + Sync>>,
+}
+
+impl PuzzleFetcher {
+    pub fn new(
+        upstreams: UpstreamUrls,
+        client: UpstreamClient,
+        rejections: RejectionLog,
+        clock: Clock,
+        random: Random,
+        funnel: PuzzleFunnel,
+    ) -> Self {
+        PuzzleFetcher {
+            upstreams: Arc::new(upstreams),
+            client,
+            rejections,
+            breakers: Breakers::default(),
+            clock,
+            random,
+            funnel,
+            in_flight: Arc::default(),
+            #[cfg(test)]
+            fault: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn with_fault(mut self, fault: impl Fn(PuzzleKey) + Send + Sync + 'static) -> Self {
+        self.fault = Some(Arc::new(fault));
+        self
+    }
+
+    #[cfg(test)]
+    pub(super) fn in_flight_is_empty(&self) -> bool {
+        self.in_flight.lock().unwrap().is_empty()
+    }
+
+    pub fn breakers(&self) -> &Breakers {
+        &self.breakers
+    }
+
+    /// Where fetched puzzles are counted, for the rest of their way to the board.
+    pub fn funnel(&self) -> &PuzzleFunnel {
+        &self.funnel
+    }
+
+    /// Fetches puzzles from the rotation until one is valid, marking it as played. Returns `None` once the rotation
+    /// has nothing left to play.
+    ///
+    /// While the next candidate's source is cooling down, it stays at the front of the rotation and a random puzzle
+    /// from the other source is played instead. If both are cooling down, waits for the first one to be available.
+    pub async fn next_puzzle(&self, rotation: &Mutex<Rotation>) -> Option<NonogrammedPuzzle> {
+        loop {
+            let mut key = rotation.lock().unwrap().next_candidate()?;
+            let mut is_fallback = false;
+            if let Err(wait) = self.breakers.allow(key.0, self.clock.now()) {
+                rotation.lock().unwrap().put_back(key);
+                let fallback = self.fallback_for(key.0);
+                if let Err(fallback_wait) = self.breakers.allow(fallback.0, self.clock.now()) {
+                    let wait = wait.min(fallback_wait);
+                    info!(
+                        seconds = wait.as_secs(),
+                        "Every upstream is cooling down, waiting."
+                    );
+                    self.clock.sleep(wait).await;
+                    continue;
+                }
+                debug!(source = %key.0, fallback = %fallback.0, "Source is cooling down, using a fallback.");
+                key = fallback;
+                is_fallback = true;
+            }
+            match self.get_puzzle(key).await {
+                Ok(puzzle) => {
+                    rotation.lock().unwrap().record_played(key);
+                    break Some(puzzle);
+                }
+                // Throttled candidates aren't rejected, so they get another chance later on.
+                Err(e) if !is_fallback && Throttled::find(&e).is_some() => {
+                    rotation.lock().unwrap().put_back(key);
+                }
+                Err(_) => (),
+            }
+        }
+    }
+
+    /// Fetches a single puzzle. If the same puzzle is already being fetched, waits for that fetch instead.
+    pub async fn get_puzzle(&self, key: PuzzleKey) -> Result<NonogrammedPuzzle> {
+        let fetch = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                let fetcher = self.clone();
+                async move {
+                    let _guard = InFlightGuard {
+                        in_flight: Arc::clone(&fetcher.in_flight),
+                        key,
+                    };
+                    fetcher.fetch_puzzle(key).await.map_err(Arc::new)
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+        fetch.await.map_err(|e| shared_error(&e))
+    }
+
+    /// Fetches a single puzzle, recording why it was rejected unless the upstream only throttled us.
+    async fn fetch_puzzle(&self, (source, puzzle_id): PuzzleKey) -> Result<NonogrammedPuzzle> {
+        #[cfg(test)]
+        if let Some(fault) = &self.fault {
+            fault((source, puzzle_id));
+        }
+        self.funnel.record(source, FunnelEvent::FetchAttempt);
+        let puzzle = match source {
+            PuzzleSource::Nonogrammed => {
+                nonogrammed::get_puzzle_data(&self.client, &self.upstreams.nonogrammed, puzzle_id)
+                    .await
+            }
+            PuzzleSource::Webpbn => {
+                webpbn::get_puzzle_data(&self.client, &self.upstreams.webpbn, puzzle_id)
+                    .await
+                    .map(|puzzle| NonogrammedPuzzle {
+                        id: puzzle.id,
+                        title: puzzle.title,
+                        copyright: puzzle.copyright,
+                        rows: puzzle.rows,
+                        columns: puzzle.columns,
+                        solution: puzzle.solution,
+                    })
+            }
+        };
+        self.breakers.record(source, self.clock.now(), &puzzle);
+        match puzzle {
+            Err(e) => {
+                self.funnel.record(source, FunnelEvent::for_error(&e));
+                if let Some(throttled) = Throttled::find(&e) {
+                    info!(%source, id = puzzle_id, %throttled, "Throttled by upstream.");
+                } else {
+                    self.rejections
+                        .record(RejectionRecord::new(source, puzzle_id, &e))
+                        .await;
+                }
+                Err(e)
+            }
+            Ok(puzzle) => {
+                debug!(%source, id = puzzle_id, "Valid puzzle.");
+                Ok(puzzle)
+            }
+        }
+    }
+
+    /// A random puzzle from the source other than `source`.
+    fn fallback_for(&self, source: PuzzleSource) -> PuzzleKey {
+        match source {
+            PuzzleSource::Nonogrammed => (
+                PuzzleSource::Webpbn,
+                *self.random.choose(&WEBPBN_PUZZLE_LIST).unwrap(),
+            ),
+            PuzzleSource::Webpbn => (
+                PuzzleSource::Nonogrammed,
+                *self.random.choose(&NONOGRAMMED_PUZZLE_LIST).unwrap(),
+            ),
+        }
+    }
+}
+
+/// A copy of an error shared by every caller of the same fetch, which keeps what it's classified by.
+fn shared_error(error: &anyhow::Error) -> anyhow::Error {
+    let copy = anyhow!("{error:#}").context(RejectionReason::classify(error));
+    match Throttled::find(error) {
+        Some(throttled) => copy.context(throttled),
+        None => copy,
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use maud::{html, Markup};
+
+/// How many buckets the heatmap has on each side, whatever the size of the board.
+pub const HEATMAP_SIDE: usize = 16;
+
+/// How often cursor positions are sampled into the heatmap.
+pub const HEATMAP_TICK: Duration = Duration::from_secs(2);
+
+/// How much of each bucket is left after every tick, so that old attention fades in about a minute.
+const DECAY: f32 = 0.9;
+
+/// Buckets below this are cleared, so that the overlay doesn't keep faint leftovers around forever.
+const MIN_VISITS: f32 = 0.05;
+
+/// Where players' cursors have been lately, as visits per bucket decayed over time.
+///
+/// Positions are fractions of the board, from `(0, 0)` at its top left to `(1, 1)` at its bottom right.
+#[derive(Clone, Debug, Default)]
+pub struct Heatmap {
+    buckets: [[f32; HEATMAP_SIDE]; HEATMAP_SIDE],
+}
+
+fn bucket(fraction: f32) -> Option<usize> {
+    (0.0..=1.0)
+        .contains(&fraction)
+        .then(|| ((fraction * HEATMAP_SIDE as f32) as usize).min(HEATMAP_SIDE - 1))
+}
+
+impl Heatmap {
+    /// Decays every bucket, then counts a visit for each position. Positions outside of the board are ignored.
+    pub fn tick(&mut self, positions: impl IntoIterator<Item = (f32, f32)>) {
+        for visits in self.buckets.iter_mut().flatten() {
+            *visits *= DECAY;
+            if *visits < MIN_VISITS {
+                *visits = 0.0;
+            }
+        }
+        for (x, y) in positions {
+            if let (Some(column), Some(row)) = (bucket(x), bucket(y)) {
+                self.buckets[row][column] += 1.0;
+            }
+        }
+    }
+
+    /// Renders the heatmap, stretched over the board. Busier buckets get bigger and more opaque rectangles.
+    pub fn svg(&self) -> Markup {
+        let max = self.buckets.iter().flatten().copied().fold(0.0, f32::max);
+        html! {
+            svg .heatmap xmlns="http://www.w3.org/2000/svg" viewBox=(format!("0 0 {HEATMAP_SIDE} {HEATMAP_SIDE}")) preserveAspectRatio="none" width="100%" height="100%" {
+                @for (row, buckets) in self.buckets.iter().enumerate() {
+                    @for (column, &visits) in buckets.iter().enumerate() {
+                        @if visits > 0.0 {
+                            @let intensity = visits / max;
+                            @let side = intensity.sqrt();
+                            rect x=(format!("{:.3}", column as f32 + (1.0 - side) / 2.0)) y=(format!("{:.3}", row as f32 + (1.0 - side) / 2.0)) width=(format!("{side:.3}")) height=(format!("{side:.3}")) fill-opacity=(format!("{:.2}", 0.2 + 0.4 * intensity)) {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_visits_per_bucket() {
+        let mut heatmap = Heatmap::default();
+        heatmap.tick([
+            (0.0, 0.0),
+            (0.03, 0.05),
+            (1.0, 1.0),
+            (0.5, 0.25),
+            (-0.1, 0.5),
+            (0.5, 1.5),
+        ]);
+        assert_eq!(heatmap.buckets[0][0], 2.0);
+        assert_eq!(heatmap.buckets[HEATMAP_SIDE - 1][HEATMAP_SIDE - 1], 1.0);
+        assert_eq!(heatmap.buckets[4][8], 1.0);
+        let total: f32 = heatmap.buckets.iter().flatten().sum();
+        assert_eq!(total, 4.0);
+
+        let svg = heatmap.svg().into_string();
+        assert_eq!(svg.matches("<rect").count(), 3);
+        assert!(
+            svg.contains(r#"x="0.000" y="0.000" width="1.000" height="1.000" fill-opacity="0.60""#)
+        );
+    }
+
+    #[test]
+    fn it_decays_old_visits() {
+        let mut heatmap = Heatmap::default();
+        heatmap.tick([(0.0, 0.0)]);
+        heatmap.tick([]);
+        assert_eq!(heatmap.buckets[0][0], DECAY);
+        heatmap.tick([(0.0, 0.0)]);
+        assert_eq!(heatmap.buckets[0][0], DECAY * DECAY + 1.0);
+        for _ in 0..100 {
+            heatmap.tick([]);
+        }
+        assert_eq!(heatmap.buckets[0][0], 0.0);
+        assert!(!heatmap.svg().into_string().contains("<rect"));
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Display},
+};
+
+use super::{CheckboxState, CursorId};
+
+/// How many cell changes are kept for diffing revisions of the current puzzle.
+pub const RETAINED_CHANGES: usize = 2000;
+
+/// A single accepted change to a cell.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Change {
+    /// Revision of the board right after the change.
+    revision: u64,
+    cell: usize,
+    before: CheckboxState,
+    after: CheckboxState,
+    session: Option<CursorId>,
+}
+
+/// How a cell differs between two revisions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CellDiff {
+    pub cell: usize,
+    pub before: CheckboxState,
+    pub after: CheckboxState,
+    /// Whoever changed the cell last within the range, if known.
+    pub session: Option<CursorId>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiffError {
+    /// The changes right after `from` were dropped, or belong to an earlier puzzle.
+    Truncated { oldest: u64 },
+    /// `from` comes after `to`, or `to` is later than the current revision.
+    InvalidRange { current: u64 },
+}
+
+impl Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffError::Truncated { oldest } => write!(
+                f,
+                "History truncated: the oldest available revision is {oldest}."
+            ),
+            DiffError::InvalidRange { current } => write!(
+                f,
+                "Invalid range: revisions must be in order and no later than the current revision {current}."
+            ),
+        }
+    }
+}
+
+/// Recent cell changes of the current puzzle, so that operators can tell who changed what between two revisions.
+#[derive(Clone, Debug)]
+pub struct RevisionHistory {
+    /// Earliest revision that diffs may start from.
+    oldest: u64,
+    changes: VecDeque<Change>,
+}
+
+impl RevisionHistory {
+    /// Starts an empty history for a board at `revision`.
+    pub fn new(revision: u64) -> Self {
+        RevisionHistory {
+            oldest: revision,
+            changes: VecDeque::new(),
+        }
+    }
+
+    /// Records a change that brought the board to `revision`, dropping the oldest one if needed.
+    pub fn record(
+        &mut self,
+        revision: u64,
+        cell: usize,
+        before: CheckboxState,
+        after: CheckboxState,
+        session: Option<CursorId>,
+    ) {
+        if self.changes.len() == RETAINED_CHANGES {
+            if let Some(dropped) = self.changes.pop_front() {
+                self.oldest = dropped.revision;
+            }
+        }
+        self.changes.push_back(Change {
+            revision,
+            cell,
+            before,
+            after,
+            session,
+        });
+    }
+
+    /// Records a change that brought the board to `revision`, merged with the latest change to the same cell by the
+    /// same session. The merged change moves to `revision`, and is dropped if it changed the cell back. Recorded on its
+    /// own if there's nothing to merge with.
+    pub fn coalesce(
+        &mut self,
+        revision: u64,
+        cell: usize,
+        before: CheckboxState,
+        after: CheckboxState,
+        session: Option<CursorId>,
+    ) {
+        let latest = self.changes.iter().rposition(|change| change.cell == cell);
+        let Some(index) = latest.filter(|&index| self.changes[index].session == session) else {
+            return self.record(revision, cell, before, after, session);
+        };
+        let merged = self.changes.remove(index).unwrap();
+        if merged.before != after {
+            self.record(revision, cell, merged.before, after, session);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Cells that differ between revisions `from` and `to`, by ID. Cells that were changed back are left out.
+    pub fn diff(&self, from: u64, to: u64, current: u64) -> Result<Vec<CellDiff>, DiffError> {
+        if from > to || to > current {
+            return Err(DiffError::InvalidRange { current });
+        }
+        if from < self.oldest {
+            return Err(DiffError::Truncated {
+                oldest: self.oldest,
+            });
+        }
+        let mut cells = BTreeMap::<usize, CellDiff>::new();
+        for change in self
+            .changes
+            .iter()
+            .filter(|change| change.revision > from && change.revision <= to)
+        {
+            cells
+                .entry(change.cell)
+                .and_modify(|diff| {
+                    diff.after = change.after;
+                    diff.session = change.session;
+                })
+                .or_insert(CellDiff {
+                    cell: change.cell,
+                    before: change.before,
+                    after: change.after,
+                    session: change.session,
+                });
+        }
+        Ok(cells
+            .into_values()
+            .filter(|diff| diff.before != diff.after)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CheckboxState::*;
+
+    #[test]
+    fn it_diffs_revisions() {
+        let mut history = RevisionHistory::new(10);
+        let alice = Some(CursorId(1));
+        let bob = Some(CursorId(2));
+        history.record(11, 0, Empty, Marked, alice);
+        history.record(12, 1, Empty, Flagged, alice);
+        history.record(13, 0, Marked, Empty, bob);
+        history.record(14, 2, Empty, Marked, None);
+        history.record(15, 1, Flagged, Marked, bob);
+        assert_eq!(
+            history.diff(10, 15, 15),
+            Ok(vec![
+                CellDiff {
+                    cell: 1,
+                    before: Empty,
+                    after: Marked,
+                    session: bob,
+                },
+                CellDiff {
+                    cell: 2,
+                    before: Empty,
+                    after: Marked,
+                    session: None,
+                },
+            ])
+        );
+        assert_eq!(
+            history.diff(11, 13, 15),
+            Ok(vec![
+                CellDiff {
+                    cell: 0,
+                    before: Marked,
+                    after: Empty,
+                    session: bob,
+                },
+                CellDiff {
+                    cell: 1,
+                    before: Empty,
+                    after: Flagged,
+                    session: alice,
+                },
+            ])
+        );
+        assert_eq!(history.diff(15, 15, 15), Ok(vec![]));
+        assert_eq!(
+            history.diff(12, 11, 15),
+            Err(DiffError::InvalidRange { current: 15 })
+        );
+        assert_eq!(
+            history.diff(12, 16, 15),
+            Err(DiffError::InvalidRange { current: 15 })
+        );
+    }
+
+    #[test]
+    fn it_coalesces_changes_by_the_same_session() {
+        let mut history = RevisionHistory::new(0);
+        let alice = Some(CursorId(1));
+        let bob = Some(CursorId(2));
+        history.record(1, 0, Empty, Marked, alice);
+        history.record(2, 1, Empty, Marked, bob);
+        history.coalesce(3, 0, Marked, Empty, alice);
+        history.coalesce(4, 0, Empty, Marked, alice);
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history.diff(2, 4, 4),
+            Ok(vec![CellDiff {
+                cell: 0,
+                before: Empty,
+                after: Marked,
+                session: alice,
+            }])
+        );
+        // Changing a cell back drops the change altogether.
+        history.coalesce(5, 1, Marked, Empty, bob);
+        assert_eq!(history.len(), 1);
+        // Someone else's change is never merged.
+        history.coalesce(6, 0, Marked, Flagged, bob);
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn it_truncates_old_changes() {
+        let mut history = RevisionHistory::new(0);
+        for revision in 1..=RETAINED_CHANGES as u64 + 5 {
+            history.record(revision, 0, Empty, Marked, None);
+        }
+        assert_eq!(history.oldest, 5);
+        assert_eq!(
+            history.diff(4, 10, RETAINED_CHANGES as u64 + 5),
+            Err(DiffError::Truncated { oldest: 5 })
+        );
+        assert!(history.diff(5, 10, RETAINED_CHANGES as u64 + 5).is_ok());
+    }
+}
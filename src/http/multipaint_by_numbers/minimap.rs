@@ -0,0 +1,109 @@
+use maud::{html, Markup};
+
+use super::CheckboxState;
+
+/// Boards smaller than this on either side fit on screen, and don't get a minimap.
+pub const MINIMAP_MIN_SIDE: usize = 15;
+
+/// How many pixels each cell takes in the minimap.
+const MINIMAP_CELL_SIZE: usize = 3;
+
+/// A horizontal run of cells in the same state, as `(row, first column, length)`.
+type Run = (usize, usize, usize);
+
+/// Splits each row into runs of marked and flagged cells, so that every run can be drawn as a single rect.
+fn runs(checkboxes: &[CheckboxState], columns: usize) -> (Vec<Run>, Vec<Run>) {
+    let mut marked = vec![];
+    let mut flagged = vec![];
+    for (i, row) in checkboxes.chunks(columns).enumerate() {
+        let mut j = 0;
+        while j < row.len() {
+            let state = row[j];
+            let length = row[j..].iter().take_while(|&&cell| cell == state).count();
+            match state {
+                CheckboxState::Marked => marked.push((i, j, length)),
+                CheckboxState::Flagged => flagged.push((i, j, length)),
+                CheckboxState::Empty => (),
+            }
+            j += length;
+        }
+    }
+    (marked, flagged)
+}
+
+/// Renders the whole board as a small SVG. Returns `None` for boards that are too small to need it.
+pub fn minimap_svg(checkboxes: &[CheckboxState], rows: usize, columns: usize) -> Option<Markup> {
+    if rows < MINIMAP_MIN_SIDE || columns < MINIMAP_MIN_SIDE {
+        return None;
+    }
+    Some(board_svg(
+        "minimap",
+        checkboxes,
+        rows,
+        columns,
+        MINIMAP_CELL_SIZE,
+    ))
+}
+
+/// Renders the whole board as an SVG with the given class, taking `cell_size` pixels per cell.
+pub fn board_svg(
+    class: &str,
+    checkboxes: &[CheckboxState],
+    rows: usize,
+    columns: usize,
+    cell_size: usize,
+) -> Markup {
+    let (marked, flagged) = runs(checkboxes, columns);
+    html! {
+        svg class=(class) xmlns="http://www.w3.org/2000/svg" viewBox=(format!("0 0 {columns} {rows}")) width=(columns * cell_size) height=(rows * cell_size) shape-rendering="crispEdges" {
+            g .minimap-flagged {
+                @for (y, x, width) in flagged {
+                    rect x=(x) y=(y) width=(width) height="1" {}
+                }
+            }
+            g .minimap-marked {
+                @for (y, x, width) in marked {
+                    rect x=(x) y=(y) width=(width) height="1" {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses rows where `#` is marked, `x` is flagged, and anything else is empty.
+    fn fixture(rows: &[&str]) -> Vec<CheckboxState> {
+        rows.iter()
+            .flat_map(|row| row.chars())
+            .map(|cell| match cell {
+                '#' => CheckboxState::Marked,
+                'x' => CheckboxState::Flagged,
+                _ => CheckboxState::Empty,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_merges_horizontal_runs() {
+        let checkboxes = fixture(&["###..#", "xx##xx", "......", "######"]);
+        let (marked, flagged) = runs(&checkboxes, 6);
+        assert_eq!(marked, vec![(0, 0, 3), (0, 5, 1), (1, 2, 2), (3, 0, 6)]);
+        assert_eq!(flagged, vec![(1, 0, 2), (1, 4, 2)]);
+    }
+
+    #[test]
+    fn it_skips_small_boards() {
+        let small = vec![CheckboxState::Marked; 14 * 40];
+        assert!(minimap_svg(&small, 14, 40).is_none());
+        assert!(minimap_svg(&small, 40, 14).is_none());
+        let large = vec![CheckboxState::Marked; 30 * 40];
+        let svg = minimap_svg(&large, 30, 40).unwrap().into_string();
+        // One rect per row, regardless of the width.
+        assert_eq!(svg.matches("<rect").count(), 30);
+        assert!(svg.contains(r#"viewBox="0 0 40 30""#));
+        assert!(svg.len() < 4096);
+    }
+}
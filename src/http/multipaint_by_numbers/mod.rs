@@ -0,0 +1,4030 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    sync::{Arc, LazyLock, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{
+        header::{CONTENT_TYPE, ETAG, RETRY_AFTER, SET_COOKIE},
+        request::Parts,
+        HeaderValue,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Extension, Router,
+};
+use bitvec::{order::Lsb0, slice::BitSlice};
+use hyper::{HeaderMap, StatusCode};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use rand::Rng;
+use serde::Deserialize;
+use tokio::{
+    sync::watch::{self, Receiver, Sender},
+    task::AbortHandle,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+mod admin;
+mod api;
+mod coaching;
+pub mod compact;
+pub mod config;
+mod fetch;
+mod heatmap;
+mod history;
+mod minimap;
+mod palette;
+mod preview;
+mod recovery;
+mod replay;
+pub mod rotation;
+mod session;
+mod view;
+
+use self::{
+    coaching::PlayStats,
+    config::MultipaintConfig,
+    fetch::PuzzleFetcher,
+    heatmap::{Heatmap, HEATMAP_TICK},
+    history::RevisionHistory,
+    preview::{PendingPuzzles, CUSTOM_PUZZLE_ID},
+    recovery::{
+        cell_char, cell_state, BoardEvent, BoardLog, BoardSnapshot, BOARD_EVENTS_FILE,
+        BOARD_SNAPSHOT_FILE, SNAPSHOT_INTERVAL,
+    },
+    replay::Replay,
+    rotation::{PuzzleKey, RecentlyPlayed, RefillStrategy, Rotation, RECENTLY_PLAYED_FILE},
+    view::{
+        render_board, render_cell, render_index, timer_shows_hours, BoardView, IndexView, TimerView,
+    },
+};
+use crate::{
+    accounting::{Gauge, MemoryAccounting},
+    assets::{self, HTMX},
+    clock::Clock,
+    handoff::ShutdownHooks,
+    http::{
+        alert::{self, AlertBanner},
+        custom_assets::CustomAssets,
+        embed::EmbedOrigins,
+        export::ExportLimiter,
+        form::LenientForm,
+        identity::Identity,
+        landing::{base_href, MountPath, SUMMARY_CACHE_CONTROL},
+        maintenance::{self, MaintenanceMode},
+        metrics::StatusSections,
+        registry::{self, ActivityContext},
+        secure_context::{self, SecureContext},
+        shedding::LoadShedder,
+        trigger::TriggerPayload,
+        tunnel_status,
+    },
+    nonogram::{
+        funnel::FunnelEvent,
+        nonogrammed::{NonogrammedPuzzle, NONOGRAMMED_PUZZLE_LIST},
+        rejection::RejectionLog,
+        PuzzleSource,
+    },
+    storage::DataDir,
+    supervisor::Supervisor,
+    tasks::TaskRegistry,
+    tunnel::{TunnelLink, TunnelLinkState, TunnelStatusCell},
+};
+
+/* Type defintions */
+
+#[derive(Copy, Clone, PartialEq)]
+enum NonogramState {
+    Unsolved,
+    Solved(Duration),
+    Failed,
+}
+
+struct Timer {
+    start: Instant,
+    duration: Duration,
+    abort_handle: Option<AbortHandle>,
+    /// When maintenance mode or a tunnel outage paused the timer, if it's paused.
+    paused_at: Option<Instant>,
+}
+
+impl Timer {
+    fn time_left(&self, now: Instant) -> Duration {
+        let until = self.paused_at.unwrap_or(now);
+        self.duration
+            .saturating_sub(until.saturating_duration_since(self.start))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum CheckboxState {
+    Empty,
+    Flagged,
+    Marked,
+}
+
+impl CheckboxState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckboxState::Empty => "empty",
+            CheckboxState::Flagged => "flagged",
+            CheckboxState::Marked => "marked",
+        }
+    }
+}
+
+struct Nonogram {
+    state: NonogramState,
+    puzzle_sender: Sender<NonogrammedPuzzle>,
+    checkboxes: Vec<CheckboxState>,
+    /// How many cells disagree with the solution. The puzzle is solved once this reaches zero.
+    wrong_squares: usize,
+    /// Increases with every change to the board, including new puzzles, so that clients can discard stale
+    /// responses.
+    revision: u64,
+    /// Increases with every new puzzle.
+    generation: u64,
+    /// Reset with every new puzzle.
+    stats: PlayStats,
+    /// Reset with every new puzzle.
+    history: RevisionHistory,
+    /// Reset with every new puzzle.
+    replay: Replay,
+    /// Who last changed each cell and when, to tell quick successive toggles apart. Reset with every new puzzle.
+    last_changes: HashMap<usize, LastChange>,
+    timer: Timer,
+    /// With `--manual-submit`, no one can check the solution again until then, so that wrong checks can't be used
+    /// to narrow down the solution. Reset with every new puzzle.
+    submit_cooldown_until: Option<Instant>,
+}
+
+/// The latest change to a cell by a known player.
+#[derive(Copy, Clone, Debug)]
+struct LastChange {
+    session: CursorId,
+    at: Instant,
+}
+
+impl Nonogram {
+    /// Whether a change to cell `id` by `session` at `now` is part of the same action as their previous change to
+    /// it, within `window`. Remembers this change for the next one.
+    fn coalesces(
+        &mut self,
+        id: usize,
+        session: Option<CursorId>,
+        now: Instant,
+        window: Duration,
+    ) -> bool {
+        let Some(session) = session else {
+            self.last_changes.remove(&id);
+            return false;
+        };
+        let previous = self
+            .last_changes
+            .insert(id, LastChange { session, at: now });
+        previous.is_some_and(|previous| {
+            previous.session == session && now.saturating_duration_since(previous.at) < window
+        })
+    }
+
+    /// Changes the state of a cell, keeping `wrong_squares` up to date. Only marks count towards the solution.
+    fn set_checkbox(
+        &mut self,
+        solution: &BitSlice<usize, Lsb0>,
+        id: usize,
+        checkbox_state: CheckboxState,
+    ) {
+        let previous = mem::replace(&mut self.checkboxes[id], checkbox_state);
+        if previous != checkbox_state {
+            self.revision += 1;
+        }
+        let was_wrong = solution[id] != (previous == CheckboxState::Marked);
+        let is_wrong = solution[id] != (checkbox_state == CheckboxState::Marked);
+        match (was_wrong, is_wrong) {
+            (false, true) => self.wrong_squares += 1,
+            (true, false) => self.wrong_squares -= 1,
+            _ => (),
+        }
+    }
+
+    fn snapshot(&self, key: PuzzleKey) -> BoardSnapshot {
+        BoardSnapshot {
+            puzzle: key,
+            generation: self.generation,
+            revision: self.revision,
+            cells: self.checkboxes.iter().copied().map(cell_char).collect(),
+        }
+    }
+
+    /// Restores a recovered board. Returns `false`, leaving the board untouched, if it doesn't fit the puzzle.
+    fn restore(&mut self, solution: &BitSlice<usize, Lsb0>, snapshot: &BoardSnapshot) -> bool {
+        let Some(cells) = snapshot
+            .cells
+            .chars()
+            .map(cell_state)
+            .collect::<Option<Vec<_>>>()
+            .filter(|cells| cells.len() == self.checkboxes.len())
+        else {
+            return false;
+        };
+        for (id, cell) in cells.into_iter().enumerate() {
+            self.set_checkbox(solution, id, cell);
+        }
+        self.revision = snapshot.revision;
+        self.generation = snapshot.generation;
+        self.history = RevisionHistory::new(snapshot.revision);
+        self.replay = Replay::from_board(self.replay.start(), &self.checkboxes);
+        self.last_changes.clear();
+        true
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+struct CursorPosition(i32, i32);
+
+#[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Copy, Clone, Debug)]
+struct CursorId(u64);
+
+struct Cursor {
+    id: CursorId,
+    name: Option<String>,
+    modified_at: Instant,
+    position: CursorPosition,
+    /// Where the cursor is as fractions of the board, for the heatmap, if the page told us its size.
+    focus: Option<(f32, f32)>,
+    /// Which palette color this cursor holds until it goes away, if there was one left.
+    slot: Option<usize>,
+    color: [u8; 3],
+    actions: u64,
+    /// When this cursor last tried to change the board.
+    last_action: Option<Instant>,
+}
+
+/// Approximate memory used by the cursors map.
+fn cursors_size(cursors: &HashMap<CursorId, Cursor>) -> usize {
+    cursors
+        .values()
+        .map(|cursor| {
+            mem::size_of::<(CursorId, Cursor)>() + cursor.name.as_ref().map_or(0, String::len)
+        })
+        .sum()
+}
+
+/// Evicts the least recently active cursors until they fit within the budget.
+fn evict_cursors(cursors: &Mutex<HashMap<CursorId, Cursor>>, budget: usize) -> usize {
+    let mut cursors = cursors.lock().unwrap();
+    let mut oldest = cursors
+        .values()
+        .map(|cursor| (cursor.modified_at, cursor.id))
+        .collect::<Vec<_>>();
+    oldest.sort();
+    let mut oldest = oldest.into_iter();
+    while cursors_size(&cursors) > budget {
+        match oldest.next() {
+            Some((_, id)) => cursors.remove(&id),
+            None => break,
+        };
+    }
+    cursors_size(&cursors)
+}
+
+impl Cursor {
+    fn new(
+        id: CursorId,
+        name: Option<String>,
+        position: CursorPosition,
+        slot: Option<usize>,
+        now: Instant,
+    ) -> Self {
+        Cursor {
+            id,
+            name,
+            modified_at: now,
+            position,
+            focus: None,
+            slot,
+            color: palette::cursor_color(slot, id.0),
+            actions: 0,
+            last_action: None,
+        }
+    }
+}
+
+/// A temporary restriction placed on a cursor by an operator. A cursor can be under both at once, each with its own
+/// expiry.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum SanctionKind {
+    /// Mutations are silently ignored, but still render as if they succeeded.
+    Muted,
+    /// The player is handed a fresh session and told to rejoin, and mutations from the old one are ignored.
+    Kicked,
+}
+
+/// While shedding load, players who changed the board this recently still get fresh boards from the poll.
+const RECENT_ACTION: Duration = Duration::from_secs(10);
+
+/// The cursor of the player making a request, from their identity or from the session cookie that we issued. Missing
+/// for anonymous players without a valid session, who can't change the board.
+struct SessionCursor(Option<CursorId>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionCursor
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let identity = Identity::from_request_parts(parts, state).await?;
+        Ok(SessionCursor(
+            identity
+                .stable_id()
+                .map(CursorId)
+                .or_else(|| session::from_headers(&parts.headers)),
+        ))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CursorsPayload {
+    #[serde(rename = "mouseX")]
+    mouse_x: i32,
+    #[serde(rename = "mouseY")]
+    mouse_y: i32,
+    #[serde(rename = "boardWidth", default)]
+    board_width: Option<f32>,
+    #[serde(rename = "boardHeight", default)]
+    board_height: Option<f32>,
+}
+
+impl CursorsPayload {
+    fn focus(&self) -> Option<(f32, f32)> {
+        let (width, height) = (self.board_width?, self.board_height?);
+        (width > 0.0 && height > 0.0)
+            .then(|| (self.mouse_x as f32 / width, self.mouse_y as f32 / height))
+    }
+}
+
+static VERSION: LazyLock<u32> = LazyLock::new(|| {
+    let mut rng = rand::thread_rng();
+    rng.gen()
+});
+
+/* Router definition */
+
+#[derive(Clone)]
+struct AppState {
+    nonogram: Arc<Mutex<Nonogram>>,
+    puzzle: Arc<Receiver<NonogrammedPuzzle>>,
+    /// Locked on its own, so that the next puzzle can be picked without blocking the board. Never lock the nonogram
+    /// while holding this.
+    rotation: Arc<Mutex<Rotation>>,
+    cursors: Arc<Mutex<HashMap<CursorId, Cursor>>>,
+    /// When each sanction on a cursor expires.
+    sanctions: Arc<Mutex<HashMap<(CursorId, SanctionKind), Instant>>>,
+    fetcher: PuzzleFetcher,
+    board_log: BoardLog,
+    cursors_gauge: Gauge,
+    /// Size of the current puzzle's replay.
+    replay_gauge: Gauge,
+    heatmap: Arc<Mutex<Heatmap>>,
+    exports: ExportLimiter,
+    /// Sites allowed to frame `/embed`.
+    embed_origins: EmbedOrigins,
+    config: MultipaintConfig,
+    /// Restarts the timer and intermission tasks if they panic.
+    supervisor: Supervisor,
+    /// Background tasks that shutdown stops.
+    tasks: TaskRegistry,
+    /// Puzzles made from uploaded images, waiting for an operator to confirm them.
+    previews: PendingPuzzles,
+    /// Freezes the board, pausing the timer and the rotation.
+    maintenance: MaintenanceMode,
+    /// Pauses the timer while players can't reach the board.
+    tunnel: TunnelLink,
+    clock: Clock,
+}
+
+impl AppState {
+    fn new(
+        first_puzzle: NonogrammedPuzzle,
+        rotation: Mutex<Rotation>,
+        fetcher: PuzzleFetcher,
+        board_log: BoardLog,
+        accounting: &MemoryAccounting,
+        clock: Clock,
+    ) -> Self {
+        let cursors = Arc::new(Mutex::new(HashMap::new()));
+        let cursors_gauge = accounting.register(
+            "multipaint_cursors",
+            Some(Box::new({
+                let cursors = Arc::clone(&cursors);
+                move |budget| evict_cursors(&cursors, budget)
+            })),
+        );
+        let replay_gauge = accounting.register("multipaint_replay", None);
+        if let Some((source, _)) = rotation.lock().unwrap().current() {
+            fetcher.funnel().record(source, FunnelEvent::Started);
+        }
+        let rows = first_puzzle.rows.len();
+        let columns = first_puzzle.columns.len();
+        let wrong_squares = first_puzzle.solution.count_ones();
+        let (tx, rx) = watch::channel(first_puzzle);
+        AppState {
+            puzzle: Arc::new(rx),
+            rotation: Arc::new(rotation),
+            nonogram: Arc::new(Mutex::new(Nonogram {
+                checkboxes: vec![CheckboxState::Empty; rows * columns],
+                wrong_squares,
+                revision: 0,
+                generation: 0,
+                stats: PlayStats::default(),
+                history: RevisionHistory::new(0),
+                replay: Replay::new(clock.now()),
+                last_changes: HashMap::new(),
+                timer: Timer {
+                    start: clock.now(),
+                    duration: get_duration_for_puzzle(rows, columns),
+                    abort_handle: None,
+                    paused_at: None,
+                },
+                state: NonogramState::Unsolved,
+                puzzle_sender: tx,
+                submit_cooldown_until: None,
+            })),
+            cursors,
+            sanctions: Arc::new(Mutex::new(HashMap::new())),
+            fetcher,
+            board_log,
+            cursors_gauge,
+            replay_gauge,
+            heatmap: Arc::new(Mutex::new(Heatmap::default())),
+            exports: ExportLimiter::default(),
+            embed_origins: EmbedOrigins::default(),
+            config: MultipaintConfig::default(),
+            supervisor: Supervisor::new(clock.clone()),
+            tasks: TaskRegistry::new(clock.clone()),
+            previews: PendingPuzzles::default(),
+            maintenance: MaintenanceMode::default(),
+            tunnel: TunnelLink::default(),
+            clock,
+        }
+    }
+}
+
+/// A lazily-created Router, to be used by the SSH client tunnels.
+///
+/// Only the shared services are taken from `context`; Multipaint's own settings come from `config`.
+pub async fn build_router(config: MultipaintConfig, context: ActivityContext) -> Result<Router> {
+    let ActivityContext {
+        upstreams,
+        upstream_client,
+        data_dir,
+        accounting,
+        status,
+        clock,
+        random,
+        timezone,
+        embed_origins,
+        funnel,
+        shutdown,
+        maintenance,
+        tasks,
+        tunnel,
+        ..
+    } = context;
+    let fetcher = PuzzleFetcher::new(
+        upstreams,
+        upstream_client,
+        RejectionLog::new(data_dir.clone(), timezone),
+        clock.clone(),
+        random.clone(),
+        funnel,
+    );
+    let recent = RecentlyPlayed::load(data_dir.as_ref(), config.recently_played()).await;
+    let recovered = match &data_dir {
+        Some(data_dir) => recovery::recover(data_dir).await.unwrap_or_else(|e| {
+            warn!(error = ?e, "Unable to recover the board.");
+            None
+        }),
+        None => None,
+    };
+    if let Some(data_dir) = &data_dir {
+        pin_live_artifacts(data_dir);
+    }
+    let board_log = BoardLog::new(data_dir.clone(), config.event_log(), &tasks);
+    let rotation = Mutex::new(
+        Rotation::new(
+            NONOGRAMMED_PUZZLE_LIST.to_vec(),
+            recent,
+            config.refill_strategy(),
+            data_dir,
+            random,
+        )
+        .with_tasks(tasks.clone()),
+    );
+    if let Some(snapshot) = &recovered {
+        rotation.lock().unwrap().enqueue(snapshot.puzzle, true);
+    }
+    let first_puzzle = fetcher
+        .next_puzzle(&rotation)
+        .await
+        .with_context(|| "No valid puzzles to start with")?;
+    let state = AppState {
+        embed_origins,
+        config,
+        maintenance,
+        tunnel,
+        supervisor: Supervisor::new(clock.clone()).with_tasks(tasks.clone()),
+        tasks,
+        ..AppState::new(
+            first_puzzle,
+            rotation,
+            fetcher,
+            board_log,
+            &accounting,
+            clock,
+        )
+    };
+    {
+        let current = state.rotation.lock().unwrap().current();
+        let mut nonogram = state.nonogram.lock().unwrap();
+        nonogram.timer.duration = state.config.durations().clamp(nonogram.timer.duration);
+        if let Some(snapshot) = recovered {
+            let puzzle = state.puzzle.borrow();
+            if current == Some(snapshot.puzzle) && nonogram.restore(&puzzle.solution, &snapshot) {
+                info!(
+                    revision = snapshot.revision,
+                    "Recovered the board from the data dir."
+                );
+            } else {
+                // Start over with a new generation, so that leftover events are never replayed.
+                nonogram.generation = snapshot.generation + 1;
+            }
+        }
+        save_snapshot(&state, &nonogram, current);
+        start_timer(&state, &mut nonogram);
+    }
+    if state.board_log.is_enabled() {
+        // Make sure that the recovered board is safe before accepting new moves.
+        state.board_log.flush().await;
+        spawn_snapshots(state.clone());
+        register_shutdown(&state, &shutdown);
+    }
+    spawn_heatmap(state.clone());
+    register_status(&state, &status);
+    register_maintenance(&state);
+    spawn_tunnel_watch(state.clone());
+    Ok(router(state))
+}
+
+/// Keeps the files that Multipaint is using from being pruned.
+pub fn pin_live_artifacts(data_dir: &DataDir) {
+    for name in [BOARD_SNAPSHOT_FILE, BOARD_EVENTS_FILE, RECENTLY_PLAYED_FILE] {
+        data_dir.pin(name);
+    }
+}
+
+/// Registers this activity as `multipaint`, configured from [`ActivityContext::multipaint`].
+pub fn register() {
+    registry::register(
+        "multipaint",
+        "Multipaint by Numbers - A multiplayer nonogram/picross.",
+        |context| build_router(context.multipaint.clone(), context),
+    );
+}
+
+/// Shows the state of the puzzle rotation and of the upstreams on the `/admin/status` page.
+fn register_status(state: &AppState, status: &StatusSections) {
+    let rotation = Arc::clone(&state.rotation);
+    status.register(
+        "Multipaint rotation",
+        Box::new(move || {
+            let rotation = rotation.lock().unwrap();
+            vec![
+                (
+                    "Puzzles left",
+                    format!("{} of {}", rotation.remaining(), rotation.total()),
+                ),
+                ("Refill strategy", rotation.strategy().to_string()),
+                ("Refills", rotation.refills().to_string()),
+                (
+                    "Session",
+                    String::from(if rotation.is_exhausted() {
+                        "Over"
+                    } else {
+                        "Running"
+                    }),
+                ),
+            ]
+        }),
+    );
+    let supervisor = state.supervisor.clone();
+    status.register(
+        "Multipaint tasks",
+        Box::new(move || {
+            vec![(
+                "Last failure",
+                supervisor
+                    .last_failure()
+                    .map_or(String::from("none"), |failure| failure.to_string()),
+            )]
+        }),
+    );
+    let breakers = state.fetcher.breakers().clone();
+    let clock = state.clock.clone();
+    status.register(
+        "Upstream circuit breakers",
+        Box::new(move || {
+            breakers
+                .states(clock.now())
+                .into_iter()
+                .map(|(source, state)| (source.as_str(), state.to_string()))
+                .collect()
+        }),
+    );
+}
+
+/// Persists the whole board, given the puzzle being played. Must be called while holding the nonogram lock.
+fn save_snapshot(state: &AppState, nonogram: &Nonogram, current: Option<PuzzleKey>) {
+    // Uploaded puzzles can't be fetched again after a restart.
+    if current.is_none() && state.puzzle.borrow().id == CUSTOM_PUZZLE_ID {
+        return;
+    }
+    let key = current.unwrap_or((PuzzleSource::Nonogrammed, state.puzzle.borrow().id));
+    state.board_log.snapshot(nonogram.snapshot(key));
+}
+
+/// Snapshots the board one last time before exiting, so that the next instance picks up every move.
+fn register_shutdown(state: &AppState, shutdown: &ShutdownHooks) {
+    let state = state.clone();
+    shutdown.register(Box::new(move || {
+        let state = state.clone();
+        Box::pin(async move {
+            let current = state.rotation.lock().unwrap().current();
+            save_snapshot(&state, &state.nonogram.lock().unwrap(), current);
+            state.board_log.flush().await;
+            info!("Saved the board before exiting.");
+        })
+    }));
+}
+
+/// Snapshots the board periodically, whenever it changed.
+fn spawn_snapshots(state: AppState) {
+    state.tasks.clone().spawn("board snapshots", async move {
+        let mut last_revision = state.nonogram.lock().unwrap().revision;
+        loop {
+            state.clock.sleep(SNAPSHOT_INTERVAL).await;
+            let current = state.rotation.lock().unwrap().current();
+            let nonogram = state.nonogram.lock().unwrap();
+            if nonogram.revision != last_revision {
+                last_revision = nonogram.revision;
+                save_snapshot(&state, &nonogram, current);
+            }
+        }
+    });
+}
+
+/// Samples every cursor into the heatmap periodically.
+fn spawn_heatmap(state: AppState) {
+    state.tasks.clone().spawn("heatmap sampling", async move {
+        loop {
+            state.clock.sleep(HEATMAP_TICK).await;
+            let positions = state
+                .cursors
+                .lock()
+                .unwrap()
+                .values()
+                .filter_map(|cursor| cursor.focus)
+                .collect::<Vec<_>>();
+            state.heatmap.lock().unwrap().tick(positions);
+        }
+    });
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(index))
+        .route("/htmx.js", get(htmx_minified))
+        .route("/nonogram", get(nonogram))
+        .route("/minimap", get(minimap))
+        .route("/heatmap.svg", get(heatmap))
+        .route("/embed", get(embed))
+        .route("/embed/board", get(embed_board))
+        .route("/summary", get(summary))
+        .route("/cursor", post(cursor))
+        .route("/submit", post(submit))
+        .route("/flag/:id", put(flag_checkbox).delete(unflag_checkbox))
+        .route("/checkbox/:id", put(mark_checkbox).delete(unmark_checkbox))
+        .route("/cell/:row/:column", put(mark_cell).delete(unmark_cell))
+        .route(
+            "/cell/:row/:column/flag",
+            put(flag_cell).delete(unflag_cell),
+        )
+        .merge(admin::router())
+        .nest("/api", api::router())
+        .with_state(state)
+}
+
+/* Main page elements */
+
+static STYLE: &str = r#"
+body {
+    color: #06060c;
+    background-color: #fff;
+    min-height: 100vh;
+}
+a {
+    color: #22e;
+}
+.hidden {
+    display: none;
+}
+.insecure-notice {
+    padding: 0.5em;
+    border: 1px solid #c90;
+    background-color: #fff4d6;
+}
+h2#congratulations {
+    color: #060;
+}
+hr {
+    margin-top: 28px;
+    margin-bottom: 28px;
+}
+.nonogram-scroll {
+    overflow: auto;
+    max-width: 100%;
+    max-height: 90vh;
+}
+table {
+    border-collapse: collapse;
+    overflow: clip;
+}
+tbody tr:nth-child(5n + 1) {
+    border-top: 1pt solid;
+    border-top-color: #000;
+}
+tr th:nth-child(5n - 3), tr td:nth-child(5n - 3) {
+    border-left: 1pt solid;
+    border-left-color: #000;
+}
+th.column-clue {
+    vertical-align: bottom;
+}
+th.column-clue > div {
+    display: flex;
+    flex-direction: column;
+    justify-content: end;
+}
+th.row-clue > div {
+    display: flex;
+    justify-content: end;
+    column-gap: 6px;
+    margin-right: 2px;
+}
+tr:hover {
+    background-color: #ff9;
+}
+td, th {
+    position: relative;
+}
+thead th, th.row-clue {
+    position: sticky;
+    background-color: #fff;
+    z-index: 5;
+}
+thead th {
+    top: 0;
+}
+th.row-clue, th.corner {
+    left: 0;
+}
+th.corner {
+    z-index: 6;
+}
+td:hover::after, th:hover::after {
+    content: "";
+    position: absolute;
+    background-color: #ff9;
+    left: 0;
+    top: -5023px;
+    height: 13337px;
+    width: 100%;
+    z-index: -1;
+}
+.checkbox {
+    position: relative;
+}
+.checkbox div {
+    pointer-events: none;
+}
+.checkbox .mark {
+    position: absolute;
+    inset: 0;
+    z-index: 2;
+}
+table:not(.solved) .checkbox.flagged .mark {
+    background: #c76;
+    border-radius: 2px;
+}
+table.solved .checkbox.marked .mark {
+    background: #111;
+}
+input[type="checkbox"] {
+    z-index: 1;
+    transform: scale(1.4);
+}
+#cursors {
+    position: absolute;
+    z-index: 3;
+    overflow: visible;
+    pointer-events: none;
+}
+svg.cursor {
+    position: absolute;
+    top: 0;
+    left: 0;
+    opacity: 0.9;
+    transition-property: transform;
+    transition-timing-function: cubic-bezier(0.4, 0, 0.2, 1);
+    transition-duration: 150ms;
+}
+#heatmap {
+    position: absolute;
+    z-index: 3;
+    pointer-events: none;
+}
+svg.heatmap {
+    display: block;
+    fill: #f60;
+}
+#minimap {
+    position: fixed;
+    right: 12px;
+    bottom: 12px;
+    z-index: 7;
+    pointer-events: none;
+}
+svg.minimap {
+    display: block;
+    background-color: #fff;
+    border: 1pt solid #000;
+    opacity: 0.85;
+}
+.minimap-marked {
+    fill: #111;
+}
+.minimap-flagged {
+    fill: #c76;
+    opacity: 0.6;
+}
+.tunnel-banner {
+    padding: 8px;
+    background-color: #fd6;
+    color: #06060c;
+}
+.cursor-name {
+    position: absolute;
+    top: 16px;
+    left: 8px;
+    font-size: 0.75em;
+    white-space: nowrap;
+    text-shadow: 0 0 2px #000;
+    transition-property: transform;
+    transition-timing-function: cubic-bezier(0.4, 0, 0.2, 1);
+    transition-duration: 150ms;
+}
+.hint {
+    z-index: 4;
+}
+th > div.compact {
+    display: block;
+    max-width: 6em;
+    font-size: 0.6em;
+    overflow-wrap: anywhere;
+}
+#puzzles-left {
+    font-size: 0.85em;
+    font-style: italic;
+}
+@media(prefers-color-scheme: dark) {
+    body {
+        color: #ccc;
+        background-color: #111;
+    }
+    a {
+        color: #4df;
+    }
+    h2#congratulations {
+        color: #7d7;
+    }
+    tr:hover, td:hover::after, th:hover::after {
+        background-color: #663;
+    }
+    thead th, th.row-clue {
+        background-color: #111;
+    }
+    tbody tr:nth-child(5n + 1) {
+        border-top-color: #fff;
+    }
+    tr th:nth-child(5n - 3), tr td:nth-child(5n - 3) {
+        border-left-color: #fff;
+    }
+    svg.minimap {
+        background-color: #111;
+        border-color: #fff;
+    }
+    .minimap-marked {
+        fill: #ccc;
+    }
+}
+"#;
+
+static SCRIPT: &str = r#"
+document.addEventListener("contextmenu", (e) => {
+    if (e.target.closest("td")) {
+        e.preventDefault();
+    }
+});
+
+let multipaintVersion = null;
+document.addEventListener("multipaintVersion", (e) => {
+    if (multipaintVersion === null) {
+        multipaintVersion = e.detail.value;
+    } else if (multipaintVersion !== e.detail.value) {
+        location.reload();
+    }
+});
+
+function isTouchDevice() {
+    return hasTouch;
+}
+let hasTouch = (navigator.maxTouchPoints > 0 || navigator.msMaxTouchPoints > 0);
+if (!hasTouch) {
+    document.addEventListener("touchstart", (e) => {
+        hasTouch = true;
+    }, {
+        once: true,
+    });
+}
+
+// The server has already handed us a fresh session cookie, so start over with it.
+document.addEventListener("multipaintKicked", (e) => {
+    location.reload();
+});
+let table = null;
+let cursors = null;
+let mouseX = 0;
+let mouseY = 0;
+let boardWidth = 0;
+let boardHeight = 0;
+document.addEventListener("mousemove", (e) => {
+    if (table === null || cursors === null) {
+        table = document.querySelector("table");
+        cursors = document.getElementById("cursors");
+    }
+    let tableBbox = table.getBoundingClientRect();
+    mouseX = e.pageX - tableBbox.left;
+    mouseY = e.pageY - tableBbox.top;
+    boardWidth = tableBbox.width;
+    boardHeight = tableBbox.height;
+    cursors.style.top = tableBbox.top;
+    cursors.style.left = tableBbox.left;
+    placeHeatmap();
+});
+// Stretches the heatmap overlay over the board, if it's shown.
+function placeHeatmap() {
+    let heatmap = document.getElementById("heatmap");
+    let board = document.querySelector("table");
+    if (heatmap === null || board === null) {
+        return;
+    }
+    let bbox = board.getBoundingClientRect();
+    heatmap.style.top = `${bbox.top + window.scrollY}px`;
+    heatmap.style.left = `${bbox.left + window.scrollX}px`;
+    heatmap.style.width = `${bbox.width}px`;
+    heatmap.style.height = `${bbox.height}px`;
+}
+function toggleHeatmap(show) {
+    let heatmap = document.getElementById("heatmap");
+    if (!show) {
+        heatmap?.remove();
+    } else if (heatmap === null) {
+        heatmap = document.createElement("div");
+        heatmap.id = "heatmap";
+        heatmap.setAttribute("hx-get", "heatmap.svg");
+        heatmap.setAttribute("hx-trigger", "load, every 2s");
+        document.body.append(heatmap);
+        htmx.process(heatmap);
+        placeHeatmap();
+    }
+}
+
+// Responses can arrive out of order over a flaky connection, so never swap in a board older than the one shown.
+function renderedRevision(target) {
+    let revisions = [target, ...target.querySelectorAll("[data-revision]")]
+        .map((element) => Number(element.dataset.revision ?? -1));
+    return Math.max(...revisions);
+}
+document.addEventListener("htmx:beforeSwap", (e) => {
+    // The server is busy, and the board shown is good enough.
+    if (e.detail.xhr.status === 304) {
+        e.detail.shouldSwap = false;
+        return;
+    }
+    // Tell whoever checked the solution too soon how long to wait.
+    if (e.detail.xhr.status === 429 && e.detail.target.id === "submit-result") {
+        e.detail.shouldSwap = true;
+        e.detail.isError = false;
+        return;
+    }
+    let revision = e.detail.xhr.getResponseHeader("X-Board-Revision");
+    if (revision !== null && Number(revision) < renderedRevision(e.detail.target)) {
+        e.detail.shouldSwap = false;
+    }
+});
+
+document.addEventListener("multipaintPuzzlesLeft", (e) => {
+    let note = document.getElementById("puzzles-left");
+    if (note) {
+        note.classList.toggle("hidden", e.detail.value >= Number(note.dataset.threshold));
+        note.querySelectorAll(".count").forEach((count) => count.innerText = e.detail.value);
+    }
+});
+
+document.addEventListener("nonogramTitle", (e) => {
+    document.title = e.detail.value + " - Multipaint by Numbers";
+});
+
+let baseTimestamp = document.timeline.currentTime;
+let nonogramTimeLeft = null;
+let nonogramTimerHours = false;
+let nonogramTimerPaused = false;
+document.addEventListener("nonogramTimeLeft", (e) => {
+    baseTimestamp = document.timeline.currentTime;
+    nonogramTimeLeft = e.detail.value;
+    // Triggered right after this one, but only when true.
+    nonogramTimerHours = false;
+    nonogramTimerPaused = false;
+});
+document.addEventListener("nonogramTimerHours", (e) => {
+    nonogramTimerHours = e.detail.value;
+});
+document.addEventListener("nonogramTimerPaused", (e) => {
+    nonogramTimerPaused = e.detail.value;
+});
+function updateFrame(currentTimestamp) {
+    if (Number.isInteger(nonogramTimeLeft)) {
+        let timerElapsed = document.getElementById("timer-elapsed");
+        let timerDone = document.getElementById("timer-done");
+        let timeLeft = nonogramTimerPaused
+            ? nonogramTimeLeft
+            : nonogramTimeLeft + baseTimestamp - currentTimestamp;
+        if (timeLeft <= 0) {
+            if (timerElapsed) {
+                timerElapsed.classList.add("hidden");
+            }
+            if (timerDone) {
+                timerDone.classList.remove("hidden");
+            }
+        } else {
+            if (timerElapsed) {
+                let pad = (value) => (value < 10 ? "0" : "") + value;
+                let hours = Math.floor(timeLeft / 3600000);
+                let minutes = Math.floor((timeLeft % 3600000) / 60000);
+                let seconds = Math.floor((timeLeft % 60000) / 1000);
+                timerElapsed.innerText = "Time left: " + (nonogramTimerHours
+                    ? hours + ":" + pad(minutes) + ":" + pad(seconds)
+                    : minutes + 60 * hours + ":" + pad(seconds));
+                timerElapsed.classList.remove("hidden");
+            }
+            if (timerDone) {
+                timerDone.classList.add("hidden");
+            }
+        }
+    }
+    requestAnimationFrame(updateFrame);
+}
+requestAnimationFrame(updateFrame);
+"#;
+
+async fn htmx_minified(headers: HeaderMap) -> Response {
+    let etag = assets::etag(&HTMX);
+    if api::is_fresh(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+    ([(ETAG, etag)], HTMX.bytes).into_response()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn index(
+    State(state): State<AppState>,
+    custom_assets: Option<Extension<CustomAssets>>,
+    tunnel: Option<Extension<TunnelStatusCell>>,
+    alert: Option<Extension<AlertBanner>>,
+    mount: Option<Extension<MountPath>>,
+    identity: Identity,
+    session: SessionCursor,
+    secure: SecureContext,
+) -> Response {
+    let tunnel = tunnel.as_ref().map(|Extension(status)| status);
+    let strategy = state.rotation.lock().unwrap().strategy();
+    let base = base_href(mount.as_ref());
+    let mut headers = HeaderMap::new();
+    if session.0.is_none() {
+        headers.insert(SET_COOKIE, session::set_cookie(session::issue(), secure.0));
+    }
+    let page = render_index(&IndexView {
+        base: String::from(base),
+        custom_head: custom_assets.map(|Extension(custom_assets)| custom_assets.head()),
+        banners: html! {
+            (alert::banner(alert.as_ref().map(|Extension(alert)| alert)))
+            (tunnel_status::banner(tunnel))
+            (maintenance::overlay(Some(&state.maintenance)))
+            (secure_context::notice(secure, &identity))
+        },
+        footer: tunnel_status::operator_footer(tunnel, &identity),
+        manual_submit: state.config.manual_submit(),
+        ends_when_exhausted: strategy == RefillStrategy::Stop,
+        public_url: tunnel
+            .and_then(|status| status.get().public_url)
+            .map(|url| format!("{}{base}", url.trim_end_matches('/'))),
+    });
+    (headers, page).into_response()
+}
+
+/// Cell size of the embedded board when `?cell` isn't given, in pixels.
+const DEFAULT_EMBED_CELL_SIZE: usize = 8;
+
+/// Largest cell size of the embedded board, in pixels.
+const MAX_EMBED_CELL_SIZE: usize = 32;
+
+/// Largest side of the embedded board, in pixels. Cells of larger boards get smaller to fit.
+const MAX_EMBED_SIDE: usize = 640;
+
+static EMBED_STYLE: &str = r#"
+body {
+    margin: 0;
+    font-family: sans-serif;
+    font-size: 12px;
+}
+svg.embed-board {
+    display: block;
+    background-color: #fff;
+    outline: 1px solid #000;
+}
+.minimap-marked {
+    fill: #111;
+}
+.minimap-flagged {
+    fill: #d22;
+    opacity: 0.5;
+}
+"#;
+
+#[derive(Deserialize)]
+struct EmbedQuery {
+    /// Requested size of each cell, in pixels.
+    cell: Option<usize>,
+}
+
+/// Cell size for an embedded board, from the requested size but small enough for the board to fit.
+fn embed_cell_size(requested: Option<usize>, rows: usize, columns: usize) -> usize {
+    let fitting = (MAX_EMBED_SIDE / rows.max(columns).max(1)).max(1);
+    requested
+        .unwrap_or(DEFAULT_EMBED_CELL_SIZE)
+        .clamp(1, MAX_EMBED_CELL_SIZE)
+        .min(fitting)
+}
+
+/// A read-only, frame-friendly view of the board for other sites, which only `--embed-origin` may frame.
+async fn embed(
+    State(state): State<AppState>,
+    mount: Option<Extension<MountPath>>,
+    Query(query): Query<EmbedQuery>,
+) -> (HeaderMap, Markup) {
+    let (board, title) = embed_board_markup(&state, query.cell);
+    let markup = html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            base href=(base_href(mount.as_ref()));
+            title { "Multipaint by Numbers" }
+            script src="htmx.js" {}
+            style { (PreEscaped(EMBED_STYLE)) }
+        }
+        body {
+            // Keeps the requested size as given, so that it's clamped against each new puzzle.
+            #embed-board hx-get=(format!("embed/board?cell={}", query.cell.unwrap_or(DEFAULT_EMBED_CELL_SIZE))) hx-trigger="every 2s" {
+                (board)
+            }
+            a href="." target="_blank" {
+                (title.as_deref().unwrap_or("Multipaint by Numbers"))
+            }
+        }
+    };
+    (state.embed_origins.headers(), markup)
+}
+
+async fn embed_board(State(state): State<AppState>, Query(query): Query<EmbedQuery>) -> Markup {
+    embed_board_markup(&state, query.cell).0
+}
+
+/// The embedded board, and the title of its puzzle.
+fn embed_board_markup(state: &AppState, cell: Option<usize>) -> (Markup, Option<String>) {
+    let nonogram = state.nonogram.lock().unwrap();
+    let puzzle = state.puzzle.borrow();
+    let (rows, columns) = (puzzle.rows.len(), puzzle.columns.len());
+    let board = minimap::board_svg(
+        "embed-board",
+        &nonogram.checkboxes,
+        rows,
+        columns,
+        embed_cell_size(cell, rows, columns),
+    );
+    (board, puzzle.title.clone())
+}
+
+/* HTMX components */
+
+/// Whether the player acted on the board recently, rather than just watching.
+fn acted_recently(state: &AppState, session: &SessionCursor) -> bool {
+    let now = state.clock.now();
+    session
+        .0
+        .and_then(|cursor_id| state.cursors.lock().unwrap().get(&cursor_id)?.last_action)
+        .is_some_and(|last_action| now.saturating_duration_since(last_action) < RECENT_ACTION)
+}
+
+async fn nonogram(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    shedder: LoadShedder,
+    alert: Option<Extension<AlertBanner>>,
+) -> Response {
+    // Spectators keep the board they have, so that players' moves go through.
+    if shedder.is_shedding() && !acted_recently(&state, &session) {
+        return shedder.shed(StatusCode::NOT_MODIFIED);
+    }
+    let (puzzles_left, exhausted) = {
+        let rotation = state.rotation.lock().unwrap();
+        (rotation.remaining(), rotation.is_exhausted())
+    };
+    // Both under the nonogram lock, so that a transition can't pair the old board with the new puzzle.
+    let nonogram = state.nonogram.lock().unwrap();
+    let puzzle = state.puzzle.borrow();
+    let revision = nonogram.revision;
+    let duration = nonogram.timer.duration;
+    let time_left = nonogram.timer.time_left(state.clock.now());
+    let timer_paused = nonogram.timer.paused_at.is_some();
+    let puzzle_state = nonogram.state;
+    let stats = matches!(puzzle_state, NonogramState::Solved(_)).then_some(&nonogram.stats);
+    let trigger = TriggerPayload {
+        nonogram_time_left: time_left.as_millis() as u64,
+        nonogram_timer_hours: timer_shows_hours(duration),
+        nonogram_timer_paused: timer_paused,
+        multipaint_version: *VERSION,
+        multipaint_puzzles_left: Some(puzzles_left),
+        nonogram_title: puzzle.title.clone(),
+    };
+    let board = render_board(&BoardView {
+        id: puzzle.id,
+        title: puzzle.title.as_deref(),
+        copyright: puzzle.copyright.as_deref(),
+        rows: &puzzle.rows,
+        columns: &puzzle.columns,
+        checkboxes: &nonogram.checkboxes,
+        revision,
+        timer: TimerView {
+            state: puzzle_state,
+            time_left,
+            duration,
+        },
+        summary: stats.map(|stats| stats.summary(&puzzle.solution)),
+        exhausted,
+    });
+    drop(nonogram);
+    (
+        trigger,
+        board_headers(&puzzle, revision),
+        html! {
+            (alert::banner_update(alert.as_ref().map(|Extension(alert)| alert)))
+            (maintenance::overlay_update(Some(&state.maintenance)))
+            (board)
+        },
+    )
+        .into_response()
+}
+
+/// The current puzzle and how far along it is, for the landing page.
+async fn summary(State(state): State<AppState>) -> impl IntoResponse {
+    let nonogram = state.nonogram.lock().unwrap();
+    let marked = nonogram
+        .checkboxes
+        .iter()
+        .filter(|&&state| state == CheckboxState::Marked)
+        .count();
+    let puzzle_state = nonogram.state;
+    drop(nonogram);
+    let players = state.cursors.lock().unwrap().len();
+    let puzzle = state.puzzle.borrow();
+    (
+        [SUMMARY_CACHE_CONTROL],
+        html! {
+            p {
+                "Puzzle: "
+                @if let Some(title) = &puzzle.title {
+                    (title) " "
+                }
+                "(#" (puzzle.id) ", " (puzzle.rows.len()) "x" (puzzle.columns.len()) ")"
+            }
+            p {
+                @match puzzle_state {
+                    NonogramState::Solved(_) => "Solved!",
+                    NonogramState::Failed => "Time's up!",
+                    NonogramState::Unsolved => {
+                        (marked) " of " (puzzle.solution.count_ones()) " cells marked"
+                    }
+                }
+            }
+            p { (players) " players" }
+        },
+    )
+}
+
+/// An overview of the whole board, for boards that are too large to see at once.
+async fn minimap(State(state): State<AppState>, shedder: LoadShedder) -> Response {
+    if shedder.is_shedding() {
+        return shedder.unavailable();
+    }
+    // Both under the nonogram lock, so that a transition can't pair the old board with the new puzzle.
+    let nonogram = state.nonogram.lock().unwrap();
+    let puzzle = state.puzzle.borrow();
+    minimap::minimap_svg(
+        &nonogram.checkboxes,
+        puzzle.rows.len(),
+        puzzle.columns.len(),
+    )
+    .unwrap_or_default()
+    .into_response()
+}
+
+async fn heatmap(State(state): State<AppState>, shedder: LoadShedder) -> Response {
+    if shedder.is_shedding() {
+        return shedder.unavailable();
+    }
+    let svg = state.heatmap.lock().unwrap().svg();
+    ([(CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+}
+
+fn cursor_item(cursor: &Cursor) -> Markup {
+    let style = format!(
+        "transform: translate({}px, {}px); color: rgb({}, {}, {});",
+        cursor.position.0, cursor.position.1, cursor.color[0], cursor.color[1], cursor.color[2],
+    );
+    html! {
+        svg .cursor id=(format!("cursor-{}", cursor.id.0)) style=(style) width="9.6014509" height="16.11743" viewBox="0 0 2.5403839 4.2644034" {
+            path style="fill:currentColor;fill-opacity:1;fill-rule:evenodd;stroke:#000000;stroke-width:0.26;stroke-linejoin:round;stroke-dasharray:none;stroke-opacity:1" d="M 0.11675524,0.11673874 V 3.7065002 L 0.96455178,3.1233122 1.5307982,4.1165827 2.0934927,3.7711802 1.5414863,2.8366035 2.3925647,2.3925482 Z" {}
+        }
+        @if let Some(name) = &cursor.name {
+            span .cursor-name style=(style) { (name) }
+        }
+    }
+}
+
+async fn cursor(
+    State(state): State<AppState>,
+    identity: Identity,
+    session: SessionCursor,
+    secure: SecureContext,
+    shedder: LoadShedder,
+    LenientForm(payload): LenientForm<CursorsPayload>,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    let position = CursorPosition(payload.mouse_x, payload.mouse_y);
+    let focus = payload.focus();
+    // Named players keep the same cursor across tabs and reloads. Anonymous players without a session, such as after a
+    // restart, get one here.
+    let cursor_id = session.0.unwrap_or_else(|| {
+        let cursor_id = session::issue();
+        headers.insert(SET_COOKIE, session::set_cookie(cursor_id, secure.0));
+        cursor_id
+    });
+    if is_sanctioned(&state, cursor_id, SanctionKind::Kicked) {
+        // The old session stays kicked, so anonymous players rejoin with a new one.
+        if identity.stable_id().is_none() {
+            headers.insert(SET_COOKIE, session::set_cookie(session::issue(), secure.0));
+        }
+        headers.insert("HX-Trigger", HeaderValue::from_static("multipaintKicked"));
+        return (headers, html! {}).into_response();
+    }
+    let now = state.clock.now();
+    let mut cursors = state.cursors.lock().unwrap();
+    // Expired cursors go first, so that newcomers can take their colors.
+    cursors.retain(|_, cursor| {
+        now.saturating_duration_since(cursor.modified_at) <= Duration::from_secs(20)
+    });
+    let slot = (!cursors.contains_key(&cursor_id))
+        .then(|| palette::free_slot(cursors.values().filter_map(|cursor| cursor.slot)))
+        .flatten();
+    cursors
+        .entry(cursor_id)
+        .and_modify(|cursor| {
+            cursor.position = position;
+            cursor.modified_at = now;
+        })
+        .or_insert_with_key(|id| {
+            Cursor::new(*id, identity.name().map(String::from), position, slot, now)
+        })
+        .focus = focus;
+    // The position is still kept, but the other cursors aren't worth rendering right now.
+    if shedder.is_shedding() {
+        drop(cursors);
+        return (headers, shedder.shed(StatusCode::NO_CONTENT)).into_response();
+    }
+    let markup = html! {
+        @for cursor_data in cursors.iter().filter(|(&id, _)| id != cursor_id) {
+            (cursor_item(cursor_data.1))
+        }
+    };
+    let size = cursors_size(&cursors);
+    drop(cursors);
+    state.cursors_gauge.set(size);
+    (headers, markup).into_response()
+}
+
+/// Header with the current puzzle's size as `ROWSxCOLUMNS`, so that clients can sanity-check cell coordinates.
+const BOARD_DIMENSIONS_HEADER: &str = "X-Board-Dimensions";
+
+/// Header with the board revision that a response reflects. The page script drops responses older than what it
+/// already shows.
+const BOARD_REVISION_HEADER: &str = "X-Board-Revision";
+
+fn board_headers(puzzle: &NonogrammedPuzzle, revision: u64) -> [(&'static str, String); 2] {
+    [
+        (
+            BOARD_DIMENSIONS_HEADER,
+            format!("{}x{}", puzzle.rows.len(), puzzle.columns.len()),
+        ),
+        (BOARD_REVISION_HEADER, revision.to_string()),
+    ]
+}
+
+/// A cell, either by its flat ID or by its zero-based row and column.
+#[derive(Copy, Clone, Debug)]
+enum CellRef {
+    Id(usize),
+    Coordinates { row: usize, column: usize },
+}
+
+impl CellRef {
+    /// Converts into a flat ID for the given puzzle, or `None` if it's out of range. Should be called while holding
+    /// the nonogram lock, so that the puzzle can't change between validating the coordinates and applying the change.
+    fn resolve(self, puzzle: &NonogrammedPuzzle) -> Option<usize> {
+        let rows = puzzle.rows.len();
+        let columns = puzzle.columns.len();
+        match self {
+            CellRef::Id(id) => (id < rows * columns).then_some(id),
+            CellRef::Coordinates { row, column } => {
+                (row < rows && column < columns).then_some(row * columns + column)
+            }
+        }
+    }
+
+    fn out_of_range(self, puzzle: &NonogrammedPuzzle, revision: u64) -> Response {
+        match self {
+            CellRef::Id(_) => StatusCode::NOT_FOUND.into_response(),
+            CellRef::Coordinates { row, column } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                board_headers(puzzle, revision),
+                format!(
+                    "Cell ({row}, {column}) is out of range: row must be within 0..{} and column within 0..{}.",
+                    puzzle.rows.len(),
+                    puzzle.columns.len()
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum CellChange {
+    Flag,
+    Unflag,
+    Mark,
+    Unmark,
+}
+
+impl CellChange {
+    fn new_state(self) -> CheckboxState {
+        match self {
+            CellChange::Flag => CheckboxState::Flagged,
+            CellChange::Mark => CheckboxState::Marked,
+            CellChange::Unflag | CellChange::Unmark => CheckboxState::Empty,
+        }
+    }
+
+    fn applies_to(self, state: CheckboxState) -> bool {
+        match self {
+            CellChange::Flag => state == CheckboxState::Empty,
+            CellChange::Unflag => state == CheckboxState::Flagged,
+            CellChange::Mark => state != CheckboxState::Marked,
+            CellChange::Unmark => state == CheckboxState::Marked,
+        }
+    }
+
+    /// Whether the change can solve the puzzle. Only marks count towards the solution.
+    fn affects_solution(self) -> bool {
+        matches!(self, CellChange::Mark | CellChange::Unmark)
+    }
+}
+
+/// Shared logic for every endpoint that marks or flags a cell.
+fn update_cell(
+    state: AppState,
+    session: &SessionCursor,
+    cell: CellRef,
+    change: CellChange,
+) -> Response {
+    let allowed = allow_mutation(&state, session);
+    let mut nonogram = state.nonogram.lock().unwrap();
+    let (id, headers) = {
+        let puzzle = state.puzzle.borrow();
+        let revision = nonogram.revision;
+        let Some(id) = cell.resolve(&puzzle) else {
+            return cell.out_of_range(&puzzle, revision);
+        };
+        let current = nonogram.checkboxes[id];
+        if state.maintenance.is_on() {
+            return (
+                board_headers(&puzzle, revision),
+                maintenance::rejection(render_cell(id, false, &current, revision)),
+            )
+                .into_response();
+        }
+        if !allowed {
+            return (
+                board_headers(&puzzle, revision),
+                render_cell(id, false, &change.new_state(), revision),
+            )
+                .into_response();
+        }
+        if nonogram.state != NonogramState::Unsolved || !change.applies_to(current) {
+            return (
+                board_headers(&puzzle, revision),
+                render_cell(id, !change.affects_solution(), &current, revision),
+            )
+                .into_response();
+        }
+        let now = state.clock.now();
+        // Quick toggles by the same player, such as from dragging or double-clicking, are recorded as one action.
+        let coalesced = nonogram.coalesces(id, session.0, now, state.config.coalesce_window());
+        nonogram.set_checkbox(&puzzle.solution, id, change.new_state());
+        let revision = nonogram.revision;
+        nonogram.stats.record(id, change, &puzzle.solution);
+        if coalesced {
+            nonogram
+                .history
+                .coalesce(revision, id, current, change.new_state(), session.0);
+            nonogram.replay.coalesce(now, id, change.new_state());
+        } else {
+            nonogram
+                .history
+                .record(revision, id, current, change.new_state(), session.0);
+            nonogram.replay.record(now, id, change.new_state());
+        }
+        state.replay_gauge.set(nonogram.replay.size());
+        state.board_log.event(BoardEvent::new(
+            nonogram.generation,
+            nonogram.revision,
+            id,
+            change.new_state(),
+        ));
+        (id, board_headers(&puzzle, nonogram.revision))
+    };
+    let revision = nonogram.revision;
+    // With manual submissions, a complete board waits for someone to check it.
+    let solved = change.affects_solution()
+        && !state.config.manual_submit()
+        && check_if_solved(nonogram, state.clone());
+    (
+        headers,
+        render_cell(id, solved, &change.new_state(), revision),
+    )
+        .into_response()
+}
+
+async fn flag_checkbox(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path(id): Path<usize>,
+) -> Response {
+    update_cell(state, &session, CellRef::Id(id), CellChange::Flag)
+}
+
+async fn unflag_checkbox(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path(id): Path<usize>,
+) -> Response {
+    update_cell(state, &session, CellRef::Id(id), CellChange::Unflag)
+}
+
+async fn mark_checkbox(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path(id): Path<usize>,
+) -> Response {
+    update_cell(state, &session, CellRef::Id(id), CellChange::Mark)
+}
+
+async fn unmark_checkbox(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path(id): Path<usize>,
+) -> Response {
+    update_cell(state, &session, CellRef::Id(id), CellChange::Unmark)
+}
+
+async fn flag_cell(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path((row, column)): Path<(usize, usize)>,
+) -> Response {
+    update_cell(
+        state,
+        &session,
+        CellRef::Coordinates { row, column },
+        CellChange::Flag,
+    )
+}
+
+async fn unflag_cell(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path((row, column)): Path<(usize, usize)>,
+) -> Response {
+    update_cell(
+        state,
+        &session,
+        CellRef::Coordinates { row, column },
+        CellChange::Unflag,
+    )
+}
+
+async fn mark_cell(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path((row, column)): Path<(usize, usize)>,
+) -> Response {
+    update_cell(
+        state,
+        &session,
+        CellRef::Coordinates { row, column },
+        CellChange::Mark,
+    )
+}
+
+async fn unmark_cell(
+    State(state): State<AppState>,
+    session: SessionCursor,
+    Path((row, column)): Path<(usize, usize)>,
+) -> Response {
+    update_cell(
+        state,
+        &session,
+        CellRef::Coordinates { row, column },
+        CellChange::Unmark,
+    )
+}
+
+/* Logic handlers */
+
+/// Whether a cursor is under a sanction of this kind that hasn't expired yet.
+fn is_sanctioned(state: &AppState, cursor_id: CursorId, kind: SanctionKind) -> bool {
+    let mut sanctions = state.sanctions.lock().unwrap();
+    let now = state.clock.now();
+    sanctions.retain(|_, until| *until > now);
+    sanctions.contains_key(&(cursor_id, kind))
+}
+
+/// Shared check for every handler that mutates the board. Counts the action towards the player's cursor, and
+/// returns whether it should actually be applied: never without a session, nor for muted or kicked cursors.
+fn allow_mutation(state: &AppState, session: &SessionCursor) -> bool {
+    let Some(cursor_id) = session.0 else {
+        return false;
+    };
+    if let Some(cursor) = state.cursors.lock().unwrap().get_mut(&cursor_id) {
+        cursor.actions += 1;
+        cursor.last_action = Some(state.clock.now());
+    }
+    !is_sanctioned(state, cursor_id, SanctionKind::Muted)
+        && !is_sanctioned(state, cursor_id, SanctionKind::Kicked)
+}
+
+//  5 x  5:  367s
+// 10 x 10:  685s
+// 20 x 20: 1277s
+fn get_duration_for_puzzle(rows: usize, columns: usize) -> Duration {
+    Duration::from_secs(f32::powf(20_000f32 * rows as f32 * columns as f32, 0.45) as u64)
+}
+
+/// How long everyone waits after a wrong check of the solution, with `--manual-submit`.
+const SUBMIT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Checks the board against the solution, with `--manual-submit`. Only tells how many cells are wrong, not which.
+async fn submit(State(state): State<AppState>, session: SessionCursor) -> Response {
+    if !state.config.manual_submit() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if state.maintenance.is_on() {
+        return maintenance::rejection(html! {});
+    }
+    // Muted players are ignored without a hint.
+    if !allow_mutation(&state, &session) {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    let mut nonogram = state.nonogram.lock().unwrap();
+    if nonogram.state != NonogramState::Unsolved {
+        return html! { "This puzzle is over." }.into_response();
+    }
+    let now = state.clock.now();
+    if let Some(until) = nonogram.submit_cooldown_until.filter(|&until| until > now) {
+        let wait = until.saturating_duration_since(now).as_secs().max(1);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, wait.to_string())],
+            html! { "Someone just checked. Try again in " (wait) "s." },
+        )
+            .into_response();
+    }
+    let wrong_squares = nonogram.wrong_squares;
+    if wrong_squares > 0 {
+        nonogram.submit_cooldown_until = Some(now + SUBMIT_COOLDOWN);
+        return html! {
+            "Not yet: " (wrong_squares) " " (if wrong_squares == 1 { "cell differs" } else { "cells differ" }) "."
+        }
+        .into_response();
+    }
+    check_if_solved(nonogram, state.clone());
+    html! { "Solved!" }.into_response()
+}
+
+/// Marks the puzzle as solved if there are no wrong squares left, and schedules the next puzzle.
+fn check_if_solved(mut nonogram: MutexGuard<'_, Nonogram>, state: AppState) -> bool {
+    let wrong_squares = nonogram.wrong_squares;
+    let is_solved = wrong_squares == 0;
+    if is_solved {
+        nonogram.state = NonogramState::Solved(state.clock.elapsed(nonogram.timer.start));
+        drop(nonogram);
+        record_current(&state, FunnelEvent::Solved);
+        wait_and_start_new_puzzle(state);
+    } else {
+        debug!("There are {wrong_squares} wrong squares!");
+    }
+    is_solved
+}
+
+/// Counts a step of the current puzzle's way through the funnel.
+fn record_current(state: &AppState, event: FunnelEvent) {
+    let current = state.rotation.lock().unwrap().current();
+    if let Some((source, _)) = current {
+        state.fetcher.funnel().record(source, event);
+    }
+}
+
+/// Name of the supervised task that waits between puzzles, then starts the next one.
+const INTERMISSION_TASK: &str = "intermission";
+
+/// Name of the supervised task that fails the puzzle once its time is up.
+const TIMER_TASK: &str = "timer";
+
+fn wait_and_start_new_puzzle(state: AppState) {
+    let generation = state.nonogram.lock().unwrap().generation;
+    // If it panics, the puzzle that it was fetching is skipped, and the next one is started after another wait.
+    let restart = {
+        let state = state.clone();
+        move || wait_and_start_new_puzzle(state)
+    };
+    state.supervisor.clone().spawn(
+        INTERMISSION_TASK,
+        generation,
+        start_new_puzzle(state, generation),
+        restart,
+    );
+}
+
+async fn start_new_puzzle(state: AppState, generation: u64) {
+    state.clock.sleep(Duration::from_secs(10)).await;
+    // The rotation halts while the board is frozen.
+    state.maintenance.wait_until_off().await;
+    // An operator already loaded another puzzle during the wait.
+    if state.nonogram.lock().unwrap().generation != generation {
+        return;
+    }
+    let Some(next_puzzle) = state.fetcher.next_puzzle(&state.rotation).await else {
+        info!("No puzzles left to play, ending the session.");
+        return;
+    };
+    let current = state.rotation.lock().unwrap().current();
+    load_puzzle(&state, next_puzzle, current);
+}
+
+/// Puts a puzzle on an empty board and starts its timer. `current` is its key in the rotation, if it came from there.
+fn load_puzzle(state: &AppState, next_puzzle: NonogrammedPuzzle, current: Option<PuzzleKey>) {
+    if let Some((source, _)) = current {
+        state.fetcher.funnel().record(source, FunnelEvent::Started);
+    }
+    let mut nonogram = state.nonogram.lock().unwrap();
+    let _ = mem::replace(
+        &mut nonogram.checkboxes,
+        vec![CheckboxState::Empty; next_puzzle.rows.len() * next_puzzle.columns.len()],
+    );
+    nonogram.wrong_squares = next_puzzle.solution.count_ones();
+    nonogram.revision += 1;
+    nonogram.generation += 1;
+    nonogram.stats = PlayStats::default();
+    nonogram.history = RevisionHistory::new(nonogram.revision);
+    nonogram.replay = Replay::new(state.clock.now());
+    nonogram.last_changes.clear();
+    state.replay_gauge.set(nonogram.replay.size());
+    nonogram.submit_cooldown_until = None;
+    let duration = state.config.durations().clamp(get_duration_for_puzzle(
+        next_puzzle.rows.len(),
+        next_puzzle.columns.len(),
+    ));
+    nonogram.puzzle_sender.send_replace(next_puzzle);
+    save_snapshot(state, &nonogram, current);
+    nonogram.timer.duration = duration;
+    nonogram.state = NonogramState::Unsolved;
+    start_timer(state, &mut nonogram);
+}
+
+/// Restarts the puzzle timer. The puzzle is failed if it's still unsolved once the timer's duration is up.
+fn start_timer(state: &AppState, nonogram: &mut Nonogram) {
+    nonogram.timer.start = state.clock.now();
+    nonogram.timer.paused_at = None;
+    spawn_timer(state, nonogram);
+    if timer_held(state) {
+        pause_timer(state, nonogram);
+    }
+}
+
+/// Whether the timer should stay paused: while the board is frozen, or players can't reach it.
+fn timer_held(state: &AppState) -> bool {
+    state.maintenance.is_on() || !state.tunnel.is_connected()
+}
+
+/// Stops the timer until [`resume_timer`], keeping the time left.
+fn pause_timer(state: &AppState, nonogram: &mut Nonogram) {
+    if nonogram.timer.paused_at.is_some() {
+        return;
+    }
+    nonogram.timer.paused_at = Some(state.clock.now());
+    if let Some(handle) = nonogram.timer.abort_handle.take() {
+        handle.abort();
+    }
+}
+
+/// Restarts a paused timer with the time that it had left.
+fn resume_timer(state: &AppState, nonogram: &mut Nonogram) {
+    let Some(paused_at) = nonogram.timer.paused_at.take() else {
+        return;
+    };
+    nonogram.timer.start += state.clock.now().saturating_duration_since(paused_at);
+    if nonogram.state == NonogramState::Unsolved {
+        spawn_timer(state, nonogram);
+    }
+}
+
+/// Pauses and resumes the timer as maintenance mode is switched.
+fn register_maintenance(state: &AppState) {
+    let maintenance = state.maintenance.clone();
+    let state = state.clone();
+    maintenance.on_change(Box::new(move |enabled| {
+        let mut nonogram = state.nonogram.lock().unwrap();
+        if enabled {
+            pause_timer(&state, &mut nonogram);
+        } else if state.tunnel.is_connected() {
+            resume_timer(&state, &mut nonogram);
+        }
+    }));
+}
+
+/// Pauses the timer while the tunnel is down, and resumes it once players can reach the board again.
+fn spawn_tunnel_watch(state: AppState) {
+    state.tasks.clone().spawn("tunnel watch", async move {
+        let mut link = state.tunnel.clone();
+        while let Some(event) = link.changed().await {
+            let mut nonogram = state.nonogram.lock().unwrap();
+            if event.state != TunnelLinkState::Connected {
+                info!(state = ?event.state, "Pausing the timer while the tunnel is down.");
+                pause_timer(&state, &mut nonogram);
+            } else if !state.maintenance.is_on() {
+                resume_timer(&state, &mut nonogram);
+            }
+        }
+    });
+}
+
+/// Spawns the task that fails the puzzle at the timer's deadline, replacing any previous one.
+fn spawn_timer(state: &AppState, nonogram: &mut Nonogram) {
+    let deadline = nonogram.timer.start + nonogram.timer.duration;
+    let generation = nonogram.generation;
+    let task = {
+        let state = state.clone();
+        async move {
+            state.clock.sleep_until(deadline).await;
+            let mut nonogram = state.nonogram.lock().unwrap();
+            // A solved puzzle is already on its way out, and a paused one is resumed with a new task.
+            if nonogram.state == NonogramState::Unsolved && nonogram.timer.paused_at.is_none() {
+                nonogram.state = NonogramState::Failed;
+                drop(nonogram);
+                record_current(&state, FunnelEvent::Failed);
+                wait_and_start_new_puzzle(state.clone());
+            }
+        }
+    };
+    // If it panics, it's spawned again with the same deadline, unless the puzzle is over by then.
+    let restart = {
+        let state = state.clone();
+        move || {
+            let mut nonogram = state.nonogram.lock().unwrap();
+            if nonogram.generation == generation
+                && nonogram.state == NonogramState::Unsolved
+                && nonogram.timer.paused_at.is_none()
+            {
+                spawn_timer(&state, &mut nonogram);
+            }
+        }
+    };
+    let abort_handle = state
+        .supervisor
+        .spawn(TIMER_TASK, generation, task, restart);
+    if let Some(previous) = nonogram.timer.abort_handle.replace(abort_handle) {
+        previous.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        compact::{CompactBoard, CompactCell},
+        palette::PALETTE_SIZE,
+        rotation::DEFAULT_RECENTLY_PLAYED,
+        *,
+    };
+    use crate::{
+        clock::ManualClock,
+        http::audit::{AuditLog, AUDIT_ARTIFACT},
+        nonogram::{
+            funnel::PuzzleFunnel,
+            mock::{self, spawn_mock_upstream},
+            populate_board,
+            rejection::{RejectionReason, REJECTIONS_ARTIFACT},
+            throttle::BreakerState,
+            upstream::UpstreamClient,
+            UpstreamUrls,
+        },
+        random::Random,
+        storage::{
+            tests::{dated_file, temp_data_dir},
+            RetentionRule,
+        },
+        supervisor::task_panics,
+        tunnel::TunnelState,
+    };
+    use axum::{body::Body, extract::Request, http::header::COOKIE, response::Redirect};
+    use bitvec::bitvec;
+    use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::task::JoinHandle;
+    use tower::ServiceExt;
+
+    use super::preview::{PENDING_TTL, PREVIEW_SIDES};
+    use crate::nonogram::image::tests::fixture_png;
+
+    fn test_puzzle() -> NonogrammedPuzzle {
+        let solution =
+            bitvec![0, 1, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 1, 0, 0];
+        let board = populate_board(&solution, 5, 5).unwrap();
+        NonogrammedPuzzle {
+            id: 1,
+            title: Some(String::from("Heart")),
+            copyright: None,
+            rows: board.rows,
+            columns: board.columns,
+            solution: board.solution,
+        }
+    }
+
+    fn test_rotation(catalog: Vec<u32>, strategy: RefillStrategy) -> Mutex<Rotation> {
+        Mutex::new(Rotation::new(
+            catalog,
+            RecentlyPlayed::new(DEFAULT_RECENTLY_PLAYED),
+            strategy,
+            None,
+            Random::default(),
+        ))
+    }
+
+    fn test_state() -> AppState {
+        test_state_with_upstreams(UpstreamUrls::default()).0
+    }
+
+    /// State driven by a manual clock, fetching the next puzzles from the given upstreams.
+    fn test_state_with_upstreams(upstreams: UpstreamUrls) -> (AppState, ManualClock) {
+        let (clock, manual) = Clock::manual();
+        let state = AppState::new(
+            test_puzzle(),
+            test_rotation(NONOGRAMMED_PUZZLE_LIST.to_vec(), RefillStrategy::default()),
+            PuzzleFetcher::new(
+                upstreams,
+                UpstreamClient::default(),
+                RejectionLog::default(),
+                clock.clone(),
+                Random::default(),
+                PuzzleFunnel::default(),
+            ),
+            BoardLog::default(),
+            &MemoryAccounting::default(),
+            clock,
+        );
+        (state, manual)
+    }
+
+    /// A fetcher that can't reach any upstream.
+    fn test_fetcher(clock: Clock) -> PuzzleFetcher {
+        PuzzleFetcher::new(
+            UpstreamUrls::default(),
+            UpstreamClient::default(),
+            RejectionLog::default(),
+            clock,
+            Random::default(),
+            PuzzleFunnel::default(),
+        )
+    }
+
+    async fn mock_upstreams() -> UpstreamUrls {
+        let base_url = spawn_mock_upstream(0).await.unwrap();
+        UpstreamUrls {
+            nonogrammed: base_url.clone(),
+            webpbn: base_url,
+        }
+    }
+
+    /// Lets spawned tasks run until `count` of them are waiting on the clock.
+    async fn wait_for_sleepers(manual: &ManualClock, count: usize) {
+        for _ in 0..1000 {
+            if manual.sleepers() >= count {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+        panic!("Expected {count} sleepers, found {}.", manual.sleepers());
+    }
+
+    /// Waits until the next puzzle has been fetched.
+    async fn next_puzzle(state: &AppState) -> NonogrammedPuzzle {
+        let mut receiver = Receiver::clone(&state.puzzle);
+        receiver.mark_unchanged();
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+            .await
+            .expect("Timed out waiting for the next puzzle.")
+            .unwrap();
+        let puzzle = receiver.borrow().clone();
+        puzzle
+    }
+
+    fn admin() -> Identity {
+        Identity::Named {
+            name: String::from("operator"),
+            is_admin: true,
+        }
+    }
+
+    async fn send(state: &AppState, request: Request) -> (StatusCode, HeaderMap, String) {
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// The cookie of a session that we issued.
+    fn session_cookie(session: u64) -> String {
+        format!(
+            "{}={}",
+            session::COOKIE_NAME,
+            session::token(CursorId(session))
+        )
+    }
+
+    fn session_request(method: &str, uri: &str, session: u64) -> Request {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(COOKIE, session_cookie(session))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn admin_request(uri: &str) -> Request {
+        let mut request = Request::post(uri).body(Body::empty()).unwrap();
+        request.extensions_mut().insert(admin());
+        request
+    }
+
+    fn count_wrong_squares(
+        solution: &BitSlice<usize, Lsb0>,
+        checkboxes: &[CheckboxState],
+    ) -> usize {
+        solution
+            .iter()
+            .zip(checkboxes.iter())
+            .filter(|(solution, &state)| solution.ne(&(state == CheckboxState::Marked)))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn wrong_squares_matches_a_full_recount() {
+        let state = test_state();
+        let solution = state.puzzle.borrow().solution.clone();
+        let mut nonogram = state.nonogram.lock().unwrap();
+        assert_eq!(nonogram.wrong_squares, solution.count_ones());
+        let mut rng = thread_rng();
+        for _ in 0..10_000 {
+            let id = rng.gen_range(0..solution.len());
+            let checkbox_state = *[
+                CheckboxState::Empty,
+                CheckboxState::Flagged,
+                CheckboxState::Marked,
+            ]
+            .choose(&mut rng)
+            .unwrap();
+            nonogram.set_checkbox(&solution, id, checkbox_state);
+            assert_eq!(
+                nonogram.wrong_squares,
+                count_wrong_squares(&solution, &nonogram.checkboxes)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn handlers_keep_wrong_squares_consistent() {
+        let state = test_state();
+        let solution = state.puzzle.borrow().solution.clone();
+        let mut rng = thread_rng();
+        for _ in 0..500 {
+            let id = rng.gen_range(0..solution.len());
+            let (method, uri) = *[
+                ("PUT", "checkbox"),
+                ("DELETE", "checkbox"),
+                ("PUT", "flag"),
+                ("DELETE", "flag"),
+            ]
+            .choose(&mut rng)
+            .unwrap();
+            send(&state, session_request(method, &format!("/{uri}/{id}"), 1)).await;
+            let mut nonogram = state.nonogram.lock().unwrap();
+            assert_eq!(
+                nonogram.wrong_squares,
+                count_wrong_squares(&solution, &nonogram.checkboxes)
+            );
+            if nonogram.state != NonogramState::Unsolved {
+                // Keep playing on the same board after an accidental solve.
+                nonogram.state = NonogramState::Unsolved;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cells_can_be_addressed_by_coordinates() {
+        let state = test_state();
+        let (status, headers, _) = send(&state, session_request("PUT", "/cell/0/0", 1)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[BOARD_DIMENSIONS_HEADER], "5x5");
+        send(&state, session_request("PUT", "/cell/4/4/flag", 1)).await;
+        send(&state, session_request("PUT", "/cell/1/3", 1)).await;
+        send(&state, session_request("DELETE", "/cell/1/3", 1)).await;
+        {
+            let nonogram = state.nonogram.lock().unwrap();
+            assert_eq!(nonogram.checkboxes[0], CheckboxState::Marked);
+            assert_eq!(nonogram.checkboxes[24], CheckboxState::Flagged);
+            assert_eq!(nonogram.checkboxes[8], CheckboxState::Empty);
+        }
+        for uri in ["/cell/5/0", "/cell/0/5", "/cell/5/5/flag"] {
+            let (status, headers, body) = send(&state, session_request("PUT", uri, 1)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{uri}");
+            assert_eq!(headers[BOARD_DIMENSIONS_HEADER], "5x5");
+            assert!(body.contains("row must be within 0..5 and column within 0..5"));
+        }
+        let (status, _, _) = send(&state, session_request("PUT", "/checkbox/25", 1)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn stale_coordinates_are_checked_against_the_new_puzzle() {
+        let state = test_state();
+        let (_, headers, body) = send(
+            &state,
+            Request::get("/nonogram").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(headers[BOARD_DIMENSIONS_HEADER], "5x5");
+        assert!(body.contains(r#"data-rows="5" data-columns="5""#));
+        // The puzzle changes to a 3x8 one while a client still thinks it's 5x5.
+        {
+            let board = populate_board(&bitvec![1; 24], 3, 8).unwrap();
+            let mut nonogram = state.nonogram.lock().unwrap();
+            nonogram.checkboxes = vec![CheckboxState::Empty; 24];
+            nonogram.wrong_squares = 24;
+            nonogram.puzzle_sender.send_replace(NonogrammedPuzzle {
+                id: 2,
+                title: None,
+                copyright: None,
+                rows: board.rows,
+                columns: board.columns,
+                solution: board.solution,
+            });
+        }
+        let (status, headers, body) = send(&state, session_request("PUT", "/cell/4/2", 1)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(headers[BOARD_DIMENSIONS_HEADER], "3x8");
+        assert!(body.contains("row must be within 0..3 and column within 0..8"));
+        let (status, _, _) = send(&state, session_request("PUT", "/cell/2/7", 1)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[23],
+            CheckboxState::Marked
+        );
+    }
+
+    #[tokio::test]
+    async fn revisions_increase_with_every_mutation() {
+        let state = test_state();
+        let revision = |headers: &HeaderMap| -> u64 {
+            headers[BOARD_REVISION_HEADER]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        let (_, headers, body) = send(
+            &state,
+            Request::get("/nonogram").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let mut last = revision(&headers);
+        assert!(body.contains(&format!(r#"data-revision="{last}""#)));
+        for (method, uri) in [
+            ("PUT", "/checkbox/0"),
+            ("PUT", "/flag/2"),
+            ("DELETE", "/checkbox/0"),
+            ("DELETE", "/flag/2"),
+            ("PUT", "/cell/1/1"),
+        ] {
+            let (_, headers, body) = send(&state, session_request(method, uri, 1)).await;
+            let current = revision(&headers);
+            assert!(current > last, "{method} {uri}: {current} <= {last}");
+            assert!(body.contains(&format!(r#"data-revision="{current}""#)));
+            last = current;
+        }
+        // Requests that don't change the board keep the same revision.
+        let (_, headers, _) = send(&state, session_request("PUT", "/cell/1/1", 1)).await;
+        assert_eq!(revision(&headers), last);
+        let (_, headers, body) = send(
+            &state,
+            Request::get("/nonogram").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(revision(&headers), last);
+        assert_eq!(
+            body.matches(&format!(r#"data-revision="{last}""#)).count(),
+            26
+        );
+    }
+
+    #[tokio::test]
+    async fn it_never_prunes_files_in_use() {
+        let data_dir = temp_data_dir("prune-live").await;
+        let now = std::time::SystemTime::now();
+        // Only today's audit log is left, and it's still being appended to.
+        dated_file(&data_dir, "audit_2026-01-09.jsonl", 100, 2, now);
+        for name in [BOARD_SNAPSHOT_FILE, BOARD_EVENTS_FILE, RECENTLY_PLAYED_FILE] {
+            dated_file(&data_dir, name, 100, 30, now);
+        }
+        pin_live_artifacts(&data_dir);
+        let rules = [REJECTIONS_ARTIFACT, AUDIT_ARTIFACT].map(|kind| RetentionRule {
+            kind,
+            max_age: Some(Duration::ZERO),
+            max_total_size: Some(0),
+        });
+        let summary = data_dir.prune(&rules, now).await.unwrap();
+        assert_eq!(summary.removed, Vec::<String>::new());
+        assert!(data_dir.file("audit_2026-01-09.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn boards_are_recovered_after_a_crash() {
+        let data_dir = temp_data_dir("board-crash").await;
+        let (clock, _manual) = Clock::manual();
+        let state = AppState::new(
+            test_puzzle(),
+            test_rotation(vec![1], RefillStrategy::default()),
+            test_fetcher(clock.clone()),
+            BoardLog::new(Some(data_dir.clone()), true, &TaskRegistry::default()),
+            &MemoryAccounting::default(),
+            clock,
+        );
+        save_snapshot(&state, &state.nonogram.lock().unwrap(), None);
+        send(&state, session_request("PUT", "/checkbox/1", 1)).await;
+        send(&state, session_request("PUT", "/flag/0", 1)).await;
+        save_snapshot(&state, &state.nonogram.lock().unwrap(), None);
+        for (method, uri) in [
+            ("PUT", "/checkbox/3"),
+            ("DELETE", "/flag/0"),
+            ("PUT", "/flag/24"),
+            ("DELETE", "/checkbox/1"),
+            ("PUT", "/checkbox/7"),
+        ] {
+            send(&state, session_request(method, uri, 1)).await;
+        }
+        state.board_log.flush().await;
+        // The process dies here, without a final snapshot.
+        let (checkboxes, revision) = {
+            let nonogram = state.nonogram.lock().unwrap();
+            (nonogram.checkboxes.clone(), nonogram.revision)
+        };
+        drop(state);
+
+        let snapshot = recovery::recover(&data_dir).await.unwrap().unwrap();
+        let restored = test_state();
+        let mut nonogram = restored.nonogram.lock().unwrap();
+        assert!(nonogram.restore(&test_puzzle().solution, &snapshot));
+        assert_eq!(nonogram.checkboxes, checkboxes);
+        assert_eq!(nonogram.revision, revision);
+        assert_eq!(
+            nonogram.wrong_squares,
+            count_wrong_squares(&test_puzzle().solution, &checkboxes)
+        );
+    }
+
+    #[tokio::test]
+    async fn board_snapshots_revalidate_with_etags() {
+        let state = test_state();
+        let snapshot = |etag: Option<&str>| {
+            let mut request = Request::get("/api/board.snapshot");
+            if let Some(etag) = etag {
+                request = request.header("If-None-Match", etag);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let (status, headers, body) = send(&state, snapshot(None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let etag = headers["ETag"].to_str().unwrap().to_owned();
+        let board: CompactBoard = serde_json::from_str(&body).unwrap();
+        assert_eq!(board.cells().unwrap(), vec![CompactCell::Empty; 25]);
+
+        let (status, headers, body) = send(&state, snapshot(Some(&etag))).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers["ETag"], etag.as_str());
+        assert!(body.is_empty());
+
+        send(&state, session_request("PUT", "/checkbox/1", 1)).await;
+        send(&state, session_request("PUT", "/flag/2", 1)).await;
+        let (status, headers, body) = send(&state, snapshot(Some(&etag))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(headers["ETag"], etag.as_str());
+        let board: CompactBoard = serde_json::from_str(&body).unwrap();
+        assert_eq!(board.revision, 2);
+        assert_eq!(
+            board.cells().unwrap()[..3],
+            [
+                CompactCell::Empty,
+                CompactCell::Marked,
+                CompactCell::Flagged
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn board_snapshots_are_gzipped_on_request() {
+        let state = test_state();
+        let response = router(state)
+            .oneshot(
+                Request::get("/api/board.snapshot")
+                    .header("Accept-Encoding", "br;q=1.0, gzip;q=0.8")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["Content-Encoding"], "gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let board: CompactBoard =
+            serde_json::from_reader(flate2::read::GzDecoder::new(&body[..])).unwrap();
+        assert_eq!(board.cells().unwrap().len(), 25);
+    }
+
+    #[tokio::test]
+    async fn board_pngs_are_streamed_a_few_at_a_time() {
+        let mut state = test_state();
+        state.exports = ExportLimiter::new(1);
+        send(&state, session_request("PUT", "/cell/0/1", 1)).await;
+        let png = || Request::get("/api/board.png").body(Body::empty()).unwrap();
+
+        let permit = state.exports.try_start().unwrap();
+        let (status, headers, body) = send(&state, png()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers["Retry-After"], "5");
+        assert!(body.contains("exports_busy"));
+        drop(permit);
+
+        let response = router(state.clone()).oneshot(png()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "image/png");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let image = image::load_from_memory(&body).unwrap().into_luma8();
+        assert_eq!(image.dimensions(), (5 * 8, 5 * 8));
+        assert_eq!(image.get_pixel(0, 0).0, [255]);
+        assert_eq!(image.get_pixel(8, 0).0, [0]);
+        assert_eq!(image.get_pixel(15, 7).0, [0]);
+        assert_eq!(image.get_pixel(16, 0).0, [255]);
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_json_while_fragments_stay_plain() {
+        let state = test_state();
+        send(&state, session_request("PUT", "/cell/0/1", 1)).await;
+        let (status, headers, body) = send(
+            &state,
+            Request::get("/api/cell/0/1").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"row":0,"column":1,"state":"marked"}"#);
+        assert_eq!(headers["Content-Type"], "application/json");
+        let (_, _, body) = send(
+            &state,
+            Request::get("/api/board").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(body.contains(r##""cells":".#......................."}"##));
+
+        for (uri, status, code) in [
+            ("/api/cell/zero/1", StatusCode::BAD_REQUEST, "invalid_path"),
+            (
+                "/api/cell/5/0",
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "cell_out_of_range",
+            ),
+            ("/api/nowhere", StatusCode::NOT_FOUND, "not_found"),
+        ] {
+            let (actual_status, headers, body) =
+                send(&state, Request::get(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(actual_status, status, "{uri}");
+            assert_eq!(headers["Content-Type"], "application/json");
+            let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(json["error"]["code"], code);
+            assert!(json["error"]["message"].is_string());
+        }
+        let (status, headers, _) = send(&state, session_request("PUT", "/cell/zero/1", 1)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(headers["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let (status, headers, _) = send(&state, session_request("PUT", "/cell/5/0", 1)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(headers["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn summary_shows_puzzle_progress() {
+        let state = test_state();
+        send(&state, session_request("PUT", "/checkbox/1", 1)).await;
+        let (status, headers, body) = send(
+            &state,
+            Request::get("/summary").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["cache-control"], "max-age=5");
+        assert!(body.contains("Heart (#1, 5x5)"));
+        assert!(body.contains("1 of 16 cells marked"));
+    }
+
+    #[tokio::test]
+    async fn marking_the_solution_solves_the_puzzle() {
+        let state = test_state();
+        let solution = state.puzzle.borrow().solution.clone();
+        let first = solution.first_one().unwrap();
+        send(&state, session_request("PUT", &format!("/flag/{first}"), 1)).await;
+        send(
+            &state,
+            session_request("DELETE", &format!("/flag/{first}"), 1),
+        )
+        .await;
+        for id in solution.iter_ones() {
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+            // Idempotent re-marks must not change the counter.
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+        }
+        {
+            let nonogram = state.nonogram.lock().unwrap();
+            assert_eq!(nonogram.wrong_squares, 0);
+            assert!(matches!(nonogram.state, NonogramState::Solved(_)));
+        }
+        let (_, _, body) = send(
+            &state,
+            Request::get("/nonogram").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(body.contains("1 flag was on a painted cell."));
+        assert!(body.contains(&format!(
+            "Longest streak: {} correct marks in a row.",
+            solution.count_ones()
+        )));
+    }
+
+    #[tokio::test]
+    async fn muted_sessions_do_not_change_the_board() {
+        let state = test_state();
+        let (status, _, _) = send(&state, admin_request("/admin/cursors/42/mute")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _, body) = send(&state, session_request("PUT", "/checkbox/0", 42)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("checkbox marked"));
+        let (_, _, body) = send(&state, session_request("PUT", "/flag/1", 42)).await;
+        assert!(body.contains("checkbox flagged"));
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[..2],
+            [CheckboxState::Empty, CheckboxState::Empty]
+        );
+
+        let (_, _, body) = send(&state, session_request("PUT", "/checkbox/0", 7)).await;
+        assert!(body.contains("checkbox marked"));
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[0],
+            CheckboxState::Marked
+        );
+
+        send(&state, admin_request("/admin/cursors/42/unmute")).await;
+        send(&state, session_request("PUT", "/flag/1", 42)).await;
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[1],
+            CheckboxState::Flagged
+        );
+    }
+
+    #[tokio::test]
+    async fn operators_can_diff_revisions() {
+        let state = test_state();
+        for (method, uri, session) in [
+            ("PUT", "/checkbox/0", 7),
+            ("PUT", "/flag/1", 7),
+            ("DELETE", "/checkbox/0", 8),
+            ("PUT", "/checkbox/6", 8),
+        ] {
+            send(&state, session_request(method, uri, session)).await;
+        }
+        let diff_request = |uri: &str| {
+            let mut request = Request::get(uri).body(Body::empty()).unwrap();
+            request.extensions_mut().insert(admin());
+            request
+        };
+        let (status, _, body) =
+            send(&state, diff_request("/admin/diff?from=0&to=4&format=json")).await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            json["cells"],
+            serde_json::json!([
+                {"row": 0, "column": 1, "before": "empty", "after": "flagged", "session": 7},
+                {"row": 1, "column": 1, "before": "empty", "after": "marked", "session": 8},
+            ])
+        );
+        let (_, _, body) = send(&state, diff_request("/admin/diff?from=1&to=3")).await;
+        assert!(body.contains("<td>0</td><td>0</td><td>marked</td><td>empty</td><td>#8</td>"));
+        assert!(body.contains("<td>0</td><td>1</td><td>empty</td><td>flagged</td><td>#7</td>"));
+
+        let (status, _, _) = send(
+            &state,
+            Request::get("/admin/diff?from=0&to=4")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = send(&state, diff_request("/admin/diff?from=0&to=5")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Pretend that the first changes were dropped.
+        state.nonogram.lock().unwrap().history = RevisionHistory::new(2);
+        let (status, _, body) = send(&state, diff_request("/admin/diff?from=1&to=4")).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert!(body.contains("History truncated: the oldest available revision is 2."));
+        let (status, _, body) =
+            send(&state, diff_request("/admin/diff?from=1&to=4&format=json")).await;
+        assert_eq!(status, StatusCode::GONE);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["code"], "history_truncated");
+    }
+
+    #[tokio::test]
+    async fn quick_toggles_of_a_cell_are_one_action() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        for (method, session) in [("PUT", 1), ("DELETE", 1), ("PUT", 1), ("DELETE", 2)] {
+            send(&state, session_request(method, "/checkbox/0", session)).await;
+            manual.advance(Duration::from_millis(100));
+        }
+        {
+            let nonogram = state.nonogram.lock().unwrap();
+            // The board always follows the latest request.
+            assert_eq!(nonogram.checkboxes[0], CheckboxState::Empty);
+            assert_eq!(nonogram.revision, 4);
+            // Session 1's three toggles became one change, and session 2's was never merged into it.
+            assert_eq!(nonogram.history.len(), 2);
+            assert_eq!(
+                nonogram.history.diff(0, 3, 4),
+                Ok(vec![history::CellDiff {
+                    cell: 0,
+                    before: CheckboxState::Empty,
+                    after: CheckboxState::Marked,
+                    session: Some(CursorId(1)),
+                }])
+            );
+            let events = nonogram.replay.expand();
+            assert_eq!(
+                events.iter().map(|event| event.state).collect::<Vec<_>>(),
+                vec![CheckboxState::Marked, CheckboxState::Empty]
+            );
+        }
+
+        // Slower toggles are separate actions.
+        manual.advance(config::DEFAULT_COALESCE_WINDOW);
+        send(&state, session_request("PUT", "/checkbox/0", 2)).await;
+        assert_eq!(state.nonogram.lock().unwrap().history.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn operators_can_view_the_replay() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        for (method, uri) in [
+            ("PUT", "/checkbox/0"),
+            ("DELETE", "/checkbox/0"),
+            ("PUT", "/checkbox/0"),
+            ("PUT", "/flag/6"),
+        ] {
+            send(&state, session_request(method, uri, 7)).await;
+            // Slower than a double-click, so that every change is its own action.
+            manual.advance(Duration::from_secs(1));
+        }
+        let mut request = Request::get("/admin/replay").body(Body::empty()).unwrap();
+        request.extensions_mut().insert(admin());
+        let (status, _, body) = send(&state, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        // Only the first and last changes to the first cell are kept.
+        assert_eq!(
+            json["events"],
+            serde_json::json!([
+                {"at": 0, "row": 0, "column": 0, "state": "marked"},
+                {"at": 2000, "row": 0, "column": 0, "state": "marked"},
+                {"at": 3000, "row": 1, "column": 1, "state": "flagged"},
+            ])
+        );
+        let board = state
+            .nonogram
+            .lock()
+            .unwrap()
+            .checkboxes
+            .iter()
+            .copied()
+            .map(cell_char)
+            .collect::<String>();
+        assert_eq!(json["board"], board);
+        assert!(state.replay_gauge.get() > 0);
+
+        let (status, _, _) = send(
+            &state,
+            Request::get("/admin/replay").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn maintenance_freezes_the_board_and_pauses_the_timer() {
+        let (state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        register_maintenance(&state);
+        let duration = state.nonogram.lock().unwrap().timer.duration;
+        start_timer(&state, &mut state.nonogram.lock().unwrap());
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(Duration::from_secs(60));
+        let left = duration - Duration::from_secs(60);
+
+        state.maintenance.set(true).await;
+        let (status, _, body) = send(&state, session_request("PUT", "/checkbox/1", 7)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body.contains(maintenance::NOTICE), "{body}");
+        let (status, _, _) = send(&state, session_request("PUT", "/cell/0/1/flag", 7)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[1],
+            CheckboxState::Empty
+        );
+        // The deadline goes by without failing the puzzle.
+        manual.advance(duration);
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        let (_, headers, body) = send(&state, session_request("GET", "/nonogram", 7)).await;
+        assert!(body.contains("maintenance-overlay"), "{body}");
+        let trigger = headers["HX-Trigger"].to_str().unwrap();
+        assert!(
+            trigger.contains(r#""nonogramTimerPaused":true"#),
+            "{trigger}"
+        );
+        assert!(trigger.contains(&format!(r#""nonogramTimeLeft":{}"#, left.as_millis())));
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Unsolved);
+
+        state.maintenance.set(false).await;
+        let (status, _, _) = send(&state, session_request("PUT", "/checkbox/1", 7)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, headers, body) = send(&state, session_request("GET", "/nonogram", 7)).await;
+        assert!(!body.contains(maintenance::NOTICE));
+        let trigger = headers["HX-Trigger"].to_str().unwrap();
+        assert!(!trigger.contains("nonogramTimerPaused"), "{trigger}");
+        assert!(trigger.contains(&format!(r#""nonogramTimeLeft":{}"#, left.as_millis())));
+        // Only the time that was left before the pause is needed to time out.
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(left);
+        wait_for_sleepers(&manual, 1).await;
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Failed);
+
+        // The rotation waits for maintenance to end before the next puzzle.
+        let generation = state.nonogram.lock().unwrap().generation;
+        state.maintenance.set(true).await;
+        manual.advance(Duration::from_secs(10));
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.nonogram.lock().unwrap().generation, generation);
+        state.maintenance.set(false).await;
+        next_puzzle(&state).await;
+        assert_eq!(state.nonogram.lock().unwrap().generation, generation + 1);
+    }
+
+    #[tokio::test]
+    async fn the_timer_waits_for_the_tunnel() {
+        let (mut state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        let tunnel = TunnelStatusCell::new(TunnelState::Connected, vec![]);
+        state.tunnel = tunnel.link();
+        register_maintenance(&state);
+        spawn_tunnel_watch(state.clone());
+        let duration = state.nonogram.lock().unwrap().timer.duration;
+        start_timer(&state, &mut state.nonogram.lock().unwrap());
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(Duration::from_secs(60));
+        let left = duration - Duration::from_secs(60);
+
+        tunnel.connection_lost();
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert!(state.nonogram.lock().unwrap().timer.paused_at.is_some());
+        manual.advance(duration);
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Unsolved);
+        // Maintenance ending doesn't resume the timer while the tunnel is still down.
+        state.maintenance.set(true).await;
+        state.maintenance.set(false).await;
+        assert!(state.nonogram.lock().unwrap().timer.paused_at.is_some());
+
+        tunnel.connected();
+        wait_for_sleepers(&manual, 1).await;
+        let (_, headers, _) = send(&state, session_request("GET", "/nonogram", 7)).await;
+        let trigger = headers["HX-Trigger"].to_str().unwrap();
+        assert!(!trigger.contains("nonogramTimerPaused"), "{trigger}");
+        assert!(trigger.contains(&format!(r#""nonogramTimeLeft":{}"#, left.as_millis())));
+        manual.advance(left);
+        wait_for_sleepers(&manual, 1).await;
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Failed);
+    }
+
+    /// Uploads an image, returning the preview's tokens for each size.
+    async fn upload_puzzle(state: &AppState, png: Vec<u8>) -> Vec<String> {
+        let mut request = Request::post("/admin/puzzle/preview?title=Corner")
+            .body(Body::from(png))
+            .unwrap();
+        request.extensions_mut().insert(admin());
+        let (status, _, body) = send(state, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body.split(r#"name="token" value=""#)
+            .skip(1)
+            .map(|rest| String::from(&rest[..rest.find('"').unwrap()]))
+            .collect()
+    }
+
+    fn confirm_request(token: &str) -> Request {
+        let mut request = Request::post("/admin/puzzle/confirm")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(format!("token={token}")))
+            .unwrap();
+        request.extensions_mut().insert(admin());
+        request
+    }
+
+    #[tokio::test]
+    async fn uploaded_puzzles_are_confirmed_at_each_size() {
+        let state = test_state();
+        let png = fixture_png(&["##..", "##..", "....", "...."]);
+        for (i, side) in PREVIEW_SIDES.into_iter().enumerate() {
+            let tokens = upload_puzzle(&state, png.clone()).await;
+            assert_eq!(tokens.len(), PREVIEW_SIDES.len());
+            let generation = state.nonogram.lock().unwrap().generation;
+            let (status, _, body) = send(&state, confirm_request(&tokens[i])).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let puzzle = state.puzzle.borrow().clone();
+            assert_eq!((puzzle.rows.len(), puzzle.columns.len()), (side, side));
+            assert_eq!(puzzle.title.as_deref(), Some("Corner"));
+            assert!(puzzle.solution[0]);
+            let nonogram = state.nonogram.lock().unwrap();
+            assert_eq!(nonogram.generation, generation + 1);
+            assert_eq!(nonogram.checkboxes.len(), side * side);
+            assert_eq!(nonogram.wrong_squares, puzzle.solution.count_ones());
+        }
+        assert_eq!(state.rotation.lock().unwrap().current(), None);
+
+        let (status, _, _) = send(&state, confirm_request("0123")).await;
+        assert_eq!(status, StatusCode::GONE);
+        let (status, _, _) = send(
+            &state,
+            Request::post("/admin/puzzle/preview")
+                .body(Body::from(png))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn expired_previews_are_rejected() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        let tokens = upload_puzzle(&state, fixture_png(&["#.", ".#"])).await;
+        manual.advance(PENDING_TTL);
+        let (status, _, body) = send(&state, confirm_request(&tokens[0])).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert!(body.contains("expired"));
+        assert_eq!(state.puzzle.borrow().id, 1);
+        assert_eq!(state.nonogram.lock().unwrap().generation, 0);
+    }
+
+    #[tokio::test]
+    async fn the_intermission_skips_itself_after_an_upload() {
+        let (state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        let tokens = upload_puzzle(&state, fixture_png(&["#.", ".#"])).await;
+        state.nonogram.lock().unwrap().state = NonogramState::Failed;
+        wait_and_start_new_puzzle(state.clone());
+        wait_for_sleepers(&manual, 1).await;
+        send(&state, confirm_request(&tokens[0])).await;
+        manual.advance(Duration::from_secs(10));
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.puzzle.borrow().id, CUSTOM_PUZZLE_ID);
+        assert_eq!(state.puzzle.borrow().rows.len(), PREVIEW_SIDES[0]);
+        assert_eq!(state.nonogram.lock().unwrap().generation, 1);
+    }
+
+    #[tokio::test]
+    async fn the_banner_is_shown_and_polled() {
+        let state = test_state();
+        let alert = AlertBanner::new(alert::DEFAULT_BANNER_DURATION, state.clock.clone());
+        alert
+            .set("Tunnel flaky tonight", alert::Severity::Critical, None)
+            .await
+            .unwrap();
+        let page = router(state.clone()).layer(Extension(alert.clone()));
+        for uri in ["/", "/nonogram"] {
+            let response = page
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains("alert-critical"), "{uri}: {body}");
+            assert!(body.contains("Tunnel flaky tonight"), "{uri}: {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn kicked_sessions_are_told_to_rejoin() {
+        let state = test_state();
+        let cursor = || {
+            Request::post("/cursor")
+                .header(COOKIE, session_cookie(42))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("mouseX=10&mouseY=20"))
+                .unwrap()
+        };
+        let (_, headers, _) = send(&state, cursor()).await;
+        assert!(headers.get("HX-Trigger").is_none());
+        assert!(state.cursors.lock().unwrap().contains_key(&CursorId(42)));
+
+        send(&state, admin_request("/admin/cursors/42/kick")).await;
+        assert!(!state.cursors.lock().unwrap().contains_key(&CursorId(42)));
+        let (_, headers, _) = send(&state, cursor()).await;
+        assert_eq!(headers["HX-Trigger"], "multipaintKicked");
+        assert!(headers.contains_key(SET_COOKIE));
+        assert!(!state.cursors.lock().unwrap().contains_key(&CursorId(42)));
+        send(&state, session_request("PUT", "/checkbox/0", 42)).await;
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[0],
+            CheckboxState::Empty
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_hand_out_a_session_cookie() {
+        let state = test_state();
+        let index =
+            |forwarded_proto: Option<&'static str>, identity: Identity, session: Option<u64>| {
+                let mut request = Request::get("/");
+                if let Some(proto) = forwarded_proto {
+                    request = request.header("X-Forwarded-Proto", proto);
+                }
+                if let Some(session) = session {
+                    request = request.header(COOKIE, session_cookie(session));
+                }
+                let mut request = request.body(Body::empty()).unwrap();
+                request.extensions_mut().insert(identity);
+                request
+            };
+        let (_, headers, body) = send(&state, index(None, Identity::Anonymous, None)).await;
+        let cookie = headers[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("multipaint_session="), "{cookie}");
+        assert!(cookie.contains("HttpOnly"), "{cookie}");
+        assert!(!cookie.contains("Secure"), "{cookie}");
+        assert!(!body.contains(r#"class="insecure-notice""#), "{body}");
+        // The cookie that came with it is a valid session.
+        let request_headers = HeaderMap::from_iter([(
+            COOKIE,
+            HeaderValue::from_str(cookie.split(';').next().unwrap()).unwrap(),
+        )]);
+        assert!(session::from_headers(&request_headers).is_some());
+
+        let (_, headers, _) = send(&state, index(Some("https"), Identity::Anonymous, None)).await;
+        assert!(headers[SET_COOKIE].to_str().unwrap().contains("Secure"));
+        let (_, headers, _) = send(&state, index(None, Identity::Anonymous, Some(7))).await;
+        assert!(headers.get(SET_COOKIE).is_none());
+        // Operators are named, so they need no session.
+        let (_, headers, body) = send(&state, index(None, admin(), None)).await;
+        assert!(headers.get(SET_COOKIE).is_none());
+        assert!(body.contains("insecure-notice"), "{body}");
+        let (_, _, body) = send(&state, index(Some("https"), admin(), None)).await;
+        assert!(!body.contains(r#"class="insecure-notice""#), "{body}");
+    }
+
+    #[tokio::test]
+    async fn only_issued_sessions_change_the_board() {
+        let state = test_state();
+        let request = |cookie: Option<&str>| {
+            let mut request = Request::put("/checkbox/0");
+            if let Some(cookie) = cookie {
+                request = request.header(COOKIE, cookie);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        send(&state, request(None)).await;
+        send(&state, request(Some("multipaint_session=42"))).await;
+        send(&state, session_request("PUT", "/flag/1", 42)).await;
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[..2],
+            [CheckboxState::Empty, CheckboxState::Flagged]
+        );
+    }
+
+    #[tokio::test]
+    async fn unmuting_leaves_kicks_alone() {
+        let state = test_state();
+        send(&state, admin_request("/admin/cursors/42/kick")).await;
+        send(&state, admin_request("/admin/cursors/42/mute")).await;
+        send(&state, admin_request("/admin/cursors/42/unmute")).await;
+        assert!(is_sanctioned(&state, CursorId(42), SanctionKind::Kicked));
+        send(&state, session_request("PUT", "/checkbox/0", 42)).await;
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[0],
+            CheckboxState::Empty
+        );
+    }
+
+    #[tokio::test]
+    async fn board_admin_actions_are_audited() {
+        let state = test_state();
+        let log = AuditLog::default();
+        for uri in [
+            "/admin/cursors/42/mute",
+            "/admin/queue/webpbn/1234?force=true",
+        ] {
+            let mut request = admin_request(uri);
+            request.extensions_mut().insert(log.clone());
+            let (status, _, _) = send(&state, request).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let entries = log
+            .recent()
+            .into_iter()
+            .map(|entry| (entry.operator, entry.action, entry.parameters))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    String::from("operator"),
+                    String::from("cursor_action"),
+                    serde_json::json!({"cursor": 42, "action": "mute"}),
+                ),
+                (
+                    String::from("operator"),
+                    String::from("queue_puzzle"),
+                    serde_json::json!({"source": "webpbn", "puzzle": 1234, "force": true}),
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_heatmap_samples_cursors_on_the_board() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        for (id, x, y) in [(1, 10, 10), (2, 12, 8), (3, 190, 95), (4, 10, 10)] {
+            let mut body = format!("mouseX={x}&mouseY={y}");
+            if id != 4 {
+                body.push_str("&boardWidth=200&boardHeight=100");
+            }
+            let request = Request::post("/cursor")
+                .header(COOKIE, session_cookie(id))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from(body))
+                .unwrap();
+            send(&state, request).await;
+        }
+        spawn_heatmap(state.clone());
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(HEATMAP_TICK);
+        wait_for_sleepers(&manual, 1).await;
+        let (status, headers, body) = send(
+            &state,
+            Request::get("/heatmap.svg").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["Content-Type"], "image/svg+xml");
+        // The first two cursors share a bucket, and the fourth one didn't say where the board is.
+        assert_eq!(body.matches("<rect").count(), 2);
+        assert!(body.contains(r#"x="0.000" y="1.000" width="1.000""#));
+    }
+
+    #[tokio::test]
+    async fn the_embed_is_framed_only_by_allowed_origins() {
+        let state = test_state();
+        let (status, headers, body) =
+            send(&state, Request::get("/embed").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-security-policy"], "frame-ancestors 'none'");
+        assert!(body.contains(r#"hx-get="embed/board?cell=8""#));
+        assert!(body.contains(r#"width="40""#));
+        assert!(!body.contains("cursors"));
+
+        let state = AppState {
+            embed_origins: EmbedOrigins::new(vec![String::from("https://friend.example")]),
+            ..state
+        };
+        let (_, headers, _) =
+            send(&state, Request::get("/embed").body(Body::empty()).unwrap()).await;
+        assert_eq!(
+            headers["content-security-policy"],
+            "frame-ancestors https://friend.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_embed_cells_are_clamped() {
+        let state = test_state();
+        let (status, _, body) = send(
+            &state,
+            Request::get("/embed/board?cell=100000")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let side = 5 * MAX_EMBED_CELL_SIZE;
+        assert!(body.contains(&format!(r#"width="{side}" height="{side}""#)));
+        // Larger boards get smaller cells, to fit.
+        assert_eq!(embed_cell_size(Some(100_000), 200, 100), 3);
+        assert_eq!(embed_cell_size(Some(0), 5, 5), 1);
+        assert_eq!(embed_cell_size(None, 5, 5), DEFAULT_EMBED_CELL_SIZE);
+    }
+
+    #[tokio::test]
+    async fn optional_work_is_shed_but_moves_are_not() {
+        let state = test_state();
+        let shedder = LoadShedder::new(Some(Duration::from_millis(100)));
+        for _ in 0..5 {
+            shedder.record(
+                "/nonogram",
+                Duration::from_secs(1),
+                std::time::Instant::now(),
+            );
+        }
+        assert!(shedder.is_shedding());
+        let send = |request: Request| {
+            let router = router(state.clone()).layer(Extension(shedder.clone()));
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+        let cursor = || {
+            Request::post("/cursor")
+                .header(COOKIE, session_cookie(1))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("mouseX=10&mouseY=20"))
+                .unwrap()
+        };
+
+        assert_eq!(send(cursor()).await, StatusCode::NO_CONTENT);
+        assert_eq!(state.cursors.lock().unwrap().len(), 1);
+        assert_eq!(
+            send(session_request("GET", "/nonogram", 1)).await,
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            send(session_request("GET", "/minimap", 1)).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            send(session_request("GET", "/heatmap.svg", 1)).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        // Moves go through, and whoever made them still sees the board.
+        assert_eq!(
+            send(session_request("PUT", "/checkbox/1", 1)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            state.nonogram.lock().unwrap().checkboxes[1],
+            CheckboxState::Marked
+        );
+        assert_eq!(
+            send(session_request("GET", "/nonogram", 1)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(session_request("GET", "/nonogram", 2)).await,
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(shedder.shed_total(), 5);
+    }
+
+    #[tokio::test]
+    async fn cursors_get_distinct_colors_until_they_expire() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        let move_cursor = |id: usize| {
+            Request::post("/cursor")
+                .header(COOKIE, session_cookie(id as u64))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("mouseX=10&mouseY=20"))
+                .unwrap()
+        };
+        let slot = |id: usize| state.cursors.lock().unwrap()[&CursorId(id as u64)].slot;
+        for id in 0..=PALETTE_SIZE {
+            send(&state, move_cursor(id)).await;
+        }
+        for id in 0..PALETTE_SIZE {
+            assert_eq!(slot(id), Some(id));
+        }
+        // Past the palette, colors are random.
+        assert_eq!(slot(PALETTE_SIZE), None);
+        // Moving around keeps the same color.
+        send(&state, move_cursor(3)).await;
+        assert_eq!(slot(3), Some(3));
+
+        manual.advance(Duration::from_secs(15));
+        for id in [1, 2] {
+            send(&state, move_cursor(id)).await;
+        }
+        manual.advance(Duration::from_secs(15));
+        // Everyone else expired, so the newcomer gets the lowest freed slot.
+        send(&state, move_cursor(100)).await;
+        assert_eq!(slot(100), Some(0));
+        send(&state, move_cursor(101)).await;
+        assert_eq!(slot(101), Some(3));
+        assert_eq!(
+            state.cursors.lock().unwrap()[&CursorId(101)].color,
+            palette::slot_color(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn manual_submissions_only_tell_how_many_cells_are_wrong() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        assert_eq!(
+            send(&state, session_request("POST", "/submit", 1)).await.0,
+            StatusCode::NOT_FOUND
+        );
+        let state = AppState {
+            config: MultipaintConfig::builder()
+                .manual_submit(true)
+                .build()
+                .unwrap(),
+            ..state
+        };
+        let solution = state.puzzle.borrow().solution.clone();
+        let mut cells = solution.iter_ones().collect::<Vec<_>>();
+        let last = cells.pop().unwrap();
+        for id in cells {
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+        }
+        let (status, _, body) = send(&state, session_request("POST", "/submit", 1)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "Not yet: 1 cell differs.");
+
+        // Checking again right away is refused, even for someone else.
+        let (status, headers, body) = send(&state, session_request("POST", "/submit", 2)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers[RETRY_AFTER], "30");
+        assert!(body.contains("Try again in 30s"));
+
+        // A complete board isn't solved until someone checks it.
+        send(
+            &state,
+            session_request("PUT", &format!("/checkbox/{last}"), 1),
+        )
+        .await;
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Unsolved);
+        manual.advance(Duration::from_secs(20));
+        let (status, headers, _) = send(&state, session_request("POST", "/submit", 1)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers[RETRY_AFTER], "10");
+        manual.advance(Duration::from_secs(10));
+        let (status, _, body) = send(&state, session_request("POST", "/submit", 1)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "Solved!");
+        assert!(matches!(
+            state.nonogram.lock().unwrap().state,
+            NonogramState::Solved(_)
+        ));
+        let (_, _, body) = send(&state, session_request("POST", "/submit", 1)).await;
+        assert_eq!(body, "This puzzle is over.");
+    }
+
+    #[tokio::test]
+    async fn malformed_cursor_payloads_are_skipped() {
+        let state = test_state();
+        let request = Request::post("/cursor")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from("mouseX=NaN&mouseY=20"))
+            .unwrap();
+        let (status, _, body) = send(&state, request).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(body.is_empty());
+        assert!(state.cursors.lock().unwrap().is_empty());
+        assert!(crate::http::form::rejected_forms()
+            .iter()
+            .any(|(route, count)| route == "/cursor" && *count > 0));
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_moderate_cursors() {
+        let state = test_state();
+        let request = Request::post("/admin/cursors/42/mute")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, _) = send(&state, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = send(&state, session_request("GET", "/admin/cursors", 42)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(state.sanctions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn clue_headers_are_split_from_the_board() {
+        let state = test_state();
+        let (_, _, body) = send(&state, session_request("GET", "/nonogram", 1)).await;
+        let (head, board) = body.split_once("</thead>").unwrap();
+        let head = &head[head.find("<thead>").unwrap()..];
+        assert_eq!(head.matches("<tr>").count(), 1);
+        assert_eq!(head.matches(r#"class="column-clue""#).count(), 5);
+        assert!(!head.contains("<td"));
+        let board = &board[board.find("<tbody>").unwrap()..board.find("</tbody>").unwrap()];
+        assert_eq!(board.matches("<tr>").count(), 5);
+        assert_eq!(board.matches(r#"class="row-clue""#).count(), 5);
+        assert_eq!(board.matches(r#"class="checkbox-cell""#).count(), 25);
+    }
+
+    #[tokio::test]
+    async fn cells_have_coordinate_titles() {
+        let state = test_state();
+        let (_, _, body) = send(&state, session_request("GET", "/nonogram", 1)).await;
+        for row in 1..=5 {
+            for column in 1..=5 {
+                assert_eq!(
+                    body.matches(&format!(r#"title="R{row} C{column}""#))
+                        .count(),
+                    1
+                );
+            }
+        }
+        // Cell 8 is on the second row, fourth column.
+        let cell = body.find(r#"id="checkbox-8""#).unwrap();
+        let title = body[..cell].rfind("title=").unwrap();
+        assert!(body[title..].starts_with(r#"title="R2 C4""#));
+    }
+
+    #[tokio::test]
+    async fn oldest_cursors_are_evicted_over_the_soft_cap() {
+        let cursor_size = mem::size_of::<(CursorId, Cursor)>();
+        let accounting = MemoryAccounting::new(HashMap::from([(
+            String::from("multipaint_cursors"),
+            2 * cursor_size,
+        )]));
+        let state = AppState::new(
+            test_puzzle(),
+            test_rotation(vec![], RefillStrategy::default()),
+            test_fetcher(Clock::default()),
+            BoardLog::default(),
+            &accounting,
+            Clock::manual().0,
+        );
+        for id in 1..=3 {
+            let request = Request::post("/cursor")
+                .header(COOKIE, session_cookie(id))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("mouseX=10&mouseY=20"))
+                .unwrap();
+            send(&state, request).await;
+        }
+        let mut ids = state
+            .cursors
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert!(ids == vec![CursorId(2), CursorId(3)]);
+        assert_eq!(state.cursors_gauge.get(), 2 * cursor_size);
+        assert_eq!(accounting.snapshot()[0].evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn throttled_sources_fall_back_to_the_other_one() {
+        let listener = tokio::net::TcpListener::bind(("localhost", 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let throttled = Router::new()
+                .fallback(|| async { (StatusCode::TOO_MANY_REQUESTS, [("Retry-After", "120")]) });
+            axum::serve(listener, throttled).await.unwrap();
+        });
+        let (clock, _manual) = Clock::manual();
+        let fetcher = PuzzleFetcher::new(
+            UpstreamUrls {
+                nonogrammed: format!("http://{address}"),
+                webpbn: spawn_mock_upstream(0).await.unwrap(),
+            },
+            UpstreamClient::default(),
+            RejectionLog::default(),
+            clock.clone(),
+            Random::default(),
+            PuzzleFunnel::default(),
+        );
+        let rotation = test_rotation(vec![1, 2, 3], RefillStrategy::default());
+        fetcher.next_puzzle(&rotation).await.unwrap();
+        assert_eq!(
+            rotation.lock().unwrap().current().unwrap().0,
+            PuzzleSource::Webpbn
+        );
+        assert_eq!(
+            fetcher.breakers().states(clock.now()),
+            vec![
+                (
+                    PuzzleSource::Nonogrammed,
+                    BreakerState::Open {
+                        remaining: Duration::from_secs(120)
+                    }
+                ),
+                (PuzzleSource::Webpbn, BreakerState::Closed),
+            ]
+        );
+        // The throttled candidate is still waiting for its turn.
+        let candidate = rotation.lock().unwrap().next_candidate().unwrap();
+        assert_eq!(candidate.0, PuzzleSource::Nonogrammed);
+    }
+
+    /// Serves the mock upstream slowly enough for fetches to overlap, counting every request. If `fail` is set, every
+    /// request gets a 404 instead.
+    async fn spawn_counting_upstream(fail: bool) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let listener = tokio::net::TcpListener::bind(("localhost", 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = mock::get_router().layer(axum::middleware::from_fn({
+            let requests = requests.clone();
+            move |request: Request, next: axum::middleware::Next| {
+                requests.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    if fail {
+                        StatusCode::NOT_FOUND.into_response()
+                    } else {
+                        next.run(request).await
+                    }
+                }
+            }
+        }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (format!("http://{address}"), requests)
+    }
+
+    fn counting_fetcher(nonogrammed: String) -> PuzzleFetcher {
+        PuzzleFetcher::new(
+            UpstreamUrls {
+                nonogrammed,
+                webpbn: String::new(),
+            },
+            UpstreamClient::default(),
+            RejectionLog::default(),
+            Clock::tokio(),
+            Random::default(),
+            PuzzleFunnel::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_of_a_puzzle_share_one_request() {
+        let (upstream, requests) = spawn_counting_upstream(false).await;
+        let fetcher = counting_fetcher(upstream);
+        let key = (PuzzleSource::Nonogrammed, 7);
+        let fetches = (0..20).map(|_| {
+            let fetcher = fetcher.clone();
+            tokio::spawn(async move { fetcher.get_puzzle(key).await })
+        });
+        for fetch in futures::future::join_all(fetches).await {
+            assert_eq!(fetch.unwrap().unwrap().id, 7);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(fetcher.in_flight_is_empty());
+
+        // Results aren't kept once the fetch is over.
+        fetcher.get_puzzle(key).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        fetcher
+            .get_puzzle((PuzzleSource::Nonogrammed, 8))
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_are_shared_but_not_cached() {
+        let (upstream, requests) = spawn_counting_upstream(true).await;
+        let fetcher = counting_fetcher(upstream);
+        let key = (PuzzleSource::Nonogrammed, 7);
+        let fetches = (0..10).map(|_| fetcher.get_puzzle(key));
+        for result in futures::future::join_all(fetches).await {
+            let Err(error) = result else {
+                panic!("The fetch should have failed.");
+            };
+            assert_eq!(RejectionReason::classify(&error), RejectionReason::NotFound);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        assert!(fetcher.get_puzzle(key).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn a_panicking_fetcher_skips_to_the_next_puzzle() {
+        let (clock, manual) = Clock::manual();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let fetcher = PuzzleFetcher::new(
+            UpstreamUrls {
+                nonogrammed: spawn_mock_upstream(0).await.unwrap(),
+                webpbn: String::new(),
+            },
+            UpstreamClient::default(),
+            RejectionLog::default(),
+            clock.clone(),
+            Random::default(),
+            PuzzleFunnel::default(),
+        )
+        .with_fault({
+            let fetches = fetches.clone();
+            move |(_, id)| {
+                if fetches.fetch_add(1, Ordering::SeqCst) == 1 {
+                    panic!("Bug while fetching puzzle {id}.");
+                }
+            }
+        });
+        let rotation = test_rotation(vec![2, 3], RefillStrategy::Stop);
+        rotation
+            .lock()
+            .unwrap()
+            .enqueue((PuzzleSource::Nonogrammed, 1), true);
+        let first_puzzle = fetcher.next_puzzle(&rotation).await.unwrap();
+        let state = AppState::new(
+            first_puzzle,
+            rotation,
+            fetcher,
+            BoardLog::default(),
+            &MemoryAccounting::default(),
+            clock,
+        );
+        assert_eq!(state.puzzle.borrow().id, 1);
+
+        let solution = state.puzzle.borrow().solution.clone();
+        for id in solution.iter_ones() {
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+        }
+        // The intermission panics while fetching the second puzzle...
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(Duration::from_secs(10));
+        for _ in 0..1000 {
+            if state.supervisor.last_failure().is_some() {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        let failure = state.supervisor.last_failure().unwrap();
+        assert_eq!(failure.task, INTERMISSION_TASK);
+        assert_eq!(failure.generation, 0);
+        assert!(failure.restarted);
+        let skipped = failure
+            .message
+            .trim_start_matches("Bug while fetching puzzle ")
+            .trim_end_matches('.')
+            .parse::<u64>()
+            .unwrap();
+        assert!(task_panics().contains(&(INTERMISSION_TASK, 1)));
+
+        // ...and is restarted, so that the game moves on to the puzzle after it.
+        wait_for_sleepers(&manual, 1).await;
+        let (next, ()) = tokio::join!(next_puzzle(&state), async {
+            manual.advance(Duration::from_secs(10))
+        });
+        assert_eq!(next.id, if skipped == 2 { 3 } else { 2 });
+        let nonogram = state.nonogram.lock().unwrap();
+        assert_eq!(nonogram.generation, 1);
+        assert!(nonogram.state == NonogramState::Unsolved);
+        assert!(nonogram.timer.abort_handle.is_some());
+        drop(nonogram);
+        assert_eq!(
+            failure.to_string(),
+            format!("intermission panicked in generation 0: Bug while fetching puzzle {skipped}. (restarted)")
+        );
+    }
+
+    #[tokio::test]
+    async fn the_funnel_follows_puzzles_from_fetch_to_solve() {
+        let mock = spawn_mock_upstream(0).await.unwrap();
+        let listener = tokio::net::TcpListener::bind(("localhost", 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Puzzle 1 is too large to play, and every other one comes from the mock upstream.
+            let upstream = Router::new().route(
+                "/index.php",
+                get(move |Query(query): Query<HashMap<String, u32>>| async move {
+                    match query["NUM"] {
+                        1 => format!(
+                            "var data = '{}';\nvar height = parseInt(70);\nvar width = parseInt(70);\n",
+                            "0".repeat(70 * 70)
+                        )
+                        .into_response(),
+                        id => Redirect::temporary(&format!("{mock}/index.php?NUM={id}"))
+                            .into_response(),
+                    }
+                }),
+            );
+            axum::serve(listener, upstream).await.unwrap();
+        });
+        let (clock, manual) = Clock::manual();
+        let funnel = PuzzleFunnel::default();
+        let fetcher = PuzzleFetcher::new(
+            UpstreamUrls {
+                nonogrammed: format!("http://{address}"),
+                webpbn: String::new(),
+            },
+            UpstreamClient::default(),
+            RejectionLog::default(),
+            clock.clone(),
+            Random::default(),
+            funnel.clone(),
+        );
+        let rotation = test_rotation(vec![3], RefillStrategy::Stop);
+        rotation
+            .lock()
+            .unwrap()
+            .enqueue((PuzzleSource::Nonogrammed, 1), true);
+        let first_puzzle = fetcher.next_puzzle(&rotation).await.unwrap();
+        let state = AppState::new(
+            first_puzzle,
+            rotation,
+            fetcher,
+            BoardLog::default(),
+            &MemoryAccounting::default(),
+            clock,
+        );
+        let count = |event| funnel.count(PuzzleSource::Nonogrammed, event);
+        assert_eq!(count(FunnelEvent::FetchAttempt), 2);
+        assert_eq!(
+            count(FunnelEvent::ValidationRejection(RejectionReason::TooLarge)),
+            1
+        );
+        assert_eq!(count(FunnelEvent::Started), 1);
+
+        let solution = state.puzzle.borrow().solution.clone();
+        for id in solution.iter_ones() {
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+        }
+        assert_eq!(count(FunnelEvent::Solved), 1);
+        assert_eq!(count(FunnelEvent::Failed), 0);
+        assert!(funnel
+            .prometheus()
+            .contains("htmx_ssh_games_puzzles_solved_total{source=\"nonogrammed\"} 1\n"));
+        assert_eq!(
+            funnel.status()[0].1,
+            "2 fetched, 1 playable (50%), 1 started, 1 solved, 0 failed"
+        );
+        // The rotation stops there, so nothing else is started.
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(Duration::from_secs(10));
+        assert_eq!(count(FunnelEvent::Started), 1);
+    }
+
+    #[tokio::test]
+    async fn the_session_ends_when_the_rotation_stops() {
+        let (clock, manual) = Clock::manual();
+        let state = AppState::new(
+            test_puzzle(),
+            test_rotation(vec![], RefillStrategy::Stop),
+            test_fetcher(clock.clone()),
+            BoardLog::default(),
+            &MemoryAccounting::default(),
+            clock,
+        );
+        let (_, headers, body) = send(
+            &state,
+            Request::get("/nonogram").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(headers["HX-Trigger"]
+            .to_str()
+            .unwrap()
+            .contains(r#""multipaintPuzzlesLeft":0"#));
+        assert!(!body.contains("session-over"));
+        let (_, _, body) = send(&state, Request::get("/").body(Body::empty()).unwrap()).await;
+        assert!(body.contains("puzzles left before the session ends"));
+
+        let duration = state.nonogram.lock().unwrap().timer.duration;
+        start_timer(&state, &mut state.nonogram.lock().unwrap());
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(duration);
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(Duration::from_secs(10));
+        for _ in 0..1000 {
+            if state.rotation.lock().unwrap().is_exhausted() {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert!(state.rotation.lock().unwrap().is_exhausted());
+        let (_, _, body) = send(
+            &state,
+            Request::get("/nonogram").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(body.contains("That was the last puzzle."));
+        assert_eq!(manual.sleepers(), 0);
+    }
+
+    #[tokio::test]
+    async fn solved_puzzles_rotate_once_even_if_the_timer_runs_out() {
+        let (state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        let duration = state.nonogram.lock().unwrap().timer.duration;
+        start_timer(&state, &mut state.nonogram.lock().unwrap());
+        wait_for_sleepers(&manual, 1).await;
+        let solution = state.puzzle.borrow().solution.clone();
+        for id in solution.iter_ones() {
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+        }
+        assert!(matches!(
+            state.nonogram.lock().unwrap().state,
+            NonogramState::Solved(_)
+        ));
+        // The timer runs out during the intermission.
+        wait_for_sleepers(&manual, 2).await;
+        manual.advance(duration);
+        next_puzzle(&state).await;
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        manual.advance(Duration::from_secs(10));
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.nonogram.lock().unwrap().generation, 1);
+    }
+
+    /// A random request against the mutation surface, biased towards the solution so that puzzles get solved.
+    fn fuzz_request(rng: &mut StdRng, puzzle: &NonogrammedPuzzle) -> Request {
+        let (rows, columns) = (puzzle.rows.len(), puzzle.columns.len());
+        let solution = puzzle.solution.iter_ones().collect::<Vec<_>>();
+        let id = match rng.gen_range(0..10) {
+            0 => rng.gen_range(0..rows * columns + 10),
+            1..=5 if !solution.is_empty() => *solution.choose(rng).unwrap(),
+            _ => rng.gen_range(0..rows * columns),
+        };
+        let (row, column) = (id / columns, id % columns);
+        let method = if rng.gen_bool(0.7) { "PUT" } else { "DELETE" };
+        let session = rng.gen_range(0..8);
+        let uri = match rng.gen_range(0..12) {
+            0..=3 => format!("/checkbox/{id}"),
+            4 => format!("/flag/{id}"),
+            5 => format!("/cell/{row}/{column}"),
+            6 => format!("/cell/{row}/{column}/flag"),
+            7 => return session_request("GET", "/nonogram", session),
+            8 => return session_request("GET", "/api/board", session),
+            9 => return session_request("GET", "/api/board.snapshot", session),
+            10 => return session_request("GET", "/minimap", session),
+            _ => {
+                return Request::post("/cursor")
+                    .header(COOKIE, session_cookie(session))
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .body(Body::from(format!(
+                        "mouseX={}&mouseY={}&boardWidth=100&boardHeight=100",
+                        rng.gen_range(-10..110),
+                        rng.gen_range(-10..110),
+                    )))
+                    .unwrap()
+            }
+        };
+        session_request(method, &uri, session)
+    }
+
+    /// Checks what must hold between any two requests.
+    fn check_invariants(state: &AppState, seed: u64) {
+        let nonogram = state.nonogram.lock().unwrap();
+        let puzzle = state.puzzle.borrow();
+        assert_eq!(
+            nonogram.checkboxes.len(),
+            puzzle.rows.len() * puzzle.columns.len(),
+            "Board doesn't fit the puzzle of generation {} (seed {seed}).",
+            nonogram.generation
+        );
+        assert_eq!(
+            nonogram.wrong_squares,
+            count_wrong_squares(&puzzle.solution, &nonogram.checkboxes),
+            "Wrong squares drifted from a recount (seed {seed})."
+        );
+    }
+
+    /// Random interleavings of every mutation from a few sessions at once, while the clock forces timeouts and
+    /// transitions. Set `MULTIPAINT_FUZZ_SEED` to replay a failure, and `MULTIPAINT_FUZZ_STEPS` to run longer.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn random_request_interleavings_keep_the_board_consistent() {
+        let seed: u64 = std::env::var("MULTIPAINT_FUZZ_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| thread_rng().gen());
+        let steps: usize = std::env::var("MULTIPAINT_FUZZ_STEPS")
+            .ok()
+            .and_then(|steps| steps.parse().ok())
+            .unwrap_or(300);
+        let (state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        let duration = state.nonogram.lock().unwrap().timer.duration;
+        start_timer(&state, &mut state.nonogram.lock().unwrap());
+
+        let workers = (0..4)
+            .map(|worker| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(worker));
+                    for _ in 0..steps {
+                        let puzzle = state.puzzle.borrow().clone();
+                        let request = fuzz_request(&mut rng, &puzzle);
+                        let (method, uri) = (request.method().clone(), request.uri().clone());
+                        let (status, _, body) = send(&state, request).await;
+                        assert!(
+                            !status.is_server_error(),
+                            "{method} {uri} answered {status}: {body} (seed {seed})."
+                        );
+                        check_invariants(&state, seed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        // Time keeps moving on from here, so that puzzles time out and rotate under the workers' feet, until they're
+        // done and at least one transition went through.
+        let started = std::time::Instant::now();
+        let mut rng = StdRng::seed_from_u64(seed);
+        while !workers.iter().all(JoinHandle::is_finished)
+            || state.nonogram.lock().unwrap().generation == 0
+        {
+            assert!(
+                started.elapsed() < Duration::from_secs(60),
+                "Handlers deadlocked (seed {seed})."
+            );
+            manual.advance(Duration::from_secs(
+                rng.gen_range(1..=duration.as_secs() / 4),
+            ));
+            tokio::task::yield_now().await;
+        }
+        for worker in workers {
+            worker.await.unwrap();
+        }
+        check_invariants(&state, seed);
+    }
+
+    #[tokio::test]
+    async fn unsolved_puzzles_time_out_and_rotate() {
+        let (state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        let duration = state.nonogram.lock().unwrap().timer.duration;
+        start_timer(&state, &mut state.nonogram.lock().unwrap());
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(duration - Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Unsolved);
+
+        manual.advance(Duration::from_secs(1));
+        // The timer fails the puzzle, then waits before fetching the next one.
+        wait_for_sleepers(&manual, 1).await;
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Failed);
+        manual.advance(Duration::from_secs(10));
+        let puzzle = next_puzzle(&state).await;
+        let nonogram = state.nonogram.lock().unwrap();
+        assert!(nonogram.state == NonogramState::Unsolved);
+        assert_eq!(
+            nonogram.checkboxes.len(),
+            puzzle.rows.len() * puzzle.columns.len()
+        );
+        assert_eq!(nonogram.wrong_squares, puzzle.solution.count_ones());
+        assert_eq!(state.clock.elapsed(nonogram.timer.start), Duration::ZERO);
+    }
+
+    /// Plays through the first few puzzles of a new router by letting their timers run out.
+    async fn first_puzzle_ids(upstreams: UpstreamUrls, seed: u64, count: usize) -> Vec<u64> {
+        let (clock, manual) = Clock::manual();
+        let router = build_router(
+            MultipaintConfig::default(),
+            ActivityContext {
+                upstreams,
+                clock,
+                random: Random::seeded(seed),
+                ..ActivityContext::default()
+            },
+        )
+        .await
+        .unwrap();
+        let current_id = || async {
+            let response = router
+                .clone()
+                .oneshot(Request::get("/api/board").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let board: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            board["puzzle"].as_u64().unwrap()
+        };
+        let mut ids = vec![current_id().await];
+        while ids.len() < count {
+            // Time out the current puzzle, then the pause before the next one.
+            wait_for_sleepers(&manual, 1).await;
+            manual.advance(Duration::from_secs(60 * 60));
+            wait_for_sleepers(&manual, 1).await;
+            manual.advance(Duration::from_secs(60));
+            let previous = *ids.last().unwrap();
+            for _ in 0..1000 {
+                let id = current_id().await;
+                if id != previous {
+                    ids.push(id);
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+            assert_ne!(*ids.last().unwrap(), previous, "No new puzzle was started.");
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn routers_with_the_same_seed_play_the_same_puzzles() {
+        let upstreams = mock_upstreams().await;
+        let first = first_puzzle_ids(upstreams.clone(), 42, 5).await;
+        assert_eq!(first.len(), 5);
+        assert_eq!(first_puzzle_ids(upstreams.clone(), 42, 5).await, first);
+        assert_ne!(first_puzzle_ids(upstreams, 43, 5).await, first);
+    }
+
+    #[tokio::test]
+    async fn solved_puzzles_rotate_after_a_pause() {
+        let (state, manual) = test_state_with_upstreams(mock_upstreams().await);
+        manual.advance(Duration::from_secs(42));
+        let solution = state.puzzle.borrow().solution.clone();
+        for id in solution.iter_ones() {
+            send(
+                &state,
+                session_request("PUT", &format!("/checkbox/{id}"), 1),
+            )
+            .await;
+        }
+        assert!(
+            state.nonogram.lock().unwrap().state == NonogramState::Solved(Duration::from_secs(42))
+        );
+        wait_for_sleepers(&manual, 1).await;
+        manual.advance(Duration::from_secs(9));
+        tokio::task::yield_now().await;
+        assert!(matches!(
+            state.nonogram.lock().unwrap().state,
+            NonogramState::Solved(_)
+        ));
+        manual.advance(Duration::from_secs(1));
+        next_puzzle(&state).await;
+        assert!(state.nonogram.lock().unwrap().state == NonogramState::Unsolved);
+    }
+
+    #[tokio::test]
+    async fn idle_cursors_and_sanctions_expire() {
+        let (state, manual) = test_state_with_upstreams(UpstreamUrls::default());
+        let cursor = |id: u64| {
+            Request::post("/cursor")
+                .header(COOKIE, session_cookie(id))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("mouseX=10&mouseY=20"))
+                .unwrap()
+        };
+        send(&state, cursor(1)).await;
+        send(&state, admin_request("/admin/cursors/1/mute")).await;
+        manual.advance(Duration::from_secs(21));
+        send(&state, cursor(2)).await;
+        assert!(!state.cursors.lock().unwrap().contains_key(&CursorId(1)));
+        assert!(is_sanctioned(&state, CursorId(1), SanctionKind::Muted));
+        manual.advance(admin::MUTE_DURATION);
+        assert!(!is_sanctioned(&state, CursorId(1), SanctionKind::Muted));
+    }
+}
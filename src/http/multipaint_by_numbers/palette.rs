@@ -0,0 +1,96 @@
+use random_color::{Luminosity, RandomColor};
+
+/// How many cursors can get a color of their own before colors are picked at random.
+pub const PALETTE_SIZE: usize = 16;
+
+/// Each slot's hue is this far from the previous one, so that the slots in use are always spread apart.
+const GOLDEN_ANGLE: f32 = 137.507_76;
+
+const SATURATION: f32 = 0.75;
+
+/// Light enough for black text, like the random colors used past the palette.
+const LIGHTNESS: f32 = 0.7;
+
+/// The hue of a palette slot, in degrees.
+pub fn hue(slot: usize) -> f32 {
+    (slot as f32 * GOLDEN_ANGLE) % 360.0
+}
+
+pub fn slot_color(slot: usize) -> [u8; 3] {
+    hsl_to_rgb(hue(slot), SATURATION, LIGHTNESS)
+}
+
+/// The lowest slot that isn't taken, if any is left.
+pub fn free_slot(taken: impl IntoIterator<Item = usize>) -> Option<usize> {
+    let mut free = [true; PALETTE_SIZE];
+    for slot in taken {
+        if let Some(free) = free.get_mut(slot) {
+            *free = false;
+        }
+    }
+    free.iter().position(|&free| free)
+}
+
+/// The color of a cursor with the given palette slot, or a random color seeded with its ID without one.
+pub fn cursor_color(slot: Option<usize>, id: u64) -> [u8; 3] {
+    match slot {
+        Some(slot) => slot_color(slot),
+        None => RandomColor::new()
+            .luminosity(Luminosity::Light)
+            .seed(id)
+            .to_rgb_array(),
+    }
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [u8; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [r, g, b].map(|channel| ((channel + m) * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn the_palette_spreads_hues_apart() {
+        const HUE_BUCKET: f32 = 15.0;
+        let buckets = (0..PALETTE_SIZE)
+            .map(|slot| (hue(slot) / HUE_BUCKET) as u32)
+            .collect::<HashSet<_>>();
+        assert_eq!(buckets.len(), PALETTE_SIZE);
+        let colors = (0..PALETTE_SIZE).map(slot_color).collect::<HashSet<_>>();
+        assert_eq!(colors.len(), PALETTE_SIZE);
+    }
+
+    #[test]
+    fn it_converts_hsl() {
+        assert_eq!(hsl_to_rgb(0.0, 1.0, 0.5), [255, 0, 0]);
+        assert_eq!(hsl_to_rgb(120.0, 1.0, 0.5), [0, 255, 0]);
+        assert_eq!(hsl_to_rgb(240.0, 1.0, 0.5), [0, 0, 255]);
+        assert_eq!(hsl_to_rgb(300.0, 0.0, 0.7), [179, 179, 179]);
+    }
+
+    #[test]
+    fn freed_slots_are_reused_first() {
+        assert_eq!(free_slot([]), Some(0));
+        assert_eq!(free_slot([0, 1, 3]), Some(2));
+        assert_eq!(free_slot(0..PALETTE_SIZE), None);
+        let all_but_five = (0..PALETTE_SIZE).filter(|&slot| slot != 5);
+        assert_eq!(free_slot(all_but_five), Some(5));
+        assert_eq!(cursor_color(Some(5), 1), slot_color(5));
+        assert_eq!(cursor_color(None, 1), cursor_color(None, 1));
+    }
+}
@@ -0,0 +1,233 @@
+//! Turning an uploaded image into a puzzle in two steps. The upload only renders a preview at a few sizes, and
+//! nothing is played until an operator confirms one of them with its token.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use maud::{html, Markup};
+use tokio::time::Instant;
+
+use super::{minimap::board_svg, CheckboxState};
+use crate::nonogram::{
+    image::DecodedImage,
+    nonogrammed::NonogrammedPuzzle,
+    populate_board,
+    solver::{assess, Solvability},
+};
+
+/// Board sides that every upload is previewed at.
+pub const PREVIEW_SIDES: [usize; 3] = [15, 20, 25];
+
+/// Most previews kept waiting for confirmation. The oldest ones are dropped past this.
+pub const MAX_PENDING: usize = 8 * PREVIEW_SIDES.len();
+
+/// How long a preview can be confirmed for.
+pub const PENDING_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How many pixels each cell takes in a preview.
+const PREVIEW_CELL_SIZE: usize = 6;
+
+/// ID of puzzles that were made from an upload, and can't be fetched again.
+pub const CUSTOM_PUZZLE_ID: u32 = 0;
+
+/// One of the sizes that an upload was converted at.
+pub struct PuzzleVariant {
+    pub side: usize,
+    /// `None` if no cell is dark enough at this size.
+    pub puzzle: Option<NonogrammedPuzzle>,
+    pub solvability: Solvability,
+}
+
+/// Converts an image at every size in [`PREVIEW_SIDES`], decoding it only once.
+pub fn build_variants(
+    bytes: &[u8],
+    threshold: u8,
+    title: Option<&str>,
+) -> Result<Vec<PuzzleVariant>> {
+    let image = DecodedImage::decode(bytes)?;
+    PREVIEW_SIDES
+        .iter()
+        .map(|&side| {
+            let solution = image.to_cells(side, side, threshold)?;
+            if solution.not_any() {
+                return Ok(PuzzleVariant {
+                    side,
+                    puzzle: None,
+                    solvability: Solvability::LineSolvable,
+                });
+            }
+            let board = populate_board(&solution, side as u16, side as u16)?;
+            Ok(PuzzleVariant {
+                side,
+                solvability: assess(&board.rows, &board.columns),
+                puzzle: Some(NonogrammedPuzzle {
+                    id: CUSTOM_PUZZLE_ID,
+                    title: title.map(String::from),
+                    copyright: None,
+                    rows: board.rows,
+                    columns: board.columns,
+                    solution: board.solution,
+                }),
+            })
+        })
+        .collect()
+}
+
+struct Pending {
+    token: String,
+    puzzle: NonogrammedPuzzle,
+    expires_at: Instant,
+}
+
+/// Previews waiting for an operator to confirm them, keyed by a random token. Shared by every clone.
+#[derive(Clone, Default)]
+pub struct PendingPuzzles(Arc<Mutex<VecDeque<Pending>>>);
+
+impl PendingPuzzles {
+    /// Holds on to `puzzle` until [`PENDING_TTL`] from `now`, and returns the token that confirms it.
+    pub fn insert(&self, puzzle: NonogrammedPuzzle, now: Instant) -> String {
+        let token = format!("{:032x}", rand::random::<u128>());
+        let mut pending = self.0.lock().unwrap();
+        pending.retain(|pending| pending.expires_at > now);
+        while pending.len() >= MAX_PENDING {
+            pending.pop_front();
+        }
+        pending.push_back(Pending {
+            token: token.clone(),
+            puzzle,
+            expires_at: now + PENDING_TTL,
+        });
+        token
+    }
+
+    /// Whether `token` still confirms a pending puzzle, without taking it.
+    pub fn contains(&self, token: &str, now: Instant) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|pending| pending.token == token && pending.expires_at > now)
+    }
+
+    /// Removes the puzzle for `token`, unless it expired or was dropped to make room.
+    pub fn take(&self, token: &str, now: Instant) -> Option<NonogrammedPuzzle> {
+        let mut pending = self.0.lock().unwrap();
+        pending.retain(|pending| pending.expires_at > now);
+        let index = pending.iter().position(|pending| pending.token == token)?;
+        pending.remove(index).map(|pending| pending.puzzle)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+/// Every variant as a small SVG of its solution, along with how solvable it is and a button to confirm it.
+pub fn render_variants(variants: &[(PuzzleVariant, Option<String>)]) -> Markup {
+    html! {
+        .puzzle-previews {
+            @for (variant, token) in variants {
+                figure {
+                    @match &variant.puzzle {
+                        Some(puzzle) => {
+                            @let checkboxes = puzzle
+                                .solution
+                                .iter()
+                                .map(|cell| if *cell { CheckboxState::Marked } else { CheckboxState::Empty })
+                                .collect::<Vec<_>>();
+                            (board_svg("preview", &checkboxes, variant.side, variant.side, PREVIEW_CELL_SIZE))
+                            figcaption { (variant.side) "x" (variant.side) ": " (variant.solvability) }
+                            @if let Some(token) = token {
+                                form hx-post="admin/puzzle/confirm" hx-target="#puzzle-upload-result" {
+                                    input type="hidden" name="token" value=(token);
+                                    button { "Play this one" }
+                                }
+                            }
+                        }
+                        None => {
+                            figcaption { (variant.side) "x" (variant.side) ": Nothing is dark enough at this size." }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitvec::{bitvec, order::Lsb0};
+
+    use super::*;
+    use crate::{clock::Clock, nonogram::image::tests::fixture_png};
+
+    fn puzzle() -> NonogrammedPuzzle {
+        NonogrammedPuzzle {
+            id: CUSTOM_PUZZLE_ID,
+            title: None,
+            copyright: None,
+            rows: vec![vec![1]],
+            columns: vec![vec![1]],
+            solution: bitvec![1],
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_expire() {
+        let (clock, manual) = Clock::manual();
+        let pending = PendingPuzzles::default();
+        let first = pending.insert(puzzle(), clock.now());
+        manual.advance(PENDING_TTL / 2);
+        let second = pending.insert(puzzle(), clock.now());
+        assert_ne!(first, second);
+        manual.advance(PENDING_TTL / 2);
+        assert!(pending.take(&first, clock.now()).is_none());
+        assert!(pending.take(&second, clock.now()).is_some());
+        // Tokens only work once.
+        assert!(pending.take(&second, clock.now()).is_none());
+        assert_eq!(pending.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn the_oldest_previews_make_room() {
+        let (clock, _) = Clock::manual();
+        let pending = PendingPuzzles::default();
+        let tokens = (0..=MAX_PENDING)
+            .map(|_| pending.insert(puzzle(), clock.now()))
+            .collect::<Vec<_>>();
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert!(pending.take(&tokens[0], clock.now()).is_none());
+        assert!(pending.take(&tokens[MAX_PENDING], clock.now()).is_some());
+    }
+
+    #[test]
+    fn every_size_shares_the_same_conversion() {
+        let png = fixture_png(&["##..", "##..", "....", "...#"]);
+        let variants = build_variants(&png, 128, Some("Corners")).unwrap();
+        assert_eq!(
+            variants
+                .iter()
+                .map(|variant| variant.side)
+                .collect::<Vec<_>>(),
+            PREVIEW_SIDES
+        );
+        for variant in &variants {
+            let puzzle = variant.puzzle.as_ref().unwrap();
+            assert_eq!(puzzle.rows.len(), variant.side);
+            assert_eq!(puzzle.columns.len(), variant.side);
+            assert_eq!(puzzle.title.as_deref(), Some("Corners"));
+            assert!(puzzle.solution[0]);
+            assert!(puzzle.solution[variant.side * variant.side - 1]);
+        }
+        let blank = fixture_png(&["..", ".."]);
+        assert!(build_variants(&blank, 128, None)
+            .unwrap()
+            .iter()
+            .all(|variant| variant.puzzle.is_none()));
+    }
+}
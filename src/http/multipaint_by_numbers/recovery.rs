@@ -0,0 +1,364 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::{rotation::PuzzleKey, CheckboxState};
+use crate::{
+    storage::DataDir,
+    tasks::{recv_or_drain, TaskRegistry},
+};
+
+/// File within the data dir with the latest board snapshot.
+pub const BOARD_SNAPSHOT_FILE: &str = "board_snapshot.json";
+
+/// File within the data dir with every change since the latest snapshot, when `--event-log` is set.
+pub const BOARD_EVENTS_FILE: &str = "board_events.jsonl";
+
+/// How often the board is snapshotted, if it changed.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Most commands that the writer handles before syncing the event log to disk.
+const MAX_BATCH: usize = 64;
+
+pub(super) fn cell_char(state: CheckboxState) -> char {
+    match state {
+        CheckboxState::Empty => '.',
+        CheckboxState::Flagged => 'x',
+        CheckboxState::Marked => '#',
+    }
+}
+
+pub(super) fn cell_state(cell: char) -> Option<CheckboxState> {
+    match cell {
+        '.' => Some(CheckboxState::Empty),
+        'x' => Some(CheckboxState::Flagged),
+        '#' => Some(CheckboxState::Marked),
+        _ => None,
+    }
+}
+
+/// The whole board at a given revision.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BoardSnapshot {
+    pub puzzle: PuzzleKey,
+    /// Increases with every new puzzle, so that events from a different puzzle are never replayed.
+    pub generation: u64,
+    pub revision: u64,
+    /// One character per cell, in row-major order.
+    pub cells: String,
+}
+
+/// A single accepted change to the board, kept short since there's one line per change.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BoardEvent {
+    #[serde(rename = "g")]
+    pub generation: u64,
+    #[serde(rename = "r")]
+    pub revision: u64,
+    #[serde(rename = "c")]
+    pub cell: usize,
+    #[serde(rename = "s")]
+    pub state: char,
+    /// Seconds since the Unix epoch.
+    #[serde(rename = "t")]
+    pub timestamp: u64,
+}
+
+impl BoardEvent {
+    pub fn new(generation: u64, revision: u64, cell: usize, state: CheckboxState) -> Self {
+        BoardEvent {
+            generation,
+            revision,
+            cell,
+            state: cell_char(state),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Event(BoardEvent),
+    Snapshot(BoardSnapshot),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Where board snapshots and events are written. Without a data dir, nothing is persisted.
+///
+/// Everything goes through a single writer task, so that a snapshot always lands after the events it includes.
+/// Callers should send events and snapshots while holding the nonogram lock, to keep them in revision order.
+#[derive(Clone, Debug, Default)]
+pub struct BoardLog {
+    sender: Option<mpsc::UnboundedSender<Command>>,
+    events: bool,
+}
+
+impl BoardLog {
+    /// Starts the writer task. Events are only written if `events` is set; snapshots are always written.
+    pub fn new(data_dir: Option<DataDir>, events: bool, tasks: &TaskRegistry) -> Self {
+        let Some(data_dir) = data_dir else {
+            return BoardLog::default();
+        };
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tasks.spawn_cooperative("board log writer", |token| {
+            run_writer(data_dir, receiver, token)
+        });
+        BoardLog {
+            sender: Some(sender),
+            events,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn event(&self, event: BoardEvent) {
+        if self.events {
+            self.send(Command::Event(event));
+        }
+    }
+
+    /// Replaces the snapshot, and truncates the event log.
+    pub fn snapshot(&self, snapshot: BoardSnapshot) {
+        self.send(Command::Snapshot(snapshot));
+    }
+
+    /// Waits until everything sent so far has been written.
+    pub async fn flush(&self) {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Flush(sender));
+        let _ = receiver.await;
+    }
+
+    fn send(&self, command: Command) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(command);
+        }
+    }
+}
+
+async fn run_writer(
+    data_dir: DataDir,
+    mut receiver: mpsc::UnboundedReceiver<Command>,
+    token: CancellationToken,
+) {
+    let mut events_file = None;
+    let mut buffer = String::new();
+    let mut acks = vec![];
+    while let Some(command) = recv_or_drain(&mut receiver, &token).await {
+        let mut commands = vec![command];
+        while commands.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(command) => commands.push(command),
+                Err(_) => break,
+            }
+        }
+        for command in commands {
+            match command {
+                Command::Event(event) => match serde_json::to_string(&event) {
+                    Ok(line) => {
+                        buffer.push_str(&line);
+                        buffer.push('\n');
+                    }
+                    Err(e) => warn!(error = ?e, "Unable to serialize board event."),
+                },
+                Command::Snapshot(snapshot) => {
+                    // The snapshot already includes every buffered event.
+                    buffer.clear();
+                    if let Err(e) = write_snapshot(&data_dir, &mut events_file, &snapshot).await {
+                        warn!(error = ?e, "Unable to persist board snapshot.");
+                    }
+                }
+                Command::Flush(ack) => acks.push(ack),
+            }
+        }
+        if !buffer.is_empty() {
+            if let Err(e) = append_events(&data_dir, &mut events_file, &buffer).await {
+                warn!(error = ?e, "Unable to persist board events.");
+            }
+            buffer.clear();
+        }
+        for ack in acks.drain(..) {
+            let _ = ack.send(());
+        }
+    }
+}
+
+async fn open_events(data_dir: &DataDir, events_file: &mut Option<File>) -> Result<()> {
+    if events_file.is_none() {
+        let path = data_dir.file(BOARD_EVENTS_FILE);
+        *events_file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .with_context(|| format!("Unable to open {}", path.display()))?,
+        );
+    }
+    Ok(())
+}
+
+async fn append_events(
+    data_dir: &DataDir,
+    events_file: &mut Option<File>,
+    lines: &str,
+) -> Result<()> {
+    open_events(data_dir, events_file).await?;
+    let file = events_file.as_mut().unwrap();
+    file.write_all(lines.as_bytes())
+        .await
+        .with_context(|| "Unable to append board events")?;
+    file.sync_data()
+        .await
+        .with_context(|| "Unable to sync board events")
+}
+
+async fn write_snapshot(
+    data_dir: &DataDir,
+    events_file: &mut Option<File>,
+    snapshot: &BoardSnapshot,
+) -> Result<()> {
+    data_dir.write_json(BOARD_SNAPSHOT_FILE, snapshot).await?;
+    if events_file.is_none() && !data_dir.file(BOARD_EVENTS_FILE).exists() {
+        return Ok(());
+    }
+    open_events(data_dir, events_file).await?;
+    events_file
+        .as_mut()
+        .unwrap()
+        .set_len(0)
+        .await
+        .with_context(|| "Unable to truncate board events")
+}
+
+/// Reads the latest snapshot, and replays every later event from the same puzzle onto it.
+pub async fn recover(data_dir: &DataDir) -> Result<Option<BoardSnapshot>> {
+    let Some(mut snapshot) = data_dir
+        .read_json::<BoardSnapshot>(BOARD_SNAPSHOT_FILE)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let events = data_dir
+        .read_json_lines::<BoardEvent>(BOARD_EVENTS_FILE)
+        .await?;
+    let mut cells = snapshot.cells.chars().collect::<Vec<_>>();
+    for event in events {
+        if event.generation != snapshot.generation || event.revision <= snapshot.revision {
+            continue;
+        }
+        match (cells.get_mut(event.cell), cell_state(event.state)) {
+            (Some(cell), Some(_)) => {
+                *cell = event.state;
+                snapshot.revision = event.revision;
+            }
+            _ => warn!(?event, "Skipping invalid board event."),
+        }
+    }
+    snapshot.cells = cells.into_iter().collect();
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{nonogram::PuzzleSource, storage::tests::temp_data_dir, tasks::SHUTDOWN_TIMEOUT};
+
+    fn snapshot(generation: u64, revision: u64, cells: &str) -> BoardSnapshot {
+        BoardSnapshot {
+            puzzle: (PuzzleSource::Nonogrammed, 7),
+            generation,
+            revision,
+            cells: String::from(cells),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_replays_events_newer_than_the_snapshot() {
+        let data_dir = temp_data_dir("board-replay").await;
+        assert_eq!(recover(&data_dir).await.unwrap(), None);
+        let log = BoardLog::new(Some(data_dir.clone()), true, &TaskRegistry::default());
+        log.snapshot(snapshot(3, 10, "....."));
+        log.event(BoardEvent::new(3, 11, 0, CheckboxState::Marked));
+        log.event(BoardEvent::new(3, 12, 4, CheckboxState::Flagged));
+        log.event(BoardEvent::new(3, 13, 0, CheckboxState::Empty));
+        log.event(BoardEvent::new(3, 14, 2, CheckboxState::Marked));
+        log.flush().await;
+        assert_eq!(
+            recover(&data_dir).await.unwrap(),
+            Some(snapshot(3, 14, "..#.x"))
+        );
+        // A new snapshot truncates the log.
+        log.snapshot(snapshot(3, 14, "..#.x"));
+        log.flush().await;
+        assert_eq!(
+            std::fs::read_to_string(data_dir.file(BOARD_EVENTS_FILE)).unwrap(),
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_pending_events() {
+        let data_dir = temp_data_dir("board-shutdown").await;
+        let tasks = TaskRegistry::default();
+        let log = BoardLog::new(Some(data_dir.clone()), true, &tasks);
+        log.snapshot(snapshot(1, 0, "..."));
+        log.event(BoardEvent::new(1, 1, 2, CheckboxState::Marked));
+        assert!(tasks.shutdown(SHUTDOWN_TIMEOUT).await.is_empty());
+        assert_eq!(
+            recover(&data_dir).await.unwrap(),
+            Some(snapshot(1, 1, "..#"))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_ignores_events_from_other_puzzles() {
+        let data_dir = temp_data_dir("board-generation").await;
+        data_dir
+            .write_json(BOARD_SNAPSHOT_FILE, &snapshot(2, 5, "..."))
+            .await
+            .unwrap();
+        for event in [
+            BoardEvent::new(1, 6, 0, CheckboxState::Marked),
+            BoardEvent::new(2, 4, 1, CheckboxState::Marked),
+            BoardEvent::new(2, 6, 9, CheckboxState::Marked),
+            BoardEvent::new(2, 7, 2, CheckboxState::Flagged),
+        ] {
+            data_dir
+                .append_json_line(BOARD_EVENTS_FILE, &event)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            recover(&data_dir).await.unwrap(),
+            Some(snapshot(2, 7, "..x"))
+        );
+    }
+
+    #[tokio::test]
+    async fn events_are_only_written_when_enabled() {
+        let data_dir = temp_data_dir("board-no-events").await;
+        let log = BoardLog::new(Some(data_dir.clone()), false, &TaskRegistry::default());
+        log.snapshot(snapshot(1, 0, ".."));
+        log.event(BoardEvent::new(1, 1, 0, CheckboxState::Marked));
+        log.flush().await;
+        assert_eq!(
+            recover(&data_dir).await.unwrap(),
+            Some(snapshot(1, 0, ".."))
+        );
+        BoardLog::default().flush().await;
+    }
+}
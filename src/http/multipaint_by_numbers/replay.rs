@@ -0,0 +1,381 @@
+//! Every accepted change to the current puzzle, kept compact enough to hold on to for a whole long solve.
+//!
+//! Changes are compacted as they come in: consecutive changes to the same cell only keep the first and the last
+//! one, timestamps are stored as the delta from the previous change, and everything goes into a buffer of varints.
+//! The buffer is only expanded back into events when someone views the replay.
+
+use std::{mem, time::Duration};
+
+use tokio::time::Instant;
+
+use super::CheckboxState;
+
+/// A single change to a cell, at some time after the puzzle started.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReplayEvent {
+    pub at: Duration,
+    pub cell: usize,
+    pub state: CheckboxState,
+}
+
+/// Consecutive changes to the same cell, of which only the first and the last are kept.
+#[derive(Copy, Clone, Debug)]
+struct Run {
+    first: ReplayEvent,
+    last: Option<ReplayEvent>,
+}
+
+/// The compacted changes of the current puzzle. Reset with every new puzzle.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    start: Instant,
+    /// Encoded events, each as the milliseconds since the previous one followed by its cell and state.
+    buffer: Vec<u8>,
+    /// Milliseconds since `start` of the last encoded event.
+    last_at: u64,
+    /// Changes to the latest cell, not encoded yet since they may still be coalesced.
+    run: Option<Run>,
+}
+
+impl Replay {
+    pub fn new(start: Instant) -> Self {
+        Replay {
+            start,
+            buffer: Vec::new(),
+            last_at: 0,
+            run: None,
+        }
+    }
+
+    /// Starts a replay for a board that already has cells set, such as one recovered after a restart.
+    pub fn from_board(start: Instant, checkboxes: &[CheckboxState]) -> Self {
+        let mut replay = Replay::new(start);
+        for (cell, &state) in checkboxes.iter().enumerate() {
+            if state != CheckboxState::Empty {
+                replay.push(ReplayEvent {
+                    at: Duration::ZERO,
+                    cell,
+                    state,
+                });
+            }
+        }
+        replay
+    }
+
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Records a change to `cell` at `now`.
+    pub fn record(&mut self, now: Instant, cell: usize, state: CheckboxState) {
+        self.push(ReplayEvent {
+            at: now.saturating_duration_since(self.start),
+            cell,
+            state,
+        });
+    }
+
+    /// Records a change to `cell` at `now` that replaces the latest change to it, if that one is still pending.
+    pub fn coalesce(&mut self, now: Instant, cell: usize, state: CheckboxState) {
+        let event = ReplayEvent {
+            at: now.saturating_duration_since(self.start),
+            cell,
+            state,
+        };
+        match &mut self.run {
+            Some(run) if run.first.cell == cell && run.last.is_none() => run.first = event,
+            _ => self.push(event),
+        }
+    }
+
+    fn push(&mut self, event: ReplayEvent) {
+        if let Some(run) = &mut self.run {
+            if run.first.cell == event.cell {
+                run.last = Some(event);
+                return;
+            }
+        }
+        let previous = self.run.replace(Run {
+            first: event,
+            last: None,
+        });
+        if let Some(run) = previous {
+            self.encode_run(run);
+        }
+    }
+
+    fn encode_run(&mut self, run: Run) {
+        self.encode(run.first);
+        if let Some(last) = run.last {
+            self.encode(last);
+        }
+    }
+
+    fn encode(&mut self, event: ReplayEvent) {
+        // Clamped so that timestamps never go backwards.
+        let at = (event.at.as_millis() as u64).max(self.last_at);
+        write_varint(&mut self.buffer, at - self.last_at);
+        write_varint(
+            &mut self.buffer,
+            event.cell as u64 * 3 + state_code(event.state),
+        );
+        self.last_at = at;
+    }
+
+    /// Decodes every event, in the order that they happened.
+    pub fn expand(&self) -> Vec<ReplayEvent> {
+        let mut events = Vec::new();
+        let mut at = 0;
+        let mut bytes = self.buffer.as_slice();
+        while !bytes.is_empty() {
+            let (Some(delta), Some(code)) = (read_varint(&mut bytes), read_varint(&mut bytes))
+            else {
+                unreachable!("Replays only contain whole events.");
+            };
+            at += delta;
+            events.push(ReplayEvent {
+                at: Duration::from_millis(at),
+                cell: (code / 3) as usize,
+                state: code_state(code % 3),
+            });
+        }
+        if let Some(run) = self.run {
+            events.extend(
+                [Some(run.first), run.last]
+                    .into_iter()
+                    .flatten()
+                    .map(|event| ReplayEvent {
+                        at: event.at.max(Duration::from_millis(self.last_at)),
+                        ..event
+                    }),
+            );
+        }
+        events
+    }
+
+    /// Approximate memory used by the replay.
+    pub fn size(&self) -> usize {
+        mem::size_of::<Self>() + self.buffer.capacity()
+    }
+}
+
+/// Plays `events` on an empty board of `cells` cells.
+pub fn reconstruct(events: &[ReplayEvent], cells: usize) -> Vec<CheckboxState> {
+    let mut checkboxes = vec![CheckboxState::Empty; cells];
+    for event in events {
+        if let Some(checkbox) = checkboxes.get_mut(event.cell) {
+            *checkbox = event.state;
+        }
+    }
+    checkboxes
+}
+
+fn state_code(state: CheckboxState) -> u64 {
+    match state {
+        CheckboxState::Empty => 0,
+        CheckboxState::Flagged => 1,
+        CheckboxState::Marked => 2,
+    }
+}
+
+fn code_state(code: u64) -> CheckboxState {
+    match code {
+        0 => CheckboxState::Empty,
+        1 => CheckboxState::Flagged,
+        _ => CheckboxState::Marked,
+    }
+}
+
+/// Writes `value` seven bits at a time, lowest first, with the high bit set on every byte but the last.
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (index, &byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[index + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::clock::Clock;
+
+    const STATES: [CheckboxState; 3] = [
+        CheckboxState::Empty,
+        CheckboxState::Flagged,
+        CheckboxState::Marked,
+    ];
+
+    #[test]
+    fn varints_round_trip() {
+        let values = [0, 1, 127, 128, 300, 16_383, 16_384, u64::MAX];
+        let mut buffer = Vec::new();
+        for value in values {
+            write_varint(&mut buffer, value);
+        }
+        assert_eq!(buffer[..4], [0, 1, 127, 0x80]);
+        let mut bytes = buffer.as_slice();
+        for value in values {
+            assert_eq!(read_varint(&mut bytes), Some(value));
+        }
+        assert!(bytes.is_empty());
+        assert_eq!(read_varint(&mut [0x80].as_slice()), None);
+    }
+
+    #[test]
+    fn repeated_changes_to_a_cell_keep_the_first_and_last() {
+        let start = Instant::now();
+        let mut replay = Replay::new(start);
+        let at = |ms| start + Duration::from_millis(ms);
+        replay.record(at(10), 4, CheckboxState::Marked);
+        replay.record(at(20), 4, CheckboxState::Empty);
+        replay.record(at(30), 4, CheckboxState::Flagged);
+        replay.record(at(40), 5, CheckboxState::Marked);
+        replay.record(at(50), 4, CheckboxState::Marked);
+        let event = |ms, cell, state| ReplayEvent {
+            at: Duration::from_millis(ms),
+            cell,
+            state,
+        };
+        assert_eq!(
+            replay.expand(),
+            vec![
+                event(10, 4, CheckboxState::Marked),
+                event(30, 4, CheckboxState::Flagged),
+                event(40, 5, CheckboxState::Marked),
+                event(50, 4, CheckboxState::Marked),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesced_changes_replace_the_latest_one() {
+        let start = Instant::now();
+        let mut replay = Replay::new(start);
+        let at = |ms| start + Duration::from_millis(ms);
+        replay.record(at(10), 4, CheckboxState::Marked);
+        replay.coalesce(at(20), 4, CheckboxState::Empty);
+        replay.coalesce(at(30), 4, CheckboxState::Marked);
+        replay.record(at(40), 5, CheckboxState::Marked);
+        // The change to cell 4 was already encoded, so this one is kept on its own.
+        replay.coalesce(at(50), 4, CheckboxState::Flagged);
+        assert_eq!(
+            replay.expand(),
+            vec![
+                ReplayEvent {
+                    at: Duration::from_millis(30),
+                    cell: 4,
+                    state: CheckboxState::Marked
+                },
+                ReplayEvent {
+                    at: Duration::from_millis(40),
+                    cell: 5,
+                    state: CheckboxState::Marked
+                },
+                ReplayEvent {
+                    at: Duration::from_millis(50),
+                    cell: 4,
+                    state: CheckboxState::Flagged
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn compacted_replays_reconstruct_the_same_board() {
+        let (clock, manual) = Clock::manual();
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let cells = rng.gen_range(1..=625);
+            let mut replay = Replay::new(clock.now());
+            let mut raw = Vec::new();
+            for _ in 0..rng.gen_range(0..2000) {
+                manual.advance(Duration::from_millis(rng.gen_range(0..500)));
+                // Favour the previous cell, as when dragging or toggling back and forth.
+                let cell = match raw.last() {
+                    Some(&ReplayEvent { cell, .. }) if rng.gen_bool(0.5) => cell,
+                    _ => rng.gen_range(0..cells),
+                };
+                let state = STATES[rng.gen_range(0..STATES.len())];
+                replay.record(clock.now(), cell, state);
+                raw.push(ReplayEvent {
+                    at: clock.now() - replay.start(),
+                    cell,
+                    state,
+                });
+            }
+            let expanded = replay.expand();
+            assert_eq!(
+                reconstruct(&expanded, cells),
+                reconstruct(&raw, cells),
+                "seed {seed}"
+            );
+            assert!(expanded.len() <= raw.len());
+            assert!(expanded.windows(2).all(|pair| pair[0].at <= pair[1].at));
+            assert!(expanded.iter().all(|event| raw.contains(event)));
+        }
+    }
+
+    #[test]
+    fn long_sessions_are_much_smaller_than_raw_events() {
+        let start = Instant::now();
+        let mut replay = Replay::new(start);
+        let mut rng = StdRng::seed_from_u64(750);
+        let mut raw = Vec::new();
+        let mut at = Duration::ZERO;
+        // A 30-minute solve of a 25x25 board, with bursts of toggling the same cell.
+        while at < Duration::from_secs(30 * 60) {
+            at += Duration::from_millis(rng.gen_range(10..150));
+            let cell = if rng.gen_bool(0.6) {
+                raw.last().map_or(0, |event: &ReplayEvent| event.cell)
+            } else {
+                rng.gen_range(0..625)
+            };
+            let state = STATES[rng.gen_range(0..STATES.len())];
+            replay.record(start + at, cell, state);
+            raw.push(ReplayEvent { at, cell, state });
+        }
+        assert!(raw.len() > 20_000);
+        let raw_size = raw.len() * mem::size_of::<ReplayEvent>();
+        assert!(
+            replay.size() * 8 < raw_size,
+            "{} bytes compacted, {raw_size} bytes raw",
+            replay.size()
+        );
+        assert_eq!(reconstruct(&replay.expand(), 625), reconstruct(&raw, 625));
+    }
+
+    #[test]
+    fn recovered_boards_are_the_starting_point() {
+        let start = Instant::now();
+        let board = [
+            CheckboxState::Marked,
+            CheckboxState::Empty,
+            CheckboxState::Flagged,
+        ];
+        let mut replay = Replay::from_board(start, &board);
+        replay.record(start + Duration::from_secs(1), 1, CheckboxState::Marked);
+        assert_eq!(
+            reconstruct(&replay.expand(), 3),
+            vec![
+                CheckboxState::Marked,
+                CheckboxState::Marked,
+                CheckboxState::Flagged
+            ]
+        );
+    }
+}
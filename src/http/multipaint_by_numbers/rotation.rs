@@ -0,0 +1,450 @@
+use std::{collections::VecDeque, fmt};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{nonogram::PuzzleSource, random::Random, storage::DataDir, tasks::TaskRegistry};
+
+/// File within the data dir where recently played puzzles are kept across restarts.
+pub const RECENTLY_PLAYED_FILE: &str = "recently_played.json";
+
+/// How many puzzles are remembered as recently played, unless overridden with `--recently-played`.
+pub const DEFAULT_RECENTLY_PLAYED: usize = 50;
+
+/// How many puzzles may be left in the shuffled list before players are told that it's about to run out.
+pub const FEW_PUZZLES_LEFT: usize = 10;
+
+/// Identifies a puzzle across every source.
+pub type PuzzleKey = (PuzzleSource, u32);
+
+/// The last few puzzles that were played, most recent last.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RecentlyPlayed {
+    capacity: usize,
+    entries: VecDeque<PuzzleKey>,
+}
+
+impl RecentlyPlayed {
+    pub fn new(capacity: usize) -> Self {
+        RecentlyPlayed {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Loads the persisted list, if any, keeping the most recent entries that fit in `capacity`.
+    pub async fn load(data_dir: Option<&DataDir>, capacity: usize) -> Self {
+        let mut recent = RecentlyPlayed::new(capacity);
+        let Some(data_dir) = data_dir else {
+            return recent;
+        };
+        match data_dir
+            .read_json::<RecentlyPlayed>(RECENTLY_PLAYED_FILE)
+            .await
+        {
+            Ok(Some(persisted)) => persisted
+                .entries
+                .into_iter()
+                .for_each(|key| recent.record(key)),
+            Ok(None) => (),
+            Err(e) => warn!(error = ?e, "Unable to load recently played puzzles."),
+        }
+        recent
+    }
+
+    pub async fn save(&self, data_dir: &DataDir) {
+        if let Err(e) = data_dir.write_json(RECENTLY_PLAYED_FILE, self).await {
+            warn!(error = ?e, "Unable to persist recently played puzzles.");
+        }
+    }
+
+    pub fn contains(&self, key: PuzzleKey) -> bool {
+        self.entries.contains(&key)
+    }
+
+    /// Marks a puzzle as the most recently played one, forgetting the oldest entry if full.
+    pub fn record(&mut self, key: PuzzleKey) {
+        self.entries.retain(|&entry| entry != key);
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(key);
+    }
+}
+
+/// What to do once every puzzle in the shuffled list has been played.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum RefillStrategy {
+    /// Shuffle every puzzle back in.
+    ReshuffleAll,
+    /// Shuffle every puzzle back in, except for the recently played ones.
+    #[default]
+    ExcludeRecent,
+    /// End the session once the list runs out.
+    Stop,
+}
+
+impl fmt::Display for RefillStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefillStrategy::ReshuffleAll => write!(f, "reshuffle-all"),
+            RefillStrategy::ExcludeRecent => write!(f, "exclude-recent"),
+            RefillStrategy::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct QueuedPuzzle {
+    key: PuzzleKey,
+    /// Set by operators, to play the puzzle even if it was played recently.
+    force: bool,
+}
+
+/// Decides which puzzle to play next.
+pub struct Rotation {
+    /// Every puzzle that may be picked, in no particular order.
+    catalog: Vec<u32>,
+    /// Shuffled puzzles to go through once the queue is empty, drawn from the catalog.
+    puzzle_list: Vec<u32>,
+    /// Puzzles which were explicitly requested, in order.
+    pending: VecDeque<QueuedPuzzle>,
+    recent: RecentlyPlayed,
+    /// The puzzle being played.
+    current: Option<PuzzleKey>,
+    strategy: RefillStrategy,
+    /// How many times the shuffled list has been filled, including the first time.
+    fills: usize,
+    /// Set once there's nothing left to play with [`RefillStrategy::Stop`].
+    exhausted: bool,
+    data_dir: Option<DataDir>,
+    random: Random,
+    /// Where saving the recently played puzzles is spawned, so that shutdown waits for it.
+    tasks: TaskRegistry,
+}
+
+impl Rotation {
+    /// Starts a rotation over every puzzle in `catalog`, which is shuffled right away.
+    pub fn new(
+        catalog: Vec<u32>,
+        recent: RecentlyPlayed,
+        strategy: RefillStrategy,
+        data_dir: Option<DataDir>,
+        random: Random,
+    ) -> Self {
+        let mut rotation = Rotation {
+            catalog,
+            puzzle_list: vec![],
+            pending: VecDeque::new(),
+            recent,
+            current: None,
+            strategy,
+            fills: 0,
+            exhausted: false,
+            data_dir,
+            random,
+            tasks: TaskRegistry::default(),
+        };
+        rotation.refill();
+        rotation
+    }
+
+    /// Spawns its background writes through `tasks` instead of a registry of its own.
+    pub fn with_tasks(mut self, tasks: TaskRegistry) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    /// Fills the shuffled list according to the refill strategy. Returns `false` if the session should end instead.
+    fn refill(&mut self) -> bool {
+        if self.fills > 0 && self.strategy == RefillStrategy::Stop {
+            return false;
+        }
+        self.puzzle_list.clone_from(&self.catalog);
+        if self.strategy == RefillStrategy::ExcludeRecent {
+            self.puzzle_list
+                .retain(|&id| !self.recent.contains((PuzzleSource::Nonogrammed, id)));
+            // Everything was played recently, so there's nothing better to do than replaying it.
+            if self.puzzle_list.is_empty() {
+                self.puzzle_list.clone_from(&self.catalog);
+            }
+        }
+        self.random.shuffle(&mut self.puzzle_list);
+        self.fills += 1;
+        debug!(
+            strategy = %self.strategy,
+            len = self.puzzle_list.len(),
+            "Refilled the puzzle list."
+        );
+        !self.puzzle_list.is_empty()
+    }
+
+    /// Requests a puzzle to be played next. Returns `false` if it's already queued.
+    ///
+    /// Unless `force` is set, the puzzle will be skipped if it was played recently.
+    pub fn enqueue(&mut self, key: PuzzleKey, force: bool) -> bool {
+        if let Some(queued) = self.pending.iter_mut().find(|queued| queued.key == key) {
+            queued.force |= force;
+            return false;
+        }
+        self.pending.push_back(QueuedPuzzle { key, force });
+        true
+    }
+
+    /// Returns a candidate that couldn't be fetched for now, so that it's tried again first.
+    pub fn put_back(&mut self, key: PuzzleKey) {
+        self.pending.push_front(QueuedPuzzle { key, force: true });
+    }
+
+    /// Picks the next puzzle to try, skipping recently played ones unless they were forced.
+    ///
+    /// Returns `None` once the session is over, which only happens with [`RefillStrategy::Stop`].
+    pub fn next_candidate(&mut self) -> Option<PuzzleKey> {
+        while let Some(QueuedPuzzle { key, force }) = self.pending.pop_front() {
+            if force || !self.recent.contains(key) {
+                return Some(key);
+            }
+            debug!(source = %key.0, id = key.1, "Skipping recently played puzzle.");
+        }
+        // Give up on skipping if everything was played recently, rather than looping forever.
+        let mut skips_left = self.catalog.len();
+        loop {
+            let Some(id) = self.puzzle_list.pop() else {
+                if self.refill() {
+                    continue;
+                }
+                self.exhausted = true;
+                return None;
+            };
+            let key = (PuzzleSource::Nonogrammed, id);
+            if self.strategy != RefillStrategy::ExcludeRecent
+                || skips_left == 0
+                || !self.recent.contains(key)
+            {
+                return Some(key);
+            }
+            skips_left -= 1;
+        }
+    }
+
+    /// How many puzzles are left in the shuffled list before it's refilled.
+    pub fn remaining(&self) -> usize {
+        self.puzzle_list.len()
+    }
+
+    /// How many puzzles the shuffled list is drawn from.
+    pub fn total(&self) -> usize {
+        self.catalog.len()
+    }
+
+    pub fn strategy(&self) -> RefillStrategy {
+        self.strategy
+    }
+
+    /// How many times the shuffled list ran out and was refilled.
+    pub fn refills(&self) -> usize {
+        self.fills.saturating_sub(1)
+    }
+
+    /// Whether the session is over, with no puzzles left to play.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    pub fn current(&self) -> Option<PuzzleKey> {
+        self.current
+    }
+
+    /// Forgets the current puzzle while one that isn't from any source is played, such as an uploaded image.
+    pub fn play_custom(&mut self) {
+        self.current = None;
+    }
+
+    /// Remembers that a puzzle is being played, persisting the list in the background.
+    pub fn record_played(&mut self, key: PuzzleKey) {
+        self.current = Some(key);
+        self.recent.record(key);
+        self.pending
+            .retain(|queued| queued.key != key || queued.force);
+        if let Some(data_dir) = self.data_dir.clone() {
+            let recent = self.recent.clone();
+            self.tasks
+                .spawn_cooperative("recently played writer", |_| async move {
+                    recent.save(&data_dir).await
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temp_data_dir;
+
+    fn key(id: u32) -> PuzzleKey {
+        (PuzzleSource::Nonogrammed, id)
+    }
+
+    #[test]
+    fn it_forgets_the_oldest_entries() {
+        let mut recent = RecentlyPlayed::new(3);
+        for id in 1..=4 {
+            recent.record(key(id));
+        }
+        assert!(!recent.contains(key(1)));
+        assert!(recent.contains(key(2)) && recent.contains(key(4)));
+        // Replaying an entry makes it the most recent one.
+        recent.record(key(2));
+        recent.record(key(5));
+        assert!(!recent.contains(key(3)));
+        assert!(recent.contains(key(2)));
+        assert!(!recent.contains((PuzzleSource::Webpbn, 2)));
+        let mut disabled = RecentlyPlayed::new(0);
+        disabled.record(key(1));
+        assert!(!disabled.contains(key(1)));
+    }
+
+    #[tokio::test]
+    async fn it_persists_recently_played_puzzles() {
+        let data_dir = temp_data_dir("recently-played").await;
+        assert_eq!(
+            RecentlyPlayed::load(Some(&data_dir), 3).await,
+            RecentlyPlayed::new(3)
+        );
+        let mut recent = RecentlyPlayed::new(5);
+        for id in 1..=4 {
+            recent.record(key(id));
+        }
+        recent.save(&data_dir).await;
+        assert_eq!(RecentlyPlayed::load(Some(&data_dir), 5).await, recent);
+        // A smaller capacity keeps the most recent entries.
+        let loaded = RecentlyPlayed::load(Some(&data_dir), 2).await;
+        assert!(loaded.contains(key(3)) && loaded.contains(key(4)));
+        assert!(!loaded.contains(key(2)));
+    }
+
+    #[test]
+    fn it_skips_recently_played_puzzles_unless_forced() {
+        let mut recent = RecentlyPlayed::new(10);
+        recent.record(key(1));
+        recent.record(key(2));
+        let mut rotation = Rotation::new(
+            vec![1, 2, 3],
+            recent,
+            RefillStrategy::ExcludeRecent,
+            None,
+            Random::default(),
+        );
+        assert!(rotation.enqueue(key(2), false));
+        assert!(rotation.enqueue(key(1), true));
+        assert!(rotation.enqueue(key(4), false));
+        assert_eq!(rotation.next_candidate(), Some(key(1)));
+        assert_eq!(rotation.next_candidate(), Some(key(4)));
+        // The shuffled list also skips recently played puzzles.
+        assert_eq!(rotation.next_candidate(), Some(key(3)));
+    }
+
+    #[test]
+    fn it_dedupes_the_queue() {
+        let mut rotation = Rotation::new(
+            vec![],
+            RecentlyPlayed::new(10),
+            RefillStrategy::ReshuffleAll,
+            None,
+            Random::default(),
+        );
+        assert!(rotation.enqueue(key(7), false));
+        assert!(!rotation.enqueue(key(7), false));
+        assert!(!rotation.enqueue(key(7), true));
+        assert_eq!(rotation.pending.len(), 1);
+        assert!(rotation.pending[0].force);
+        assert_eq!(rotation.next_candidate(), Some(key(7)));
+        assert!(rotation.pending.is_empty());
+        // Playing a puzzle drops non-forced requests for it.
+        rotation.enqueue(key(8), false);
+        rotation.record_played(key(8));
+        assert!(rotation.pending.is_empty());
+    }
+
+    /// Plays every puzzle in the shuffled list, and returns the ids in the order that they were picked.
+    fn play_through(rotation: &mut Rotation) -> Vec<u32> {
+        let mut played = vec![];
+        for _ in 0..rotation.remaining() {
+            let key = rotation.next_candidate().unwrap();
+            rotation.record_played(key);
+            played.push(key.1);
+        }
+        played.sort();
+        played
+    }
+
+    #[test]
+    fn it_refills_according_to_the_strategy() {
+        let catalog = vec![1, 2, 3, 4, 5];
+        let mut recent = RecentlyPlayed::new(2);
+        recent.record(key(1));
+
+        let mut rotation = Rotation::new(
+            catalog.clone(),
+            recent.clone(),
+            RefillStrategy::ReshuffleAll,
+            None,
+            Random::default(),
+        );
+        assert_eq!((rotation.remaining(), rotation.total()), (5, 5));
+        assert_eq!(play_through(&mut rotation), vec![1, 2, 3, 4, 5]);
+        assert_eq!(rotation.remaining(), 0);
+        assert!(rotation.next_candidate().is_some());
+        assert_eq!((rotation.remaining(), rotation.refills()), (4, 1));
+
+        let mut rotation = Rotation::new(
+            catalog.clone(),
+            recent.clone(),
+            RefillStrategy::ExcludeRecent,
+            None,
+            Random::default(),
+        );
+        assert_eq!(play_through(&mut rotation), vec![2, 3, 4, 5]);
+        // The last two puzzles are left out of the next shuffle.
+        let last_two = rotation.recent.entries.clone();
+        assert!(rotation.refill());
+        assert_eq!(rotation.remaining(), 3);
+        let played = play_through(&mut rotation);
+        assert!(played.iter().all(|&id| !last_two.contains(&key(id))));
+
+        let mut rotation = Rotation::new(
+            catalog,
+            recent,
+            RefillStrategy::Stop,
+            None,
+            Random::default(),
+        );
+        assert_eq!(play_through(&mut rotation), vec![1, 2, 3, 4, 5]);
+        assert!(!rotation.is_exhausted());
+        assert_eq!(rotation.next_candidate(), None);
+        assert!(rotation.is_exhausted());
+        // Operators can still queue puzzles.
+        rotation.enqueue(key(9), false);
+        assert_eq!(rotation.next_candidate(), Some(key(9)));
+    }
+
+    #[test]
+    fn it_replays_everything_if_everything_was_played_recently() {
+        let mut recent = RecentlyPlayed::new(10);
+        recent.record(key(1));
+        recent.record(key(2));
+        let mut rotation = Rotation::new(
+            vec![1, 2],
+            recent,
+            RefillStrategy::ExcludeRecent,
+            None,
+            Random::default(),
+        );
+        assert_eq!(rotation.remaining(), 2);
+        assert!(rotation.next_candidate().is_some());
+    }
+}
@@ -0,0 +1,117 @@
+//! Session cookies that the server issues to anonymous players, so that clients can't pick their own cursor ID.
+//!
+//! A session is a random cursor ID along with a tag that only this process can compute, so that clients can't claim
+//! someone else's cursor or make up a new one to shake off a sanction. The key is made anew on startup, so sessions
+//! from a previous instance are replaced on their next cursor update.
+
+use std::{fmt::Write, sync::LazyLock};
+
+use axum::http::{header::COOKIE, HeaderMap, HeaderValue};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use super::CursorId;
+
+/// Name of the cookie that holds the session.
+pub(super) const COOKIE_NAME: &str = "multipaint_session";
+
+static KEY: LazyLock<[u8; 32]> = LazyLock::new(|| rand::thread_rng().gen());
+
+/// A new session for an anonymous player.
+pub(super) fn issue() -> CursorId {
+    CursorId(rand::random())
+}
+
+fn tag(id: CursorId) -> [u8; 16] {
+    let digest = Sha256::new()
+        .chain_update(*KEY)
+        .chain_update(id.0.to_be_bytes())
+        .finalize();
+    digest[..16].try_into().unwrap()
+}
+
+/// The cookie value for session `id`.
+pub(super) fn token(id: CursorId) -> String {
+    tag(id)
+        .iter()
+        .fold(format!("{}.", id.0), |mut token, byte| {
+            write!(token, "{byte:02x}").unwrap();
+            token
+        })
+}
+
+/// The session in `token`, if this process issued it.
+fn verify(token: &str) -> Option<CursorId> {
+    let (id, hex) = token.split_once('.')?;
+    let id = CursorId(id.parse().ok()?);
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let tag = tag(id);
+    // Compares every byte, so that the time taken doesn't tell how much of the tag was right.
+    let mut difference = 0;
+    for (i, expected) in tag.iter().enumerate() {
+        let byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        difference |= byte ^ expected;
+    }
+    (difference == 0).then_some(id)
+}
+
+/// The session from the request's cookies, if it has a valid one.
+pub(super) fn from_headers(headers: &HeaderMap) -> Option<CursorId> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == COOKIE_NAME).then_some(value)
+        })
+        .find_map(verify)
+}
+
+/// A `Set-Cookie` value that hands session `id` to the browser. Page scripts can't read it, and it's only sent back
+/// over HTTPS if the page was served over HTTPS.
+pub(super) fn set_cookie(id: CursorId, secure: bool) -> HeaderValue {
+    let secure = if secure { "; Secure" } else { "" };
+    HeaderValue::try_from(format!(
+        "{COOKIE_NAME}={}; Path=/; HttpOnly; SameSite=Lax{secure}",
+        token(id)
+    ))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookies(value: &str) -> HeaderMap {
+        HeaderMap::from_iter([(COOKIE, HeaderValue::try_from(value).unwrap())])
+    }
+
+    #[test]
+    fn only_issued_sessions_are_accepted() {
+        let id = issue();
+        let token = token(id);
+        assert_eq!(
+            from_headers(&cookies(&format!("theme=dark; {COOKIE_NAME}={token}"))),
+            Some(id)
+        );
+        assert_eq!(from_headers(&HeaderMap::new()), None);
+        // Someone else's ID, or a tag for another ID.
+        let other = CursorId(id.0.wrapping_add(1));
+        let forged = format!("{}.{}", other.0, token.split_once('.').unwrap().1);
+        assert_eq!(
+            from_headers(&cookies(&format!("{COOKIE_NAME}={forged}"))),
+            None
+        );
+        for token in ["", "42", "42.", "42.zz", "x.00"] {
+            assert_eq!(
+                from_headers(&cookies(&format!("{COOKIE_NAME}={token}"))),
+                None,
+                "{token}"
+            );
+        }
+    }
+}
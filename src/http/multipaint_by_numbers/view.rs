@@ -0,0 +1,477 @@
+//! Markup of the game pages, rendered from plain data that the handlers gather while holding their locks.
+//!
+//! Views never touch the app state, so that every one of them can be checked against the snapshots in
+//! `src/http/snapshots`. After an intentional markup change, run the tests with `UPDATE_SNAPSHOTS=1` and review the
+//! updated snapshots along with the code.
+
+use std::time::Duration;
+
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+
+use super::{rotation::FEW_PUZZLES_LEFT, CheckboxState, NonogramState, SCRIPT, STYLE};
+use crate::{
+    format::{format_duration, DurationStyle},
+    http::{alert, maintenance},
+};
+
+/// Everything that the main page shows outside of the polled board.
+pub struct IndexView {
+    pub base: String,
+    /// Extra `<head>` contents from `--extra-css` and `--extra-js`.
+    pub custom_head: Option<Markup>,
+    /// Banners and overlays at the top of the page.
+    pub banners: Markup,
+    pub footer: Markup,
+    pub manual_submit: bool,
+    /// Whether the session ends once the rotation runs out, rather than reshuffling.
+    pub ends_when_exhausted: bool,
+    /// Where players reach this page, as announced by the tunnel server.
+    pub public_url: Option<String>,
+}
+
+/// The page's `og:url` when the tunnel server didn't announce a public URL.
+const DEFAULT_URL: &str = "https://multipaint.sish.top";
+
+pub fn render_index(view: &IndexView) -> Markup {
+    html! {
+    (DOCTYPE)
+    head {
+        meta charset="utf-8";
+        base href=(view.base);
+        title { "Multipaint by Numbers" }
+        meta property="og:title" content="Multipaint by Numbers" {}
+        meta property="og:url" content=(view.public_url.as_deref().unwrap_or(DEFAULT_URL)) {}
+        meta property="og:description" content="Multiplayer picross/nonogram, powered by htmx." {}
+        // script src="https://unpkg.com/htmx.org@2.0.2" integrity="sha384-Y7hw+L/jvKeWIRRkqWYfPcvVxHzVzn5REgzbawhxAuQGwX1XWe70vji+VSeHOThJ" crossorigin="anonymous" {}
+        // script src="https://unpkg.com/htmx.org@2.0.2/dist/htmx.js" integrity="sha384-yZq+5izaUBKcRgFbxgkRYwpHhHHCpp5nseXp0MEQ1A4MTWVMnqkmcuFez8x5qfxr" crossorigin="anonymous" {}
+        script src="htmx.js" {}
+        style { (PreEscaped(STYLE)) }
+        style { (PreEscaped(alert::STYLE)) }
+        style { (PreEscaped(maintenance::STYLE)) }
+        script { (PreEscaped(SCRIPT)) }
+        @if let Some(custom_head) = &view.custom_head {
+            (custom_head)
+        }
+    }
+    body {
+        (view.banners)
+        #cursors hx-post="cursor" hx-trigger="load, mousemove delay:500ms, every 1500ms" hx-vals="javascript:{mouseX: mouseX, mouseY: mouseY, boardWidth: boardWidth, boardHeight: boardHeight}" {}
+        h1 { "Multipaint by Numbers" }
+        hr {}
+        main {
+            #nonogram hx-get="nonogram" hx-trigger="load, every 2s" {}
+            #minimap hx-get="minimap" hx-trigger="load, every 2s" {}
+        }
+        @if view.manual_submit {
+            p #submit {
+                button hx-post="submit" hx-target="#submit-result" { "Check solution" }
+                " "
+                span #submit-result {}
+            }
+        }
+        hr {}
+        p #puzzles-left .hidden data-threshold=(FEW_PUZZLES_LEFT) {
+            @if view.ends_when_exhausted {
+                "Only " span .count {} " puzzles left before the session ends."
+            } @else {
+                "Fresh shuffle coming up."
+            }
+        }
+        p { "Click or touch to mark, right-click or long-touch to flag. Dragging with a cursor also works." }
+        p {
+            label {
+                input #show-heatmap type="checkbox" onchange="toggleHeatmap(this.checked)";
+                " Show where everyone is looking"
+            }
+        }
+        p {
+            "Puzzles from "
+            a href="https://nonogrammed.com/" target="_blank" {
+                "Nonogrammed"
+            }
+            ". The source code for this website is "
+            a href="https://github.com/BadMannersXYZ/htmx-ssh-games" target="_blank" {
+                "on Github"
+            }
+            ". I know it's jank :^)"
+        }
+        (view.footer)
+        }
+    }
+}
+
+/// The countdown, or how long the solve took.
+#[derive(Copy, Clone)]
+pub struct TimerView {
+    pub state: NonogramState,
+    pub time_left: Duration,
+    /// Full duration of the puzzle.
+    pub duration: Duration,
+}
+
+/// Whether the countdown for a puzzle of the given duration shows hours, so that it keeps the same shape throughout.
+pub fn timer_shows_hours(duration: Duration) -> bool {
+    duration > Duration::from_secs(60 * 60)
+}
+
+pub fn render_timer(view: &TimerView) -> Markup {
+    if let NonogramState::Solved(success) = view.state {
+        return html! {
+            p #timer {
+                "Solved in " (format_duration(success, DurationStyle::Clock)) "!"
+            }
+        };
+    };
+    let style = if timer_shows_hours(view.duration) {
+        DurationStyle::HourClock
+    } else {
+        DurationStyle::Clock
+    };
+    let time_left = view.time_left;
+    html! {
+        p #timer {
+            span #timer-elapsed .hidden[time_left == Duration::ZERO] {
+                "Time left: " (format_duration(time_left, style))
+            }
+            span #timer-done .hidden[time_left > Duration::ZERO] {
+                "Time's up!"
+            }
+        }
+    }
+}
+
+/// The polled board of the current puzzle, along with its title and timer.
+pub struct BoardView<'a> {
+    pub id: u32,
+    pub title: Option<&'a str>,
+    /// Trusted HTML from the upstream.
+    pub copyright: Option<&'a str>,
+    pub rows: &'a [Vec<u8>],
+    pub columns: &'a [Vec<u8>],
+    pub checkboxes: &'a [CheckboxState],
+    pub revision: u64,
+    pub timer: TimerView,
+    /// How the players did, once the puzzle is solved.
+    pub summary: Option<Markup>,
+    /// Whether that was the last puzzle of the session.
+    pub exhausted: bool,
+}
+
+pub fn render_board(view: &BoardView) -> Markup {
+    let puzzle_state = view.timer.state;
+    let columns_len = view.columns.len();
+    html! {
+        @if matches!(puzzle_state, NonogramState::Solved(_)) {
+            h2 #congratulations {
+                "Congratulations!!"
+            }
+        }
+        @if let Some(summary) = &view.summary {
+            (summary)
+        }
+        @if view.exhausted && puzzle_state != NonogramState::Unsolved {
+            p #session-over {
+                "That was the last puzzle. Thanks for playing!"
+            }
+        }
+        @if let Some(title) = view.title {
+            h3 {
+                "Puzzle: " (title) " (#" (view.id) ")"
+            }
+        }
+        @if let Some(copyright) = view.copyright {
+            p {
+                em .copyright {
+                    (PreEscaped(copyright))
+                }
+            }
+        }
+        (render_timer(&view.timer))
+        .nonogram-scroll {
+            table #nonogram-table .solved[matches!(puzzle_state, NonogramState::Solved(_))] data-rows=(view.rows.len()) data-columns=(columns_len) data-revision=(view.revision) {
+                thead {
+                    tr {
+                        th .corner {}
+                        @for column in view.columns {
+                            th .column-clue scope="col" {
+                                (render_clues(column))
+                            }
+                        }
+                    }
+                }
+                tbody {
+                    @for (i, row) in view.rows.iter().enumerate() {
+                        tr {
+                            th .row-clue scope="row" {
+                                (render_clues(row))
+                            }
+                            @let id_range = i * columns_len..(i + 1) * columns_len;
+                            @let slice = &view.checkboxes[id_range.clone()];
+                            @for (j, (id, &state)) in id_range.zip(slice).enumerate() {
+                                td.checkbox-cell title=(format!("R{} C{}", i + 1, j + 1)) {
+                                    (render_cell(id, puzzle_state != NonogramState::Unsolved, &state, view.revision))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Most clues that a single line shows one by one. Longer lists are compacted, so that a few busy lines can't push
+/// the board off-screen.
+pub const MAX_EXPANDED_CLUES: usize = 12;
+
+/// The clues of a single row or column.
+pub fn render_clues(values: &[u8]) -> Markup {
+    html! {
+        @if values.len() > MAX_EXPANDED_CLUES {
+            @let joined = values.iter().map(u8::to_string).collect::<Vec<_>>().join(",");
+            div .compact title=(joined) {
+                .hint { (joined) }
+            }
+        } @else {
+            div {
+                @for value in values {
+                    .hint { (value) }
+                }
+            }
+        }
+    }
+}
+
+/// A single cell. `revision` is the board revision it reflects.
+pub fn render_cell(id: usize, disabled: bool, state: &CheckboxState, revision: u64) -> Markup {
+    match state {
+        CheckboxState::Marked => html! {
+            .checkbox.marked data-revision=(revision) {
+                input id=(format!("checkbox-{id}")) type="checkbox" disabled[disabled] checked {}
+                .mark {}
+                div hx-delete=(format!("checkbox/{id}")) hx-trigger=(format!("mousedown[buttons==1] from:#checkbox-{id}, mouseenter[buttons==1] from:#checkbox-{id}")) hx-swap="outerHTML" hx-target="closest .checkbox" {}
+            }
+        },
+        CheckboxState::Flagged if !disabled => html! {
+            .checkbox.flagged data-revision=(revision) hx-delete=(format!("flag/{id}")) hx-trigger="contextmenu[pointerType=='touch']" hx-swap="outerHTML" {
+                input id=(format!("checkbox-{id}")) type="checkbox" disabled[disabled] {}
+                .mark {}
+                div hx-put=(format!("checkbox/{id}")) hx-trigger=(format!("mousedown[buttons==1] from:#checkbox-{id}, mouseenter[buttons==1] from:#checkbox-{id}")) hx-swap="outerHTML" hx-target="closest .checkbox" {}
+                div hx-delete=(format!("flag/{id}")) hx-trigger=(format!("mousedown[buttons==2] from:#checkbox-{id}, mouseenter[buttons==2] from:#checkbox-{id}, contextmenu[isTouchDevice()] from:#checkbox-{id}")) hx-swap="outerHTML" hx-target="closest .checkbox" {}
+            }
+        },
+        _ => html! {
+            .checkbox.empty data-revision=(revision) {
+                input id=(format!("checkbox-{id}")) type="checkbox" disabled[disabled] {}
+                .mark {}
+                div hx-put=(format!("checkbox/{id}")) hx-trigger=(format!("mousedown[buttons==1] from:#checkbox-{id}, mouseenter[buttons==1] from:#checkbox-{id}")) hx-swap="outerHTML" hx-target="closest .checkbox" {}
+                div hx-put=(format!("flag/{id}")) hx-trigger=(format!("mousedown[buttons==2] from:#checkbox-{id}, mouseenter[buttons==2] from:#checkbox-{id}, contextmenu[isTouchDevice()] from:#checkbox-{id}")) hx-swap="outerHTML" hx-target="closest .checkbox" {}
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::snapshot::assert_snapshot;
+
+    fn minutes(count: u64) -> Duration {
+        Duration::from_secs(count * 60)
+    }
+
+    fn index_view(manual_submit: bool, ends_when_exhausted: bool) -> IndexView {
+        IndexView {
+            base: String::from("/multipaint/"),
+            custom_head: None,
+            banners: html! { p .banner { "Banner" } },
+            footer: html! { footer { "Footer" } },
+            manual_submit,
+            ends_when_exhausted,
+            public_url: None,
+        }
+    }
+
+    const ROWS: [&[u8]; 2] = [&[1], &[2]];
+    const COLUMNS: [&[u8]; 2] = [&[2], &[1]];
+
+    fn board_view<'a>(
+        rows: &'a [Vec<u8>],
+        columns: &'a [Vec<u8>],
+        checkboxes: &'a [CheckboxState],
+        state: NonogramState,
+    ) -> BoardView<'a> {
+        BoardView {
+            id: 42,
+            title: Some("Corner"),
+            copyright: Some("&copy; <a href=\"user.php?NAME=mock\">mock</a>"),
+            rows,
+            columns,
+            checkboxes,
+            revision: 7,
+            timer: TimerView {
+                state,
+                time_left: if state == NonogramState::Unsolved {
+                    minutes(3)
+                } else {
+                    Duration::ZERO
+                },
+                duration: minutes(5),
+            },
+            summary: None,
+            exhausted: false,
+        }
+    }
+
+    #[test]
+    fn the_index_matches_its_snapshots() {
+        assert_snapshot("multipaint_index", render_index(&index_view(false, false)));
+        assert_snapshot(
+            "multipaint_index_manual_submit_last_puzzles",
+            render_index(&index_view(true, true)),
+        );
+    }
+
+    #[test]
+    fn the_public_url_is_announced() {
+        let index = render_index(&IndexView {
+            public_url: Some(String::from("https://abc123.lhr.life/multipaint/")),
+            ..index_view(false, false)
+        })
+        .into_string();
+        assert!(
+            index.contains(
+                r#"<meta property="og:url" content="https://abc123.lhr.life/multipaint/">"#
+            ),
+            "{index}"
+        );
+    }
+
+    #[test]
+    fn the_board_matches_its_snapshots() {
+        let rows = ROWS.map(<[u8]>::to_vec);
+        let columns = COLUMNS.map(<[u8]>::to_vec);
+        let unsolved = [
+            CheckboxState::Marked,
+            CheckboxState::Flagged,
+            CheckboxState::Marked,
+            CheckboxState::Empty,
+        ];
+        let solved = [
+            CheckboxState::Marked,
+            CheckboxState::Flagged,
+            CheckboxState::Marked,
+            CheckboxState::Marked,
+        ];
+        assert_snapshot(
+            "multipaint_board_unsolved",
+            render_board(&board_view(
+                &rows,
+                &columns,
+                &unsolved,
+                NonogramState::Unsolved,
+            )),
+        );
+        assert_snapshot(
+            "multipaint_board_solved",
+            render_board(&BoardView {
+                summary: Some(html! { p .coaching { "Summary" } }),
+                exhausted: true,
+                ..board_view(&rows, &columns, &solved, NonogramState::Solved(minutes(2)))
+            }),
+        );
+        assert_snapshot(
+            "multipaint_board_failed",
+            render_board(&BoardView {
+                title: None,
+                copyright: None,
+                ..board_view(&rows, &columns, &unsolved, NonogramState::Failed)
+            }),
+        );
+    }
+
+    #[test]
+    fn cells_match_their_snapshots() {
+        for state in [
+            CheckboxState::Empty,
+            CheckboxState::Flagged,
+            CheckboxState::Marked,
+        ] {
+            for disabled in [false, true] {
+                let name = format!(
+                    "multipaint_cell_{}{}",
+                    state.as_str(),
+                    if disabled { "_disabled" } else { "" }
+                );
+                assert_snapshot(&name, render_cell(3, disabled, &state, 7));
+            }
+        }
+    }
+
+    #[test]
+    fn the_timer_matches_its_snapshots() {
+        let timer = |state, time_left, duration| {
+            render_timer(&TimerView {
+                state,
+                time_left,
+                duration,
+            })
+        };
+        let unsolved = NonogramState::Unsolved;
+        assert_snapshot(
+            "multipaint_timer_running",
+            timer(unsolved, minutes(3), minutes(5)),
+        );
+        assert_snapshot(
+            "multipaint_timer_hours",
+            timer(unsolved, minutes(61), minutes(90)),
+        );
+        assert_snapshot(
+            "multipaint_timer_done",
+            timer(unsolved, Duration::ZERO, minutes(5)),
+        );
+        assert_snapshot(
+            "multipaint_timer_solved",
+            timer(
+                NonogramState::Solved(minutes(61)),
+                Duration::ZERO,
+                minutes(90),
+            ),
+        );
+    }
+
+    #[test]
+    fn long_timers_show_hours() {
+        let render = |state, time_left| {
+            render_timer(&TimerView {
+                state,
+                time_left,
+                duration: minutes(90),
+            })
+            .into_string()
+        };
+        assert!(render(NonogramState::Unsolved, minutes(61)).contains("Time left: 1:01:00"));
+        assert!(render(NonogramState::Unsolved, minutes(59)).contains("Time left: 0:59:00"));
+        assert!(render_timer(&TimerView {
+            state: NonogramState::Unsolved,
+            time_left: minutes(59),
+            duration: minutes(60),
+        })
+        .into_string()
+        .contains("Time left: 59:00"));
+        assert!(render(NonogramState::Solved(minutes(61)), Duration::ZERO)
+            .contains("Solved in 1:01:00!"));
+    }
+
+    #[test]
+    fn long_clue_lists_are_compacted() {
+        let short = render_clues(&[1; MAX_EXPANDED_CLUES]).into_string();
+        assert_eq!(short.matches(r#"class="hint""#).count(), MAX_EXPANDED_CLUES);
+        assert!(!short.contains("compact"));
+        let long = render_clues(&[1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1]).into_string();
+        assert_eq!(
+            long,
+            r#"<div class="compact" title="1,2,3,1,2,3,1,2,3,1,2,3,1"><div class="hint">1,2,3,1,2,3,1,2,3,1,2,3,1</div></div>"#
+        );
+    }
+}
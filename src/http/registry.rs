@@ -0,0 +1,200 @@
+//! Activities that can be served, by name.
+//!
+//! The built-in activities register themselves through [`register_builtins`]. Downstream binaries can add their
+//! own with [`register`] before building a router:
+//!
+//! ```
+//! use axum::{routing::get, Router};
+//! use htmx_ssh_games::http::registry::{self, ActivityContext};
+//!
+//! # tokio_test_block_on(async {
+//! registry::register("hello", "Hello - Says hi.", |_context| async {
+//!     Ok(Router::new()
+//!         .route("/", get(|| async { "Hello!" }))
+//!         .route("/summary", get(|| async { "Saying hi" })))
+//! });
+//! assert!(registry::names().contains(&"hello"));
+//! let router = registry::build("hello", ActivityContext::default()).await.unwrap();
+//! # });
+//! # fn tokio_test_block_on(future: impl std::future::Future<Output = ()>) {
+//! #     tokio::runtime::Runtime::new().unwrap().block_on(future)
+//! # }
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use axum::Router;
+
+use super::{
+    checkbox::{self, CheckboxBoard, CheckboxConfig},
+    embed::EmbedOrigins,
+    maintenance::MaintenanceMode,
+    metrics::StatusSections,
+    multipaint_by_numbers::{self, config::MultipaintConfig},
+};
+use crate::{
+    accounting::MemoryAccounting,
+    clock::Clock,
+    handoff::ShutdownHooks,
+    nonogram::{funnel::PuzzleFunnel, upstream::UpstreamClient, UpstreamUrls},
+    random::Random,
+    schedule::TimeZone,
+    storage::DataDir,
+    tasks::TaskRegistry,
+    tunnel::TunnelLink,
+};
+
+/// Everything that an activity may need to build its router, as configured from the command line.
+///
+/// Activities are free to ignore the settings that don't apply to them.
+#[derive(Clone, Default)]
+pub struct ActivityContext {
+    pub upstreams: UpstreamUrls,
+    /// Sends every request to the upstreams, identifying us with `--contact-url` and `--contact-email`.
+    pub upstream_client: UpstreamClient,
+    pub data_dir: Option<DataDir>,
+    pub accounting: MemoryAccounting,
+    /// Extra sections for the `/admin/status` page.
+    pub status: StatusSections,
+    pub clock: Clock,
+    /// Settings for the checkboxes activity.
+    pub checkboxes: CheckboxConfig,
+    /// The checkboxes board, which `serve-ssh` shares with its terminal UI.
+    pub checkbox_board: CheckboxBoard,
+    /// Settings for Multipaint.
+    pub multipaint: MultipaintConfig,
+    /// Shared randomness for picking puzzles, seeded with `--seed`.
+    pub random: Random,
+    /// Time zone that days are counted in, from `--timezone`.
+    pub timezone: TimeZone,
+    /// Sites allowed to frame embeddable pages, from `--embed-origin`.
+    pub embed_origins: EmbedOrigins,
+    /// Counts how fetched puzzles turn into played ones, for `/metrics`.
+    pub funnel: PuzzleFunnel,
+    /// What to persist before the process exits.
+    pub shutdown: ShutdownHooks,
+    /// Freezes the boards while operators switch it on.
+    pub maintenance: MaintenanceMode,
+    /// Where background tasks are spawned, so that shutdown can stop them.
+    pub tasks: TaskRegistry,
+    /// Whether players can reach us through the main tunnel. Always connected when serving locally.
+    pub tunnel: TunnelLink,
+}
+
+type BuilderFuture = Pin<Box<dyn Future<Output = Result<Router>> + Send>>;
+
+type Builder = Arc<dyn Fn(ActivityContext) -> BuilderFuture + Send + Sync>;
+
+#[derive(Clone)]
+struct Registration {
+    name: &'static str,
+    description: &'static str,
+    builder: Builder,
+}
+
+static REGISTRY: RwLock<Vec<Registration>> = RwLock::new(vec![]);
+
+/// Makes an activity available under `name`, replacing any activity previously registered with the same name.
+///
+/// The activity's router must serve its page at `/`, and a short fragment for the landing page at `/summary`. Since
+/// it may be mounted under a path, every URL in its pages should be relative to a
+/// [`base_href`](super::landing::base_href).
+pub fn register<F, Fut>(name: &'static str, description: &'static str, builder: F)
+where
+    F: Fn(ActivityContext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Router>> + Send + 'static,
+{
+    let registration = Registration {
+        name,
+        description,
+        builder: Arc::new(move |context| Box::pin(builder(context))),
+    };
+    let mut registry = REGISTRY.write().unwrap();
+    match registry.iter_mut().find(|existing| existing.name == name) {
+        Some(existing) => *existing = registration,
+        None => registry.push(registration),
+    }
+}
+
+/// Registers the activities that ship with this crate.
+pub fn register_builtins() {
+    checkbox::register();
+    multipaint_by_numbers::register();
+}
+
+/// Names of every registered activity, in registration order.
+pub fn names() -> Vec<&'static str> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .map(|registration| registration.name)
+        .collect()
+}
+
+pub fn description(name: &str) -> Option<&'static str> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|registration| registration.name == name)
+        .map(|registration| registration.description)
+}
+
+/// Builds the router for a registered activity.
+pub async fn build(name: &str, context: ActivityContext) -> Result<Router> {
+    let builder = REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|registration| registration.name == name)
+        .map(|registration| Arc::clone(&registration.builder))
+        .with_context(|| format!("Unknown activity {name}"))?;
+    builder(context)
+        .await
+        .with_context(|| format!("Unable to build activity {name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request, routing::get};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn registered_activities_can_be_built() {
+        register_builtins();
+        register("test-hello", "Hello", |_| async {
+            Ok(Router::new().route("/", get(|| async { "Hello!" })))
+        });
+        register("test-hello", "Hello again", |_| async {
+            Ok(Router::new().route("/", get(|| async { "Hello again!" })))
+        });
+        let names = names();
+        assert_eq!(&names[..2], ["checkboxes", "multipaint"]);
+        assert_eq!(
+            names.iter().filter(|&&name| name == "test-hello").count(),
+            1
+        );
+        assert_eq!(description("test-hello"), Some("Hello again"));
+        let router = build("test-hello", ActivityContext::default())
+            .await
+            .unwrap();
+        let response = router
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"Hello again!");
+        assert!(build("test-missing", ActivityContext::default())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Whether the browser sees a page over HTTPS, so that pages can avoid APIs that only work in secure contexts.
+//!
+//! Behind a tunnel or a reverse proxy, the frontend that terminates TLS tells us through `X-Forwarded-Proto` or
+//! `Forwarded`. When the local server does it with `--tls-cert`, its requests carry [`TlsConnection`], since HTTP/1.1
+//! requests don't say their scheme. Otherwise, only an absolute `https` request URI, such as HTTP/2's, says so.
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, uri::Scheme},
+};
+use maud::{html, Markup};
+
+use crate::http::identity::Identity;
+
+/// Request extension for connections that we terminated TLS on ourselves.
+#[derive(Copy, Clone, Debug)]
+pub struct TlsConnection;
+
+/// Whether the request reached the frontend over HTTPS, as far as we can tell.
+pub fn is_https(parts: &Parts) -> bool {
+    let headers = &parts.headers;
+    // With several proxies, the first value is the one closest to the browser.
+    if let Some(proto) = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+    {
+        return proto.trim().eq_ignore_ascii_case("https");
+    }
+    if let Some(forwarded) = headers
+        .get("forwarded")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+    {
+        if let Some(proto) = forwarded.split(';').find_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            key.trim()
+                .eq_ignore_ascii_case("proto")
+                .then(|| value.trim().trim_matches('"'))
+        }) {
+            return proto.eq_ignore_ascii_case("https");
+        }
+    }
+    parts.extensions.get::<TlsConnection>().is_some() || parts.uri.scheme() == Some(&Scheme::HTTPS)
+}
+
+/// Whether the page is served over HTTPS, per [`is_https`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SecureContext(pub bool);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SecureContext
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(SecureContext(is_https(parts)))
+    }
+}
+
+/// A dismissible warning for operators that the page is served over plain HTTP. Empty for everyone else.
+pub fn notice(secure: SecureContext, identity: &Identity) -> Markup {
+    if secure.0 || !identity.is_admin() {
+        return html! {};
+    }
+    html! {
+        p .insecure-notice role="status" {
+            "This page is served over plain HTTP, so browsers disable some of their APIs. Serve it over HTTPS to "
+            "get all of its features."
+            " "
+            button type="button" onclick="this.parentElement.remove()" { "Dismiss" }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Request, http::Uri};
+
+    use super::*;
+
+    fn parts(uri: &'static str, headers: &[(&'static str, &'static str)]) -> Parts {
+        let mut request = Request::get(Uri::from_static(uri));
+        for &(name, value) in headers {
+            request = request.header(name, value);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn local_http_is_not_secure() {
+        assert!(!is_https(&parts("/", &[])));
+        assert!(!is_https(&parts("http://localhost:5555/", &[])));
+    }
+
+    #[test]
+    fn tunnels_are_secure_behind_a_tls_frontend() {
+        assert!(is_https(&parts("/", &[("x-forwarded-proto", "https")])));
+        assert!(is_https(&parts(
+            "/",
+            &[("x-forwarded-proto", "HTTPS, http")]
+        )));
+        assert!(!is_https(&parts("/", &[("x-forwarded-proto", "http")])));
+        assert!(is_https(&parts(
+            "/",
+            &[(
+                "forwarded",
+                "for=192.0.2.1;proto=https, for=10.0.0.1;proto=http"
+            )]
+        )));
+        assert!(!is_https(&parts("/", &[("forwarded", "for=192.0.2.1")])));
+        // The frontend knows better than the request line.
+        assert!(!is_https(&parts(
+            "https://localhost/",
+            &[("x-forwarded-proto", "http")]
+        )));
+    }
+
+    #[test]
+    fn only_operators_see_the_notice() {
+        let operator = Identity::Named {
+            name: String::from("operator"),
+            is_admin: true,
+        };
+        assert!(notice(SecureContext(false), &operator)
+            .into_string()
+            .contains("plain HTTP"));
+        assert_eq!(notice(SecureContext(true), &operator).into_string(), "");
+        assert_eq!(
+            notice(SecureContext(false), &Identity::Anonymous).into_string(),
+            ""
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Load shedding: when handlers get slow, optional work (cursor updates, polls from spectators, overlays) is
+//! answered cheaply until latency recovers, so that moves still go through quickly. Mutations are never shed.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::{header::RETRY_AFTER, request::Parts, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Extension, Router,
+};
+use tracing::{info, warn};
+
+/// Default latency above which optional work is shed, in milliseconds.
+pub const DEFAULT_SHED_THRESHOLD_MS: u64 = 250;
+
+/// What to send in `Retry-After` for shed requests, in seconds.
+pub const SHED_RETRY_AFTER_SECS: u64 = 5;
+
+/// How much each new sample moves a route's average.
+const EWMA_WEIGHT: f64 = 0.2;
+
+/// Shedding stops once every route is faster than this fraction of the threshold, so that it doesn't flap.
+const RECOVERY_RATIO: f64 = 0.5;
+
+/// Routes without samples for this long no longer count, such as routes that are being shed.
+const STALE_AFTER: Duration = Duration::from_secs(10);
+
+/// Whether to shed load, given whether it's already being shed and the slowest route's average latency.
+pub fn should_shed(shedding: bool, slowest: Duration, threshold: Duration) -> bool {
+    if shedding {
+        slowest.as_secs_f64() >= threshold.as_secs_f64() * RECOVERY_RATIO
+    } else {
+        slowest > threshold
+    }
+}
+
+/// Exponentially weighted moving averages of handler latency, per route.
+#[derive(Clone, Debug, Default)]
+pub struct RouteLatencies(BTreeMap<String, (f64, Instant)>);
+
+impl RouteLatencies {
+    pub fn record(&mut self, route: &str, latency: Duration, now: Instant) {
+        let latency = latency.as_secs_f64();
+        self.0
+            .entry(String::from(route))
+            .and_modify(|(average, updated_at)| {
+                *average += EWMA_WEIGHT * (latency - *average);
+                *updated_at = now;
+            })
+            // Starting from zero, so that a single slow request on a new route doesn't count as a burst.
+            .or_insert((EWMA_WEIGHT * latency, now));
+    }
+
+    /// The highest average among routes with recent samples.
+    pub fn slowest(&self, now: Instant) -> Duration {
+        let slowest = self
+            .0
+            .values()
+            .filter(|(_, updated_at)| now.saturating_duration_since(*updated_at) < STALE_AFTER)
+            .map(|(average, _)| *average)
+            .fold(0.0, f64::max);
+        Duration::from_secs_f64(slowest)
+    }
+
+    /// Every route's average, for metrics.
+    pub fn averages(&self) -> Vec<(String, Duration)> {
+        self.0
+            .iter()
+            .map(|(route, (average, _))| (route.clone(), Duration::from_secs_f64(*average)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct ShedderState {
+    latencies: RouteLatencies,
+    shedding: bool,
+}
+
+/// Decides when to shed load, from the latency measured by [`with_load_shedding`].
+///
+/// Handlers can always extract it; without the layer, or without a threshold, it never sheds.
+#[derive(Clone, Debug, Default)]
+pub struct LoadShedder {
+    threshold: Option<Duration>,
+    state: Arc<Mutex<ShedderState>>,
+    shed_total: Arc<AtomicU64>,
+}
+
+/// Marks responses to shed requests, so that their latency isn't measured.
+#[derive(Clone, Copy, Debug)]
+struct Shed;
+
+impl LoadShedder {
+    /// Sheds load while handlers take longer than `threshold` on average. Never sheds without one.
+    pub fn new(threshold: Option<Duration>) -> Self {
+        LoadShedder {
+            threshold,
+            ..LoadShedder::default()
+        }
+    }
+
+    pub fn threshold(&self) -> Option<Duration> {
+        self.threshold
+    }
+
+    /// Records how long a route took to respond, and updates whether load is being shed.
+    pub fn record(&self, route: &str, latency: Duration, now: Instant) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.latencies.record(route, latency, now);
+        let slowest = state.latencies.slowest(now);
+        let shedding = should_shed(state.shedding, slowest, threshold);
+        if shedding != state.shedding {
+            if shedding {
+                warn!(?slowest, "Handlers are slow, shedding optional work.");
+            } else {
+                info!(?slowest, "Handlers recovered, no longer shedding.");
+            }
+            state.shedding = shedding;
+        }
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.state.lock().unwrap().shedding
+    }
+
+    /// How many requests were shed so far.
+    pub fn shed_total(&self) -> u64 {
+        self.shed_total.load(Ordering::Relaxed)
+    }
+
+    /// Every route's average latency.
+    pub fn averages(&self) -> Vec<(String, Duration)> {
+        self.state.lock().unwrap().latencies.averages()
+    }
+
+    /// Counts a shed request, and marks its response so that its latency isn't measured.
+    pub fn shed(&self, response: impl IntoResponse) -> Response {
+        self.shed_total.fetch_add(1, Ordering::Relaxed);
+        let mut response = response.into_response();
+        response.extensions_mut().insert(Shed);
+        response
+    }
+
+    /// Sheds a request with 503 Service Unavailable, asking the client to retry later.
+    pub fn unavailable(&self) -> Response {
+        self.shed((
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, SHED_RETRY_AFTER_SECS.to_string())],
+        ))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for LoadShedder
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<LoadShedder>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Measures the latency of every route, and makes the shedder available to handlers. Must be added after every
+/// route, since it's only applied to existing ones.
+pub fn with_load_shedding(router: Router, shedder: LoadShedder) -> Router {
+    router
+        .route_layer(middleware::from_fn_with_state(
+            shedder.clone(),
+            measure_latency,
+        ))
+        .layer(Extension(shedder))
+}
+
+async fn measure_latency(
+    State(shedder): State<LoadShedder>,
+    route: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let response = next.run(request).await;
+    if response.extensions().get::<Shed>().is_none() {
+        shedder.record(route.as_str(), start.elapsed(), Instant::now());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use tower::ServiceExt;
+
+    const THRESHOLD: Duration = Duration::from_millis(100);
+
+    /// Feeds latencies in milliseconds to a single route, one per second, returning the decision after each one.
+    fn decisions(shedder: &LoadShedder, route: &str, series: &[u64], start: Instant) -> Vec<bool> {
+        series
+            .iter()
+            .enumerate()
+            .map(|(i, &millis)| {
+                shedder.record(
+                    route,
+                    Duration::from_millis(millis),
+                    start + Duration::from_secs(i as u64),
+                );
+                shedder.is_shedding()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_sheds_with_hysteresis() {
+        assert!(!should_shed(false, THRESHOLD, THRESHOLD));
+        assert!(should_shed(false, THRESHOLD * 2, THRESHOLD));
+        assert!(should_shed(true, THRESHOLD / 2, THRESHOLD));
+        assert!(!should_shed(true, THRESHOLD / 3, THRESHOLD));
+
+        let start = Instant::now();
+        // A single slow request isn't enough.
+        let shedder = LoadShedder::new(Some(THRESHOLD));
+        assert_eq!(
+            decisions(&shedder, "/nonogram", &[20, 300, 20, 20], start),
+            [false, false, false, false]
+        );
+        // A burst is, and latency has to fall well under the threshold to recover.
+        let shedder = LoadShedder::new(Some(THRESHOLD));
+        assert_eq!(
+            decisions(&shedder, "/nonogram", &[400, 400, 400], start),
+            [false, true, true]
+        );
+        assert_eq!(
+            decisions(&shedder, "/nonogram", &[10; 7], start + STALE_AFTER / 2),
+            [true, true, true, true, true, true, false]
+        );
+        assert_eq!(shedder.averages().len(), 1);
+    }
+
+    #[test]
+    fn stale_routes_stop_counting() {
+        let shedder = LoadShedder::new(Some(THRESHOLD));
+        let start = Instant::now();
+        assert_eq!(
+            decisions(&shedder, "/cursor", &[1000, 1000], start),
+            [true, true]
+        );
+        // The slow route is being shed, so it gets no new samples.
+        let later = start + STALE_AFTER * 2;
+        assert_eq!(decisions(&shedder, "/checkbox/:id", &[10], later), [false]);
+    }
+
+    #[test]
+    fn it_never_sheds_without_a_threshold() {
+        let shedder = LoadShedder::new(None);
+        decisions(&shedder, "/cursor", &[10_000; 10], Instant::now());
+        assert!(!shedder.is_shedding());
+        assert!(shedder.averages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_measures_routes_but_not_shed_responses() {
+        let shedder = LoadShedder::new(Some(THRESHOLD));
+        let router = Router::new()
+            .route("/slow/:id", get(|| async { "slow" }))
+            .route(
+                "/shed",
+                get(|shedder: LoadShedder| async move { shedder.unavailable() }),
+            );
+        let router = with_load_shedding(router, shedder.clone());
+        let response = router
+            .clone()
+            .oneshot(Request::get("/slow/4").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router
+            .oneshot(Request::get("/shed").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+        let routes = shedder
+            .averages()
+            .into_iter()
+            .map(|(route, _)| route)
+            .collect::<Vec<_>>();
+        assert_eq!(routes, vec![String::from("/slow/:id")]);
+        assert_eq!(shedder.shed_total(), 1);
+    }
+}
@@ -0,0 +1,57 @@
+//! Golden-file checks for rendered markup, so that changes to it show up in review instead of in the browser.
+//!
+//! Snapshots live in `src/http/snapshots`, with a line break between tags so that their diffs stay readable. Set
+//! `UPDATE_SNAPSHOTS=1` to write the current markup instead of comparing against it.
+
+use std::{env, fs, path::PathBuf};
+
+use maud::Markup;
+
+fn snapshot_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/http/snapshots")
+        .join(format!("{name}.html"))
+}
+
+/// Puts every tag on its own line.
+fn split_tags(markup: &str) -> String {
+    let mut split = markup.replace("><", ">\n<");
+    split.push('\n');
+    split
+}
+
+/// Fails unless `markup` matches the snapshot called `name`.
+#[track_caller]
+pub fn assert_snapshot(name: &str, markup: Markup) {
+    let actual = split_tags(&markup.into_string());
+    let path = snapshot_path(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let Ok(expected) = fs::read_to_string(&path) else {
+        panic!(
+            "Missing snapshot {}, run the tests with UPDATE_SNAPSHOTS=1 to create it.",
+            path.display()
+        );
+    };
+    if let Some((line, (expected, actual))) = expected
+        .lines()
+        .zip(actual.lines())
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        panic!(
+            "Snapshot {name} differs at line {}:\n  expected: {expected}\n  actual:   {actual}\nRun the tests with \
+             UPDATE_SNAPSHOTS=1 if the change is intended.",
+            line + 1
+        );
+    }
+    assert_eq!(
+        expected.lines().count(),
+        actual.lines().count(),
+        "Snapshot {name} has a different number of lines. Run the tests with UPDATE_SNAPSHOTS=1 if the change is \
+         intended."
+    );
+}
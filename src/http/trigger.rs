@@ -0,0 +1,151 @@
+use std::fmt::Write;
+
+use axum::response::{IntoResponseParts, ResponseParts};
+use hyper::header::HeaderValue;
+use serde::Serialize;
+use tracing::warn;
+
+/// Largest HX-Trigger header that we're willing to send, in bytes. Optional fields are dropped to fit under it.
+pub const MAX_TRIGGER_SIZE: usize = 1024;
+
+/// Events sent to the page through the HX-Trigger response header. Each field becomes its own event, with the
+/// value available in `event.detail.value`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TriggerPayload {
+    /// Milliseconds left until the current puzzle times out.
+    pub nonogram_time_left: u64,
+    /// Whether the countdown shows hours, because the puzzle's whole duration is longer than an hour.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub nonogram_timer_hours: bool,
+    /// Whether the countdown is paused, because maintenance mode froze the board.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub nonogram_timer_paused: bool,
+    /// Random value for the current server process, so that clients reload after a restart.
+    pub multipaint_version: u32,
+    /// How many puzzles are left in the shuffled list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multipaint_puzzles_left: Option<usize>,
+    /// Title of the current puzzle. Optional, and the first thing dropped when the payload is too large.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonogram_title: Option<String>,
+}
+
+impl TriggerPayload {
+    /// Serializes the payload into a header-safe value, dropping optional fields until it fits the size cap.
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        let mut payload = self.clone();
+        loop {
+            match header_safe_json(&payload) {
+                Some(json) if json.len() <= MAX_TRIGGER_SIZE => {
+                    return HeaderValue::from_str(&json).ok();
+                }
+                Some(json) if payload.nonogram_title.is_some() => {
+                    warn!(
+                        len = json.len(),
+                        "HX-Trigger payload too large, dropping title."
+                    );
+                    payload.nonogram_title = None;
+                }
+                _ => {
+                    warn!("Unable to build HX-Trigger header.");
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl IntoResponseParts for TriggerPayload {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(value) = self.to_header_value() {
+            res.headers_mut().insert("HX-Trigger", value);
+        }
+        Ok(res)
+    }
+}
+
+/// Serializes into JSON that only contains visible ASCII, escaping everything else as `\uXXXX`.
+///
+/// Non-ASCII characters can only appear inside JSON strings, so escaping them keeps the JSON equivalent.
+fn header_safe_json<T: Serialize>(value: &T) -> Option<String> {
+    let json = serde_json::to_string(value).ok()?;
+    let mut escaped = String::with_capacity(json.len());
+    for char in json.chars() {
+        if char.is_ascii() && !char.is_ascii_control() {
+            escaped.push(char);
+        } else {
+            let mut buffer = [0u16; 2];
+            for unit in char.encode_utf16(&mut buffer) {
+                write!(escaped, "\\u{:04x}", unit).ok()?;
+            }
+        }
+    }
+    Some(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(title: &str) -> TriggerPayload {
+        TriggerPayload {
+            nonogram_time_left: 300_000,
+            nonogram_timer_hours: false,
+            nonogram_timer_paused: false,
+            multipaint_version: 1234,
+            multipaint_puzzles_left: Some(7),
+            nonogram_title: Some(String::from(title)),
+        }
+    }
+
+    fn decode(value: &HeaderValue) -> serde_json::Value {
+        serde_json::from_str(value.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn it_escapes_hostile_titles() {
+        for title in [
+            "Line\nbreak\r\n",
+            "Emoji 🎨🖌️",
+            "Ünïcödé \u{7f}\u{0}",
+            "\"quoted\" \\ back",
+        ] {
+            let value = payload(title).to_header_value().unwrap();
+            let json = decode(&value);
+            assert_eq!(json["nonogramTitle"], title);
+            assert_eq!(json["nonogramTimeLeft"], 300_000);
+            assert_eq!(json["multipaintVersion"], 1234);
+            assert_eq!(json["multipaintPuzzlesLeft"], 7);
+        }
+    }
+
+    #[test]
+    fn it_drops_the_title_when_too_large() {
+        let value = payload(&"🎨".repeat(2048)).to_header_value().unwrap();
+        assert!(value.len() <= MAX_TRIGGER_SIZE);
+        let json = decode(&value);
+        assert!(json.get("nonogramTitle").is_none());
+        assert_eq!(json["nonogramTimeLeft"], 300_000);
+    }
+
+    #[test]
+    fn it_omits_missing_titles() {
+        let value = TriggerPayload {
+            nonogram_time_left: 0,
+            nonogram_timer_hours: false,
+            nonogram_timer_paused: false,
+            multipaint_version: 1,
+            multipaint_puzzles_left: None,
+            nonogram_title: None,
+        }
+        .to_header_value()
+        .unwrap();
+        assert_eq!(
+            value.to_str().unwrap(),
+            r#"{"nonogramTimeLeft":0,"multipaintVersion":1}"#
+        );
+    }
+}
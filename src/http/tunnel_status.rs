@@ -0,0 +1,325 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use maud::{html, Markup, DOCTYPE};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accounting::HumanBytes,
+    http::{identity::Identity, maintenance::MaintenanceMode},
+    tunnel::{TunnelState, TunnelStatus, TunnelStatusCell},
+};
+
+/// Adds the public `/status` endpoint, with only what the pages' banners need, and makes the tunnel status available to
+/// them. The details are left to the opt-in [`with_status_page`].
+pub fn with_tunnel_status(router: Router, status: TunnelStatusCell) -> Router {
+    router
+        .merge(
+            Router::new()
+                .route("/status", get(tunnel_status))
+                .with_state(status.clone()),
+        )
+        .layer(Extension(status))
+}
+
+#[derive(Serialize)]
+struct StatusBody {
+    state: TunnelState,
+    /// Whether the boards are frozen with `POST /admin/maintenance`.
+    maintenance: bool,
+}
+
+async fn tunnel_status(
+    State(status): State<TunnelStatusCell>,
+    maintenance: Option<Extension<MaintenanceMode>>,
+) -> Json<StatusBody> {
+    Json(StatusBody {
+        state: status.get().state,
+        maintenance: maintenance.is_some_and(|Extension(mode)| mode.is_on()),
+    })
+}
+
+/// Adds the `/_status` page, with the tunnel's health as HTML, or as JSON with `?format=json`.
+pub fn with_status_page(router: Router, status: TunnelStatusCell) -> Router {
+    router.merge(
+        Router::new()
+            .route("/_status", get(status_page))
+            .with_state(status),
+    )
+}
+
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum StatusFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(Deserialize, Debug)]
+struct StatusParams {
+    #[serde(default)]
+    format: StatusFormat,
+}
+
+async fn status_page(
+    State(status): State<TunnelStatusCell>,
+    Query(params): Query<StatusParams>,
+) -> Response {
+    let tunnel = status.get();
+    match params.format {
+        StatusFormat::Json => Json(tunnel).into_response(),
+        StatusFormat::Html => render_status(&tunnel).into_response(),
+    }
+}
+
+fn render_status(tunnel: &TunnelStatus) -> Markup {
+    let or_none = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+    html! {
+        (DOCTYPE)
+        head {
+            meta charset="utf-8";
+            title { "Tunnel status" }
+        }
+        body {
+            h1 { "Tunnel status" }
+            dl {
+                dt { "State" }
+                dd { (format!("{:?}", tunnel.state)) }
+                dt { "Connected since" }
+                dd { (or_none(tunnel.connected_since.map(|timestamp| timestamp.to_string()))) }
+                dt { "Reconnects" }
+                dd { (tunnel.reconnects) }
+                @if let Some(remote_socket) = &tunnel.remote_socket {
+                    dt { "Remote socket" }
+                    dd { (remote_socket) }
+                } @else {
+                    dt { "Remote port" }
+                    dd { (or_none(tunnel.remote_port.map(|port| port.to_string()))) }
+                }
+                dt { "Open channels" }
+                dd { (or_none(tunnel.open_channels.map(|channels| channels.to_string()))) }
+                dt { "Public URL" }
+                dd { (or_none(tunnel.public_url.clone())) }
+                @if let Some(traffic) = &tunnel.traffic {
+                    dt { "Forwarded connections" }
+                    dd { (traffic.connections) }
+                    dt { "Requests" }
+                    dd { (traffic.requests) }
+                    dt { "Received" }
+                    dd { (HumanBytes(traffic.bytes_received as usize)) }
+                    dt { "Sent" }
+                    dd { (HumanBytes(traffic.bytes_sent as usize)) }
+                }
+                @if let Some(info) = &tunnel.last_disconnect {
+                    dt { "Last disconnect" }
+                    dd { (info.reason_code) ": " (info.message) }
+                }
+            }
+        }
+    }
+}
+
+/// A notice for players while the tunnel server is under maintenance.
+pub fn banner(status: Option<&TunnelStatusCell>) -> Markup {
+    let state = status.map(|status| status.get().state);
+    html! {
+        @if state == Some(TunnelState::Maintenance) {
+            p .tunnel-banner role="status" {
+                "Tunnel maintenance, reconnecting…"
+            }
+        }
+    }
+}
+
+/// Which instance is serving this page. Only shown to operators listed in `--admin-users`.
+pub fn operator_footer(status: Option<&TunnelStatusCell>, identity: &Identity) -> Markup {
+    let deployment = status.and_then(TunnelStatusCell::deployment);
+    html! {
+        @if let (true, Some(deployment)) = (identity.is_admin(), deployment) {
+            footer .operator-info {
+                small { (deployment) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::{DeploymentInfo, DeploymentMode};
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use std::path::PathBuf;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn only_the_status_page_reports_the_last_disconnect() {
+        let status =
+            TunnelStatusCell::new(TunnelState::Connected, vec![String::from("maintenance")]);
+        status.record_disconnect("ByApplication", "Going down for maintenance");
+        status.connection_lost();
+        let response = with_tunnel_status(Router::new(), status.clone())
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"state": "maintenance", "maintenance": false})
+        );
+        let response = with_status_page(Router::new(), status.clone())
+            .oneshot(
+                Request::get("/_status?format=json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["last_disconnect"]["message"],
+            "Going down for maintenance"
+        );
+        assert!(banner(Some(&status))
+            .into_string()
+            .contains("Tunnel maintenance"));
+        status.connected();
+        assert_eq!(banner(Some(&status)).into_string(), "");
+        assert_eq!(banner(None).into_string(), "");
+    }
+
+    #[tokio::test]
+    async fn it_reports_maintenance_mode() {
+        let mode = MaintenanceMode::default();
+        mode.set(true).await;
+        let router = with_tunnel_status(
+            Router::new(),
+            TunnelStatusCell::new(TunnelState::Connected, vec![]),
+        )
+        .layer(Extension(mode));
+        let response = router
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["state"], "connected");
+        assert_eq!(json["maintenance"], true);
+    }
+
+    #[tokio::test]
+    async fn the_status_page_is_html_or_json() {
+        let status = TunnelStatusCell::new(TunnelState::Connecting, vec![]);
+        status.connected();
+        status.forwarding(49152);
+        let router = with_status_page(Router::new(), status);
+        let get = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap()
+            }
+        };
+        let page = String::from_utf8(get("/_status").await.to_vec()).unwrap();
+        assert!(page.contains("Tunnel status"));
+        assert!(page.contains("49152"));
+        let json: serde_json::Value =
+            serde_json::from_slice(&get("/_status?format=json").await).unwrap();
+        assert_eq!(json["state"], "connected");
+        assert_eq!(json["remote_port"], 49152);
+        assert_eq!(json["reconnects"], 0);
+        assert!(json["connected_since"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn the_status_page_shows_forwarded_sockets() {
+        let status = TunnelStatusCell::new(TunnelState::Connecting, vec![]);
+        status.connected();
+        status.forwarding_socket("/run/apps/games.sock");
+        let response = with_status_page(Router::new(), status)
+            .oneshot(Request::get("/_status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("Remote socket"), "{page}");
+        assert!(page.contains("/run/apps/games.sock"), "{page}");
+        assert!(!page.contains("Remote port"), "{page}");
+    }
+
+    #[test]
+    fn only_admins_see_the_deployment_footer() {
+        let status =
+            TunnelStatusCell::new(TunnelState::Connected, vec![]).with_deployment(DeploymentInfo {
+                mode: DeploymentMode::Ssh {
+                    hostname: String::from("sish.top"),
+                    port: 2222,
+                    remote_host: String::from("multipaint"),
+                    remote_port: 80,
+                    remote_socket: None,
+                },
+                puzzle_source: Some(String::from("https://nonogrammed.com")),
+                version: "1.2.3",
+            });
+        let admin = Identity::Named {
+            name: String::from("operator"),
+            is_admin: true,
+        };
+        let player = Identity::Named {
+            name: String::from("alice"),
+            is_admin: false,
+        };
+        let footer = operator_footer(Some(&status), &admin).into_string();
+        assert!(footer.contains("ssh to sish.top:2222, forwarding multipaint:80"));
+        assert!(footer.contains("puzzles from https://nonogrammed.com"));
+        assert!(footer.contains("v1.2.3"));
+        assert_eq!(operator_footer(Some(&status), &player).into_string(), "");
+        assert_eq!(
+            operator_footer(Some(&status), &Identity::Anonymous).into_string(),
+            ""
+        );
+        assert_eq!(operator_footer(None, &admin).into_string(), "");
+    }
+
+    #[test]
+    fn the_deployment_footer_shows_unix_sockets() {
+        let status =
+            TunnelStatusCell::new(TunnelState::Local, vec![]).with_deployment(DeploymentInfo {
+                mode: DeploymentMode::UnixSocket {
+                    path: PathBuf::from("/run/games/games.sock"),
+                },
+                puzzle_source: None,
+                version: "1.2.3",
+            });
+        let admin = Identity::Named {
+            name: String::from("operator"),
+            is_admin: true,
+        };
+        let footer = operator_footer(Some(&status), &admin).into_string();
+        assert!(
+            footer.contains("local on /run/games/games.sock | v1.2.3"),
+            "{footer}"
+        );
+        assert!(!footer.contains("localhost"), "{footer}");
+    }
+}
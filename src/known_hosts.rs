@@ -0,0 +1,361 @@
+//! Checks the tunnel server's host key against `known_hosts` files, the way OpenSSH does, so that nobody can pose as
+//! the server and intercept the tunnel.
+//!
+//! Entries are matched with OpenSSH's syntax, including hashed hostnames (`|1|salt|hash`) and non-standard ports
+//! (`[host]:port`). Unlike OpenSSH, a host whose recorded keys are all of other types is treated as a mismatch rather
+//! than as a new host, since nothing legitimate should change the type of a tunnel server's key behind our back.
+//!
+//! For throwaway deployments, the server's key can be pinned by fingerprint instead, in which case `known_hosts` files
+//! aren't used at all. What happens to hosts that aren't recorded anywhere is up to the [`HostKeyPolicy`].
+
+use std::{
+    env,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+use russh::keys::{
+    key::PublicKey,
+    known_hosts::{known_host_keys_path, learn_known_hosts_path},
+};
+use tracing::warn;
+
+use crate::ssh::{HostKeyPolicy, HostKeyStore};
+
+/// Why the server's host key was refused.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostKeyError {
+    /// The host is known with other keys, so someone may be intercepting the connection.
+    Mismatch {
+        host: String,
+        fingerprint: String,
+        path: PathBuf,
+    },
+    /// The host isn't in any `known_hosts` file, and new hosts aren't accepted.
+    Unknown { host: String, fingerprint: String },
+    /// The key doesn't have any of the pinned fingerprints.
+    NotPinned { host: String, fingerprint: String },
+    /// A `known_hosts` file couldn't be read or written.
+    File { path: PathBuf, message: String },
+}
+
+impl Display for HostKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyError::Mismatch {
+                host,
+                fingerprint,
+                path,
+            } => write!(
+                f,
+                "Host key for {host} has changed! The server offered {fingerprint}, which doesn't match the key \
+                 recorded in {}. Someone could be intercepting the connection. If the key was changed on purpose, \
+                 remove the old entry.",
+                path.display()
+            ),
+            HostKeyError::Unknown { host, fingerprint } => write!(
+                f,
+                "Unknown host {host}, with key {fingerprint}. Add it to a known_hosts file, or pass \
+                 --host-key-policy accept-new to record it on the first connection."
+            ),
+            HostKeyError::NotPinned { host, fingerprint } => write!(
+                f,
+                "Host key for {host} is {fingerprint}, which doesn't match any --host-fingerprint."
+            ),
+            HostKeyError::File { path, message } => {
+                write!(f, "Unable to use {}: {message}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for HostKeyError {}
+
+/// The user's own `~/.ssh/known_hosts`, if there's a home directory.
+pub fn user_known_hosts() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh").join("known_hosts"))
+}
+
+/// Formats a key's fingerprint like OpenSSH does, as `SHA256:` and unpadded base64.
+pub fn fingerprint(key: &PublicKey) -> String {
+    format!("SHA256:{}", key.fingerprint())
+}
+
+/// Names `host` the way known_hosts files do, with brackets and the port unless it's 22.
+pub fn display_host(host: &str, port: u16) -> String {
+    if port == 22 {
+        String::from(host)
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+/// Compares fingerprints case-insensitively, with or without the `SHA256:` prefix and base64 padding.
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    let fingerprint = fingerprint.trim();
+    let fingerprint = match fingerprint.split_at_checked(7) {
+        Some((prefix, rest)) if prefix.eq_ignore_ascii_case("SHA256:") => rest,
+        _ => fingerprint,
+    };
+    fingerprint.trim_end_matches('=').to_lowercase()
+}
+
+/// Where host keys are checked, and what to do with unknown hosts.
+#[derive(Clone, Debug)]
+pub struct KnownHosts {
+    /// Checked in order. New hosts are recorded in the last one.
+    paths: Vec<PathBuf>,
+    policy: HostKeyPolicy,
+    /// Normalized fingerprints. When there are any, they're the only keys accepted.
+    pinned: Vec<String>,
+}
+
+impl KnownHosts {
+    pub fn new(paths: Vec<PathBuf>, policy: HostKeyPolicy) -> Self {
+        KnownHosts {
+            paths,
+            policy,
+            pinned: Vec::new(),
+        }
+    }
+
+    /// Only accepts keys with one of `fingerprints`, regardless of the `known_hosts` files.
+    pub fn with_pinned_fingerprints(mut self, fingerprints: Vec<String>) -> Self {
+        self.pinned = fingerprints
+            .iter()
+            .map(|fingerprint| normalize_fingerprint(fingerprint))
+            .collect();
+        self
+    }
+
+    /// Accepts `key` for `host` on `port` as the policy says: if a `known_hosts` file has it, or if the host is new and
+    /// new hosts are accepted, in which case the key is recorded. With pinned fingerprints, only checks those.
+    pub fn verify(&self, host: &str, port: u16, key: &PublicKey) -> Result<(), HostKeyError> {
+        self.policy.check(self, host, port, key)
+    }
+}
+
+impl HostKeyStore for KnownHosts {
+    fn is_known(&self, host: &str, port: u16, key: &PublicKey) -> Result<bool, HostKeyError> {
+        if !self.pinned.is_empty() {
+            let fingerprint = fingerprint(key);
+            if self.pinned.contains(&normalize_fingerprint(&fingerprint)) {
+                return Ok(true);
+            }
+            return Err(HostKeyError::NotPinned {
+                host: display_host(host, port),
+                fingerprint,
+            });
+        }
+        let mut recorded_in = None;
+        for path in &self.paths {
+            let keys = known_host_keys_path(host, port, path).map_err(|e| HostKeyError::File {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            if keys.iter().any(|(_, recorded)| recorded == key) {
+                return Ok(true);
+            }
+            if !keys.is_empty() && recorded_in.is_none() {
+                recorded_in = Some(path.clone());
+            }
+        }
+        match recorded_in {
+            Some(path) => Err(HostKeyError::Mismatch {
+                host: display_host(host, port),
+                fingerprint: fingerprint(key),
+                path,
+            }),
+            None => Ok(false),
+        }
+    }
+
+    /// Records the key in the last `known_hosts` file. Without any, the host stays unknown.
+    fn record(&self, host: &str, port: u16, key: &PublicKey) -> Result<(), HostKeyError> {
+        let Some(path) = self.paths.last() else {
+            return Err(HostKeyError::Unknown {
+                host: display_host(host, port),
+                fingerprint: fingerprint(key),
+            });
+        };
+        learn_known_hosts_path(host, port, key, path).map_err(|e| HostKeyError::File {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        warn!(
+            host = display_host(host, port),
+            fingerprint = fingerprint(key),
+            path = %path.display(),
+            "Recorded the host key of a new host."
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use russh::keys::parse_public_key_base64;
+
+    use super::*;
+
+    const KEY_A: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIBraQ5pL3t3j8z0u0WpneQxFODC8ur5q+DpA7TGdGbU5";
+    const KEY_B: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIGWc+C7Hmu9TDX2Kh46NuclV3q+H5fcQtJg/Wl61RFCE";
+
+    /// Hashed by `ssh-keygen -H`, for `hashed.example.com` and `[hashed.example.com]:2222` with `KEY_B`.
+    const HASHED: &str = "\
+|1|D8VirDdtb4lt6auYOOlVM6/2TcI=|vKAWqBe9Sz9eKO0Bpj1WDUIf2mM= ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGWc+C7Hmu9TDX2Kh46NuclV3q+H5fcQtJg/Wl61RFCE
+|1|6wXOJYrbWqEYKT6UEdUye0po3YM=|+vdDnacwSwp/P5GgO7zcfet+oKo= ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGWc+C7Hmu9TDX2Kh46NuclV3q+H5fcQtJg/Wl61RFCE
+";
+
+    fn key(base64: &str) -> PublicKey {
+        parse_public_key_base64(base64).unwrap()
+    }
+
+    fn known_hosts_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "htmx-ssh-games-{}-{name}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn recorded_keys_are_accepted() {
+        let path = known_hosts_file(
+            "known-hosts",
+            &format!(
+                "# Comment\nexample.com,192.0.2.1 ssh-ed25519 {KEY_A}\n[tunnel.example.com]:2222 ssh-ed25519 \
+                 {KEY_A}\n{HASHED}"
+            ),
+        );
+        let known_hosts = KnownHosts::new(vec![path.clone()], HostKeyPolicy::Strict);
+        assert_eq!(known_hosts.verify("example.com", 22, &key(KEY_A)), Ok(()));
+        assert_eq!(known_hosts.verify("192.0.2.1", 22, &key(KEY_A)), Ok(()));
+        assert_eq!(
+            known_hosts.verify("tunnel.example.com", 2222, &key(KEY_A)),
+            Ok(())
+        );
+        assert_eq!(
+            known_hosts.verify("hashed.example.com", 22, &key(KEY_B)),
+            Ok(())
+        );
+        assert_eq!(
+            known_hosts.verify("hashed.example.com", 2222, &key(KEY_B)),
+            Ok(())
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn other_keys_are_refused() {
+        let path = known_hosts_file(
+            "known-hosts-mismatch",
+            &format!("example.com ssh-ed25519 {KEY_A}\n{HASHED}"),
+        );
+        let known_hosts = KnownHosts::new(vec![path.clone()], HostKeyPolicy::AcceptNew);
+        let error = known_hosts
+            .verify("example.com", 22, &key(KEY_B))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            HostKeyError::Mismatch {
+                host: String::from("example.com"),
+                fingerprint: fingerprint(&key(KEY_B)),
+                path: path.clone(),
+            }
+        );
+        assert!(error.to_string().contains(&fingerprint(&key(KEY_B))));
+        assert!(matches!(
+            known_hosts.verify("hashed.example.com", 2222, &key(KEY_A)),
+            Err(HostKeyError::Mismatch { host, .. }) if host == "[hashed.example.com]:2222"
+        ));
+        // Accepting new hosts never overwrites a key that doesn't match.
+        assert!(known_hosts.verify("example.com", 22, &key(KEY_B)).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn new_hosts_are_refused_unless_accepted() {
+        let user = known_hosts_file("known-hosts-user", "");
+        let extra = known_hosts_file("known-hosts-extra", "");
+        let strict = KnownHosts::new(vec![user.clone(), extra.clone()], HostKeyPolicy::Strict);
+        assert_eq!(
+            strict.verify("new.example.com", 2222, &key(KEY_A)),
+            Err(HostKeyError::Unknown {
+                host: String::from("[new.example.com]:2222"),
+                fingerprint: fingerprint(&key(KEY_A)),
+            })
+        );
+
+        let accept_new =
+            KnownHosts::new(vec![user.clone(), extra.clone()], HostKeyPolicy::AcceptNew);
+        assert_eq!(
+            accept_new.verify("new.example.com", 2222, &key(KEY_A)),
+            Ok(())
+        );
+        // The key went to the last file, and is trusted from then on.
+        assert_eq!(std::fs::read_to_string(&user).unwrap(), "");
+        assert!(std::fs::read_to_string(&extra)
+            .unwrap()
+            .lines()
+            .any(|line| line == format!("[new.example.com]:2222 ssh-ed25519 {KEY_A}")));
+        assert_eq!(strict.verify("new.example.com", 2222, &key(KEY_A)), Ok(()));
+        assert!(strict.verify("new.example.com", 2222, &key(KEY_B)).is_err());
+        std::fs::remove_file(user).unwrap();
+        std::fs::remove_file(extra).unwrap();
+    }
+
+    #[test]
+    fn pinned_fingerprints_replace_known_hosts() {
+        let path = known_hosts_file(
+            "known-hosts-pinned",
+            &format!("example.com ssh-ed25519 {KEY_A}\n"),
+        );
+        let fingerprint_b = fingerprint(&key(KEY_B));
+        let without_prefix = fingerprint_b.strip_prefix("SHA256:").unwrap();
+        for pin in [
+            fingerprint_b.clone(),
+            without_prefix.to_uppercase(),
+            format!("sha256:{without_prefix}="),
+        ] {
+            let known_hosts = KnownHosts::new(vec![path.clone()], HostKeyPolicy::Strict)
+                .with_pinned_fingerprints(vec![String::from("SHA256:other"), pin]);
+            assert_eq!(known_hosts.verify("example.com", 22, &key(KEY_B)), Ok(()));
+        }
+
+        let known_hosts = KnownHosts::new(vec![path.clone()], HostKeyPolicy::AcceptNew)
+            .with_pinned_fingerprints(vec![fingerprint_b]);
+        let error = known_hosts
+            .verify("example.com", 22, &key(KEY_A))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            HostKeyError::NotPinned {
+                host: String::from("example.com"),
+                fingerprint: fingerprint(&key(KEY_A)),
+            }
+        );
+        assert!(error.to_string().contains(&fingerprint(&key(KEY_A))));
+        // Nothing is learned either.
+        assert!(known_hosts
+            .verify("new.example.com", 22, &key(KEY_A))
+            .is_err());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("example.com ssh-ed25519 {KEY_A}\n")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_files_have_no_hosts() {
+        let path = env::temp_dir().join("htmx-ssh-games-missing-known-hosts");
+        let known_hosts = KnownHosts::new(vec![path], HostKeyPolicy::Strict);
+        assert!(matches!(
+            known_hosts.verify("example.com", 22, &key(KEY_A)),
+            Err(HostKeyError::Unknown { .. })
+        ));
+    }
+}
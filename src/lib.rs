@@ -1,7 +1,24 @@
+pub mod accounting;
+pub mod agent;
+pub mod assets;
+pub mod clock;
+pub mod connection;
 pub mod entrypoint;
+pub mod format;
+pub mod handoff;
 pub mod http;
+pub mod known_hosts;
 pub mod nonogram;
+pub mod random;
+pub mod schedule;
 pub mod ssh;
+pub mod storage;
+pub mod supervisor;
+pub mod systemd;
+pub mod tasks;
+pub mod tls;
+pub mod tui;
+pub mod tunnel;
 
 pub fn unwrap_infallible<T>(result: Result<T, std::convert::Infallible>) -> T {
     match result {
@@ -1,17 +1,85 @@
-use std::path::PathBuf;
+use std::{
+    env,
+    io::{self, IsTerminal},
+    iter,
+    ops::RangeInclusive,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use axum::{http::HeaderName, Extension};
+use clap::{builder::PossibleValuesParser, Args, Parser, Subcommand};
 use htmx_ssh_games::{
-    entrypoint::{local_server_entrypoint, ssh_entrypoint},
-    http::{checkbox, multipaint_by_numbers, ROUTER},
+    accounting::{parse_soft_cap, HumanBytes, MemoryAccounting},
+    assets::{check_embedded_assets, ASSETS},
+    clock::Clock,
+    connection::ConnectionTimeouts,
+    entrypoint::{
+        check_connection_entrypoint, local_server_entrypoint, ssh_entrypoint,
+        tunnel_test_entrypoint, LocalAddress, SshOptions, TunnelConfig,
+    },
+    format::{format_duration, parse_duration, DurationStyle},
+    handoff::{
+        spawn_handoff_on_sigusr2, spawn_shutdown_on_signal, Drain, ShutdownHooks,
+        BIND_RETRY_INTERVAL,
+    },
+    http::{
+        alert::{with_alert_banner, AlertBanner},
+        audit::{with_audit_log, AuditLog, AUDIT_ARTIFACT},
+        checkbox::{CheckboxArgs, CheckboxBoard, CheckboxConfig},
+        custom_assets::{with_custom_assets, CustomAssets},
+        embed::{parse_embed_origin, EmbedOrigins},
+        identity::{with_identity, IdentityConfig},
+        landing::{self, Activity},
+        maintenance::{with_maintenance_mode, MaintenanceMode},
+        metrics::{with_memory_metrics, StatusSections},
+        multipaint_by_numbers::{
+            self,
+            config::{MultipaintArgs, MultipaintConfig},
+        },
+        registry::{self, ActivityContext},
+        shedding::{with_load_shedding, LoadShedder, DEFAULT_SHED_THRESHOLD_MS},
+        tunnel_status::{with_status_page, with_tunnel_status},
+        ROUTER,
+    },
+    known_hosts::{user_known_hosts, KnownHosts},
+    nonogram::{
+        funnel::PuzzleFunnel,
+        mock::spawn_mock_upstream,
+        rejection::{report, RejectionLog, ReportFormat, REJECTIONS_ARTIFACT},
+        upstream::{parse_contact_email, parse_contact_url, Contact, UpstreamClient},
+        UpstreamUrls,
+    },
+    random::Random,
+    schedule::{parse_timezone, TimeZone},
+    ssh::{
+        forwarded_agent_socket, parse_cipher, parse_host_key_algorithm, parse_kex_algorithm,
+        parse_mac, parse_port_range, BackoffConfig, BackoffStrategy, Credentials, Destination,
+        HostKeyPolicy, JumpHost, Keepalive, KeySource, ProtocolSettings, RemoteOutput, SshTarget,
+        DEFAULT_RECONNECT_JITTER_PERCENT, PRIVATE_KEY_VAR,
+    },
+    storage::{parse_max_age, spawn_pruning, DataDir, RetentionRule, TAKEOVER_TIMEOUT},
+    systemd::{render_unit, service_args, ServiceKind, UnitSpec},
+    tasks::{TaskRegistry, SHUTDOWN_TIMEOUT},
+    tls::TlsCertificate,
+    tui::{load_host_key, tui_entrypoint},
+    tunnel::{
+        parse_public_url_pattern, DeploymentInfo, DeploymentMode, RetryPolicy, TunnelState,
+        TunnelStatusCell, DEFAULT_MAINTENANCE_REASON, DEFAULT_PUBLIC_URL_PATTERN,
+    },
 };
-use tracing::trace;
+use regex::Regex;
+use russh::{cipher, kex, keys::key, mac};
+use tokio::task::JoinSet;
+use tracing::{error, info_span, trace, warn, Instrument};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+// Parsed once at startup, so the size of the largest variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Subcommand)]
-enum OperationMode {
+enum ServiceMode {
     /// Run a conventional HTTP server locally.
     LocalServer {
         /// Hostname to listen to.
@@ -21,96 +89,1294 @@ enum OperationMode {
         /// Local port to expose our site.
         #[arg(short, long, default_value_t = 5023)]
         port: u16,
+
+        /// Listen on a Unix domain socket at this path instead, as for a reverse proxy on the same machine. It's made
+        /// with mode 0660, replacing a socket left behind by a previous run, and removed on shutdown.
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["hostname", "port"])]
+        unix_socket: Option<PathBuf>,
+
+        /// PEM file with the certificate to serve HTTPS with, followed by its intermediates. It's read again on
+        /// SIGHUP, along with `--tls-key`.
+        #[arg(long, value_name = "FILE", requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+
+        /// PEM file with the private key of `--tls-cert`.
+        #[arg(long, value_name = "FILE", requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
     },
 
-    /// Expose the HTTP server through SSH remote port forwarding.
-    Ssh {
-        /// SSH hostname.
+    /// Serve the checkboxes as a terminal UI to SSH clients, with any credentials.
+    ServeSsh {
+        /// Hostname to listen to.
+        #[arg(short = 'H', long, default_value_t = String::from("localhost"))]
         hostname: String,
 
-        /// SSH port.
-        #[arg(short, long, default_value_t = 22)]
+        /// Port to serve SSH on.
+        #[arg(short, long, default_value_t = 2222)]
         port: u16,
 
-        /// Identity file containing private key.
-        #[arg(short, long, default_value_t = String::from(""))]
-        login_name: String,
+        /// Unencrypted OpenSSH private key that identifies the server, as made by `ssh-keygen -t ed25519 -N ''`.
+        #[arg(long, value_name = "FILE")]
+        host_key: PathBuf,
+
+        /// Also serve HTTP on this port, with the same board as the terminal UI.
+        #[arg(long, value_name = "PORT")]
+        http_port: Option<u16>,
+    },
 
-        /// Identity file containing private key.
-        #[arg(short, long, value_name = "FILE")]
-        identity_file: PathBuf,
+    /// Expose the HTTP server through SSH remote port forwarding.
+    Ssh {
+        #[command(flatten)]
+        connection: SshConnectionArgs,
 
         /// Remote hostname to bind to.
         #[arg(short = 'R', long, default_value_t = String::from(""))]
         remote_host: String,
 
-        /// Remote port to bind to.
+        /// Remote port to bind to. With 0, the server picks one, which is logged.
         #[arg(short = 'P', long, default_value_t = 80)]
         remote_port: u16,
 
+        /// Unix socket on the server to listen on instead of a remote port, such as `/run/apps/games.sock`, with
+        /// OpenSSH's `streamlocal-forward@openssh.com` extension. It's let go of again before disconnecting.
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["remote_host", "remote_port", "remote_port_fallback", "wait_for_port"]
+        )]
+        remote_socket: Option<String>,
+
+        /// Remote ports to try in turn when the server refuses `--remote-port`, separated by commas. Ranges like
+        /// `8000-8010` are allowed. The port that was forwarded is logged.
+        #[arg(
+            long,
+            value_name = "PORTS",
+            value_parser = parse_port_range,
+            value_delimiter = ',',
+            conflicts_with = "wait_for_port"
+        )]
+        remote_port_fallback: Vec<RangeInclusive<u16>>,
+
+        /// Another SSH server to forward through at the same time, as `[USER@]HOST[:PORT][#REMOTE_PORT]`, for
+        /// redundancy behind round-robin DNS. The user and remote port default to `--login-name` and `--remote-port`,
+        /// and everything else is shared with the main server. Can be repeated. Every tunnel reconnects on its own, and
+        /// only the main one runs `--request-pty` and shows in `/_status`.
+        #[arg(long, value_name = "[USER@]HOST[:PORT][#REMOTE_PORT]", value_parser = SshTarget::parse)]
+        ssh_target: Vec<SshTarget>,
+
         /// Request a pseudo-terminal to be allocated with the given command.
         #[arg(long)]
         request_pty: Option<String>,
+
+        /// Forward the ssh-agent at `$SSH_AUTH_SOCK` to the command of `--request-pty`, as with `ssh -A`. Only use
+        /// this with a server that you trust, since it can use the agent's keys for as long as the session lasts.
+        #[arg(long, requires = "request_pty")]
+        forward_agent: bool,
+
+        /// Where the output of the remote session goes: raw to stdout and stderr, or line by line as log events.
+        /// Defaults to raw when stdout is a terminal, and to log events otherwise.
+        #[arg(long, value_enum)]
+        remote_output: Option<RemoteOutput>,
+
+        /// Keep asking for the remote port while another instance holds it, and take over once it's free. Start the
+        /// new instance with this, then send SIGUSR2 to the old one to hand the tunnel off. With `--data-dir`, also
+        /// pass `--takeover`, so that the new instance loads the state that the old one saves before exiting.
+        #[arg(long)]
+        wait_for_port: bool,
+
+        /// Most connections to serve through the tunnel at once. Further ones are closed right away.
+        #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..), default_value_t = 256)]
+        max_connections: u64,
+
+        #[command(flatten)]
+        protocol: SshProtocolArgs,
+
+        /// How the delay between connection attempts grows.
+        #[arg(long, value_enum, default_value_t = BackoffStrategy::default())]
+        reconnect_strategy: BackoffStrategy,
+
+        /// Delay before retrying a failed connection for the first time, with an s, m, h or d suffix.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "2s")]
+        reconnect_initial_delay: Duration,
+
+        /// Longest delay between connection attempts, with an s, m, h or d suffix.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "5m")]
+        reconnect_max_delay: Duration,
+
+        /// How much each delay between connection attempts is randomly lengthened or shortened, in percent, so that
+        /// instances sharing a server don't all reconnect at once after it restarts. 0 keeps the delays exact.
+        #[arg(
+            long,
+            value_name = "PERCENT",
+            value_parser = clap::value_parser!(u8).range(0..=100),
+            default_value_t = DEFAULT_RECONNECT_JITTER_PERCENT
+        )]
+        reconnect_jitter: u8,
+
+        /// Give up once this many attempts in a row failed to forward the remote port, whether connecting failed or the
+        /// session ended first. 0 retries forever.
+        #[arg(
+            long,
+            alias = "max-total-retries",
+            value_name = "COUNT",
+            default_value_t = 0
+        )]
+        reconnect_max_attempts: u64,
+
+        /// Give up once the tunnel has been connected again this many times after it was up, as when the connection
+        /// drops. Without this, it's connected again for as long as it takes.
+        #[arg(long, value_name = "COUNT")]
+        max_session_restarts: Option<u64>,
+
+        /// Never give up on the tunnel, whether connecting fails or the tunnel drops. This is the default, and can't
+        /// be combined with a limit.
+        #[arg(long, conflicts_with_all = ["reconnect_max_attempts", "max_session_restarts"])]
+        retry_forever: bool,
     },
 }
 
-#[derive(Debug, Copy, Clone, ValueEnum)]
-enum ActivityRouter {
-    /// 400 Checkboxes - A barebones clone of One Million Checkboxes.
-    Checkboxes,
-    /// Multipaint by Numbers - A multiplayer nonogram/picross.
-    Multipaint,
+#[derive(Debug, Clone, Subcommand)]
+enum OperationMode {
+    #[command(flatten)]
+    Service(ServiceMode),
+
+    /// Print why puzzles were rejected, grouped by reason, from the records in `--data-dir`.
+    ReportRejections {
+        /// Print CSV instead of a table.
+        #[arg(long)]
+        csv: bool,
+    },
+
+    /// Check that the assets embedded in this binary are intact, and exit.
+    Doctor,
+
+    /// Forward a local port to DESTINATION through the SSH server, like `ssh -L`, and log the bytes proxied until
+    /// Ctrl-C. Checks a tunnel's login, keys and network path without the HTTP server in the way.
+    TunnelTest {
+        #[command(flatten)]
+        connection: SshConnectionArgs,
+
+        /// Where to proxy local connections to, as `HOST:PORT` seen from the SSH server.
+        #[arg(value_parser = Destination::parse)]
+        destination: Destination,
+
+        /// Local address to listen to.
+        #[arg(short = 'L', long, value_name = "HOST:PORT", default_value_t = String::from("localhost:8022"))]
+        listen: String,
+    },
+
+    /// Connect and log in to the SSH server, have it forward the remote port and let go of it right away, then print
+    /// what happened and exit, without serving anything. Takes the same arguments as the `ssh` mode, and only checks
+    /// its main server.
+    CheckConnection {
+        #[command(subcommand)]
+        service: ServiceMode,
+    },
+
+    /// Delete the files in `--data-dir` past the `--retain-max-age` and `--retain-max-size` limits, and exit.
+    Prune,
+
+    /// Print a sandboxed systemd service unit that runs the rest of the command line, and exit. RUST_LOG is carried
+    /// over, or defaults to info.
+    PrintSystemdUnit {
+        #[command(subcommand)]
+        service: ServiceMode,
+    },
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct MainEntrypointArgs {
-    /// Which activity router to serve.
-    #[arg(value_enum, default_value_t = ActivityRouter::Checkboxes)]
-    router: ActivityRouter,
+    /// Which activity routers to serve, separated by commas. With more than one, each is served under its own
+    /// path, with a landing page at the root.
+    #[arg(
+        value_delimiter = ',',
+        default_value = "checkboxes",
+        value_parser = PossibleValuesParser::new(registry::names()),
+    )]
+    router: Vec<String>,
 
     /// Which mode to run this application as.
     #[command(subcommand)]
     mode: OperationMode,
+
+    /// Header set by the tunnel frontend with the player's name (eg. X-Forwarded-User).
+    #[arg(long, global = true, value_name = "NAME")]
+    identity_header: Option<HeaderName>,
+
+    /// Comma-separated list of identities allowed to perform administrative actions.
+    #[arg(
+        long,
+        global = true,
+        value_delimiter = ',',
+        requires = "identity_header"
+    )]
+    admin_users: Vec<String>,
+
+    /// Reject requests without a valid identity header with 401 Unauthorized.
+    #[arg(long, global = true, requires = "identity_header")]
+    require_identity: bool,
+
+    /// Base URL to fetch Nonogrammed puzzles from, instead of https://nonogrammed.com.
+    #[arg(long, global = true, value_name = "URL")]
+    nonogrammed_base_url: Option<String>,
+
+    /// Base URL to fetch webpbn puzzles from, instead of https://webpbn.com.
+    #[arg(long, global = true, value_name = "URL")]
+    webpbn_base_url: Option<String>,
+
+    /// Serve fixture puzzles on the given local port, and fetch from it unless other base URLs are given.
+    /// Meant for offline development.
+    #[arg(long, global = true, value_name = "PORT")]
+    mock_upstream: Option<u16>,
+
+    /// Never reach the real puzzle websites. Puzzles come from the mock upstream instead, on `--mock-upstream`'s port
+    /// or any free one, and any other fetch fails.
+    #[arg(long, global = true, conflicts_with_all = ["nonogrammed_base_url", "webpbn_base_url"])]
+    no_upstream: bool,
+
+    /// Where the puzzle websites can reach us about our scraping, such as https://example.com/contact. Sent in the
+    /// User-Agent of every upstream request.
+    #[arg(long, global = true, value_name = "URL", value_parser = parse_contact_url)]
+    contact_url: Option<String>,
+
+    /// Email address where the puzzle websites can reach us about our scraping. Sent in the User-Agent of every
+    /// upstream request.
+    #[arg(long, global = true, value_name = "EMAIL", value_parser = parse_contact_email)]
+    contact_email: Option<String>,
+
+    /// How long banners set through `/admin/banner` are shown for, unless set with their own duration, with an s, m,
+    /// h or d suffix.
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration, default_value = "12h")]
+    banner_duration: Duration,
+
+    /// Serve the tunnel's health at `/_status`, as a page or as JSON with `?format=json`. Open to everyone, unlike
+    /// `/admin/status`. Without it, the public `/status` only has the tunnel's state and whether the boards are
+    /// frozen.
+    #[arg(long, global = true)]
+    status_page: bool,
+
+    /// How long a client can take to send a request's headers, with an s, m, h or d suffix.
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration, default_value = "10s")]
+    header_read_timeout: Duration,
+
+    /// How long a connection can go without traffic before it's closed, with an s, m, h or d suffix.
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration, default_value = "90s")]
+    idle_timeout: Duration,
+
+    /// Stylesheet to include in the activity's page. Reloaded on SIGHUP.
+    #[arg(long, global = true, value_name = "FILE")]
+    extra_css: Option<PathBuf>,
+
+    /// Script to include in the activity's page. Reloaded on SIGHUP.
+    #[arg(long, global = true, value_name = "FILE")]
+    extra_js: Option<PathBuf>,
+
+    /// Directory where persistent state (such as rejected puzzle records) is kept.
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Soft cap on the approximate memory used by a subsystem, as SUBSYSTEM=BYTES (with an optional K, M or G
+    /// suffix). Oldest entries are evicted when exceeded. Can be repeated; see /admin/status for subsystem names.
+    #[arg(long, global = true, value_name = "SUBSYSTEM=BYTES", value_parser = parse_soft_cap)]
+    memory_cap: Vec<(String, usize)>,
+
+    #[command(flatten)]
+    checkboxes: CheckboxArgs,
+
+    /// Case-insensitive substring of a tunnel server's disconnect message which announces planned maintenance.
+    /// Reconnections are slower and more persistent afterwards. Can be repeated.
+    #[arg(long, global = true, value_name = "TEXT", default_value = DEFAULT_MAINTENANCE_REASON)]
+    maintenance_reason: Vec<String>,
+
+    /// Regex for the public URL that the tunnel server prints on the session channel, like sish and localhost.run do.
+    /// The first match of each connection is logged, shown by `/_status`, and used as the page's `og:url`.
+    #[arg(long, global = true, value_name = "REGEX", default_value = DEFAULT_PUBLIC_URL_PATTERN, value_parser = parse_public_url_pattern)]
+    public_url_pattern: Regex,
+
+    #[command(flatten)]
+    multipaint: MultipaintArgs,
+
+    /// Seed for shuffling and picking puzzles, to replay the same sequence of puzzles in another session.
+    #[arg(long, global = true, value_name = "U64")]
+    seed: Option<u64>,
+
+    /// IANA time zone that days are counted in, such as America/Sao_Paulo. Audit logs and rejection records are split
+    /// by local day in it. Defaults to UTC.
+    #[arg(long, global = true, value_name = "ZONE", value_parser = parse_timezone)]
+    timezone: Option<TimeZone>,
+
+    /// Origin of a site allowed to embed activities' read-only views in a frame, such as https://example.com. Can be
+    /// repeated. Framing is denied unless an origin is listed.
+    #[arg(long, global = true, value_name = "ORIGIN", value_parser = parse_embed_origin)]
+    embed_origin: Vec<String>,
+
+    /// Average handler latency, in milliseconds, above which optional work (cursor rendering, spectators' board polls,
+    /// overlays) is shed until things calm down. Moves are never shed. 0 disables shedding.
+    #[arg(long, global = true, value_name = "MILLISECONDS", default_value_t = DEFAULT_SHED_THRESHOLD_MS)]
+    shed_threshold: u64,
+
+    /// If another instance is using `--data-dir`, wait up to a minute for it to shut down instead of failing right
+    /// away.
+    #[arg(long, global = true, requires = "data_dir")]
+    takeover: bool,
+
+    /// Delete files of a kind from `--data-dir` once they are older than this, as KIND=AGE (with an s, m, h or d
+    /// suffix). Can be repeated; kinds are board, rejections and audit. Checked hourly.
+    #[arg(long, global = true, value_name = "KIND=AGE", value_parser = parse_max_age, requires = "data_dir")]
+    retain_max_age: Vec<(String, Duration)>,
+
+    /// Delete the oldest files of a kind from `--data-dir` while they add up to more than this, as KIND=BYTES (with
+    /// an optional K, M or G suffix). Can be repeated. Files in use are never deleted.
+    #[arg(long, global = true, value_name = "KIND=BYTES", value_parser = parse_soft_cap, requires = "data_dir")]
+    retain_max_size: Vec<(String, usize)>,
+}
+
+/// Where and how to log in to the SSH server, shared by the modes that connect to one.
+#[derive(Debug, Clone, Args)]
+struct SshConnectionArgs {
+    /// SSH hostname.
+    hostname: String,
+
+    /// SSH port.
+    #[arg(short, long, default_value_t = 22)]
+    port: u16,
+
+    /// Reach the SSH host through this bastion, given as `[USER@]HOST[:PORT]`, like OpenSSH's `ProxyJump`. Its
+    /// key is checked like the SSH host's, and the same identity is used to log in. The user defaults to the
+    /// login name.
+    #[arg(short = 'J', long, value_name = "[USER@]HOST[:PORT]", value_parser = JumpHost::parse)]
+    jump_host: Option<JumpHost>,
+
+    /// Login name on the SSH server.
+    #[arg(short, long, default_value_t = String::from(""))]
+    login_name: String,
+
+    /// Identity file containing private key, or `-` to read it from stdin. Without one, the private key in
+    /// `$SSH_PRIVATE_KEY` is used if it's set, or else the identities of the ssh-agent at `$SSH_AUTH_SOCK` are
+    /// tried in turn.
+    #[arg(short, long, value_name = "FILE")]
+    identity_file: Option<PathBuf>,
+
+    /// File whose first line is the passphrase of the private key, if it's encrypted. Otherwise, the passphrase is
+    /// asked for when running in a terminal.
+    #[arg(long, value_name = "FILE", conflicts_with = "use_agent")]
+    passphrase_file: Option<PathBuf>,
+
+    /// OpenSSH certificate of the private key, such as `id_ed25519-cert.pub`, to authenticate with instead of the
+    /// bare key. It's read again on every connection, so that a renewed certificate gets picked up.
+    #[arg(long, value_name = "FILE", conflicts_with = "use_agent")]
+    certificate_file: Option<PathBuf>,
+
+    /// File whose lines answer the server's prompts in turn, if it refuses our keys and falls back to
+    /// keyboard-interactive authentication. Without one, the prompts are only answered when running in a terminal.
+    #[arg(long, value_name = "FILE")]
+    auth_response_file: Option<PathBuf>,
+
+    /// Authenticate with the ssh-agent at `$SSH_AUTH_SOCK`, which is the default without `--identity-file` or
+    /// `$SSH_PRIVATE_KEY`.
+    #[arg(long, conflicts_with = "identity_file")]
+    use_agent: bool,
+
+    /// known_hosts file to check the server's host key against, besides ~/.ssh/known_hosts. With the accept-new
+    /// host key policy, new hosts are recorded here.
+    #[arg(long, value_name = "FILE")]
+    known_hosts_file: Option<PathBuf>,
+
+    /// What to do with the key of a host that isn't in any known_hosts file: refuse it, record it and trust it
+    /// from then on, or accept any key without checking. Keys that don't match a recorded one are refused unless
+    /// insecure.
+    #[arg(long, value_enum, default_value_t = HostKeyPolicy::default())]
+    host_key_policy: HostKeyPolicy,
+
+    /// Only trust a host key with this SHA-256 fingerprint, as printed by `ssh-keygen -l`, instead of checking
+    /// known_hosts files. Can be repeated.
+    #[arg(long, value_name = "FINGERPRINT")]
+    host_fingerprint: Vec<String>,
+}
+
+impl SshConnectionArgs {
+    /// Loads the credentials and the host keys to trust, to log in with `protocol`.
+    async fn into_options(self, protocol: ProtocolSettings) -> Result<SshOptions> {
+        let credentials = ssh_credentials(
+            self.identity_file,
+            self.passphrase_file,
+            self.certificate_file,
+            self.auth_response_file,
+            self.use_agent,
+        )
+        .await?;
+        let known_hosts = ssh_known_hosts(
+            self.known_hosts_file,
+            self.host_key_policy,
+            self.host_fingerprint,
+        )?;
+        Ok(SshOptions {
+            host: self.hostname,
+            port: self.port,
+            jump_host: self.jump_host,
+            login_name: self.login_name,
+            credentials,
+            known_hosts,
+            protocol,
+        })
+    }
+}
+
+/// How to talk to the SSH server, for servers that don't get along with the defaults.
+#[derive(Debug, Clone, Args)]
+struct SshProtocolArgs {
+    /// How long the server can stay quiet before a keepalive is sent, with an s, m, h or d suffix. 0s disables
+    /// keepalives.
+    #[arg(
+        long,
+        alias = "server-alive-interval",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    keepalive_interval: Duration,
+
+    /// Reconnect right away once this many keepalives in a row go unanswered, like OpenSSH's
+    /// `ServerAliveCountMax`. Anything received from the server counts as an answer.
+    #[arg(
+        long,
+        alias = "server-alive-count-max",
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u64).range(2..),
+        default_value_t = 3
+    )]
+    keepalive_max_missed: u64,
+
+    /// Drop the connection once nothing at all went through it for this long, with an s, m, h or d suffix.
+    /// Answered keepalives count as traffic.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    inactivity_timeout: Option<Duration>,
+
+    /// Largest SSH packet to accept, in bytes.
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(1024..=262_144))]
+    max_packet_size: Option<u32>,
+
+    /// Key exchange algorithms to offer, in order of preference and separated by commas, for servers that only
+    /// accept some of them. Defaults to Russh's safest ones.
+    #[arg(long, value_name = "ALGORITHMS", value_parser = parse_kex_algorithm, value_delimiter = ',')]
+    kex_algorithms: Vec<kex::Name>,
+
+    /// Host key algorithms to accept, in order of preference and separated by commas.
+    #[arg(long, value_name = "ALGORITHMS", value_parser = parse_host_key_algorithm, value_delimiter = ',')]
+    host_key_algorithms: Vec<key::Name>,
+
+    /// Ciphers to offer, in order of preference and separated by commas.
+    #[arg(long, value_name = "CIPHERS", value_parser = parse_cipher, value_delimiter = ',')]
+    ciphers: Vec<cipher::Name>,
+
+    /// MAC algorithms to offer, in order of preference and separated by commas.
+    #[arg(long, value_name = "ALGORITHMS", value_parser = parse_mac, value_delimiter = ',')]
+    macs: Vec<mac::Name>,
+}
+
+impl From<SshProtocolArgs> for ProtocolSettings {
+    fn from(args: SshProtocolArgs) -> Self {
+        ProtocolSettings {
+            keepalive: (!args.keepalive_interval.is_zero()).then_some(Keepalive {
+                interval: args.keepalive_interval,
+                max_missed: args.keepalive_max_missed as usize,
+            }),
+            inactivity_timeout: args.inactivity_timeout,
+            maximum_packet_size: args.max_packet_size,
+            kex_algorithms: args.kex_algorithms,
+            host_key_algorithms: args.host_key_algorithms,
+            ciphers: args.ciphers,
+            macs: args.macs,
+        }
+    }
+}
+
+/// Loads the credentials to log in to an SSH server with: the private key in `identity_file` or `$SSH_PRIVATE_KEY`, or
+/// else the ssh-agent. Keyboard-interactive is added if someone or something can answer its prompts.
+async fn ssh_credentials(
+    identity_file: Option<PathBuf>,
+    passphrase_file: Option<PathBuf>,
+    certificate_file: Option<PathBuf>,
+    auth_response_file: Option<PathBuf>,
+    use_agent: bool,
+) -> Result<Credentials> {
+    let credentials = match identity_file {
+        Some(identity_file) => {
+            Credentials::from_identity_file(&identity_file, passphrase_file.as_deref()).await?
+        }
+        None if !use_agent && env::var_os(PRIVATE_KEY_VAR).is_some() => {
+            Credentials::from_key_source(KeySource::Env, passphrase_file.as_deref()).await?
+        }
+        None if passphrase_file.is_some() => {
+            bail!(
+                "--passphrase-file needs a private key, from --identity-file or {PRIVATE_KEY_VAR}."
+            )
+        }
+        None => Credentials::from_agent_env()?,
+    };
+    let credentials = match certificate_file {
+        Some(certificate_file) => credentials.with_certificate(certificate_file)?,
+        None => credentials,
+    };
+    // Without a file or a terminal, nobody could answer the prompts.
+    Ok(
+        if auth_response_file.is_some() || io::stdin().is_terminal() {
+            credentials.with_keyboard_interactive(auth_response_file)
+        } else {
+            credentials
+        },
+    )
+}
+
+/// The host keys to trust: those in ~/.ssh/known_hosts and `known_hosts_file`, or only the pinned `fingerprints`, and
+/// new ones as `policy` says.
+fn ssh_known_hosts(
+    known_hosts_file: Option<PathBuf>,
+    policy: HostKeyPolicy,
+    fingerprints: Vec<String>,
+) -> Result<KnownHosts> {
+    if policy == HostKeyPolicy::Insecure {
+        if !fingerprints.is_empty() {
+            bail!("--host-fingerprint can't be checked with --host-key-policy insecure.");
+        }
+        eprintln!(
+            "Warning: --host-key-policy insecure accepts any host key. Anyone on the way to the SSH server can \
+             pose as it and intercept the tunnel."
+        );
+    }
+    Ok(KnownHosts::new(
+        user_known_hosts()
+            .into_iter()
+            .chain(known_hosts_file)
+            .collect(),
+        policy,
+    )
+    .with_pinned_fingerprints(fingerprints))
+}
+
+/// Combines the `--retain-*` limits into one rule per artifact kind.
+fn retention_rules(
+    max_ages: Vec<(String, Duration)>,
+    max_sizes: Vec<(String, usize)>,
+) -> Result<Vec<RetentionRule>> {
+    let kinds = [REJECTIONS_ARTIFACT, AUDIT_ARTIFACT];
+    let names = max_ages.iter().map(|(name, _)| name);
+    for name in names.chain(max_sizes.iter().map(|(name, _)| name)) {
+        if !kinds.iter().any(|kind| kind.name == name) {
+            let kinds = kinds.iter().map(|kind| kind.name).collect::<Vec<_>>();
+            bail!(
+                "Unknown artifact kind '{name}', expected one of: {}",
+                kinds.join(", ")
+            );
+        }
+    }
+    Ok(kinds
+        .into_iter()
+        .map(|kind| RetentionRule {
+            kind,
+            max_age: max_ages
+                .iter()
+                .rfind(|(name, _)| name == kind.name)
+                .map(|&(_, max_age)| max_age),
+            max_total_size: max_sizes
+                .iter()
+                .rfind(|(name, _)| name == kind.name)
+                .map(|&(_, max_size)| max_size as u64),
+        })
+        .filter(|rule| rule.max_age.is_some() || rule.max_total_size.is_some())
+        .collect())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    registry::register_builtins();
+    let args = MainEntrypointArgs::parse();
+    // Keep stdout clean for output that is meant to be redirected to a file.
+    let logs_to_stderr = matches!(
+        args.mode,
+        OperationMode::PrintSystemdUnit { .. } | OperationMode::CheckConnection { .. }
+    );
     tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
+        .with((!logs_to_stderr).then(fmt::layer))
+        .with(logs_to_stderr.then(|| fmt::layer().with_writer(io::stderr)))
+        .with(match &args.mode {
+            // Its whole point is to tell what went through the tunnel.
+            OperationMode::TunnelTest { .. } => {
+                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+            }
+            _ => EnvFilter::from_default_env(),
+        })
         .init();
     trace!("Tracing is up!");
-    let args = MainEntrypointArgs::parse();
-    match args.router {
-        ActivityRouter::Checkboxes => ROUTER.set(checkbox::get_router()).unwrap(),
-        ActivityRouter::Multipaint => ROUTER
-            .set(multipaint_by_numbers::get_router().await)
-            .unwrap(),
-    }
-    match args.mode {
-        OperationMode::LocalServer { hostname, port } => {
-            local_server_entrypoint(hostname.as_str(), port).await
+    check_embedded_assets()?;
+    let contact = Contact {
+        url: args.contact_url,
+        email: args.contact_email,
+    };
+    if let OperationMode::Doctor = args.mode {
+        for asset in ASSETS {
+            println!(
+                "{} {} ({} bytes): OK",
+                asset.name,
+                asset.version,
+                asset.bytes.len()
+            );
+        }
+        println!("User-Agent: {}", contact.user_agent());
+        if contact.is_empty() {
+            println!(
+                "Contact: MISSING, pass --contact-url or --contact-email before fetching puzzles"
+            );
+        }
+        if args.no_upstream {
+            println!("Upstream: disabled by --no-upstream");
+        }
+        return Ok(());
+    }
+    if let OperationMode::TunnelTest {
+        connection,
+        destination,
+        listen,
+    } = args.mode
+    {
+        let ssh = connection.into_options(ProtocolSettings::default()).await?;
+        let stop = async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                warn!(error = %e, "Unable to listen for Ctrl-C.");
+                std::future::pending::<()>().await;
+            }
+        };
+        let totals = tunnel_test_entrypoint(ssh, listen.as_str(), &destination, stop).await?;
+        println!(
+            "Proxied {} connections: {} received, {} sent",
+            totals.connections,
+            HumanBytes(totals.bytes_received as usize),
+            HumanBytes(totals.bytes_sent as usize)
+        );
+        return Ok(());
+    }
+    if let OperationMode::CheckConnection { service } = args.mode {
+        let ServiceMode::Ssh {
+            connection,
+            remote_host,
+            remote_port,
+            remote_port_fallback,
+            protocol,
+            ..
+        } = service
+        else {
+            bail!("check-connection only checks the ssh mode.");
+        };
+        let ssh = connection.into_options(protocol.into()).await?;
+        let (hostname, port) = (ssh.host.clone(), ssh.port);
+        let fallback_ports = remote_port_fallback
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let report =
+            check_connection_entrypoint(ssh, remote_host.as_str(), remote_port, &fallback_ports)
+                .await
+                .with_context(|| format!("Checking the connection to {hostname}:{port} failed."))?;
+        print!("{report}");
+        return Ok(());
+    }
+    let retention = retention_rules(args.retain_max_age, args.retain_max_size)?;
+    let checkboxes = CheckboxConfig::try_from(args.checkboxes)?;
+    let multipaint = MultipaintConfig::try_from(args.multipaint)?;
+    if let OperationMode::PrintSystemdUnit { service } = &args.mode {
+        let executable = env::current_exe().with_context(|| "Unable to find this executable")?;
+        let kind = match service {
+            ServiceMode::LocalServer {
+                unix_socket: Some(path),
+                ..
+            } => ServiceKind::UnixSocket { path: path.clone() },
+            ServiceMode::LocalServer { port, .. } => ServiceKind::Local { port: *port },
+            ServiceMode::ServeSsh {
+                port, http_port, ..
+            } => ServiceKind::Local {
+                port: http_port.map_or(*port, |http_port| http_port.min(*port)),
+            },
+            ServiceMode::Ssh { connection, .. } => ServiceKind::Ssh {
+                identity_file: connection.identity_file.clone(),
+                passphrase_file: connection.passphrase_file.clone(),
+            },
+        };
+        let service_files = match service {
+            ServiceMode::Ssh { connection, .. } => [
+                &connection.known_hosts_file,
+                &connection.certificate_file,
+                &connection.auth_response_file,
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            ServiceMode::ServeSsh { host_key, .. } => vec![host_key],
+            ServiceMode::LocalServer {
+                tls_cert, tls_key, ..
+            } => [tls_cert, tls_key].into_iter().flatten().collect(),
+        };
+        let tls = matches!(
+            service,
+            ServiceMode::LocalServer {
+                tls_cert: Some(_),
+                ..
+            }
+        );
+        let read_paths = [&args.extra_css, &args.extra_js]
+            .into_iter()
+            .flatten()
+            .chain(service_files)
+            .map(PathBuf::as_path)
+            .chain(checkboxes.seed_image())
+            .map(PathBuf::from)
+            .collect();
+        let unit = render_unit(&UnitSpec {
+            args: service_args(env::args_os(), &executable)?,
+            kind,
+            rust_log: env::var("RUST_LOG").unwrap_or_else(|_| String::from("info")),
+            data_dir: args.data_dir,
+            read_paths,
+            reloads_on_sighup: args.extra_css.is_some() || args.extra_js.is_some() || tls,
+            working_directory: env::current_dir()
+                .with_context(|| "Unable to read the working directory")?,
+        });
+        for warning in &unit.warnings {
+            eprintln!("Warning: {warning}");
+        }
+        print!("{}", unit.text);
+        return Ok(());
+    }
+    let data_dir = match args.data_dir {
+        Some(path) => Some(DataDir::open(path).await?),
+        None => None,
+    };
+    if let OperationMode::Prune = args.mode {
+        let data_dir = data_dir.with_context(|| "Pruning requires --data-dir")?;
+        multipaint_by_numbers::pin_live_artifacts(&data_dir);
+        let summary = data_dir.prune(&retention, SystemTime::now()).await?;
+        for name in &summary.removed {
+            println!("Removed {name}");
+        }
+        println!("{summary}");
+        return Ok(());
+    }
+    if let OperationMode::ReportRejections { csv } = args.mode {
+        let format = if csv {
+            ReportFormat::Csv
+        } else {
+            ReportFormat::Table
+        };
+        let records = RejectionLog::new(data_dir, TimeZone::default())
+            .read_all()
+            .await?;
+        print!("{}", report(&records, format));
+        return Ok(());
+    }
+    let OperationMode::Service(service) = args.mode else {
+        unreachable!()
+    };
+    // Checked before anything else starts, so that a broken pair fails right away.
+    let tls = match &service {
+        ServiceMode::LocalServer {
+            tls_cert: Some(tls_cert),
+            tls_key: Some(tls_key),
+            ..
+        } => Some(TlsCertificate::load(tls_cert.clone(), tls_key.clone()).await?),
+        _ => None,
+    };
+    let mut upstreams = UpstreamUrls::default();
+    let mock_upstream = args.mock_upstream.or(args.no_upstream.then_some(0));
+    if let Some(port) = mock_upstream {
+        let base_url = spawn_mock_upstream(port).await?;
+        upstreams.nonogrammed = base_url.clone();
+        upstreams.webpbn = base_url;
+    }
+    if let Some(base_url) = args.nonogrammed_base_url {
+        upstreams.nonogrammed = String::from(base_url.trim_end_matches('/'));
+    }
+    if let Some(base_url) = args.webpbn_base_url {
+        upstreams.webpbn = String::from(base_url.trim_end_matches('/'));
+    }
+    let mut routers = Vec::with_capacity(args.router.len());
+    for activity_router in args.router {
+        if !routers.contains(&activity_router) {
+            routers.push(activity_router);
+        }
+    }
+    let puzzle_source = routers
+        .iter()
+        .any(|name| name == "multipaint")
+        .then(|| upstreams.nonogrammed.clone());
+    let mut upstream_client = UpstreamClient::new(&contact)?;
+    if args.no_upstream {
+        upstream_client = upstream_client.offline(vec![
+            upstreams.nonogrammed.clone(),
+            upstreams.webpbn.clone(),
+        ]);
+    } else if puzzle_source.is_some() && contact.is_empty() {
+        warn!(
+            user_agent = upstream_client.user_agent(),
+            "Fetching puzzles without any contact details! Pass --contact-url or --contact-email, so that the puzzle \
+            websites can reach us instead of blocking us."
+        );
+    }
+    let clock = Clock::tokio();
+    let tasks = TaskRegistry::new(clock.clone());
+    // Held until shutdown, so that a second instance can't write over this one's state.
+    let _lock = match &data_dir {
+        Some(data_dir) => Some(
+            data_dir
+                .lock(args.takeover.then_some(TAKEOVER_TIMEOUT), &clock)
+                .await?,
+        ),
+        None => None,
+    };
+    if let Some(data_dir) = &data_dir {
+        if !retention.is_empty() {
+            // Before the first run, which starts right away.
+            multipaint_by_numbers::pin_live_artifacts(data_dir);
+            spawn_pruning(data_dir.clone(), retention, clock.clone(), &tasks);
         }
-        OperationMode::Ssh {
+    }
+    if args.banner_duration.is_zero() {
+        bail!("--banner-duration must be longer than zero.");
+    }
+    let alert = AlertBanner::load(data_dir.clone(), args.banner_duration, clock.clone()).await;
+    let maintenance = MaintenanceMode::load(data_dir.clone()).await;
+    let timezone = args.timezone.unwrap_or_default();
+    let audit = AuditLog::load(data_dir.clone(), timezone.clone(), &tasks).await;
+    let accounting = MemoryAccounting::new(args.memory_cap.into_iter().collect());
+    let status = StatusSections::default();
+    let funnel = PuzzleFunnel::default();
+    let shutdown = ShutdownHooks::default();
+    status.register(
+        "Maintenance",
+        Box::new({
+            let maintenance = maintenance.clone();
+            move || vec![("Boards frozen", maintenance.is_on().to_string())]
+        }),
+    );
+    status.register(
+        "Puzzle funnel",
+        Box::new({
+            let funnel = funnel.clone();
+            move || funnel.status()
+        }),
+    );
+    status.register(
+        "Background tasks",
+        Box::new({
+            let tasks = tasks.clone();
+            move || {
+                tasks
+                    .live()
+                    .into_iter()
+                    .map(|(name, age)| (name, format_duration(age, DurationStyle::Compact)))
+                    .collect()
+            }
+        }),
+    );
+    let checkbox_board = CheckboxBoard::default();
+    // The terminal UI shows the checkboxes even when they aren't served over HTTP.
+    if matches!(service, ServiceMode::ServeSsh { .. })
+        && !routers.iter().any(|name| name == "checkboxes")
+    {
+        checkbox_board.load_seed(&checkboxes).await?;
+    }
+    let (tunnel_state, deployment_mode) = match &service {
+        ServiceMode::LocalServer {
+            unix_socket: Some(path),
+            ..
+        } => (
+            TunnelState::Local,
+            DeploymentMode::UnixSocket { path: path.clone() },
+        ),
+        ServiceMode::LocalServer { hostname, port, .. } => (
+            TunnelState::Local,
+            DeploymentMode::Local {
+                hostname: hostname.clone(),
+                port: *port,
+            },
+        ),
+        ServiceMode::ServeSsh {
             hostname,
             port,
-            login_name,
-            identity_file,
+            http_port,
+            ..
+        } => (
+            TunnelState::Local,
+            DeploymentMode::SshServer {
+                hostname: hostname.clone(),
+                port: *port,
+                http_port: *http_port,
+            },
+        ),
+        ServiceMode::Ssh {
+            connection,
             remote_host,
             remote_port,
-            request_pty,
-        } => {
-            ssh_entrypoint(
-                hostname.as_str(),
+            remote_socket,
+            ..
+        } => (
+            TunnelState::Connecting,
+            DeploymentMode::Ssh {
+                hostname: connection.hostname.clone(),
+                port: connection.port,
+                remote_host: remote_host.clone(),
+                remote_port: *remote_port,
+                remote_socket: remote_socket.clone(),
+            },
+        ),
+    };
+    let drain = match service {
+        ServiceMode::Ssh {
+            max_connections, ..
+        } => Drain::with_max_connections(max_connections as usize),
+        ServiceMode::LocalServer { .. } | ServiceMode::ServeSsh { .. } => Drain::default(),
+    };
+    let mut tunnel_status = TunnelStatusCell::new(tunnel_state, args.maintenance_reason)
+        .with_deployment(DeploymentInfo {
+            mode: deployment_mode,
+            puzzle_source,
+            version: env!("CARGO_PKG_VERSION"),
+        });
+    if let ServiceMode::Ssh { .. } = service {
+        tunnel_status = tunnel_status
+            .with_drain(drain.clone())
+            .with_public_url_pattern(args.public_url_pattern);
+    }
+    let context = ActivityContext {
+        upstreams,
+        upstream_client,
+        data_dir,
+        accounting: accounting.clone(),
+        status: status.clone(),
+        clock: clock.clone(),
+        checkboxes,
+        checkbox_board: checkbox_board.clone(),
+        multipaint,
+        random: args.seed.map(Random::seeded).unwrap_or_default(),
+        timezone,
+        embed_origins: EmbedOrigins::new(args.embed_origin),
+        funnel: funnel.clone(),
+        shutdown: shutdown.clone(),
+        maintenance: maintenance.clone(),
+        tasks: tasks.clone(),
+        tunnel: tunnel_status.link(),
+    };
+    let mut activities = Vec::with_capacity(routers.len());
+    for name in routers {
+        let router = registry::build(&name, context.clone()).await?;
+        let name = registry::names()
+            .into_iter()
+            .find(|registered| *registered == name)
+            .unwrap();
+        activities.push(Activity {
+            slug: name,
+            title: registry::description(name).unwrap(),
+            router,
+        });
+    }
+    let router = landing::mount(activities);
+    let shedder = LoadShedder::new(
+        (args.shed_threshold > 0).then(|| Duration::from_millis(args.shed_threshold)),
+    );
+    status.register(
+        "Load shedding",
+        Box::new({
+            let shedder = shedder.clone();
+            move || {
+                let slowest = shedder
+                    .averages()
+                    .into_iter()
+                    .max_by_key(|(_, latency)| *latency);
+                vec![
+                    (
+                        "Threshold",
+                        shedder
+                            .threshold()
+                            .map_or(String::from("disabled"), |threshold| {
+                                format!("{threshold:?}")
+                            }),
+                    ),
+                    ("Shedding", shedder.is_shedding().to_string()),
+                    ("Shed requests", shedder.shed_total().to_string()),
+                    (
+                        "Slowest route",
+                        slowest.map_or(String::from("none"), |(route, latency)| {
+                            format!("{route} ({latency:.1?})")
+                        }),
+                    ),
+                ]
+            }
+        }),
+    );
+    let router = with_memory_metrics(router, accounting, status).layer(Extension(funnel));
+    let custom_assets = CustomAssets::load(args.extra_css, args.extra_js).await;
+    if custom_assets.is_enabled() {
+        custom_assets.spawn_reload_on_sighup(&tasks)?;
+    }
+    if let Some(tls) = &tls {
+        tls.spawn_reload_on_sighup(&tasks)?;
+    }
+    let router = with_custom_assets(router, custom_assets);
+    let router = with_tunnel_status(router, tunnel_status.clone());
+    let router = if args.status_page {
+        with_status_page(router, tunnel_status.clone())
+    } else {
+        router
+    };
+    let router = with_alert_banner(router, alert);
+    let router = with_maintenance_mode(router, maintenance.clone());
+    let router = with_audit_log(router, audit);
+    let identity_config = IdentityConfig {
+        header: args.identity_header,
+        admin_users: args.admin_users,
+        require_identity: args.require_identity,
+    };
+    let router = with_load_shedding(router, shedder);
+    ROUTER.set(with_identity(router, identity_config)).unwrap();
+    if let ServiceMode::Ssh { .. } = service {
+        spawn_handoff_on_sigusr2(drain.clone(), &tasks)?;
+    }
+    spawn_shutdown_on_signal(drain.clone(), &tasks)?;
+    let timeouts = ConnectionTimeouts {
+        header_read: args.header_read_timeout,
+        idle: args.idle_timeout,
+    };
+    let serve = async move {
+        match service {
+            ServiceMode::LocalServer {
+                hostname,
                 port,
-                login_name.as_str(),
-                identity_file,
-                remote_host.as_str(),
+                unix_socket,
+                ..
+            } => {
+                let address = match unix_socket {
+                    Some(path) => LocalAddress::Unix(path),
+                    None => LocalAddress::Tcp { hostname, port },
+                };
+                local_server_entrypoint(&address, tls, drain, timeouts, clock).await
+            }
+            ServiceMode::ServeSsh {
+                hostname,
+                port,
+                host_key,
+                http_port,
+            } => {
+                let tui = tui_entrypoint(
+                    hostname.as_str(),
+                    port,
+                    load_host_key(&host_key)?,
+                    checkbox_board,
+                    maintenance,
+                    drain.clone(),
+                    clock.clone(),
+                );
+                match http_port {
+                    Some(http_port) => {
+                        let address = LocalAddress::Tcp {
+                            hostname: hostname.clone(),
+                            port: http_port,
+                        };
+                        let http = local_server_entrypoint(&address, None, drain, timeouts, clock);
+                        tokio::try_join!(tui, http).map(|_| ())
+                    }
+                    None => tui.await,
+                }
+            }
+            ServiceMode::Ssh {
+                connection,
+                remote_host,
                 remote_port,
-                request_pty,
+                remote_socket,
+                remote_port_fallback,
+                ssh_target,
+                mut request_pty,
+                forward_agent,
+                remote_output,
+                wait_for_port,
+                max_connections: _,
+                protocol,
+                reconnect_strategy,
+                reconnect_initial_delay,
+                reconnect_max_delay,
+                reconnect_jitter,
+                reconnect_max_attempts,
+                max_session_restarts,
+                retry_forever: _,
+            } => {
+                let ssh = connection.into_options(protocol.into()).await?;
+                let mut forward_agent = if forward_agent {
+                    Some(forwarded_agent_socket()?)
+                } else {
+                    None
+                };
+                let tunnel = TunnelConfig {
+                    remote_host,
+                    remote_port,
+                    remote_socket,
+                    fallback_ports: remote_port_fallback.into_iter().flatten().collect(),
+                    request_pty: None,
+                    forward_agent: None,
+                    remote_output: remote_output.unwrap_or_else(RemoteOutput::detect),
+                    bind_retry: wait_for_port.then_some(BIND_RETRY_INTERVAL),
+                    retry: RetryPolicy {
+                        backoff: BackoffConfig {
+                            strategy: reconnect_strategy,
+                            initial_delay: reconnect_initial_delay,
+                            max_delay: reconnect_max_delay,
+                            max_attempts: reconnect_max_attempts,
+                            jitter_percent: reconnect_jitter,
+                        },
+                        max_session_restarts,
+                    },
+                };
+                let main_target = SshTarget {
+                    login_name: None,
+                    host: ssh.host.clone(),
+                    port: ssh.port,
+                    remote_port: None,
+                };
+                let several = !ssh_target.is_empty();
+                // Each tunnel is a task of its own, so that one giving up doesn't stop the others.
+                let mut tunnels = JoinSet::new();
+                for (index, target) in iter::once(main_target).chain(ssh_target).enumerate() {
+                    // The main tunnel runs the session command, and its status is the one that the pages show.
+                    let (status, request_pty, forward_agent) = if index == 0 {
+                        (
+                            tunnel_status.clone(),
+                            request_pty.take(),
+                            forward_agent.take(),
+                        )
+                    } else {
+                        (tunnel_status.sibling(), None, None)
+                    };
+                    let ssh = SshOptions {
+                        host: target.host.clone(),
+                        port: target.port,
+                        login_name: target
+                            .login_name
+                            .clone()
+                            .unwrap_or_else(|| ssh.login_name.clone()),
+                        ..ssh.clone()
+                    };
+                    let config = TunnelConfig {
+                        remote_port: target.remote_port.unwrap_or(tunnel.remote_port),
+                        request_pty,
+                        forward_agent,
+                        ..tunnel.clone()
+                    };
+                    let tunnel =
+                        ssh_entrypoint(ssh, config, status, clock.clone(), drain.clone(), timeouts);
+                    if several {
+                        tunnels.spawn(tunnel.instrument(info_span!("tunnel", target = %target)));
+                    } else {
+                        tunnels.spawn(tunnel);
+                    }
+                }
+                let mut failure = None;
+                while let Some(joined) = tunnels.join_next().await {
+                    if let Err(err) = joined
+                        .map_err(anyhow::Error::from)
+                        .and_then(|result| result)
+                    {
+                        if several {
+                            error!(
+                                err = %format_args!("{err:#}"),
+                                remaining = tunnels.len(),
+                                "Gave up on a tunnel."
+                            );
+                        }
+                        failure.get_or_insert(err);
+                    }
+                }
+                failure.map_or(Ok(()), Err)
+            }
+        }
+    };
+    let result = serve.await;
+    shutdown.run().await;
+    // After the hooks, so that the writers are still around to persist what the hooks save.
+    tasks.shutdown(SHUTDOWN_TIMEOUT).await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::error::ErrorKind;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<MainEntrypointArgs, clap::Error> {
+        registry::register_builtins();
+        MainEntrypointArgs::try_parse_from(["htmx-ssh-games"].iter().chain(args))
+    }
+
+    #[test]
+    fn remote_sockets_replace_the_remote_port() {
+        let args = parse(&["ssh", "sish.top", "--remote-socket", "/run/apps/games.sock"]).unwrap();
+        let OperationMode::Service(ServiceMode::Ssh { remote_socket, .. }) = args.mode else {
+            panic!("Parsed as another mode: {:?}", args.mode);
+        };
+        assert_eq!(remote_socket.as_deref(), Some("/run/apps/games.sock"));
+
+        for conflicting in [
+            ["--remote-port", "8080"],
+            ["--remote-host", "games"],
+            ["--wait-for-port", "--use-agent"],
+        ] {
+            let error = parse(
+                &[
+                    &["ssh", "sish.top", "--remote-socket", "/run/apps/games.sock"][..],
+                    &conflicting,
+                ]
+                .concat(),
             )
-            .await
+            .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{conflicting:?}");
         }
     }
+
+    #[test]
+    fn tunnel_tests_log_in_like_the_ssh_mode() {
+        let args = parse(&[
+            "tunnel-test",
+            "sish.top",
+            "localhost:80",
+            "-J",
+            "admin@bastion",
+            "-l",
+            "player",
+            "--host-fingerprint",
+            "SHA256:abc",
+        ])
+        .unwrap();
+        let OperationMode::TunnelTest {
+            connection,
+            destination,
+            ..
+        } = args.mode
+        else {
+            panic!("Parsed as another mode: {:?}", args.mode);
+        };
+        assert_eq!(connection.hostname, "sish.top");
+        assert_eq!(connection.port, 22);
+        assert_eq!(connection.login_name, "player");
+        assert_eq!(connection.jump_host.unwrap().host, "bastion");
+        assert_eq!(connection.host_fingerprint, ["SHA256:abc"]);
+        assert_eq!(destination.to_string(), "localhost:80");
+
+        let error = parse(&[
+            "tunnel-test",
+            "sish.top",
+            "localhost:80",
+            "-i",
+            "key",
+            "--use-agent",
+        ])
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
 }
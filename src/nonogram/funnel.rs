@@ -0,0 +1,277 @@
+//! How fetched puzzles turn into played ones, per source, for `/metrics` and the `/admin/status` page.
+//!
+//! Every label comes from a fixed list, so that the number of series never grows.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use super::{rejection::RejectionReason, throttle::Throttled, PuzzleSource};
+
+const SOURCES: [PuzzleSource; 2] = [PuzzleSource::Nonogrammed, PuzzleSource::Webpbn];
+
+/// Why a fetch failed before there was anything to parse.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FetchErrorKind {
+    /// The upstream asked us to slow down.
+    Throttled,
+    /// The request failed or returned an unexpected status.
+    Request,
+    /// The puzzle doesn't exist upstream.
+    NotFound,
+}
+
+impl FetchErrorKind {
+    const ALL: [FetchErrorKind; 3] = [
+        FetchErrorKind::Throttled,
+        FetchErrorKind::Request,
+        FetchErrorKind::NotFound,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetchErrorKind::Throttled => "throttled",
+            FetchErrorKind::Request => "request",
+            FetchErrorKind::NotFound => "not_found",
+        }
+    }
+}
+
+/// Reasons for rejecting a puzzle that was fetched and parsed.
+const VALIDATION_REASONS: [RejectionReason; 4] = [
+    RejectionReason::InconsistentClues,
+    RejectionReason::TooLarge,
+    RejectionReason::Multicolor,
+    RejectionReason::Other,
+];
+
+/// A step that a puzzle went through.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FunnelEvent {
+    FetchAttempt,
+    FetchError(FetchErrorKind),
+    ParseError,
+    ValidationRejection(RejectionReason),
+    Started,
+    Solved,
+    Failed,
+}
+
+impl FunnelEvent {
+    /// The step at which a fetch failed, from the error it failed with.
+    pub fn for_error(error: &anyhow::Error) -> Self {
+        if Throttled::find(error).is_some() {
+            return FunnelEvent::FetchError(FetchErrorKind::Throttled);
+        }
+        match RejectionReason::classify(error) {
+            RejectionReason::Fetch => FunnelEvent::FetchError(FetchErrorKind::Request),
+            RejectionReason::NotFound => FunnelEvent::FetchError(FetchErrorKind::NotFound),
+            RejectionReason::Parse => FunnelEvent::ParseError,
+            reason => FunnelEvent::ValidationRejection(reason),
+        }
+    }
+}
+
+/// Metric name, help text, and every event that it counts along with its extra label, if any.
+type Metric = (
+    &'static str,
+    &'static str,
+    Vec<(FunnelEvent, Option<String>)>,
+);
+
+fn metrics() -> Vec<Metric> {
+    vec![
+        (
+            "fetch_attempts",
+            "Puzzles requested from each source.",
+            vec![(FunnelEvent::FetchAttempt, None)],
+        ),
+        (
+            "fetch_errors",
+            "Fetches that failed before there was a puzzle to parse.",
+            FetchErrorKind::ALL
+                .iter()
+                .map(|&kind| {
+                    (
+                        FunnelEvent::FetchError(kind),
+                        Some(format!("kind=\"{}\"", kind.as_str())),
+                    )
+                })
+                .collect(),
+        ),
+        (
+            "parse_errors",
+            "Fetched puzzles that couldn't be parsed.",
+            vec![(FunnelEvent::ParseError, None)],
+        ),
+        (
+            "validation_rejections",
+            "Parsed puzzles that weren't fit to be played.",
+            VALIDATION_REASONS
+                .iter()
+                .map(|&reason| {
+                    (
+                        FunnelEvent::ValidationRejection(reason),
+                        Some(format!("reason=\"{reason}\"")),
+                    )
+                })
+                .collect(),
+        ),
+        (
+            "puzzles_started",
+            "Puzzles put on the board.",
+            vec![(FunnelEvent::Started, None)],
+        ),
+        (
+            "puzzles_solved",
+            "Puzzles solved in time.",
+            vec![(FunnelEvent::Solved, None)],
+        ),
+        (
+            "puzzles_failed",
+            "Puzzles whose timer ran out.",
+            vec![(FunnelEvent::Failed, None)],
+        ),
+    ]
+}
+
+/// Counts of [`FunnelEvent`]s per source.
+#[derive(Clone, Debug, Default)]
+pub struct PuzzleFunnel(Arc<Mutex<BTreeMap<(PuzzleSource, FunnelEvent), u64>>>);
+
+impl PuzzleFunnel {
+    pub fn record(&self, source: PuzzleSource, event: FunnelEvent) {
+        *self.0.lock().unwrap().entry((source, event)).or_default() += 1;
+    }
+
+    pub fn count(&self, source: PuzzleSource, event: FunnelEvent) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&(source, event))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every counter in the Prometheus text format, including the ones that are still zero.
+    pub fn prometheus(&self) -> String {
+        let counts = self.0.lock().unwrap();
+        let mut output = String::new();
+        for (name, help, events) in metrics() {
+            writeln!(output, "# HELP htmx_ssh_games_{name}_total {help}").unwrap();
+            writeln!(output, "# TYPE htmx_ssh_games_{name}_total counter").unwrap();
+            for source in SOURCES {
+                for (event, label) in &events {
+                    let count = counts.get(&(source, *event)).copied().unwrap_or_default();
+                    let label = label
+                        .as_ref()
+                        .map_or(String::new(), |label| format!(",{label}"));
+                    writeln!(
+                        output,
+                        "htmx_ssh_games_{name}_total{{source=\"{source}\"{label}}} {count}"
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        output
+    }
+
+    /// A line per source, from fetches to finished puzzles.
+    pub fn status(&self) -> Vec<(&'static str, String)> {
+        SOURCES
+            .iter()
+            .map(|&source| {
+                let attempts = self.count(source, FunnelEvent::FetchAttempt);
+                let lost = FetchErrorKind::ALL
+                    .iter()
+                    .map(|&kind| FunnelEvent::FetchError(kind))
+                    .chain([FunnelEvent::ParseError])
+                    .chain(
+                        VALIDATION_REASONS
+                            .iter()
+                            .map(|&reason| FunnelEvent::ValidationRejection(reason)),
+                    )
+                    .map(|event| self.count(source, event))
+                    .sum::<u64>();
+                let playable = attempts.saturating_sub(lost);
+                let share = (playable * 100)
+                    .checked_div(attempts)
+                    .map_or(String::new(), |share| format!(" ({share}%)"));
+                (
+                    source.as_str(),
+                    format!(
+                        "{attempts} fetched, {playable} playable{share}, {} started, {} solved, {} failed",
+                        self.count(source, FunnelEvent::Started),
+                        self.count(source, FunnelEvent::Solved),
+                        self.count(source, FunnelEvent::Failed),
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn it_classifies_fetch_errors() {
+        let throttled = anyhow::Error::new(Throttled {
+            status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+            retry_after: None,
+        });
+        assert_eq!(
+            FunnelEvent::for_error(&throttled),
+            FunnelEvent::FetchError(FetchErrorKind::Throttled)
+        );
+        let error = anyhow!("404").context(RejectionReason::NotFound);
+        assert_eq!(
+            FunnelEvent::for_error(&error),
+            FunnelEvent::FetchError(FetchErrorKind::NotFound)
+        );
+        let error = anyhow!("Bad JSON").context(RejectionReason::Parse);
+        assert_eq!(FunnelEvent::for_error(&error), FunnelEvent::ParseError);
+        let error = anyhow!("Huge").context(RejectionReason::TooLarge);
+        assert_eq!(
+            FunnelEvent::for_error(&error),
+            FunnelEvent::ValidationRejection(RejectionReason::TooLarge)
+        );
+        assert_eq!(
+            FunnelEvent::for_error(&anyhow!("?")),
+            FunnelEvent::ValidationRejection(RejectionReason::Other)
+        );
+    }
+
+    #[test]
+    fn it_exports_a_fixed_set_of_series() {
+        let funnel = PuzzleFunnel::default();
+        let empty = funnel.prometheus();
+        funnel.record(PuzzleSource::Webpbn, FunnelEvent::FetchAttempt);
+        funnel.record(
+            PuzzleSource::Webpbn,
+            FunnelEvent::ValidationRejection(RejectionReason::Multicolor),
+        );
+        let output = funnel.prometheus();
+        assert_eq!(output.lines().count(), empty.lines().count());
+        assert!(output.contains("htmx_ssh_games_fetch_attempts_total{source=\"webpbn\"} 1\n"));
+        assert!(output.contains(
+            "htmx_ssh_games_validation_rejections_total{source=\"webpbn\",reason=\"multicolor\"} 1\n"
+        ));
+        assert!(output.contains(
+            "htmx_ssh_games_fetch_errors_total{source=\"nonogrammed\",kind=\"throttled\"} 0\n"
+        ));
+        assert_eq!(
+            funnel.status()[1],
+            (
+                "webpbn",
+                String::from("1 fetched, 0 playable (0%), 0 started, 0 solved, 0 failed")
+            )
+        );
+    }
+}
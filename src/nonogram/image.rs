@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use bitvec::{bitvec, order::Lsb0, vec::BitVec};
+use image::{
+    imageops::{self, FilterType},
+    GrayAlphaImage,
+};
+
+/// Pixels darker than this (out of 255) become filled cells.
+pub const DEFAULT_THRESHOLD: u8 = 128;
+
+/// An image that was decoded once, to be turned into cells at several sizes.
+pub struct DecodedImage(GrayAlphaImage);
+
+impl DecodedImage {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(DecodedImage(
+            image::load_from_memory(bytes)
+                .with_context(|| "Unable to decode image")?
+                .into_luma_alpha8(),
+        ))
+    }
+
+    /// Width over height, to pick board sizes that don't distort the image.
+    pub fn aspect_ratio(&self) -> f64 {
+        f64::from(self.0.width()) / f64::from(self.0.height().max(1))
+    }
+
+    /// Downscales the image to `width` by `height` cells, filling every cell that is dark and mostly opaque.
+    ///
+    /// Cells are in row-major order.
+    pub fn to_cells(&self, width: usize, height: usize, threshold: u8) -> Result<BitVec> {
+        let resized = imageops::resize(
+            &self.0,
+            u32::try_from(width).with_context(|| "Width is too large")?,
+            u32::try_from(height).with_context(|| "Height is too large")?,
+            FilterType::Triangle,
+        );
+        let mut cells = bitvec![usize, Lsb0; 0; width * height];
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let [luma, alpha] = pixel.0;
+            if luma < threshold && alpha >= 128 {
+                cells.set(y as usize * width + x as usize, true);
+            }
+        }
+        Ok(cells)
+    }
+}
+
+/// Downscales an image to `width` by `height` cells, filling every cell that is dark and mostly opaque.
+///
+/// Cells are in row-major order.
+pub fn image_to_cells(bytes: &[u8], width: usize, height: usize, threshold: u8) -> Result<BitVec> {
+    DecodedImage::decode(bytes)?.to_cells(width, height, threshold)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    /// Encodes a PNG where each `#` is a black pixel, each `.` is white, and everything else is transparent.
+    pub(crate) fn fixture_png(rows: &[&str]) -> Vec<u8> {
+        let image = RgbaImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            let pixel = rows[y as usize].as_bytes()[x as usize];
+            match pixel {
+                b'#' => Rgba([0, 0, 0, 255]),
+                b'.' => Rgba([255, 255, 255, 255]),
+                _ => Rgba([0, 0, 0, 0]),
+            }
+        });
+        let mut bytes = Cursor::new(vec![]);
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn it_keeps_pixels_at_the_same_size() {
+        let png = fixture_png(&["#..", ".#_", "..#"]);
+        let cells = image_to_cells(&png, 3, 3, DEFAULT_THRESHOLD).unwrap();
+        assert_eq!(cells, bitvec![1, 0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn it_downscales_blocks() {
+        let png = fixture_png(&["##..", "##..", "....", "...."]);
+        let cells = image_to_cells(&png, 2, 2, DEFAULT_THRESHOLD).unwrap();
+        assert_eq!(cells, bitvec![1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn it_rejects_invalid_images() {
+        assert!(image_to_cells(b"not an image", 2, 2, DEFAULT_THRESHOLD).is_err());
+    }
+}
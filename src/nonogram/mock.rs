@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, Query},
+    http::header::LOCATION,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use bitvec::vec::BitVec;
+use hyper::StatusCode;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tracing::info;
+
+use super::{populate_board, PopulatedBoard};
+
+/* Fixtures */
+
+/// A small puzzle served by the mock upstream, regardless of which ID was requested.
+struct MockPuzzle {
+    title: &'static str,
+    author: &'static str,
+    rows: u16,
+    columns: u16,
+    solution: &'static str,
+}
+
+static MOCK_PUZZLES: [MockPuzzle; 3] = [
+    MockPuzzle {
+        title: "Heart",
+        author: "mock",
+        rows: 5,
+        columns: 5,
+        solution: "0101011111111110111000100",
+    },
+    MockPuzzle {
+        title: "Arrow",
+        author: "mock",
+        rows: 5,
+        columns: 5,
+        solution: "0010001110101010010000100",
+    },
+    MockPuzzle {
+        title: "Frame",
+        author: "mock",
+        rows: 10,
+        columns: 10,
+        solution: "1111111111100000000110111111011010000101101011010110101101011010000101101111110110000000011111111111",
+    },
+];
+
+impl MockPuzzle {
+    fn for_id(id: u32) -> &'static Self {
+        &MOCK_PUZZLES[id as usize % MOCK_PUZZLES.len()]
+    }
+
+    fn board(&self) -> PopulatedBoard {
+        let solution: BitVec = self.solution.chars().map(|char| char == '1').collect();
+        populate_board(&solution, self.rows, self.columns).expect("Invalid mock puzzle fixture.")
+    }
+}
+
+/* Router definition */
+
+/// A router imitating the Nonogrammed and webpbn endpoints used by our fetchers, for offline development.
+pub fn get_router() -> Router {
+    Router::new()
+        .route("/index.php", get(nonogrammed_page))
+        .route("/export.cgi/:filename", post(webpbn_export))
+        .route("/random.cgi", post(webpbn_random))
+}
+
+/// Serves the mock upstream on localhost in the background, returning its base URL.
+pub async fn spawn_mock_upstream(port: u16) -> Result<String> {
+    let listener = TcpListener::bind(("localhost", port))
+        .await
+        .with_context(|| "Failed to bind mock upstream listener")?;
+    let address = listener
+        .local_addr()
+        .with_context(|| "Mock upstream has no local address")?;
+    let base_url = format!("http://{address}");
+    info!(base_url = base_url, "Serving mock upstream.");
+    tokio::spawn(async move { axum::serve(listener, get_router()).await });
+    Ok(base_url)
+}
+
+#[derive(Deserialize)]
+struct NonogrammedQuery {
+    #[serde(rename = "NUM")]
+    num: u32,
+}
+
+async fn nonogrammed_page(Query(query): Query<NonogrammedQuery>) -> String {
+    let puzzle = MockPuzzle::for_id(query.num);
+    format!(
+        r#"<html>
+<body>
+<a href='user.php?NAME={author}'>{author}</a>
+<script>
+document.getElementById('title').innerHTML = '<b>{title}</b>';
+var data = '{solution}';
+var height = parseInt({rows});
+var width = parseInt({columns});
+</script>
+</body>
+</html>
+"#,
+        author = puzzle.author,
+        title = puzzle.title,
+        solution = puzzle.solution,
+        rows = puzzle.rows,
+        columns = puzzle.columns,
+    )
+}
+
+async fn webpbn_export(Path(filename): Path<String>) -> Result<String, StatusCode> {
+    let id = filename
+        .strip_prefix("webpbn")
+        .and_then(|name| name.strip_suffix(".non"))
+        .and_then(|id| id.parse::<u32>().ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let puzzle = MockPuzzle::for_id(id);
+    let board = puzzle.board();
+    let clues = |lines: &[Vec<u8>]| {
+        lines
+            .iter()
+            .map(|line| {
+                if line.is_empty() {
+                    String::from("0")
+                } else {
+                    line.iter()
+                        .map(|value| value.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    Ok(format!(
+        "catalogue \"webpbn.com #{id}\"\ntitle \"{title}\"\ncopyright \"&copy; {author}\"\nwidth {columns}\nheight {rows}\n\nrows\n{row_clues}\n\ncolumns\n{column_clues}\n\ngoal \"{solution}\"\n",
+        title = puzzle.title,
+        author = puzzle.author,
+        columns = puzzle.columns,
+        rows = puzzle.rows,
+        row_clues = clues(&board.rows),
+        column_clues = clues(&board.columns),
+        solution = puzzle.solution,
+    ))
+}
+
+async fn webpbn_random() -> impl IntoResponse {
+    let id = thread_rng().gen_range(1..40_000);
+    (
+        StatusCode::SEE_OTHER,
+        [(LOCATION, format!("play.cgi?id={id}&sid="))],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nonogram::{nonogrammed, upstream::UpstreamClient, webpbn};
+
+    #[tokio::test]
+    async fn it_serves_nonogrammed_puzzles() {
+        let base_url = spawn_mock_upstream(0).await.unwrap();
+        let puzzle = nonogrammed::get_puzzle_data(&UpstreamClient::default(), &base_url, 2704)
+            .await
+            .unwrap();
+        let fixture = MockPuzzle::for_id(2704);
+        assert_eq!(puzzle.id, 2704);
+        assert_eq!(puzzle.title.as_deref(), Some(fixture.title));
+        assert_eq!(puzzle.rows, fixture.board().rows);
+        assert_eq!(puzzle.columns, fixture.board().columns);
+    }
+
+    #[tokio::test]
+    async fn it_serves_webpbn_puzzles() {
+        let base_url = spawn_mock_upstream(0).await.unwrap();
+        let client = UpstreamClient::default();
+        let id = webpbn::get_random_puzzle_id(&client, &base_url)
+            .await
+            .unwrap();
+        let puzzle = webpbn::get_puzzle_data(&client, &base_url, id)
+            .await
+            .unwrap();
+        let fixture = MockPuzzle::for_id(id);
+        assert_eq!(puzzle.id, id);
+        assert_eq!(puzzle.title.as_deref(), Some(fixture.title));
+        assert_eq!(puzzle.rows, fixture.board().rows);
+        assert_eq!(puzzle.columns, fixture.board().columns);
+        assert_eq!(puzzle.solution, fixture.board().solution);
+    }
+}
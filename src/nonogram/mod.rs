@@ -1,11 +1,63 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, fmt::Display};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use bitvec::{slice::BitSlice, vec::BitVec};
+use serde::{Deserialize, Serialize};
 
+use rejection::RejectionReason;
+
+pub mod funnel;
+pub mod image;
+pub mod mock;
 pub mod nonogrammed;
+pub mod rejection;
+pub mod solver;
+pub mod throttle;
+pub mod upstream;
 pub mod webpbn;
 
+/// Largest number of rows or columns that we're willing to serve.
+pub const MAX_PUZZLE_SIDE: u16 = 60;
+
+/// Website that a puzzle ID refers to.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PuzzleSource {
+    Nonogrammed,
+    Webpbn,
+}
+
+impl PuzzleSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PuzzleSource::Nonogrammed => "nonogrammed",
+            PuzzleSource::Webpbn => "webpbn",
+        }
+    }
+}
+
+impl Display for PuzzleSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Base URLs of the websites that we fetch puzzles from.
+#[derive(Clone, Debug)]
+pub struct UpstreamUrls {
+    pub nonogrammed: String,
+    pub webpbn: String,
+}
+
+impl Default for UpstreamUrls {
+    fn default() -> Self {
+        UpstreamUrls {
+            nonogrammed: String::from(nonogrammed::NONOGRAMMED_BASE_URL),
+            webpbn: String::from(webpbn::WEBPBN_BASE_URL),
+        }
+    }
+}
+
 pub struct PopulatedBoard {
     pub rows: Vec<Vec<u8>>,
     pub columns: Vec<Vec<u8>>,
@@ -13,10 +65,11 @@ pub struct PopulatedBoard {
 }
 
 pub fn populate_board(solution: &BitSlice, rows: u16, columns: u16) -> Result<PopulatedBoard> {
+    check_board_size(rows, columns)?;
     let rows = rows as usize;
     let columns = columns as usize;
     if solution.len() != rows * columns {
-        return Err(anyhow!("Invalid board size."));
+        return Err(anyhow!("Invalid board size.")).context(RejectionReason::InconsistentClues);
     }
     let mut vec_rows: VecDeque<Vec<u8>> = VecDeque::from(vec![vec![0]; rows]);
     let mut vec_columns: VecDeque<Vec<u8>> = VecDeque::from(vec![vec![0]; columns]);
@@ -45,6 +98,37 @@ pub fn populate_board(solution: &BitSlice, rows: u16, columns: u16) -> Result<Po
     })
 }
 
+/// Rejects boards that are too large to be played comfortably.
+pub fn check_board_size(rows: u16, columns: u16) -> Result<()> {
+    if rows > MAX_PUZZLE_SIDE || columns > MAX_PUZZLE_SIDE {
+        return Err(anyhow!("Board is {rows}x{columns}.")).context(RejectionReason::TooLarge);
+    }
+    Ok(())
+}
+
+/// Rejects clue lists that couldn't possibly fit their line, since every clue but the last needs a gap after it.
+pub fn check_clues(rows: &[Vec<u8>], columns: &[Vec<u8>]) -> Result<()> {
+    for (kind, lines, length) in [
+        ("Row", rows, columns.len()),
+        ("Column", columns, rows.len()),
+    ] {
+        let max_clues = length.div_ceil(2);
+        if let Some((index, clues)) = lines
+            .iter()
+            .enumerate()
+            .find(|(_, clues)| clues.len() > max_clues)
+        {
+            return Err(anyhow!(
+                "{kind} {} has {} clues, but a line of {length} cells fits at most {max_clues}.",
+                index + 1,
+                clues.len()
+            ))
+            .context(RejectionReason::InconsistentClues);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -163,6 +247,27 @@ mod tests {
         assert_eq!(board.solution, solution);
     }
 
+    #[test]
+    fn it_rejects_impossible_clue_counts() {
+        let single_clues = |count| vec![vec![1]; count];
+        assert!(check_clues(&[vec![1, 1, 1], vec![5]], &single_clues(5)).is_ok());
+        assert!(check_clues(&[vec![1, 1]], &single_clues(4)).is_ok());
+        let error = check_clues(&[vec![1], vec![1, 1, 1]], &single_clues(4)).unwrap_err();
+        assert_eq!(
+            RejectionReason::classify(&error),
+            RejectionReason::InconsistentClues
+        );
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Row 2 has 3 clues, but a line of 4 cells fits at most 2."
+        );
+        let error = check_clues(&single_clues(3), &[vec![1, 1, 1]]).unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Column 1 has 3 clues, but a line of 3 cells fits at most 2."
+        );
+    }
+
     // #[test]
     // fn it_trims_space_around_the_board() {
     //     let rows = 5;
@@ -3,8 +3,15 @@ use std::sync::LazyLock;
 use anyhow::{anyhow, Context, Result};
 use bitvec::{bitvec, order::Lsb0, vec::BitVec};
 use regex::Regex;
+use reqwest::StatusCode;
 
-use super::{populate_board, PopulatedBoard};
+use super::{
+    populate_board, rejection::RejectionReason, throttle::Throttled, upstream::UpstreamClient,
+    PopulatedBoard,
+};
+
+/// Where puzzles are fetched from, unless overridden with `--nonogrammed-base-url`.
+pub const NONOGRAMMED_BASE_URL: &str = "https://nonogrammed.com";
 
 /// List of monochrome puzzles obtained from https://nonogrammed.com/
 pub static NONOGRAMMED_PUZZLE_LIST: [u32; 608] = [
@@ -78,26 +85,44 @@ static ROWS_RE: LazyLock<Regex> =
 static COLUMNS_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new("var width\\s*=\\s*parseInt\\((?P<columns>\\d+)\\)").unwrap());
 
-pub async fn get_puzzle_data(id: u32) -> Result<NonogrammedPuzzle> {
-    let client = reqwest::Client::new();
-    let html_response = client
-        .get(format!("https://nonogrammed.com/index.php?NUM={id}"))
+/// Fetches and parses a puzzle page from Nonogrammed, or a compatible server at `base_url`.
+pub async fn get_puzzle_data(
+    client: &UpstreamClient,
+    base_url: &str,
+    id: u32,
+) -> Result<NonogrammedPuzzle> {
+    let response = client
+        .get(format!("{base_url}/index.php?NUM={id}"))
+        .context(RejectionReason::Fetch)?
         .send()
         .await
-        .with_context(|| "URL fetch error")?
+        .with_context(|| "URL fetch error")
+        .context(RejectionReason::Fetch)?;
+    if let Some(throttled) = Throttled::from_response(&response) {
+        return Err(anyhow::Error::new(throttled)).context(RejectionReason::Fetch);
+    }
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(anyhow!("Puzzle not found.")).context(RejectionReason::NotFound);
+    }
+    let html_response = response
+        .error_for_status()
+        .with_context(|| "Unexpected status")
+        .context(RejectionReason::Fetch)?
         .text()
         .await
-        .with_context(|| "Received non-text response")?;
+        .with_context(|| "Received non-text response")
+        .context(RejectionReason::Fetch)?;
+    parse_puzzle_data(id, &html_response)
+}
+
+/// Extracts the puzzle from the scripts and markup of a Nonogrammed puzzle page.
+pub fn parse_puzzle_data(id: u32, html_response: &str) -> Result<NonogrammedPuzzle> {
     let mut title = None;
     let mut copyright = None;
     let mut rows = None;
     let mut columns = None;
     let mut solution = bitvec![];
-    let mut lines = html_response.lines();
-    loop {
-        let Some(line) = lines.next() else {
-            break;
-        };
+    for line in html_response.lines() {
         if copyright.is_none() {
             if let Some(caps) = USERNAME_RE.captures(line) {
                 let username = &caps["username"];
@@ -121,7 +146,8 @@ pub async fn get_puzzle_data(id: u32) -> Result<NonogrammedPuzzle> {
                 rows = Some(
                     caps["rows"]
                         .parse::<u16>()
-                        .with_context(|| "Invalid rows value.")?,
+                        .with_context(|| "Invalid rows value.")
+                        .context(RejectionReason::Parse)?,
                 );
             }
         }
@@ -130,19 +156,20 @@ pub async fn get_puzzle_data(id: u32) -> Result<NonogrammedPuzzle> {
                 columns = Some(
                     caps["columns"]
                         .parse::<u16>()
-                        .with_context(|| "Invalid columns value.")?,
+                        .with_context(|| "Invalid columns value.")
+                        .context(RejectionReason::Parse)?,
                 );
             }
         }
     }
     if solution.is_empty() {
-        return Err(anyhow!("Missing solution."));
+        return Err(anyhow!("Missing solution.")).context(RejectionReason::Parse);
     }
     if rows.is_none() {
-        return Err(anyhow!("Missing rows."));
+        return Err(anyhow!("Missing rows.")).context(RejectionReason::Parse);
     }
     if columns.is_none() {
-        return Err(anyhow!("Missing columns."));
+        return Err(anyhow!("Missing columns.")).context(RejectionReason::Parse);
     }
     // if title.is_none() {
     //     return Err(anyhow!("Missing title."));
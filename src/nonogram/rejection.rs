@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::PuzzleSource;
+use crate::{
+    schedule::TimeZone,
+    storage::{ArtifactKind, DataDir},
+};
+
+/// Rejection records, for `--retain-max-age` and `--retain-max-size`. Also matches `rejections.jsonl`, where records
+/// used to be appended before they were split by day.
+pub const REJECTIONS_ARTIFACT: ArtifactKind = ArtifactKind {
+    name: "rejections",
+    prefix: "rejections",
+};
+
+/// Name of the file that records from `timestamp` go to, by local date in `timezone`.
+fn file_name(timestamp: u64, timezone: &TimeZone) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp);
+    let (year, month, day) = timezone.local_date(time);
+    format!(
+        "{}_{year:04}-{month:02}-{day:02}.jsonl",
+        REJECTIONS_ARTIFACT.prefix
+    )
+}
+
+/// Why a puzzle couldn't be used. Attach one to fetch errors with `anyhow::Context::context` so that
+/// [`RejectionReason::classify`] can find it later.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// The request failed or returned an unexpected status.
+    Fetch,
+    /// The puzzle doesn't exist upstream.
+    NotFound,
+    /// The response couldn't be parsed into a puzzle.
+    Parse,
+    /// The clues don't match the solution or the board dimensions.
+    InconsistentClues,
+    /// The board is larger than we're willing to serve.
+    TooLarge,
+    /// The puzzle uses more than one color.
+    Multicolor,
+    /// Any other error.
+    Other,
+}
+
+impl RejectionReason {
+    /// Finds the reason attached to an error, if any.
+    pub fn classify(error: &anyhow::Error) -> Self {
+        error
+            .downcast_ref::<RejectionReason>()
+            .copied()
+            .unwrap_or(RejectionReason::Other)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::Fetch => "fetch",
+            RejectionReason::NotFound => "not_found",
+            RejectionReason::Parse => "parse",
+            RejectionReason::InconsistentClues => "inconsistent_clues",
+            RejectionReason::TooLarge => "too_large",
+            RejectionReason::Multicolor => "multicolor",
+            RejectionReason::Other => "other",
+        }
+    }
+}
+
+impl Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single rejected puzzle, as persisted in the data dir.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RejectionRecord {
+    pub source: PuzzleSource,
+    pub id: u32,
+    pub reason: RejectionReason,
+    pub message: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl RejectionRecord {
+    pub fn new(source: PuzzleSource, id: u32, error: &anyhow::Error) -> Self {
+        let reason = RejectionReason::classify(error);
+        // The reason is already recorded separately, so leave it out of the error chain.
+        let message = error
+            .chain()
+            .map(|cause| cause.to_string())
+            .filter(|cause| cause != reason.as_str())
+            .collect::<Vec<_>>()
+            .join(": ");
+        RejectionRecord {
+            source,
+            id,
+            reason,
+            message,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Where rejections are recorded. Without a data dir, they're only logged.
+#[derive(Clone, Debug, Default)]
+pub struct RejectionLog {
+    data_dir: Option<DataDir>,
+    /// Where the day of each file starts, from `--timezone`.
+    timezone: TimeZone,
+}
+
+impl RejectionLog {
+    pub fn new(data_dir: Option<DataDir>, timezone: TimeZone) -> Self {
+        RejectionLog { data_dir, timezone }
+    }
+
+    /// Persists the record. Failures are logged, since they shouldn't stop the puzzle rotation.
+    pub async fn record(&self, record: RejectionRecord) {
+        warn!(
+            source = %record.source,
+            id = record.id,
+            reason = %record.reason,
+            message = record.message,
+            "Puzzle rejected."
+        );
+        if let Some(data_dir) = &self.data_dir {
+            let name = file_name(record.timestamp, &self.timezone);
+            if let Err(e) = data_dir.append_json_line(&name, &record).await {
+                warn!(error = ?e, "Unable to persist rejection record.");
+            }
+        }
+    }
+
+    /// Reads back every persisted record, oldest file first.
+    pub async fn read_all(&self) -> Result<Vec<RejectionRecord>> {
+        let Some(data_dir) = &self.data_dir else {
+            return Ok(vec![]);
+        };
+        let mut records = vec![];
+        for name in data_dir.list(REJECTIONS_ARTIFACT).await? {
+            if name.ends_with(".jsonl") {
+                records.extend(data_dir.read_json_lines(&name).await?);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// How `report-rejections` prints its output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Table,
+    Csv,
+}
+
+/// Groups the latest record of each puzzle by rejection reason.
+pub fn report(records: &[RejectionRecord], format: ReportFormat) -> String {
+    let mut latest: HashMap<(PuzzleSource, u32), &RejectionRecord> = HashMap::new();
+    for record in records {
+        latest
+            .entry((record.source, record.id))
+            .and_modify(|existing| {
+                if record.timestamp >= existing.timestamp {
+                    *existing = record;
+                }
+            })
+            .or_insert(record);
+    }
+    let mut grouped: BTreeMap<RejectionReason, Vec<&RejectionRecord>> = BTreeMap::new();
+    for record in latest.into_values() {
+        grouped.entry(record.reason).or_default().push(record);
+    }
+    for records in grouped.values_mut() {
+        records.sort_by_key(|record| (record.source, record.id));
+    }
+    let mut output = String::new();
+    match format {
+        ReportFormat::Csv => {
+            output.push_str("reason,source,id,timestamp,message\n");
+            for (reason, records) in grouped {
+                for record in records {
+                    let _ = writeln!(
+                        output,
+                        "{reason},{},{},{},\"{}\"",
+                        record.source,
+                        record.id,
+                        record.timestamp,
+                        record.message.replace('"', "\"\"")
+                    );
+                }
+            }
+        }
+        ReportFormat::Table => {
+            if grouped.is_empty() {
+                output.push_str("No rejections recorded.\n");
+            }
+            for (reason, records) in grouped {
+                let _ = writeln!(output, "{reason} ({})", records.len());
+                for record in records {
+                    let _ = writeln!(
+                        output,
+                        "  {:<12} {:>6}  {:>10}  {}",
+                        record.source, record.id, record.timestamp, record.message
+                    );
+                }
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nonogram::{nonogrammed, webpbn},
+        schedule::tests::utc,
+        storage::tests::temp_data_dir,
+    };
+    use anyhow::{anyhow, Context};
+
+    fn record(
+        source: PuzzleSource,
+        id: u32,
+        reason: RejectionReason,
+        timestamp: u64,
+    ) -> RejectionRecord {
+        RejectionRecord {
+            source,
+            id,
+            reason,
+            message: format!("{reason}, \"quoted\""),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn it_classifies_errors() {
+        let error = anyhow!("Missing solution.")
+            .context(RejectionReason::Parse)
+            .context("while fetching");
+        assert_eq!(RejectionReason::classify(&error), RejectionReason::Parse);
+        assert_eq!(
+            RejectionReason::classify(&anyhow!("unknown")),
+            RejectionReason::Other
+        );
+        let error = nonogrammed::parse_puzzle_data(1, "<html></html>")
+            .err()
+            .unwrap();
+        assert_eq!(RejectionReason::classify(&error), RejectionReason::Parse);
+        let error = webpbn::parse_puzzle_data(
+            1,
+            "color white\ncolor black\ncolor red\nrows\n1\n\ncolumns\n1\n\ngoal \"1\"",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            RejectionReason::classify(&error),
+            RejectionReason::Multicolor
+        );
+        // A 3x3 board can't have four clues in its first row.
+        let error = webpbn::parse_puzzle_data(
+            1,
+            "rows\n1,1,1,1\n1\n1\n\ncolumns\n1\n1\n1\n\ngoal \"101010101\"",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            RejectionReason::classify(&error),
+            RejectionReason::InconsistentClues
+        );
+        let result: Result<()> = Err(anyhow!("io")).context(RejectionReason::Fetch);
+        assert_eq!(
+            RejectionReason::classify(&result.unwrap_err()),
+            RejectionReason::Fetch
+        );
+    }
+
+    #[test]
+    fn it_groups_the_latest_record_per_puzzle() {
+        let records = vec![
+            record(PuzzleSource::Webpbn, 7, RejectionReason::Fetch, 10),
+            record(PuzzleSource::Webpbn, 7, RejectionReason::Multicolor, 20),
+            record(PuzzleSource::Nonogrammed, 7, RejectionReason::Parse, 15),
+            record(PuzzleSource::Nonogrammed, 3, RejectionReason::Parse, 5),
+        ];
+        let table = report(&records, ReportFormat::Table);
+        assert_eq!(
+            table
+                .lines()
+                .filter(|line| !line.starts_with(' '))
+                .collect::<Vec<_>>(),
+            vec!["parse (2)", "multicolor (1)"]
+        );
+        let csv = report(&records, ReportFormat::Csv);
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                "reason,source,id,timestamp,message",
+                "parse,nonogrammed,3,5,\"parse, \"\"quoted\"\"\"",
+                "parse,nonogrammed,7,15,\"parse, \"\"quoted\"\"\"",
+                "multicolor,webpbn,7,20,\"multicolor, \"\"quoted\"\"\"",
+            ]
+        );
+        assert_eq!(
+            report(&[], ReportFormat::Table),
+            "No rejections recorded.\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_persists_records() {
+        let data_dir = temp_data_dir("rejections").await;
+        let log = RejectionLog::new(Some(data_dir), TimeZone::default());
+        let first = record(PuzzleSource::Nonogrammed, 1, RejectionReason::NotFound, 1);
+        let second = RejectionRecord::new(
+            PuzzleSource::Webpbn,
+            2,
+            &anyhow!("Too big").context(RejectionReason::TooLarge),
+        );
+        assert_eq!(second.reason, RejectionReason::TooLarge);
+        assert_eq!(second.message, "Too big");
+        log.record(first.clone()).await;
+        log.record(second.clone()).await;
+        assert_eq!(log.read_all().await.unwrap(), vec![first, second]);
+        assert!(RejectionLog::default().read_all().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn files_follow_the_local_day() {
+        // 01:00 UTC is still the day before in UTC-3.
+        let timestamp = utc(2024, 6, 2, 1)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(
+            file_name(timestamp, &TimeZone::default()),
+            "rejections_2024-06-02.jsonl"
+        );
+        assert_eq!(
+            file_name(timestamp, &TimeZone::load("America/Sao_Paulo").unwrap()),
+            "rejections_2024-06-01.jsonl"
+        );
+    }
+}
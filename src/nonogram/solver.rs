@@ -0,0 +1,209 @@
+//! A line-by-line solver, to tell whether a puzzle can be solved without guessing.
+
+use std::fmt::{self, Display};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Cell {
+    Unknown,
+    Filled,
+    Empty,
+}
+
+/// How far the clues alone get a player who only reasons about one line at a time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Solvability {
+    /// Every cell follows from the clues.
+    LineSolvable,
+    /// This many cells are left undecided, so players would have to guess or reason about several lines at once.
+    NeedsGuessing(usize),
+    /// The clues contradict each other.
+    Contradiction,
+}
+
+impl Display for Solvability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Solvability::LineSolvable => write!(f, "Solvable line by line"),
+            Solvability::NeedsGuessing(1) => write!(f, "Needs guessing (1 cell left)"),
+            Solvability::NeedsGuessing(unknown) => {
+                write!(f, "Needs guessing ({unknown} cells left)")
+            }
+            Solvability::Contradiction => write!(f, "Clues contradict each other"),
+        }
+    }
+}
+
+/// Solves as much of the board as possible one line at a time, until no line gives anything new.
+pub fn assess(rows: &[Vec<u8>], columns: &[Vec<u8>]) -> Solvability {
+    let width = columns.len();
+    let height = rows.len();
+    let mut board = vec![Cell::Unknown; width * height];
+    loop {
+        let mut progress = false;
+        for (row, clues) in rows.iter().enumerate() {
+            let cells = (0..width).map(|column| row * width + column);
+            match solve_line_at(&mut board, clues, cells.collect()) {
+                Some(changed) => progress |= changed,
+                None => return Solvability::Contradiction,
+            }
+        }
+        for (column, clues) in columns.iter().enumerate() {
+            let cells = (0..height).map(|row| row * width + column);
+            match solve_line_at(&mut board, clues, cells.collect()) {
+                Some(changed) => progress |= changed,
+                None => return Solvability::Contradiction,
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+    match board.iter().filter(|&&cell| cell == Cell::Unknown).count() {
+        0 => Solvability::LineSolvable,
+        unknown => Solvability::NeedsGuessing(unknown),
+    }
+}
+
+/// Solves the line made of the board cells at `indices`. Returns whether anything changed, or `None` on a
+/// contradiction.
+fn solve_line_at(board: &mut [Cell], clues: &[u8], indices: Vec<usize>) -> Option<bool> {
+    let line = indices
+        .iter()
+        .map(|&index| board[index])
+        .collect::<Vec<_>>();
+    let solved = solve_line(clues, &line)?;
+    let mut changed = false;
+    for (&index, (&before, after)) in indices.iter().zip(line.iter().zip(solved)) {
+        if before != after {
+            board[index] = after;
+            changed = true;
+        }
+    }
+    Some(changed)
+}
+
+/// The cells that every placement of `clues` consistent with `line` agrees on, or `None` if no placement fits.
+fn solve_line(clues: &[u8], line: &[Cell]) -> Option<Vec<Cell>> {
+    let length = line.len();
+    let count = clues.len();
+    // Whether the block fits starting at `start`, with an empty cell (or the end of the line) right after it.
+    let block_fits = |start: usize, clue: usize| {
+        start + clue <= length
+            && line[start..start + clue]
+                .iter()
+                .all(|&cell| cell != Cell::Empty)
+            && line.get(start + clue) != Some(&Cell::Filled)
+    };
+    // fits[i][j]: clues j.. can be placed in cells i..
+    let mut fits = vec![vec![false; count + 1]; length + 2];
+    fits[length][count] = true;
+    fits[length + 1][count] = true;
+    for start in (0..length).rev() {
+        for j in (0..=count).rev() {
+            let skip = line[start] != Cell::Filled && fits[start + 1][j];
+            let place = j < count && {
+                let clue = clues[j] as usize;
+                block_fits(start, clue) && fits[(start + clue + 1).min(length + 1)][j + 1]
+            };
+            fits[start][j] = skip || place;
+        }
+    }
+    if !fits[0][0] {
+        return None;
+    }
+    // Walk every placement that fits, noting which cells can be filled and which can be empty.
+    let mut can_fill = vec![false; length];
+    let mut can_empty = vec![false; length];
+    let mut reachable = vec![vec![false; count + 1]; length + 2];
+    reachable[0][0] = true;
+    for start in 0..length {
+        for j in 0..=count {
+            if !reachable[start][j] || !fits[start][j] {
+                continue;
+            }
+            if line[start] != Cell::Filled && fits[start + 1][j] {
+                can_empty[start] = true;
+                reachable[start + 1][j] = true;
+            }
+            if j < count {
+                let clue = clues[j] as usize;
+                let next = (start + clue + 1).min(length + 1);
+                if block_fits(start, clue) && fits[next][j + 1] {
+                    can_fill[start..start + clue].fill(true);
+                    if start + clue < length {
+                        can_empty[start + clue] = true;
+                    }
+                    reachable[next][j + 1] = true;
+                }
+            }
+        }
+    }
+    Some(
+        can_fill
+            .into_iter()
+            .zip(can_empty)
+            .map(|(fill, empty)| match (fill, empty) {
+                (true, false) => Cell::Filled,
+                (false, true) => Cell::Empty,
+                _ => Cell::Unknown,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bitvec::{bitvec, order::Lsb0};
+
+    use super::*;
+    use crate::nonogram::populate_board;
+
+    fn parse(line: &str) -> Vec<Cell> {
+        line.chars()
+            .map(|cell| match cell {
+                '#' => Cell::Filled,
+                'x' => Cell::Empty,
+                _ => Cell::Unknown,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_solves_overlapping_blocks() {
+        assert_eq!(solve_line(&[4], &parse("......")), Some(parse("..##..")));
+        assert_eq!(solve_line(&[2, 2], &parse(".....")), Some(parse("##x##")));
+        assert_eq!(solve_line(&[], &parse("...")), Some(parse("xxx")));
+        assert_eq!(solve_line(&[1], &parse("x.#..")), Some(parse("xx#xx")));
+        assert_eq!(solve_line(&[1], &parse("...")), Some(parse("...")));
+    }
+
+    #[test]
+    fn it_finds_contradictions() {
+        assert_eq!(solve_line(&[3], &parse("..")), None);
+        assert_eq!(solve_line(&[1, 1], &parse("##.")), None);
+        assert_eq!(solve_line(&[], &parse(".#.")), None);
+    }
+
+    #[test]
+    fn it_tells_apart_puzzles_that_need_guessing() {
+        // A heart only needs line solving.
+        let heart =
+            bitvec![0, 1, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 1, 0, 0];
+        let board = populate_board(&heart, 5, 5).unwrap();
+        assert_eq!(
+            assess(&board.rows, &board.columns),
+            Solvability::LineSolvable
+        );
+        // Two diagonal cells could just as well be on the other diagonal.
+        let diagonal = bitvec![1, 0, 0, 1];
+        let board = populate_board(&diagonal, 2, 2).unwrap();
+        assert_eq!(
+            assess(&board.rows, &board.columns),
+            Solvability::NeedsGuessing(4)
+        );
+        assert_eq!(
+            assess(&[vec![2]], &[vec![1], vec![]]),
+            Solvability::Contradiction
+        );
+    }
+}
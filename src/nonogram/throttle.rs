@@ -0,0 +1,336 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use tokio::time::Instant;
+
+use super::PuzzleSource;
+use crate::format::{format_duration, DurationStyle};
+
+/// How many throttled responses in a row open a source's circuit breaker.
+pub const THROTTLES_BEFORE_OPENING: u32 = 3;
+
+/// How long a circuit breaker stays open when the upstream didn't say how long to wait.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Longest cool-down that we honor, in case an upstream asks for something unreasonable.
+pub const MAX_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait for the probe of a half-open circuit breaker before trying again.
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+
+/// An upstream asked us to slow down, with 429 Too Many Requests or 503 Service Unavailable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    pub status: StatusCode,
+    /// From the `Retry-After` header, if it had a valid value.
+    pub retry_after: Option<Duration>,
+}
+
+impl Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Throttled by upstream ({})", self.status)?;
+        if let Some(retry_after) = self.retry_after {
+            write!(f, ", retry after {}s", retry_after.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+impl Throttled {
+    /// Checks whether a response is a throttling one.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let status = response.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+            return None;
+        }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, SystemTime::now()));
+        Some(Throttled {
+            status,
+            retry_after,
+        })
+    }
+
+    /// Finds the throttling response behind a fetch error, if any.
+    pub fn find(error: &anyhow::Error) -> Option<Self> {
+        error.downcast_ref::<Throttled>().copied()
+    }
+}
+
+/// Parses a `Retry-After` value, either as a number of seconds or as an HTTP date relative to `now`.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // Dates in the past mean that we may retry right away.
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Where a circuit breaker stands, for the status page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Requests go through as usual.
+    Closed,
+    /// No requests go through until the cool-down is over.
+    Open { remaining: Duration },
+    /// The cool-down is over, and a single request is checking whether the upstream recovered.
+    HalfOpen,
+}
+
+impl Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerState::Closed => write!(f, "Closed"),
+            BreakerState::Open { remaining } => {
+                write!(
+                    f,
+                    "Open ({} left)",
+                    format_duration(*remaining, DurationStyle::Human)
+                )
+            }
+            BreakerState::HalfOpen => write!(f, "Half-open"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Closed {
+        throttles: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The cool-down is over, and the probe has been let through.
+    HalfOpen,
+}
+
+/// Stops all requests to an upstream for a while after it throttled us several times.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    phase: Phase,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            phase: Phase::Closed { throttles: 0 },
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Whether a request may be sent now. Otherwise, returns how long to wait before asking again.
+    pub fn allow(&mut self, now: Instant) -> Result<(), Duration> {
+        match self.phase {
+            Phase::Closed { .. } => Ok(()),
+            Phase::Open { until } if now < until => Err(until - now),
+            Phase::Open { .. } => {
+                self.phase = Phase::HalfOpen;
+                Ok(())
+            }
+            Phase::HalfOpen => Err(PROBE_WAIT),
+        }
+    }
+
+    /// The upstream answered without throttling us, even if with an error.
+    pub fn record_success(&mut self) {
+        self.phase = Phase::Closed { throttles: 0 };
+    }
+
+    /// The upstream throttled us. Opens the breaker after enough throttles in a row, or right away if probing.
+    pub fn record_throttle(&mut self, now: Instant, retry_after: Option<Duration>) {
+        let cooldown = retry_after.unwrap_or(DEFAULT_COOLDOWN).min(MAX_COOLDOWN);
+        self.phase = match self.phase {
+            Phase::Closed { throttles } if throttles + 1 < THROTTLES_BEFORE_OPENING => {
+                Phase::Closed {
+                    throttles: throttles + 1,
+                }
+            }
+            Phase::Open { until } => Phase::Open {
+                until: until.max(now + cooldown),
+            },
+            Phase::Closed { .. } | Phase::HalfOpen => Phase::Open {
+                until: now + cooldown,
+            },
+        };
+    }
+
+    pub fn state(&self, now: Instant) -> BreakerState {
+        match self.phase {
+            Phase::Closed { .. } => BreakerState::Closed,
+            Phase::Open { until } if now < until => BreakerState::Open {
+                remaining: until - now,
+            },
+            Phase::Open { .. } | Phase::HalfOpen => BreakerState::HalfOpen,
+        }
+    }
+}
+
+/// A circuit breaker for every puzzle source.
+#[derive(Clone, Debug, Default)]
+pub struct Breakers(Arc<Mutex<BTreeMap<PuzzleSource, CircuitBreaker>>>);
+
+impl Breakers {
+    pub fn allow(&self, source: PuzzleSource, now: Instant) -> Result<(), Duration> {
+        self.0.lock().unwrap().entry(source).or_default().allow(now)
+    }
+
+    /// Updates the source's breaker after a fetch.
+    pub fn record<T>(&self, source: PuzzleSource, now: Instant, result: &anyhow::Result<T>) {
+        let mut breakers = self.0.lock().unwrap();
+        let breaker = breakers.entry(source).or_default();
+        match result.as_ref().err().and_then(Throttled::find) {
+            Some(throttled) => breaker.record_throttle(now, throttled.retry_after),
+            None => breaker.record_success(),
+        }
+    }
+
+    /// States of every source that was requested so far.
+    pub fn states(&self, now: Instant) -> Vec<(PuzzleSource, BreakerState)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&source, breaker)| (source, breaker.state(now)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    use crate::nonogram::rejection::RejectionReason;
+
+    #[test]
+    fn it_parses_retry_after() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        // RFC 850 and asctime dates are also valid HTTP dates.
+        assert_eq!(
+            parse_retry_after("Wednesday, 21-Oct-15 07:28:10 GMT", now),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            parse_retry_after("Wed Oct 21 07:28:05 2015", now),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
+
+    #[test]
+    fn it_finds_throttles_behind_context() {
+        let throttled = Throttled {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(Duration::from_secs(30)),
+        };
+        let error = anyhow::Error::new(throttled).context(RejectionReason::Fetch);
+        assert_eq!(Throttled::find(&error), Some(throttled));
+        assert_eq!(RejectionReason::classify(&error), RejectionReason::Fetch);
+        let error = Err::<(), _>(anyhow!("Not found")).context(RejectionReason::NotFound);
+        assert_eq!(Throttled::find(&error.unwrap_err()), None);
+    }
+
+    #[test]
+    fn it_opens_after_repeated_throttles() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 1..THROTTLES_BEFORE_OPENING {
+            breaker.record_throttle(now, None);
+            assert_eq!(breaker.state(now), BreakerState::Closed);
+            assert_eq!(breaker.allow(now), Ok(()));
+        }
+        // A response in between resets the count.
+        breaker.record_success();
+        for _ in 1..THROTTLES_BEFORE_OPENING {
+            breaker.record_throttle(now, None);
+        }
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+        breaker.record_throttle(now, Some(Duration::from_secs(30)));
+        assert_eq!(
+            breaker.state(now),
+            BreakerState::Open {
+                remaining: Duration::from_secs(30)
+            }
+        );
+        let later = now + Duration::from_secs(10);
+        assert_eq!(breaker.allow(later), Err(Duration::from_secs(20)));
+        assert_eq!(breaker.state(later).to_string(), "Open (20s left)");
+    }
+
+    #[test]
+    fn it_probes_once_half_open() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..THROTTLES_BEFORE_OPENING {
+            breaker.record_throttle(now, None);
+        }
+        let after_cooldown = now + DEFAULT_COOLDOWN;
+        assert_eq!(breaker.state(after_cooldown), BreakerState::HalfOpen);
+        assert_eq!(breaker.allow(after_cooldown), Ok(()));
+        // Only the probe goes through.
+        assert_eq!(breaker.allow(after_cooldown), Err(PROBE_WAIT));
+        // A throttled probe opens the breaker again right away.
+        breaker.record_throttle(after_cooldown, Some(Duration::from_secs(5)));
+        assert_eq!(
+            breaker.state(after_cooldown),
+            BreakerState::Open {
+                remaining: Duration::from_secs(5)
+            }
+        );
+        let after_retry = after_cooldown + Duration::from_secs(5);
+        assert_eq!(breaker.allow(after_retry), Ok(()));
+        breaker.record_success();
+        assert_eq!(breaker.state(after_retry), BreakerState::Closed);
+        assert_eq!(breaker.allow(after_retry), Ok(()));
+    }
+
+    #[test]
+    fn it_caps_the_cooldown() {
+        let now = Instant::now();
+        let breakers = Breakers::default();
+        let throttled = Err::<(), _>(anyhow::Error::new(Throttled {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+        }));
+        for _ in 0..THROTTLES_BEFORE_OPENING {
+            breakers.record(PuzzleSource::Webpbn, now, &throttled);
+        }
+        assert_eq!(
+            breakers.states(now),
+            vec![(
+                PuzzleSource::Webpbn,
+                BreakerState::Open {
+                    remaining: MAX_COOLDOWN
+                }
+            )]
+        );
+        assert!(breakers.allow(PuzzleSource::Nonogrammed, now).is_ok());
+    }
+}
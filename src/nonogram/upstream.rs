@@ -0,0 +1,252 @@
+//! The one HTTP client that every fetch from the puzzle websites goes through, so that we always identify ourselves
+//! the same way, and so that `--no-upstream` can't be bypassed by accident.
+
+use std::fmt::{self, Display};
+
+use anyhow::{bail, Context, Result};
+use reqwest::{redirect::Policy, Client, RequestBuilder};
+
+/// Prefix of our User-Agent, followed by the contact details if there are any.
+const USER_AGENT_PRODUCT: &str = concat!("htmx-ssh-games/", env!("CARGO_PKG_VERSION"));
+
+/// A fetch was attempted with `--no-upstream`, to somewhere other than the offline sources.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamDisabled {
+    pub url: String,
+}
+
+impl Display for UpstreamDisabled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Upstream requests are disabled, refused {}", self.url)
+    }
+}
+
+impl std::error::Error for UpstreamDisabled {}
+
+/// Parses a URL for `--contact-url`, as an `http` or `https` URL that can go in a header.
+pub fn parse_contact_url(value: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(value.trim()).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(String::from("expected an http:// or https:// URL"));
+    }
+    Ok(url.to_string())
+}
+
+/// Parses an address for `--contact-email`. Only checks that it looks like one and fits in a header.
+pub fn parse_contact_email(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let Some((user, domain)) = value.split_once('@') else {
+        return Err(String::from("expected an email address"));
+    };
+    let is_valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_graphic() && !"()<>;,@\"\\".contains(c))
+    };
+    if !is_valid(user) || !is_valid(domain) {
+        return Err(String::from("expected an email address"));
+    }
+    Ok(String::from(value))
+}
+
+/// How upstreams can reach us about our scraping, from `--contact-url` and `--contact-email`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Contact {
+    pub url: Option<String>,
+    pub email: Option<String>,
+}
+
+impl Contact {
+    pub fn is_empty(&self) -> bool {
+        self.url.is_none() && self.email.is_none()
+    }
+
+    /// Our User-Agent, such as `htmx-ssh-games/0.1.0 (+https://example.com/contact; admin@example.com)`.
+    pub fn user_agent(&self) -> String {
+        let details = self
+            .url
+            .iter()
+            .map(|url| format!("+{url}"))
+            .chain(self.email.clone())
+            .collect::<Vec<_>>();
+        if details.is_empty() {
+            String::from(USER_AGENT_PRODUCT)
+        } else {
+            format!("{USER_AGENT_PRODUCT} ({})", details.join("; "))
+        }
+    }
+}
+
+/// Sends every request to the puzzle websites. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct UpstreamClient {
+    client: Client,
+    /// For requests whose redirect is the answer, such as webpbn's random puzzle.
+    no_redirects: Client,
+    user_agent: String,
+    /// With `--no-upstream`, only URLs under these base URLs can be fetched.
+    offline_sources: Option<Vec<String>>,
+}
+
+impl Default for UpstreamClient {
+    fn default() -> Self {
+        UpstreamClient::new(&Contact::default()).expect("the default client is valid")
+    }
+}
+
+impl UpstreamClient {
+    pub fn new(contact: &Contact) -> Result<Self> {
+        let user_agent = contact.user_agent();
+        let client = |redirect| {
+            Client::builder()
+                .user_agent(&user_agent)
+                .redirect(redirect)
+                .build()
+                .with_context(|| "Reqwest client build error")
+        };
+        Ok(UpstreamClient {
+            client: client(Policy::default())?,
+            no_redirects: client(Policy::none())?,
+            user_agent: user_agent.clone(),
+            offline_sources: None,
+        })
+    }
+
+    /// Refuses every request outside of `sources`, such as the mock upstream's base URL.
+    pub fn offline(mut self, sources: Vec<String>) -> Self {
+        self.offline_sources = Some(sources);
+        self
+    }
+
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    pub fn get(&self, url: String) -> Result<RequestBuilder> {
+        self.check(&url)?;
+        Ok(self.client.get(url))
+    }
+
+    pub fn post(&self, url: String) -> Result<RequestBuilder> {
+        self.check(&url)?;
+        Ok(self.client.post(url))
+    }
+
+    /// Posts without following redirects, to read where they point to.
+    pub fn post_without_redirects(&self, url: String) -> Result<RequestBuilder> {
+        self.check(&url)?;
+        Ok(self.no_redirects.post(url))
+    }
+
+    fn check(&self, url: &str) -> Result<()> {
+        let Some(sources) = &self.offline_sources else {
+            return Ok(());
+        };
+        let allowed = sources.iter().any(|source| {
+            url.strip_prefix(source.as_str())
+                .is_some_and(|path| path.is_empty() || path.starts_with('/'))
+        });
+        if !allowed {
+            bail!(UpstreamDisabled {
+                url: String::from(url)
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::HeaderMap, routing::get, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::nonogram::{mock::spawn_mock_upstream, nonogrammed};
+
+    #[test]
+    fn contact_details_go_in_the_user_agent() {
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            Contact::default().user_agent(),
+            format!("htmx-ssh-games/{version}")
+        );
+        let contact = Contact {
+            url: Some(parse_contact_url("https://example.com/contact").unwrap()),
+            email: None,
+        };
+        assert_eq!(
+            contact.user_agent(),
+            format!("htmx-ssh-games/{version} (+https://example.com/contact)")
+        );
+        let contact = Contact {
+            url: contact.url,
+            email: Some(parse_contact_email("admin@example.com").unwrap()),
+        };
+        assert_eq!(
+            contact.user_agent(),
+            format!("htmx-ssh-games/{version} (+https://example.com/contact; admin@example.com)")
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_contacts() {
+        assert!(parse_contact_url("example.com").is_err());
+        assert!(parse_contact_url("ftp://example.com").is_err());
+        assert!(parse_contact_email("admin").is_err());
+        assert!(parse_contact_email("admin@").is_err());
+        assert!(parse_contact_email("ad(min)@example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_send_the_user_agent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new().route(
+            "/",
+            get(|headers: HeaderMap| async move {
+                String::from(headers["user-agent"].to_str().unwrap())
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let contact = Contact {
+            url: Some(String::from("https://example.com/contact")),
+            email: None,
+        };
+        let client = UpstreamClient::new(&contact).unwrap();
+        let user_agent = client
+            .get(format!("{base_url}/"))
+            .unwrap()
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(user_agent, contact.user_agent());
+        assert_eq!(client.user_agent(), user_agent);
+    }
+
+    #[tokio::test]
+    async fn offline_clients_only_reach_offline_sources() {
+        let base_url = spawn_mock_upstream(0).await.unwrap();
+        let blocked = UpstreamClient::default().offline(vec![]);
+        let Err(error) = nonogrammed::get_puzzle_data(&blocked, &base_url, 2704).await else {
+            panic!("The fetch should have been blocked.");
+        };
+        assert!(
+            error.chain().any(|cause| cause.is::<UpstreamDisabled>()),
+            "{error:?}"
+        );
+        // Nothing that merely starts like an offline source gets through either.
+        let offline = UpstreamClient::default().offline(vec![base_url.clone()]);
+        assert!(offline.get(format!("{base_url}0/")).is_err());
+        assert!(offline
+            .get(String::from("https://nonogrammed.com/"))
+            .is_err());
+        let puzzle = nonogrammed::get_puzzle_data(&offline, &base_url, 2704)
+            .await
+            .unwrap();
+        assert_eq!(puzzle.id, 2704);
+    }
+}
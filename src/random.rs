@@ -0,0 +1,56 @@
+use std::sync::{Arc, Mutex};
+
+use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, RngCore, SeedableRng};
+
+/// Source of randomness for everything that decides what gets played, such as shuffling and picking puzzles.
+///
+/// Production code uses [`Random::entropy`], unless a seed is given with `--seed` to make a session reproducible.
+/// Cosmetic randomness, such as cursor colors, doesn't need to go through this.
+#[derive(Clone, Debug, Default)]
+pub struct Random(Option<Arc<Mutex<StdRng>>>);
+
+impl Random {
+    /// Randomness backed by `thread_rng`.
+    pub fn entropy() -> Self {
+        Random(None)
+    }
+
+    /// A reproducible sequence, shared by every clone of this handle.
+    pub fn seeded(seed: u64) -> Self {
+        Random(Some(Arc::new(Mutex::new(StdRng::seed_from_u64(seed)))))
+    }
+
+    fn with_rng<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match &self.0 {
+            None => f(&mut thread_rng()),
+            Some(rng) => f(&mut *rng.lock().unwrap()),
+        }
+    }
+
+    pub fn shuffle<T>(&self, slice: &mut [T]) {
+        self.with_rng(|rng| slice.shuffle(rng))
+    }
+
+    pub fn choose<'a, T>(&self, slice: &'a [T]) -> Option<&'a T> {
+        self.with_rng(|rng| slice.choose(rng))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_handles_repeat_themselves() {
+        let shuffled = |random: &Random| {
+            let mut list: Vec<u32> = (0..100).collect();
+            random.shuffle(&mut list);
+            (list, *random.choose(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap())
+        };
+        assert_eq!(shuffled(&Random::seeded(42)), shuffled(&Random::seeded(42)));
+        assert_ne!(shuffled(&Random::seeded(42)), shuffled(&Random::seeded(43)));
+        // Clones share the same sequence instead of restarting it.
+        let random = Random::seeded(42);
+        assert_ne!(shuffled(&random), shuffled(&random.clone()));
+    }
+}
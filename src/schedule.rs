@@ -0,0 +1,126 @@
+//! Local calendar dates, given a time zone from `--timezone`.
+//!
+//! Zones come from the copy of the IANA time zone database that's embedded in the binary, so that they don't depend on
+//! the system's zoneinfo, which slim container images leave out.
+
+use std::{
+    fmt::{self, Display},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use jiff::{tz, Timestamp, Zoned};
+
+/// A time zone, such as `America/Sao_Paulo`. Defaults to UTC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeZone {
+    name: String,
+    zone: tz::TimeZone,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        TimeZone {
+            name: String::from("UTC"),
+            zone: tz::TimeZone::UTC,
+        }
+    }
+}
+
+impl Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// For `--timezone`.
+pub fn parse_timezone(value: &str) -> Result<TimeZone, String> {
+    TimeZone::load(value).map_err(|err| format!("{err:#}"))
+}
+
+impl TimeZone {
+    /// Looks up an IANA time zone by name.
+    pub fn load(name: &str) -> Result<Self> {
+        let zone = tz::db()
+            .get(name)
+            .with_context(|| format!("Unknown time zone '{name}'"))?;
+        Ok(TimeZone {
+            name: String::from(name),
+            zone,
+        })
+    }
+
+    fn zoned(&self, time: SystemTime) -> Zoned {
+        let timestamp = Timestamp::try_from(time).unwrap_or(if time < UNIX_EPOCH {
+            Timestamp::MIN
+        } else {
+            Timestamp::MAX
+        });
+        timestamp.to_zoned(self.zone.clone())
+    }
+
+    /// Local date at `time`, as `(year, month, day)`.
+    pub fn local_date(&self, time: SystemTime) -> (i64, u32, u32) {
+        let date = self.zoned(time).date();
+        (
+            i64::from(date.year()),
+            date.month() as u32,
+            date.day() as u32,
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const HOUR: u64 = 60 * 60;
+
+    /// `hour` o'clock UTC on the given date.
+    pub(crate) fn utc(year: i16, month: i8, day: i8, hour: u64) -> SystemTime {
+        let midnight = jiff::civil::date(year, month, day)
+            .to_zoned(tz::TimeZone::UTC)
+            .unwrap()
+            .timestamp();
+        SystemTime::from(midnight) + Duration::from_secs(hour * HOUR)
+    }
+
+    #[test]
+    fn it_follows_daylight_saving_in_dates() {
+        let zone = TimeZone::load("Europe/Berlin").unwrap();
+        // Clocks went forward on 2024-03-31 and back on 2024-10-27.
+        assert_eq!(zone.local_date(utc(2024, 3, 30, 22)), (2024, 3, 30));
+        assert_eq!(zone.local_date(utc(2024, 3, 30, 23)), (2024, 3, 31));
+        assert_eq!(zone.local_date(utc(2024, 3, 31, 21)), (2024, 3, 31));
+        assert_eq!(zone.local_date(utc(2024, 3, 31, 22)), (2024, 4, 1));
+        assert_eq!(zone.local_date(utc(2024, 10, 26, 21)), (2024, 10, 26));
+        assert_eq!(zone.local_date(utc(2024, 10, 26, 22)), (2024, 10, 27));
+        assert_eq!(zone.local_date(utc(2024, 10, 27, 22)), (2024, 10, 27));
+        assert_eq!(zone.local_date(utc(2024, 10, 27, 23)), (2024, 10, 28));
+    }
+
+    #[test]
+    fn it_starts_days_after_skipped_midnights() {
+        // Brazil used to switch to daylight saving time at midnight, so 00:00 was skipped.
+        let zone = TimeZone::load("America/Sao_Paulo").unwrap();
+        assert_eq!(zone.local_date(utc(2018, 11, 4, 2)), (2018, 11, 3));
+        assert_eq!(zone.local_date(utc(2018, 11, 4, 3)), (2018, 11, 4));
+        // It hasn't observed daylight saving time since 2019.
+        assert_eq!(zone.local_date(utc(2024, 6, 1, 2)), (2024, 5, 31));
+        assert_eq!(zone.local_date(utc(2024, 6, 1, 3)), (2024, 6, 1));
+    }
+
+    #[test]
+    fn it_rejects_invalid_zones() {
+        assert!(TimeZone::load("../etc/passwd").is_err());
+        assert!(TimeZone::load("/etc/passwd").is_err());
+        assert!(TimeZone::load("").is_err());
+        assert!(TimeZone::load("Nowhere/Special").is_err());
+        assert_eq!(TimeZone::load("UTC").unwrap(), TimeZone::default());
+        assert_eq!(
+            parse_timezone("America/Sao_Paulo").unwrap().to_string(),
+            "America/Sao_Paulo"
+        );
+    }
+}
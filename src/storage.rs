@@ -0,0 +1,607 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+    task::spawn_blocking,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    accounting::HumanBytes,
+    clock::Clock,
+    format::parse_duration,
+    tasks::{recv_or_drain, TaskRegistry},
+};
+
+/// How often [`spawn_pruning`] enforces the retention rules.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Lock file that keeps two instances from using the same data dir at once. Hidden, so that it's never pruned.
+pub const LOCK_FILE: &str = ".lock";
+
+/// How long `--takeover` waits for the instance holding the lock to shut down.
+pub const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a waiting instance checks if the lock is free.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Directory where persistent state is kept, as set by `--data-dir`.
+#[derive(Clone, Debug)]
+pub struct DataDir {
+    path: PathBuf,
+    /// Files that are in use, and must never be pruned. Shared by every clone.
+    pins: Arc<Mutex<BTreeSet<String>>>,
+}
+
+/// A type of file kept in the data dir, such as rejection records, by the prefix of its file names.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ArtifactKind {
+    pub name: &'static str,
+    pub prefix: &'static str,
+}
+
+/// How much of an artifact kind to keep. Files past either limit are deleted, oldest first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetentionRule {
+    pub kind: ArtifactKind,
+    pub max_age: Option<Duration>,
+    pub max_total_size: Option<u64>,
+}
+
+/// What a pruning run deleted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PruneSummary {
+    /// Names of the deleted files, in the order that they were deleted.
+    pub removed: Vec<String>,
+    pub reclaimed_bytes: u64,
+}
+
+impl Display for PruneSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Removed {} file(s), reclaiming {}.",
+            self.removed.len(),
+            HumanBytes(self.reclaimed_bytes as usize)
+        )
+    }
+}
+
+/// Exclusive hold on a data dir, from [`DataDir::lock`]. Let go when dropped, or by the OS if the process dies.
+///
+/// The lock file holds the PID of its holder, and is emptied on drop, so that a PID left behind means that the
+/// previous instance crashed.
+#[derive(Debug)]
+pub struct DataDirLock {
+    file: File,
+}
+
+impl Drop for DataDirLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// The PID written in a lock file, if any.
+fn read_pid(mut file: &File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// Whether a process is still running, where `/proc` can tell.
+fn is_running(pid: u32) -> Option<bool> {
+    let proc = Path::new("/proc");
+    proc.is_dir().then(|| proc.join(pid.to_string()).exists())
+}
+
+/// Parses a maximum age given as `KIND=AGE`, where the age has an `s`, `m`, `h` or `d` suffix.
+pub fn parse_max_age(value: &str) -> Result<(String, Duration), String> {
+    let (kind, age) = value
+        .split_once('=')
+        .ok_or_else(|| String::from("expected KIND=AGE"))?;
+    Ok((String::from(kind.trim()), parse_duration(age)?))
+}
+
+impl DataDir {
+    /// Uses the given directory for persistent state, creating it if necessary.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)
+            .await
+            .with_context(|| format!("Unable to create data dir {}", path.display()))?;
+        Ok(DataDir {
+            path,
+            pins: Arc::default(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of a file within the data dir.
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Takes the lock on the data dir, so that no other instance uses it at the same time. Fails right away if
+    /// another instance holds it, unless `wait` is set, in which case it waits that long for the lock to be free.
+    pub async fn lock(&self, wait: Option<Duration>, clock: &Clock) -> Result<DataDirLock> {
+        let path = self.file(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        let deadline = wait.map(|wait| clock.now() + wait);
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {
+                    let holder = match read_pid(&file) {
+                        Some(pid) => format!("another instance (PID {pid})"),
+                        None => String::from("another instance"),
+                    };
+                    match deadline {
+                        Some(deadline) if clock.now() < deadline => {
+                            if !waiting {
+                                info!("Waiting for {holder} to let go of {}.", self.path.display());
+                                waiting = true;
+                            }
+                            clock.sleep(LOCK_POLL_INTERVAL).await;
+                        }
+                        Some(_) => bail!(
+                            "Data dir {} is still in use by {holder} after waiting for it.",
+                            self.path.display()
+                        ),
+                        None => bail!(
+                            "Data dir {} is in use by {holder}. Stop it first, or pass --takeover to wait for it.",
+                            self.path.display()
+                        ),
+                    }
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("Unable to lock {}", path.display()))
+                }
+            }
+        }
+        // Nobody holds the lock anymore, so any PID left behind is from an instance that died without letting go.
+        if let Some(pid) = read_pid(&file) {
+            let running = is_running(pid);
+            warn!(
+                pid,
+                ?running,
+                "The previous instance didn't shut down cleanly."
+            );
+        }
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+        Ok(DataDirLock { file })
+    }
+
+    /// Keeps a file from ever being pruned, such as state that is being used.
+    pub fn pin(&self, name: &str) {
+        self.pins.lock().unwrap().insert(String::from(name));
+    }
+
+    /// Deletes the files past each rule's limits, oldest first. Temporary and pinned files are never deleted, and
+    /// neither is the newest file of each kind, since that's the one being appended to. Those still count towards the
+    /// total size.
+    pub async fn prune(&self, rules: &[RetentionRule], now: SystemTime) -> Result<PruneSummary> {
+        let mut summary = PruneSummary::default();
+        let pins = self.pins.lock().unwrap().clone();
+        for rule in rules {
+            let mut files = vec![];
+            let mut entries = fs::read_dir(&self.path)
+                .await
+                .with_context(|| format!("Unable to list {}", self.path.display()))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                if name.starts_with('.') || !name.starts_with(rule.kind.prefix) {
+                    continue;
+                }
+                let metadata = entry.metadata().await?;
+                if metadata.is_file() {
+                    let modified = metadata.modified().unwrap_or(now);
+                    files.push((modified, name, metadata.len()));
+                }
+            }
+            files.sort();
+            let mut total_size: u64 = files.iter().map(|(_, _, size)| size).sum();
+            files.pop();
+            for (modified, name, size) in files {
+                let too_old = rule.max_age.is_some_and(|max_age| {
+                    now.duration_since(modified).unwrap_or_default() > max_age
+                });
+                let too_big = rule
+                    .max_total_size
+                    .is_some_and(|max_total_size| total_size > max_total_size);
+                if !(too_old || too_big) || pins.contains(&name) {
+                    continue;
+                }
+                fs::remove_file(self.file(&name))
+                    .await
+                    .with_context(|| format!("Unable to delete {name}"))?;
+                total_size -= size;
+                summary.reclaimed_bytes += size;
+                summary.removed.push(name);
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Names of the files of an artifact kind, sorted by name.
+    pub async fn list(&self, kind: ArtifactKind) -> Result<Vec<String>> {
+        let mut names = vec![];
+        let mut entries = fs::read_dir(&self.path)
+            .await
+            .with_context(|| format!("Unable to list {}", self.path.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Ok(name) = entry.file_name().into_string() {
+                if name.starts_with(kind.prefix) {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Appends a value as a single line of JSON to a file in the data dir.
+    pub async fn append_json_line<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        let mut line = serde_json::to_string(value).with_context(|| "Unable to serialize")?;
+        line.push('\n');
+        let path = self.file(name);
+        // A single write call per line, so that concurrent appends don't interleave.
+        spawn_blocking(move || {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(line.as_bytes()))
+                .with_context(|| format!("Unable to append to {}", path.display()))
+        })
+        .await?
+    }
+
+    /// Reads every line of JSON from a file in the data dir. Lines that can't be parsed are skipped.
+    pub async fn read_json_lines<T: DeserializeOwned>(&self, name: &str) -> Result<Vec<T>> {
+        let path = self.file(name);
+        let contents = match fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+        };
+        Ok(contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(number, line)| match serde_json::from_str(line) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    warn!(error = ?e, file = name, line = number + 1, "Skipping invalid line.");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Replaces a file in the data dir with a value serialized as JSON. The file is written to a temporary path
+    /// first, so readers never see a partial write.
+    pub async fn write_json<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        let contents = serde_json::to_vec(value).with_context(|| "Unable to serialize")?;
+        let path = self.file(name);
+        let temp_path = self.file(&format!(".{name}.tmp"));
+        fs::write(&temp_path, contents)
+            .await
+            .with_context(|| format!("Unable to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, &path)
+            .await
+            .with_context(|| format!("Unable to replace {}", path.display()))
+    }
+
+    /// Reads a JSON file from the data dir, returning `None` if it doesn't exist.
+    pub async fn read_json<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        let path = self.file(name);
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid JSON in {}", path.display()))
+    }
+}
+
+/// Prunes the data dir periodically, starting right away.
+pub fn spawn_pruning(
+    data_dir: DataDir,
+    rules: Vec<RetentionRule>,
+    clock: Clock,
+    tasks: &TaskRegistry,
+) {
+    tasks.spawn("data dir pruning", async move {
+        loop {
+            match data_dir.prune(&rules, SystemTime::now()).await {
+                Ok(summary) if !summary.removed.is_empty() => {
+                    info!(files = ?summary.removed, "Pruned the data dir. {summary}")
+                }
+                Ok(_) => (),
+                Err(e) => warn!(error = ?e, "Unable to prune the data dir."),
+            }
+            clock.sleep(PRUNE_INTERVAL).await;
+        }
+    });
+}
+
+type Append = (String, String, oneshot::Sender<Result<()>>);
+
+/// Appends lines to files in the data dir from a single task, so that lines never interleave and each one is synced
+/// to disk before its caller hears back. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct AppendWriter {
+    sender: mpsc::UnboundedSender<Append>,
+}
+
+impl AppendWriter {
+    /// Starts the writer task, which ends once every clone is dropped, or at shutdown once the lines already sent
+    /// are written.
+    pub fn spawn(data_dir: DataDir, tasks: &TaskRegistry) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tasks.spawn_cooperative("append writer", |token| {
+            run_append_writer(data_dir, receiver, token)
+        });
+        AppendWriter { sender }
+    }
+
+    /// Appends a value as a single line of JSON, and waits until it's on disk.
+    pub async fn append_json_line<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        let mut line = serde_json::to_string(value).with_context(|| "Unable to serialize")?;
+        line.push('\n');
+        let (ack, result) = oneshot::channel();
+        self.sender
+            .send((String::from(name), line, ack))
+            .ok()
+            .with_context(|| "The append writer stopped")?;
+        result.await.with_context(|| "The append writer stopped")?
+    }
+}
+
+async fn run_append_writer(
+    data_dir: DataDir,
+    mut receiver: mpsc::UnboundedReceiver<Append>,
+    token: CancellationToken,
+) {
+    while let Some((name, line, ack)) = recv_or_drain(&mut receiver, &token).await {
+        // Opened for every line, so that a file pruned in the meantime is created again instead of written to after
+        // it was deleted.
+        let path = data_dir.file(&name);
+        let result = async {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.sync_data().await
+        }
+        .await
+        .with_context(|| format!("Unable to append to {}", path.display()));
+        let _ = ack.send(result);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::nonogram::rejection::REJECTIONS_ARTIFACT;
+
+    /// A fresh, empty data dir for a single test.
+    pub(crate) async fn temp_data_dir(name: &str) -> DataDir {
+        let path = std::env::temp_dir().join(format!(
+            "htmx-ssh-games-{}-{name}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        DataDir::open(path).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_second_instance_fails_fast() {
+        let data_dir = temp_data_dir("lock").await;
+        let clock = Clock::tokio();
+        let lock = data_dir.lock(None, &clock).await.unwrap();
+        let message = data_dir.lock(None, &clock).await.unwrap_err().to_string();
+        assert!(message.contains(&format!("PID {}", std::process::id())));
+        assert!(message.contains("--takeover"));
+
+        drop(lock);
+        let contents = std::fs::read_to_string(data_dir.file(LOCK_FILE)).unwrap();
+        assert!(contents.is_empty());
+        assert!(data_dir.lock(None, &clock).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn takeover_waits_for_the_lock() {
+        let data_dir = temp_data_dir("takeover").await;
+        let clock = Clock::tokio();
+        let lock = data_dir.lock(None, &clock).await.unwrap();
+        let message = data_dir
+            .lock(Some(Duration::from_millis(300)), &clock)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("after waiting"));
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(lock);
+        });
+        let lock = data_dir
+            .lock(Some(Duration::from_secs(10)), &clock)
+            .await
+            .unwrap();
+        assert_eq!(read_pid(&lock.file), Some(std::process::id()));
+    }
+
+    #[tokio::test]
+    async fn stale_locks_are_taken_over() {
+        let data_dir = temp_data_dir("stale-lock").await;
+        // Left behind by an instance which crashed.
+        std::fs::write(data_dir.file(LOCK_FILE), "4194305\n").unwrap();
+        let lock = data_dir.lock(None, &Clock::tokio()).await.unwrap();
+        assert_eq!(read_pid(&lock.file), Some(std::process::id()));
+    }
+
+    #[tokio::test]
+    async fn it_round_trips_json_lines() {
+        let data_dir = temp_data_dir("json-lines").await;
+        assert!(data_dir
+            .read_json_lines::<(u32, String)>("missing.jsonl")
+            .await
+            .unwrap()
+            .is_empty());
+        data_dir
+            .append_json_line("values.jsonl", &(1, "one"))
+            .await
+            .unwrap();
+        data_dir
+            .append_json_line("values.jsonl", &(2, "two"))
+            .await
+            .unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(data_dir.file("values.jsonl"))
+            .unwrap()
+            .write_all(b"garbage\n")
+            .unwrap();
+        let values: Vec<(u32, String)> = data_dir.read_json_lines("values.jsonl").await.unwrap();
+        assert_eq!(
+            values,
+            vec![(1, String::from("one")), (2, String::from("two"))]
+        );
+    }
+
+    /// Creates a file of `size` bytes, last modified `days` ago.
+    pub(crate) fn dated_file(
+        data_dir: &DataDir,
+        name: &str,
+        size: usize,
+        days: u64,
+        now: SystemTime,
+    ) {
+        let file = std::fs::File::create(data_dir.file(name)).unwrap();
+        file.set_len(size as u64).unwrap();
+        file.set_modified(now - Duration::from_secs(days * 24 * 60 * 60))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_prunes_old_files_first() {
+        let data_dir = temp_data_dir("prune").await;
+        let now = SystemTime::now();
+        for (name, days) in [
+            ("rejections.jsonl", 10),
+            ("rejections_2026-01-02.jsonl", 8),
+            ("rejections_2026-01-07.jsonl", 3),
+            ("rejections_2026-01-08.jsonl", 2),
+            ("rejections_2026-01-09.jsonl", 1),
+        ] {
+            dated_file(&data_dir, name, 100, days, now);
+        }
+        dated_file(&data_dir, "recently_played.json", 100, 30, now);
+        data_dir.pin("rejections.jsonl");
+        let rule = RetentionRule {
+            kind: REJECTIONS_ARTIFACT,
+            max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            max_total_size: None,
+        };
+        let summary = data_dir.prune(&[rule], now).await.unwrap();
+        assert_eq!(
+            summary.removed,
+            vec![String::from("rejections_2026-01-02.jsonl")]
+        );
+        assert_eq!(summary.reclaimed_bytes, 100);
+        assert!(data_dir.file("rejections.jsonl").exists());
+        assert!(data_dir.file("recently_played.json").exists());
+
+        // Pinned files and the newest one still count towards the total, so the oldest others make room.
+        let rule = RetentionRule {
+            kind: REJECTIONS_ARTIFACT,
+            max_age: None,
+            max_total_size: Some(150),
+        };
+        let summary = data_dir.prune(&[rule], now).await.unwrap();
+        assert_eq!(
+            summary.removed,
+            vec![
+                String::from("rejections_2026-01-07.jsonl"),
+                String::from("rejections_2026-01-08.jsonl")
+            ]
+        );
+        assert_eq!(summary.to_string(), "Removed 2 file(s), reclaiming 200 B.");
+        assert!(data_dir.file("rejections_2026-01-09.jsonl").exists());
+        assert!(data_dir
+            .prune(&[rule], now)
+            .await
+            .unwrap()
+            .removed
+            .is_empty());
+    }
+
+    #[test]
+    fn it_parses_max_ages() {
+        assert_eq!(
+            parse_max_age("logs=7d"),
+            Ok((String::from("logs"), Duration::from_secs(7 * 24 * 60 * 60)))
+        );
+        assert_eq!(
+            parse_max_age("logs = 90m"),
+            Ok((String::from("logs"), Duration::from_secs(90 * 60)))
+        );
+        assert!(parse_max_age("logs=7").is_err());
+        assert!(parse_max_age("7d").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_replaces_json_files() {
+        let data_dir = temp_data_dir("json").await;
+        assert_eq!(
+            data_dir.read_json::<Vec<u32>>("state.json").await.unwrap(),
+            None
+        );
+        data_dir
+            .write_json("state.json", &vec![1, 2])
+            .await
+            .unwrap();
+        data_dir.write_json("state.json", &vec![3]).await.unwrap();
+        assert_eq!(
+            data_dir.read_json::<Vec<u32>>("state.json").await.unwrap(),
+            Some(vec![3])
+        );
+        assert!(!data_dir.file(".state.json.tmp").exists());
+    }
+}
@@ -0,0 +1,241 @@
+//! Restarts background tasks that panic, so that a bug while playing one puzzle doesn't freeze the game forever.
+
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Display},
+    future::Future,
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+
+use tokio::{task::AbortHandle, time::Instant};
+use tracing::error;
+
+use crate::{clock::Clock, tasks::TaskRegistry};
+
+/// Most restarts within [`RESTART_WINDOW`], past which panicking tasks are left dead instead of looping.
+pub const MAX_RESTARTS: usize = 5;
+
+pub const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+static TASK_PANICS: LazyLock<Mutex<BTreeMap<&'static str, u64>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Counts of panics by task name, for `/metrics`.
+pub fn task_panics() -> Vec<(&'static str, u64)> {
+    TASK_PANICS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&task, &count)| (task, count))
+        .collect()
+}
+
+/// A supervised task which panicked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaskFailure {
+    pub task: &'static str,
+    /// The puzzle generation that the task was started for.
+    pub generation: u64,
+    pub message: String,
+    /// Whether the task was restarted, or there were too many restarts lately.
+    pub restarted: bool,
+}
+
+impl Display for TaskFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} panicked in generation {}: {} ({})",
+            self.task,
+            self.generation,
+            self.message,
+            if self.restarted {
+                "restarted"
+            } else {
+                "gave up"
+            }
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct SupervisorState {
+    restarts: VecDeque<Instant>,
+    last_failure: Option<TaskFailure>,
+}
+
+/// Watches spawned tasks, and restarts the ones that panic at a capped rate. Shared by every clone.
+#[derive(Clone, Debug)]
+pub struct Supervisor {
+    state: Arc<Mutex<SupervisorState>>,
+    clock: Clock,
+    tasks: TaskRegistry,
+}
+
+impl Supervisor {
+    pub fn new(clock: Clock) -> Self {
+        Supervisor {
+            state: Arc::default(),
+            tasks: TaskRegistry::new(clock.clone()),
+            clock,
+        }
+    }
+
+    /// Spawns the supervised tasks through `tasks`, so that shutdown stops them.
+    pub fn with_tasks(mut self, tasks: TaskRegistry) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    pub fn last_failure(&self) -> Option<TaskFailure> {
+        self.state.lock().unwrap().last_failure.clone()
+    }
+
+    /// Spawns `future`, and calls `restart` if it panics. Aborting the task through the returned handle, or stopping
+    /// it at shutdown, doesn't count as a failure.
+    pub fn spawn<F, R>(
+        &self,
+        task: &'static str,
+        generation: u64,
+        future: F,
+        restart: R,
+    ) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+        R: FnOnce() + Send + 'static,
+    {
+        let handle = self.tasks.spawn(task, future);
+        let abort_handle = handle.abort_handle();
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let Err(err) = handle.await else {
+                return;
+            };
+            if !err.is_panic() {
+                return;
+            }
+            let message = panic_message(err.into_panic());
+            *TASK_PANICS.lock().unwrap().entry(task).or_default() += 1;
+            let restarted = supervisor.allow_restart();
+            error!(
+                task,
+                generation, message, restarted, "Background task panicked."
+            );
+            supervisor.state.lock().unwrap().last_failure = Some(TaskFailure {
+                task,
+                generation,
+                message,
+                restarted,
+            });
+            if restarted {
+                restart();
+            }
+        });
+        abort_handle
+    }
+
+    /// Counts a restart, unless there were too many within the window.
+    fn allow_restart(&self) -> bool {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        while state
+            .restarts
+            .front()
+            .is_some_and(|&restart| now.duration_since(restart) >= RESTART_WINDOW)
+        {
+            state.restarts.pop_front();
+        }
+        if state.restarts.len() >= MAX_RESTARTS {
+            return false;
+        }
+        state.restarts.push_back(now);
+        true
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or(String::from("unknown panic"), |message| {
+                String::from(*message)
+            }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// Spawns a task that panics on every run, restarting it each time that the supervisor allows.
+    fn spawn_panicking(
+        supervisor: &Supervisor,
+        runs: Arc<AtomicUsize>,
+        done: mpsc::UnboundedSender<()>,
+    ) {
+        let restart = {
+            let supervisor = supervisor.clone();
+            let runs = runs.clone();
+            let done = done.clone();
+            move || spawn_panicking(&supervisor, runs, done)
+        };
+        supervisor.spawn(
+            "test",
+            runs.load(Ordering::SeqCst) as u64,
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                let _ = done.send(());
+                panic!("Oops.");
+            },
+            restart,
+        );
+    }
+
+    #[tokio::test]
+    async fn restarts_are_capped_within_the_window() {
+        let (clock, manual) = Clock::manual();
+        let supervisor = Supervisor::new(clock);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (done, mut receiver) = mpsc::unbounded_channel();
+        spawn_panicking(&supervisor, runs.clone(), done.clone());
+        for _ in 0..=MAX_RESTARTS {
+            receiver.recv().await.unwrap();
+        }
+        while supervisor
+            .last_failure()
+            .is_none_or(|failure| failure.restarted)
+        {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), MAX_RESTARTS + 1);
+        let failure = supervisor.last_failure().unwrap();
+        assert_eq!(failure.message, "Oops.");
+        assert_eq!(failure.generation, MAX_RESTARTS as u64);
+        assert!(task_panics().contains(&("test", (MAX_RESTARTS + 1) as u64)));
+
+        // Restarts are allowed again once the window has passed.
+        manual.advance(RESTART_WINDOW);
+        spawn_panicking(&supervisor, runs.clone(), done);
+        receiver.recv().await.unwrap();
+        receiver.recv().await.unwrap();
+        assert!(runs.load(Ordering::SeqCst) >= MAX_RESTARTS + 3);
+    }
+
+    #[tokio::test]
+    async fn aborted_tasks_are_not_failures() {
+        let supervisor = Supervisor::new(Clock::tokio());
+        let handle = supervisor.spawn("aborted", 0, std::future::pending(), || {
+            panic!("Aborted tasks aren't restarted.")
+        });
+        handle.abort();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(supervisor.last_failure(), None);
+    }
+}
@@ -0,0 +1,731 @@
+//! A systemd service unit that runs the current invocation, sandboxed as far as its features allow.
+//!
+//! The unit is a starting point for operators rather than something we install ourselves, so anything that it can't
+//! express safely (such as files that a dynamic user may be unable to read) comes back as a warning instead of an
+//! error.
+
+use std::{
+    ffi::OsString,
+    fmt::Write,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+/// Name of the subcommand that prints the unit, and which is left out of the unit's own command line.
+pub const SUBCOMMAND: &str = "print-systemd-unit";
+
+/// Where systemd keeps the directories from `StateDirectory=`.
+const STATE_ROOT: &str = "/var/lib";
+
+/// Where systemd keeps the directories from `RuntimeDirectory=`.
+const RUNTIME_ROOT: &str = "/run";
+
+/// Files that are loaded as credentials, so that the dynamic user never needs to read the originals.
+const IDENTITY_CREDENTIAL: CredentialOption = CredentialOption {
+    short: Some('i'),
+    long: "identity-file",
+    name: "identity",
+};
+const PASSPHRASE_CREDENTIAL: CredentialOption = CredentialOption {
+    short: None,
+    long: "passphrase-file",
+    name: "passphrase",
+};
+
+/// Directories hidden by `ProtectHome=yes`.
+const HOME_DIRS: &[&str] = &["/home", "/root", "/run/user"];
+
+/// Ports below this need `CAP_NET_BIND_SERVICE`.
+const FIRST_UNPRIVILEGED_PORT: u16 = 1024;
+
+/// What the service does, as far as its sandbox is concerned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServiceKind {
+    /// Listens on a local port.
+    Local { port: u16 },
+    /// Listens on a Unix domain socket at `path`.
+    UnixSocket { path: PathBuf },
+    /// Connects out to an SSH server with the private key in `identity_file`, or with an ssh-agent without one.
+    Ssh {
+        identity_file: Option<PathBuf>,
+        passphrase_file: Option<PathBuf>,
+    },
+}
+
+/// Everything about the invocation that ends up in the unit.
+#[derive(Clone, Debug)]
+pub struct UnitSpec {
+    /// Command line of the service, starting with the executable. See [`service_args`].
+    pub args: Vec<String>,
+    pub kind: ServiceKind,
+    /// Value of `RUST_LOG` for the service.
+    pub rust_log: String,
+    pub data_dir: Option<PathBuf>,
+    /// Other files that the service reads, such as `--extra-css`.
+    pub read_paths: Vec<PathBuf>,
+    /// Whether the service reloads anything on SIGHUP.
+    pub reloads_on_sighup: bool,
+    /// Directory that relative paths in `args` are resolved against.
+    pub working_directory: PathBuf,
+}
+
+/// The unit file, along with whatever the operator should double-check before installing it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Unit {
+    pub text: String,
+    pub warnings: Vec<String>,
+}
+
+/// Turns this process' arguments into the service's command line: the absolute `executable` followed by every
+/// argument but the [`SUBCOMMAND`].
+pub fn service_args(
+    args: impl IntoIterator<Item = OsString>,
+    executable: &Path,
+) -> Result<Vec<String>> {
+    let mut service_args = vec![executable
+        .to_str()
+        .with_context(|| {
+            format!(
+                "Executable path {} is not valid UTF-8",
+                executable.display()
+            )
+        })?
+        .to_owned()];
+    let mut skipped = false;
+    for arg in args.into_iter().skip(1) {
+        let arg = arg
+            .into_string()
+            .map_err(|arg| anyhow::anyhow!("Argument {arg:?} is not valid UTF-8"))?;
+        if !skipped && arg == SUBCOMMAND {
+            skipped = true;
+            continue;
+        }
+        service_args.push(arg);
+    }
+    Ok(service_args)
+}
+
+/// Escapes `%` specifiers, and quotes `value` if systemd would otherwise split or unescape it.
+fn quote(value: &str) -> String {
+    let value = value.replace('%', "%%");
+    let needs_quotes = value.is_empty()
+        || value == ";"
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '\\'));
+    if !needs_quotes {
+        return value;
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    write!(quoted, "\\x{byte:02x}").unwrap();
+                }
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escapes one word of `ExecStart=`, which also expands `$` variables.
+pub fn escape_arg(arg: &str) -> String {
+    quote(&arg.replace('$', "$$"))
+}
+
+/// Resolves `path` against `base` without touching the filesystem, since the paths may only exist on the target host.
+fn absolute(path: &Path, base: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in base.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => (),
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+/// An option whose file is loaded as a credential.
+struct CredentialOption {
+    short: Option<char>,
+    long: &'static str,
+    /// Name of the credential in `$CREDENTIALS_DIRECTORY`.
+    name: &'static str,
+}
+
+/// Replaces the values of `options` with the credentials that systemd loads them as. Returns the escaped command
+/// line, and whether each option was found.
+fn with_credentials(
+    args: &[String],
+    options: &[&CredentialOption],
+) -> Option<(Vec<String>, Vec<bool>)> {
+    let mut escaped = Vec::with_capacity(args.len());
+    let mut replaced = vec![false; options.len()];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut replacement = None;
+        for (option, replaced) in options.iter().zip(&mut replaced) {
+            if *replaced {
+                continue;
+            }
+            let credential = format!("${{CREDENTIALS_DIRECTORY}}/{}", option.name);
+            let long = format!("--{}", option.long);
+            let short = option.short.map(|short| format!("-{short}"));
+            if *arg == long || short.as_ref() == Some(arg) {
+                args.next()?;
+                replacement = Some(vec![escape_arg(arg), credential]);
+            } else if arg.starts_with(&format!("{long}=")) {
+                replacement = Some(vec![format!("{long}={credential}")]);
+            } else if let Some(short) =
+                short.filter(|short| arg.starts_with(short.as_str()) && !arg.starts_with("--"))
+            {
+                replacement = Some(vec![format!("{short}{credential}")]);
+            }
+            if replacement.is_some() {
+                *replaced = true;
+                break;
+            }
+        }
+        escaped.extend(replacement.unwrap_or_else(|| vec![escape_arg(arg)]));
+    }
+    Some((escaped, replaced))
+}
+
+/// Renders the unit for `spec`.
+pub fn render_unit(spec: &UnitSpec) -> Unit {
+    let mut warnings = Vec::new();
+    let base = &spec.working_directory;
+    let mut relative = spec
+        .data_dir
+        .iter()
+        .chain(&spec.read_paths)
+        .any(|path| path.is_relative());
+
+    let mut exec_start = spec
+        .args
+        .iter()
+        .map(|arg| escape_arg(arg))
+        .collect::<Vec<_>>();
+    let mut credentials = Vec::new();
+    if let ServiceKind::Ssh {
+        identity_file,
+        passphrase_file,
+    } = &spec.kind
+    {
+        // `-` reads the key from stdin, which isn't a file to load.
+        let identity_file = identity_file
+            .as_ref()
+            .filter(|path| path.as_path() != Path::new("-"));
+        match identity_file {
+            Some(_) => (),
+            None if matches!(&spec.kind, ServiceKind::Ssh { identity_file: Some(_), .. }) => {
+                warnings.push(String::from(
+                    "With --identity-file -, the service reads its private key from stdin, which it doesn't get. \
+                     Set SSH_PRIVATE_KEY in its environment instead, or use an identity file.",
+                ))
+            }
+            None => warnings.push(String::from(
+                "Without --identity-file, the service authenticates with SSH_PRIVATE_KEY or an ssh-agent. Set either \
+                 in its environment, SSH_AUTH_SOCK to a socket that the dynamic user can reach, or use an identity \
+                 file instead.",
+            )),
+        }
+        let files = [
+            (&IDENTITY_CREDENTIAL, identity_file),
+            (&PASSPHRASE_CREDENTIAL, passphrase_file.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(option, path)| Some((option, path?)))
+        .collect::<Vec<_>>();
+        let options = files.iter().map(|&(option, _)| option).collect::<Vec<_>>();
+        let (args, replaced) = with_credentials(&spec.args, &options)
+            .unwrap_or_else(|| (exec_start.clone(), vec![false; files.len()]));
+        exec_start = args;
+        for ((option, path), replaced) in files.into_iter().zip(replaced) {
+            relative |= path.is_relative();
+            if replaced {
+                credentials.push((option.name, absolute(path, base)));
+            } else {
+                warnings.push(format!(
+                    "Couldn't find --{} in the command line, so the dynamic user must be able to read it.",
+                    option.long
+                ));
+            }
+        }
+    }
+
+    let data_dir = spec.data_dir.as_deref().map(|path| absolute(path, base));
+    let state_directory = data_dir.as_deref().and_then(|path| {
+        let name = path.strip_prefix(STATE_ROOT).ok()?.to_str()?;
+        (!name.is_empty() && !name.contains(char::is_whitespace)).then(|| name.to_owned())
+    });
+    let mut read_write_paths = Vec::new();
+    if let (Some(data_dir), None) = (&data_dir, &state_directory) {
+        warnings.push(format!(
+            "--data-dir {} is outside {STATE_ROOT}, so systemd won't hand it over to the dynamic user. Use a \
+             directory under {STATE_ROOT} instead, or make sure that it's writable by any user.",
+            data_dir.display()
+        ));
+        read_write_paths.push(data_dir.clone());
+    }
+    let mut runtime_directory = None;
+    if let ServiceKind::UnixSocket { path } = &spec.kind {
+        relative |= path.is_relative();
+        let path = absolute(path, base);
+        let parent = path.parent().unwrap_or(Path::new("/"));
+        runtime_directory = parent.strip_prefix(RUNTIME_ROOT).ok().and_then(|name| {
+            let name = name.to_str()?;
+            (!name.is_empty() && !name.contains(char::is_whitespace)).then(|| name.to_owned())
+        });
+        if runtime_directory.is_none() {
+            warnings.push(format!(
+                "--unix-socket {} isn't in a directory under {RUNTIME_ROOT}, so systemd won't make one for the \
+                 dynamic user. Use a path like {RUNTIME_ROOT}/{}/http.sock instead, or make sure that its directory \
+                 is writable by any user.",
+                path.display(),
+                env!("CARGO_PKG_NAME"),
+            ));
+            read_write_paths.push(parent.to_owned());
+        }
+        warnings.push(String::from(
+            "The socket belongs to the dynamic user and group, with mode 0660. Set Group= to a group of the reverse \
+             proxy, such as www-data, so that it can connect.",
+        ));
+    }
+    let mut protect_home = "yes";
+    for path in &spec.read_paths {
+        let path = absolute(path, base);
+        if data_dir
+            .as_deref()
+            .is_some_and(|data_dir| path.starts_with(data_dir))
+        {
+            continue;
+        }
+        warnings.push(format!(
+            "{} is outside the state directory, so it must be readable by any user.",
+            path.display()
+        ));
+        if HOME_DIRS.iter().any(|home| path.starts_with(home)) {
+            protect_home = "read-only";
+        }
+    }
+
+    let mut text = String::new();
+    let (description, after) = match &spec.kind {
+        ServiceKind::Local { .. } | ServiceKind::UnixSocket { .. } => {
+            ("local server", "network.target")
+        }
+        ServiceKind::Ssh { .. } => ("SSH tunnel", "network-online.target"),
+    };
+    writeln!(text, "[Unit]").unwrap();
+    writeln!(
+        text,
+        "Description={} ({description})",
+        env!("CARGO_PKG_NAME")
+    )
+    .unwrap();
+    writeln!(text, "After={after}").unwrap();
+    if let ServiceKind::Ssh { .. } = spec.kind {
+        writeln!(text, "Wants={after}").unwrap();
+    }
+    writeln!(text).unwrap();
+
+    writeln!(text, "[Service]").unwrap();
+    writeln!(text, "Type=exec").unwrap();
+    writeln!(text, "ExecStart={}", exec_start.join(" ")).unwrap();
+    if spec.reloads_on_sighup {
+        writeln!(text, "ExecReload=/bin/kill -HUP $MAINPID").unwrap();
+    }
+    // Ctrl-C is what runs the shutdown hooks, which persist the boards.
+    writeln!(text, "KillSignal=SIGINT").unwrap();
+    writeln!(text, "Restart=on-failure").unwrap();
+    writeln!(
+        text,
+        "Environment={}",
+        quote(&format!("RUST_LOG={}", spec.rust_log))
+    )
+    .unwrap();
+    if relative {
+        writeln!(
+            text,
+            "WorkingDirectory={}",
+            base.display().to_string().replace('%', "%%")
+        )
+        .unwrap();
+    }
+    if let Some(name) = &state_directory {
+        writeln!(text, "StateDirectory={}", quote(name)).unwrap();
+    }
+    if let Some(name) = &runtime_directory {
+        writeln!(text, "RuntimeDirectory={}", quote(name)).unwrap();
+    }
+    for (name, path) in &credentials {
+        writeln!(
+            text,
+            "LoadCredential={name}:{}",
+            path.display().to_string().replace('%', "%%")
+        )
+        .unwrap();
+    }
+    writeln!(text).unwrap();
+
+    writeln!(text, "DynamicUser=yes").unwrap();
+    writeln!(text, "NoNewPrivileges=yes").unwrap();
+    writeln!(text, "ProtectSystem=strict").unwrap();
+    writeln!(text, "ProtectHome={protect_home}").unwrap();
+    for path in &read_write_paths {
+        writeln!(
+            text,
+            "ReadWritePaths={}",
+            quote(&path.display().to_string())
+        )
+        .unwrap();
+    }
+    writeln!(text, "PrivateTmp=yes").unwrap();
+    writeln!(text, "PrivateDevices=yes").unwrap();
+    writeln!(text, "ProtectKernelTunables=yes").unwrap();
+    writeln!(text, "ProtectKernelModules=yes").unwrap();
+    writeln!(text, "ProtectControlGroups=yes").unwrap();
+    writeln!(text, "RestrictNamespaces=yes").unwrap();
+    writeln!(text, "RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX").unwrap();
+    writeln!(text, "LockPersonality=yes").unwrap();
+    writeln!(text, "MemoryDenyWriteExecute=yes").unwrap();
+    writeln!(text, "SystemCallArchitectures=native").unwrap();
+    match spec.kind {
+        ServiceKind::Local { port } if port < FIRST_UNPRIVILEGED_PORT => {
+            writeln!(text, "AmbientCapabilities=CAP_NET_BIND_SERVICE").unwrap();
+            writeln!(text, "CapabilityBoundingSet=CAP_NET_BIND_SERVICE").unwrap();
+        }
+        _ => writeln!(text, "CapabilityBoundingSet=").unwrap(),
+    }
+    writeln!(text).unwrap();
+
+    writeln!(text, "[Install]").unwrap();
+    writeln!(text, "WantedBy=multi-user.target").unwrap();
+    Unit { text, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(args: &[&str], kind: ServiceKind) -> UnitSpec {
+        UnitSpec {
+            args: args.iter().map(|&arg| String::from(arg)).collect(),
+            kind,
+            rust_log: String::from("info"),
+            data_dir: None,
+            read_paths: Vec::new(),
+            reloads_on_sighup: false,
+            working_directory: PathBuf::from("/srv/games"),
+        }
+    }
+
+    fn directive<'a>(unit: &'a Unit, key: &str) -> Option<&'a str> {
+        unit.text
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+    }
+
+    #[test]
+    fn arguments_are_escaped() {
+        assert_eq!(escape_arg("--port"), "--port");
+        assert_eq!(escape_arg("two words"), r#""two words""#);
+        assert_eq!(escape_arg(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(escape_arg("it's"), r#""it's""#);
+        assert_eq!(escape_arg(r"C:\games"), r#""C:\\games""#);
+        assert_eq!(escape_arg(""), r#""""#);
+        assert_eq!(escape_arg(";"), r#"";""#);
+        assert_eq!(escape_arg("100%"), "100%%");
+        assert_eq!(escape_arg("$HOME"), "$$HOME");
+        assert_eq!(escape_arg("a\nb"), r#""a\nb""#);
+    }
+
+    #[test]
+    fn the_subcommand_is_left_out() {
+        let args = [
+            "/tmp/game",
+            "multipaint",
+            SUBCOMMAND,
+            "local-server",
+            "--title",
+            "My game",
+        ]
+        .map(OsString::from);
+        assert_eq!(
+            service_args(args, Path::new("/usr/bin/htmx-ssh-games")).unwrap(),
+            [
+                "/usr/bin/htmx-ssh-games",
+                "multipaint",
+                "local-server",
+                "--title",
+                "My game"
+            ]
+        );
+    }
+
+    #[test]
+    fn exec_start_reflects_the_invocation() {
+        let mut spec = spec(
+            &[
+                "/usr/bin/game",
+                "local-server",
+                "--banner-duration",
+                "1h",
+                "--title",
+                r#"The "best" game"#,
+            ],
+            ServiceKind::Local { port: 5023 },
+        );
+        spec.rust_log = String::from("htmx_ssh_games=debug,info");
+        let unit = render_unit(&spec);
+        assert_eq!(
+            directive(&unit, "ExecStart"),
+            Some(r#"/usr/bin/game local-server --banner-duration 1h --title "The \"best\" game""#)
+        );
+        assert_eq!(
+            directive(&unit, "Environment"),
+            Some("RUST_LOG=htmx_ssh_games=debug,info")
+        );
+        assert_eq!(directive(&unit, "KillSignal"), Some("SIGINT"));
+        assert_eq!(directive(&unit, "ExecReload"), None);
+        assert!(unit.warnings.is_empty());
+    }
+
+    #[test]
+    fn local_servers_listen_without_credentials() {
+        let unit = render_unit(&spec(
+            &["/usr/bin/game", "local-server"],
+            ServiceKind::Local { port: 5023 },
+        ));
+        assert_eq!(directive(&unit, "After"), Some("network.target"));
+        assert_eq!(directive(&unit, "Wants"), None);
+        assert_eq!(directive(&unit, "LoadCredential"), None);
+        assert_eq!(directive(&unit, "CapabilityBoundingSet"), Some(""));
+        assert_eq!(directive(&unit, "AmbientCapabilities"), None);
+        assert_eq!(directive(&unit, "DynamicUser"), Some("yes"));
+        assert_eq!(directive(&unit, "NoNewPrivileges"), Some("yes"));
+        assert_eq!(directive(&unit, "ProtectSystem"), Some("strict"));
+
+        let unit = render_unit(&spec(
+            &["/usr/bin/game", "local-server", "-p", "80"],
+            ServiceKind::Local { port: 80 },
+        ));
+        assert_eq!(
+            directive(&unit, "AmbientCapabilities"),
+            Some("CAP_NET_BIND_SERVICE")
+        );
+        assert_eq!(
+            directive(&unit, "CapabilityBoundingSet"),
+            Some("CAP_NET_BIND_SERVICE")
+        );
+    }
+
+    #[test]
+    fn ssh_tunnels_load_the_identity_as_a_credential() {
+        let kind = ServiceKind::Ssh {
+            identity_file: Some(PathBuf::from("keys/id ed25519")),
+            passphrase_file: None,
+        };
+        let unit = render_unit(&spec(
+            &[
+                "/usr/bin/game",
+                "ssh",
+                "example.com",
+                "-i",
+                "keys/id ed25519",
+            ],
+            kind.clone(),
+        ));
+        assert_eq!(directive(&unit, "After"), Some("network-online.target"));
+        assert_eq!(directive(&unit, "Wants"), Some("network-online.target"));
+        assert_eq!(
+            directive(&unit, "ExecStart"),
+            Some("/usr/bin/game ssh example.com -i ${CREDENTIALS_DIRECTORY}/identity")
+        );
+        assert_eq!(
+            directive(&unit, "LoadCredential"),
+            Some("identity:/srv/games/keys/id ed25519")
+        );
+        assert_eq!(directive(&unit, "WorkingDirectory"), Some("/srv/games"));
+        assert_eq!(directive(&unit, "CapabilityBoundingSet"), Some(""));
+        assert!(unit.warnings.is_empty());
+
+        for (arg, expected) in [
+            (
+                "--identity-file=/keys/id",
+                "--identity-file=${CREDENTIALS_DIRECTORY}/identity",
+            ),
+            ("-i/keys/id", "-i${CREDENTIALS_DIRECTORY}/identity"),
+        ] {
+            let unit = render_unit(&spec(
+                &["/usr/bin/game", "ssh", "example.com", arg],
+                kind.clone(),
+            ));
+            assert_eq!(
+                directive(&unit, "ExecStart"),
+                Some(format!("/usr/bin/game ssh example.com {expected}").as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn passphrase_files_are_credentials_too() {
+        let unit = render_unit(&spec(
+            &[
+                "/usr/bin/game",
+                "ssh",
+                "example.com",
+                "--passphrase-file",
+                "/keys/passphrase",
+                "-i",
+                "/keys/id",
+            ],
+            ServiceKind::Ssh {
+                identity_file: Some(PathBuf::from("/keys/id")),
+                passphrase_file: Some(PathBuf::from("/keys/passphrase")),
+            },
+        ));
+        assert_eq!(
+            directive(&unit, "ExecStart"),
+            Some(
+                "/usr/bin/game ssh example.com --passphrase-file ${CREDENTIALS_DIRECTORY}/passphrase -i \
+                 ${CREDENTIALS_DIRECTORY}/identity"
+            )
+        );
+        assert!(unit.text.contains("LoadCredential=identity:/keys/id\n"));
+        assert!(unit
+            .text
+            .contains("LoadCredential=passphrase:/keys/passphrase\n"));
+        assert!(unit.warnings.is_empty());
+    }
+
+    #[test]
+    fn keys_on_stdin_are_not_credentials() {
+        let unit = render_unit(&spec(
+            &["/usr/bin/game", "ssh", "example.com", "-i", "-"],
+            ServiceKind::Ssh {
+                identity_file: Some(PathBuf::from("-")),
+                passphrase_file: None,
+            },
+        ));
+        assert_eq!(
+            directive(&unit, "ExecStart"),
+            Some("/usr/bin/game ssh example.com -i -")
+        );
+        assert_eq!(directive(&unit, "LoadCredential"), None);
+        assert_eq!(unit.warnings.len(), 1);
+        assert!(unit.warnings[0].contains("SSH_PRIVATE_KEY"));
+    }
+
+    #[test]
+    fn ssh_agents_need_a_reachable_socket() {
+        let unit = render_unit(&spec(
+            &["/usr/bin/game", "ssh", "example.com", "--use-agent"],
+            ServiceKind::Ssh {
+                identity_file: None,
+                passphrase_file: None,
+            },
+        ));
+        assert_eq!(
+            directive(&unit, "ExecStart"),
+            Some("/usr/bin/game ssh example.com --use-agent")
+        );
+        assert_eq!(directive(&unit, "LoadCredential"), None);
+        assert_eq!(unit.warnings.len(), 1);
+        assert!(unit.warnings[0].contains("SSH_AUTH_SOCK"));
+    }
+
+    #[test]
+    fn the_data_dir_becomes_the_state_directory() {
+        let mut spec = spec(
+            &["/usr/bin/game", "local-server"],
+            ServiceKind::Local { port: 5023 },
+        );
+        spec.data_dir = Some(PathBuf::from("/var/lib/htmx-ssh-games"));
+        spec.read_paths = vec![
+            PathBuf::from("/var/lib/htmx-ssh-games/extra.css"),
+            PathBuf::from("/home/operator/extra.js"),
+        ];
+        spec.reloads_on_sighup = true;
+        let unit = render_unit(&spec);
+        assert_eq!(directive(&unit, "StateDirectory"), Some("htmx-ssh-games"));
+        assert_eq!(directive(&unit, "ReadWritePaths"), None);
+        assert_eq!(directive(&unit, "ProtectHome"), Some("read-only"));
+        assert_eq!(directive(&unit, "WorkingDirectory"), None);
+        assert_eq!(
+            directive(&unit, "ExecReload"),
+            Some("/bin/kill -HUP $MAINPID")
+        );
+        assert_eq!(unit.warnings.len(), 1);
+        assert!(unit.warnings[0].contains("/home/operator/extra.js"));
+    }
+
+    #[test]
+    fn data_dirs_elsewhere_are_warned_about() {
+        let mut spec = spec(
+            &["/usr/bin/game", "local-server"],
+            ServiceKind::Local { port: 5023 },
+        );
+        spec.data_dir = Some(PathBuf::from("../state dir"));
+        let unit = render_unit(&spec);
+        assert_eq!(directive(&unit, "StateDirectory"), None);
+        assert_eq!(
+            directive(&unit, "ReadWritePaths"),
+            Some(r#""/srv/state dir""#)
+        );
+        assert_eq!(directive(&unit, "ProtectHome"), Some("yes"));
+        assert_eq!(directive(&unit, "WorkingDirectory"), Some("/srv/games"));
+        assert_eq!(unit.warnings.len(), 1);
+        assert!(unit.warnings[0].contains("outside /var/lib"));
+    }
+
+    #[test]
+    fn unix_sockets_get_a_runtime_directory() {
+        let unit = render_unit(&spec(
+            &[
+                "/usr/bin/game",
+                "local-server",
+                "--unix-socket",
+                "/run/games/http.sock",
+            ],
+            ServiceKind::UnixSocket {
+                path: PathBuf::from("/run/games/http.sock"),
+            },
+        ));
+        assert_eq!(directive(&unit, "RuntimeDirectory"), Some("games"));
+        assert_eq!(directive(&unit, "ReadWritePaths"), None);
+        assert_eq!(directive(&unit, "CapabilityBoundingSet"), Some(""));
+        assert_eq!(unit.warnings.len(), 1);
+        assert!(unit.warnings[0].contains("Group="));
+
+        let unit = render_unit(&spec(
+            &[
+                "/usr/bin/game",
+                "local-server",
+                "--unix-socket",
+                "/run/games.sock",
+            ],
+            ServiceKind::UnixSocket {
+                path: PathBuf::from("/run/games.sock"),
+            },
+        ));
+        assert_eq!(directive(&unit, "RuntimeDirectory"), None);
+        assert_eq!(directive(&unit, "ReadWritePaths"), Some("/run"));
+        assert_eq!(unit.warnings.len(), 2);
+        assert!(unit.warnings[0].contains("/run/htmx-ssh-games/http.sock"));
+    }
+}
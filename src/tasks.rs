@@ -0,0 +1,244 @@
+//! Background tasks that shutdown can wait for, so that a write in progress isn't cut short when the process exits.
+//!
+//! Every task is spawned with a name, and with a [`CancellationToken`] that shutdown cancels. Tasks that don't stop
+//! within the timeout are aborted and reported, instead of holding the process hostage.
+
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{mpsc, watch},
+    task::{AbortHandle, JoinHandle},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::clock::Clock;
+
+/// Longest that shutdown waits for background tasks to stop before abandoning them.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct LiveTask {
+    name: &'static str,
+    started: Instant,
+    /// Only missing until the task is spawned.
+    abort_handle: Option<AbortHandle>,
+}
+
+#[derive(Debug, Default)]
+struct LiveTasks {
+    next_id: u64,
+    tasks: BTreeMap<u64, LiveTask>,
+}
+
+/// Tracks the application's background tasks, from spawning to shutdown. Shared by every clone.
+#[derive(Clone, Debug)]
+pub struct TaskRegistry {
+    live: Arc<watch::Sender<LiveTasks>>,
+    token: CancellationToken,
+    clock: Clock,
+}
+
+impl Default for TaskRegistry {
+    fn default() -> Self {
+        TaskRegistry::new(Clock::default())
+    }
+}
+
+impl TaskRegistry {
+    pub fn new(clock: Clock) -> Self {
+        TaskRegistry {
+            live: Arc::new(watch::Sender::new(LiveTasks::default())),
+            token: CancellationToken::new(),
+            clock,
+        }
+    }
+
+    /// Spawns the future returned by `task`, which should finish its work and return soon after the token is
+    /// cancelled.
+    pub fn spawn_cooperative<T, F>(&self, name: &'static str, task: T) -> JoinHandle<()>
+    where
+        T: FnOnce(CancellationToken) -> F,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let future = task(self.token.clone());
+        // Registered before spawning, so that shutdown can't miss a task which is just starting.
+        let mut id = 0;
+        let started = self.clock.now();
+        self.live.send_modify(|live| {
+            id = live.next_id;
+            live.next_id += 1;
+            live.tasks.insert(
+                id,
+                LiveTask {
+                    name,
+                    started,
+                    abort_handle: None,
+                },
+            );
+        });
+        let guard = TaskGuard {
+            live: Arc::clone(&self.live),
+            id,
+        };
+        let handle = tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        });
+        let abort_handle = handle.abort_handle();
+        self.live.send_if_modified(|live| {
+            if let Some(task) = live.tasks.get_mut(&id) {
+                task.abort_handle = Some(abort_handle);
+            }
+            false
+        });
+        handle
+    }
+
+    /// Spawns `future`, which is simply dropped at its next await point once the token is cancelled. For tasks with
+    /// nothing to finish, such as periodic loops.
+    pub fn spawn<F>(&self, name: &'static str, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn_cooperative(name, |token| async move {
+            tokio::select! {
+                () = token.cancelled() => (),
+                () = future => (),
+            }
+        })
+    }
+
+    /// Names and ages of the tasks still running, oldest first.
+    pub fn live(&self) -> Vec<(&'static str, Duration)> {
+        let now = self.clock.now();
+        self.live
+            .borrow()
+            .tasks
+            .values()
+            .map(|task| (task.name, now.saturating_duration_since(task.started)))
+            .collect()
+    }
+
+    /// Cancels every task, and waits up to `timeout` for them to stop. Tasks still running by then are aborted, and
+    /// their names returned.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<&'static str> {
+        self.token.cancel();
+        let mut receiver = self.live.subscribe();
+        tokio::select! {
+            _ = receiver.wait_for(|live| live.tasks.is_empty()) => Vec::new(),
+            () = self.clock.sleep(timeout) => {
+                let mut abandoned = BTreeMap::new();
+                self.live.send_modify(|live| abandoned = std::mem::take(&mut live.tasks));
+                let names = abandoned.values().map(|task| task.name).collect::<Vec<_>>();
+                for task in abandoned.into_values() {
+                    if let Some(abort_handle) = task.abort_handle {
+                        abort_handle.abort();
+                    }
+                }
+                warn!(tasks = ?names, "Abandoned background tasks that didn't stop in time.");
+                names
+            }
+        }
+    }
+}
+
+/// Unregisters a task once it finishes, panics or is aborted.
+struct TaskGuard {
+    live: Arc<watch::Sender<LiveTasks>>,
+    id: u64,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.live
+            .send_if_modified(|live| live.tasks.remove(&self.id).is_some());
+    }
+}
+
+/// Receives the next value, until every sender is gone. Once `token` is cancelled, stops accepting new values but
+/// still returns the ones already sent, so that writers lose nothing at shutdown.
+pub async fn recv_or_drain<T>(
+    receiver: &mut mpsc::UnboundedReceiver<T>,
+    token: &CancellationToken,
+) -> Option<T> {
+    if !token.is_cancelled() {
+        tokio::select! {
+            value = receiver.recv() => return value,
+            () = token.cancelled() => (),
+        }
+    }
+    receiver.close();
+    receiver.recv().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_waits_for_cooperative_tasks() {
+        let (clock, manual) = Clock::manual();
+        let tasks = TaskRegistry::new(clock);
+        let finished = Arc::new(AtomicBool::new(false));
+        tasks.spawn_cooperative("writer", {
+            let finished = Arc::clone(&finished);
+            |token| async move {
+                token.cancelled().await;
+                // Some last work after being asked to stop.
+                tokio::task::yield_now().await;
+                finished.store(true, Ordering::SeqCst);
+            }
+        });
+        tasks.spawn("loop", std::future::pending());
+        manual.advance(Duration::from_secs(5));
+        assert_eq!(
+            tasks.live(),
+            [
+                ("writer", Duration::from_secs(5)),
+                ("loop", Duration::from_secs(5))
+            ]
+        );
+
+        assert!(tasks.shutdown(SHUTDOWN_TIMEOUT).await.is_empty());
+        assert!(finished.load(Ordering::SeqCst));
+        assert!(tasks.live().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shutdown_abandons_stuck_tasks() {
+        let (clock, manual) = Clock::manual();
+        let tasks = TaskRegistry::new(clock);
+        let stuck = tasks.spawn_cooperative("stuck", |_| std::future::pending());
+        tasks.spawn_cooperative("quick", |token| async move { token.cancelled().await });
+
+        let shutdown = tokio::spawn({
+            let tasks = tasks.clone();
+            async move { tasks.shutdown(SHUTDOWN_TIMEOUT).await }
+        });
+        while manual.sleepers() == 0 || tasks.live().len() > 1 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(tasks.live()[0].0, "stuck");
+        manual.advance(SHUTDOWN_TIMEOUT);
+        assert_eq!(shutdown.await.unwrap(), ["stuck"]);
+        assert!(stuck.await.unwrap_err().is_cancelled());
+        assert!(tasks.live().is_empty());
+    }
+
+    #[tokio::test]
+    async fn draining_keeps_what_was_sent() {
+        let token = CancellationToken::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender.send(1).unwrap();
+        assert_eq!(recv_or_drain(&mut receiver, &token).await, Some(1));
+        sender.send(2).unwrap();
+        token.cancel();
+        assert_eq!(recv_or_drain(&mut receiver, &token).await, Some(2));
+        assert!(sender.send(3).is_err());
+        assert_eq!(recv_or_drain(&mut receiver, &token).await, None);
+    }
+}
@@ -0,0 +1,531 @@
+//! Serves the checkboxes over SSH as a terminal UI, for players on networks that let SSH through but not the web.
+//!
+//! Any SSH client can connect, with whatever credentials. Each shell session gets the board drawn with ANSI escape
+//! sequences, and a cursor to move around with the arrow keys. The board is the same [`CheckboxBoard`] that the HTTP
+//! handlers use, so players on either side see each other's moves at the next redraw.
+
+use std::{
+    fmt::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use russh::{
+    keys::{
+        key::{KeyPair, PublicKey},
+        load_secret_key,
+    },
+    server::{self, Auth, Msg, Session},
+    Channel, ChannelId, CryptoVec,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::{
+    clock::Clock,
+    handoff::Drain,
+    http::{
+        checkbox::{Cell, CheckboxBoard, CHECKBOX_HEIGHT, CHECKBOX_WIDTH},
+        maintenance::{self, MaintenanceMode},
+    },
+};
+
+/// How often the board is checked for other players' moves.
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Switches to the alternate screen and hides the cursor, as full-screen programs do.
+const ENTER_SCREEN: &str = "\x1b[?1049h\x1b[?25l";
+/// Undoes [`ENTER_SCREEN`].
+const LEAVE_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+
+/// Loads the server's host key from an OpenSSH private key file, which must not be encrypted.
+pub fn load_host_key(path: &Path) -> Result<KeyPair> {
+    load_secret_key(path, None)
+        .with_context(|| format!("Unable to read the host key {}.", path.display()))
+}
+
+/// Serves the terminal UI on `hostname:port`, with `host_key` as the server's identity.
+///
+/// Returns once a shutdown is requested through `drain`, after closing the open sessions.
+pub async fn tui_entrypoint(
+    hostname: &str,
+    port: u16,
+    host_key: KeyPair,
+    board: CheckboxBoard,
+    maintenance: MaintenanceMode,
+    drain: Drain,
+    clock: Clock,
+) -> Result<()> {
+    let listener = TcpListener::bind((hostname, port))
+        .await
+        .with_context(|| "Failed to bind the SSH listener")?;
+    println!("Listening on ssh://{}:{}", hostname, port);
+    let config = Arc::new(server::Config {
+        keys: vec![host_key],
+        // Nobody needs credentials, so there's nothing to slow down guessing for.
+        auth_rejection_time: Duration::ZERO,
+        ..Default::default()
+    });
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                // Such as running out of file descriptors, which may pass once some connections close.
+                Err(e) => {
+                    warn!(error = %e, "Unable to accept an SSH connection.");
+                    clock.sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = drain.handoff_requested() => break,
+        };
+        let Ok(guard) = drain.begin_connection() else {
+            continue;
+        };
+        let session = TuiSession {
+            board: board.clone(),
+            maintenance: maintenance.clone(),
+            clock: clock.clone(),
+            drain: drain.clone(),
+            screen: Arc::default(),
+            redraws: None,
+        };
+        let config = Arc::clone(&config);
+        tokio::spawn(
+            async move {
+                let _guard = guard;
+                let result = match server::run_stream(config, stream, session).await {
+                    Ok(running) => running.await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    debug!(err = %format_args!("{err:#}"), "SSH session ended with an error.");
+                }
+            }
+            .instrument(info_span!("tui", peer = %peer)),
+        );
+    }
+    drain.drained(drain.timeout(), &clock).await;
+    Ok(())
+}
+
+/// What one player sees.
+#[derive(Default)]
+struct Screen {
+    /// Index of the cell under the cursor.
+    cursor: usize,
+    /// Why the last key didn't do anything, until the next one.
+    notice: Option<&'static str>,
+    /// What was last sent, so that unchanged frames aren't sent again.
+    last_frame: String,
+}
+
+struct TuiSession {
+    board: CheckboxBoard,
+    maintenance: MaintenanceMode,
+    clock: Clock,
+    drain: Drain,
+    screen: Arc<Mutex<Screen>>,
+    /// Redraws the board for other players' moves while the shell is open.
+    redraws: Option<JoinHandle<()>>,
+}
+
+impl Drop for TuiSession {
+    fn drop(&mut self) {
+        if let Some(redraws) = self.redraws.take() {
+            redraws.abort();
+        }
+    }
+}
+
+impl TuiSession {
+    /// Applies a key, and returns whether the player is done.
+    fn press(&self, key: Key) -> bool {
+        let mut screen = self.screen.lock().unwrap();
+        screen.notice = None;
+        let (row, column) = (
+            screen.cursor / CHECKBOX_WIDTH,
+            screen.cursor % CHECKBOX_WIDTH,
+        );
+        screen.cursor = match key {
+            Key::Up => row.saturating_sub(1) * CHECKBOX_WIDTH + column,
+            Key::Down => (row + 1).min(CHECKBOX_HEIGHT - 1) * CHECKBOX_WIDTH + column,
+            Key::Left => row * CHECKBOX_WIDTH + column.saturating_sub(1),
+            Key::Right => row * CHECKBOX_WIDTH + (column + 1).min(CHECKBOX_WIDTH - 1),
+            Key::Toggle => {
+                if self.maintenance.is_on() {
+                    screen.notice = Some(maintenance::NOTICE);
+                } else if let Some(cell) = self.board.get(screen.cursor) {
+                    if self.board.set(screen.cursor, !cell.checked) == Some(cell) {
+                        screen.notice = Some("This checkbox is locked.");
+                    }
+                }
+                screen.cursor
+            }
+            Key::Quit => return true,
+        };
+        false
+    }
+}
+
+/// Draws the board for `screen`, and returns the frame unless it's what was last sent.
+fn next_frame(
+    board: &CheckboxBoard,
+    maintenance: &MaintenanceMode,
+    screen: &Mutex<Screen>,
+) -> Option<String> {
+    let mut screen = screen.lock().unwrap();
+    let frame = render_frame(
+        &board.cells(),
+        screen.cursor,
+        screen
+            .notice
+            .or(maintenance.is_on().then_some(maintenance::NOTICE)),
+    );
+    (frame != screen.last_frame).then(|| {
+        screen.last_frame.clone_from(&frame);
+        frame
+    })
+}
+
+/// Draws the whole screen from its top-left corner, clearing what's left of every line.
+fn render_frame(cells: &[Cell], cursor: usize, notice: Option<&str>) -> String {
+    let mut frame = String::from("\x1b[H");
+    let checked = cells.iter().filter(|cell| cell.checked).count();
+    write!(
+        frame,
+        "{} Checkboxes, {checked} checked\x1b[K\r\n\x1b[K\r\n",
+        cells.len()
+    )
+    .unwrap();
+    for (row, cells) in cells.chunks(CHECKBOX_WIDTH).enumerate() {
+        for (column, cell) in cells.iter().enumerate() {
+            let mark = if cell.checked { "[x]" } else { "[ ]" };
+            // Reverse video for the cursor, and faint for locked cells.
+            let style = match (row * CHECKBOX_WIDTH + column == cursor, cell.locked) {
+                (true, true) => "\x1b[2;7m",
+                (true, false) => "\x1b[7m",
+                (false, true) => "\x1b[2m",
+                (false, false) => "",
+            };
+            if style.is_empty() {
+                frame.push_str(mark);
+            } else {
+                write!(frame, "{style}{mark}\x1b[0m").unwrap();
+            }
+        }
+        frame.push_str("\x1b[K\r\n");
+    }
+    frame.push_str("\x1b[K\r\nArrow keys or hjkl to move, space to toggle, q to quit.\x1b[K\r\n");
+    frame.push_str(notice.unwrap_or_default());
+    frame.push_str("\x1b[K");
+    frame
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Toggle,
+    Quit,
+}
+
+/// Picks out the keys that the UI knows about from terminal input, skipping anything else.
+fn parse_keys(mut input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    while let Some((&byte, rest)) = input.split_first() {
+        input = rest;
+        let key = match byte {
+            // Arrow keys, in either cursor key mode.
+            0x1b => match input {
+                [b'[' | b'O', arrow @ b'A'..=b'D', rest @ ..] => {
+                    input = rest;
+                    match arrow {
+                        b'A' => Key::Up,
+                        b'B' => Key::Down,
+                        b'C' => Key::Right,
+                        _ => Key::Left,
+                    }
+                }
+                _ => continue,
+            },
+            b'k' => Key::Up,
+            b'j' => Key::Down,
+            b'h' => Key::Left,
+            b'l' => Key::Right,
+            b' ' | b'\r' | b'x' => Key::Toggle,
+            // Ctrl-C and Ctrl-D as well, since the client's terminal is raw.
+            b'q' | 0x03 | 0x04 => Key::Quit,
+            _ => continue,
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+#[async_trait]
+impl server::Handler for TuiSession {
+    type Error = anyhow::Error;
+
+    async fn auth_none(&mut self, _user: &str) -> Result<Auth, Self::Error> {
+        Ok(Auth::Accept)
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        _user: &str,
+        _public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        Ok(Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        _channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        // One board per connection.
+        Ok(self.redraws.is_none())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(russh::Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel);
+        info!("Player joined over SSH.");
+        let mut first_frame = String::from(ENTER_SCREEN);
+        first_frame.push_str("\x1b[2J");
+        first_frame.extend(next_frame(&self.board, &self.maintenance, &self.screen));
+        session.data(channel, CryptoVec::from(first_frame));
+        let handle = session.handle();
+        let (board, maintenance, clock, drain, screen) = (
+            self.board.clone(),
+            self.maintenance.clone(),
+            self.clock.clone(),
+            self.drain.clone(),
+            Arc::clone(&self.screen),
+        );
+        self.redraws = Some(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    () = clock.sleep(REDRAW_INTERVAL) => (),
+                    () = drain.handoff_requested() => {
+                        let goodbye = format!("{LEAVE_SCREEN}The server is going away, see you soon!\r\n");
+                        let _ = handle.data(channel, CryptoVec::from(goodbye)).await;
+                        let _ = handle.close(channel).await;
+                        break;
+                    }
+                }
+                if let Some(frame) = next_frame(&board, &maintenance, &screen) {
+                    if handle.data(channel, CryptoVec::from(frame)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        for key in parse_keys(data) {
+            if self.press(key) {
+                if let Some(redraws) = self.redraws.take() {
+                    redraws.abort();
+                }
+                session.data(channel, CryptoVec::from_slice(LEAVE_SCREEN.as_bytes()));
+                session.exit_status_request(channel, 0);
+                session.eof(channel);
+                session.close(channel);
+                info!("Player left over SSH.");
+                return Ok(());
+            }
+        }
+        if let Some(frame) = next_frame(&self.board, &self.maintenance, &self.screen) {
+            session.data(channel, CryptoVec::from(frame));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::{client, ChannelMsg};
+
+    #[test]
+    fn keys_are_picked_out_of_terminal_input() {
+        assert_eq!(
+            parse_keys(b"\x1b[A\x1bOBhl \x1b[Zq"),
+            [
+                Key::Up,
+                Key::Down,
+                Key::Left,
+                Key::Right,
+                Key::Toggle,
+                Key::Quit
+            ]
+        );
+        assert_eq!(parse_keys(b"\x1b\x03"), [Key::Quit]);
+    }
+
+    #[test]
+    fn the_cursor_stays_on_the_board() {
+        let session = TuiSession {
+            board: CheckboxBoard::default(),
+            maintenance: MaintenanceMode::default(),
+            clock: Clock::tokio(),
+            drain: Drain::default(),
+            screen: Arc::default(),
+            redraws: None,
+        };
+        session.press(Key::Up);
+        session.press(Key::Left);
+        assert_eq!(session.screen.lock().unwrap().cursor, 0);
+        for _ in 0..CHECKBOX_WIDTH {
+            session.press(Key::Right);
+            session.press(Key::Down);
+        }
+        assert_eq!(
+            session.screen.lock().unwrap().cursor,
+            CHECKBOX_WIDTH * CHECKBOX_HEIGHT - 1
+        );
+        assert!(session.press(Key::Quit));
+    }
+
+    #[test]
+    fn the_frame_shows_the_cursor_and_locked_cells() {
+        let mut cells = vec![
+            Cell {
+                checked: false,
+                locked: false
+            };
+            CHECKBOX_WIDTH * CHECKBOX_HEIGHT
+        ];
+        cells[1] = Cell {
+            checked: true,
+            locked: true,
+        };
+        let frame = render_frame(&cells, 0, Some("Notice"));
+        assert!(frame.starts_with("\x1b[H400 Checkboxes, 1 checked\x1b[K\r\n"));
+        assert!(
+            frame.contains("\r\n\x1b[7m[ ]\x1b[0m\x1b[2m[x]\x1b[0m[ ]"),
+            "{frame:?}"
+        );
+        assert!(frame.ends_with("q to quit.\x1b[K\r\nNotice\x1b[K"));
+    }
+
+    struct Player;
+
+    #[async_trait]
+    impl client::Handler for Player {
+        type Error = anyhow::Error;
+
+        async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    /// Reads from `channel` until a frame with `text` shows up.
+    async fn wait_for(channel: &mut Channel<client::Msg>, text: &str) {
+        let mut output = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !String::from_utf8_lossy(&output).contains(text) {
+                match channel.wait().await {
+                    Some(ChannelMsg::Data { data }) => output.extend_from_slice(&data),
+                    Some(_) => (),
+                    None => panic!("The channel closed"),
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("Never saw {text:?}: {:?}", String::from_utf8_lossy(&output)));
+    }
+
+    #[tokio::test]
+    async fn players_toggle_the_shared_board() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let board = CheckboxBoard::default();
+        let drain = Drain::default();
+        let serving = tokio::spawn(tui_entrypoint(
+            "127.0.0.1",
+            port,
+            KeyPair::generate_ed25519(),
+            board.clone(),
+            MaintenanceMode::default(),
+            drain.clone(),
+            Clock::tokio(),
+        ));
+
+        let mut session = None;
+        for _ in 0..50 {
+            match client::connect(Default::default(), ("127.0.0.1", port), Player).await {
+                Ok(connected) => {
+                    session = Some(connected);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        let mut session = session.expect("Never connected");
+        assert!(session.authenticate_none("player").await.unwrap());
+        let mut channel = session.channel_open_session().await.unwrap();
+        channel
+            .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+            .await
+            .unwrap();
+        channel.request_shell(true).await.unwrap();
+        wait_for(&mut channel, "0 checked").await;
+
+        channel.data(&b"\x1b[Cj "[..]).await.unwrap();
+        wait_for(&mut channel, "1 checked").await;
+        assert_eq!(
+            board.get(CHECKBOX_WIDTH + 1).map(|cell| cell.checked),
+            Some(true)
+        );
+        // Moves from elsewhere show up at the next redraw.
+        board.set(0, true);
+        wait_for(&mut channel, "2 checked").await;
+
+        channel.data(&b"q"[..]).await.unwrap();
+        let mut exit_status = None;
+        while let Some(msg) = channel.wait().await {
+            if let ChannelMsg::ExitStatus { exit_status: code } = msg {
+                exit_status = Some(code);
+            }
+        }
+        assert_eq!(exit_status, Some(0));
+
+        drain.request_shutdown();
+        serving.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,963 @@
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::{
+    handoff::Drain,
+    ssh::{Backoff, BackoffConfig, BackoffStrategy},
+};
+
+/// Substring which marks a disconnect reason as planned maintenance, unless overridden with `--maintenance-reason`.
+pub const DEFAULT_MAINTENANCE_REASON: &str = "maintenance";
+
+/// What a public URL looks like in the tunnel server's output, unless overridden with `--public-url-pattern`.
+pub const DEFAULT_PUBLIC_URL_PATTERN: &str = r"https?://\S+";
+
+/// Terminal escape sequences, which services like sish use to color the URLs that they print.
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").unwrap());
+
+/// Parses `--public-url-pattern`, which must not match an empty string.
+pub fn parse_public_url_pattern(value: &str) -> Result<Regex, String> {
+    let pattern = Regex::new(value).map_err(|err| err.to_string())?;
+    if pattern.is_match("") {
+        return Err(String::from("The pattern matches an empty string."));
+    }
+    Ok(pattern)
+}
+
+/// What the tunnel is currently doing.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelState {
+    /// Served directly, without a tunnel.
+    Local,
+    Connecting,
+    Connected,
+    /// The connection was lost unexpectedly.
+    Reconnecting,
+    /// The tunnel server went down for planned maintenance.
+    Maintenance,
+}
+
+/// Whether a disconnect was announced as planned maintenance.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectKind {
+    Maintenance,
+    Other,
+}
+
+/// The last disconnect message sent by the tunnel server.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DisconnectInfo {
+    pub reason_code: String,
+    pub message: String,
+    pub kind: DisconnectKind,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct TunnelStatus {
+    pub state: TunnelState,
+    pub last_disconnect: Option<DisconnectInfo>,
+    /// When the current connection was made, in seconds since the Unix epoch.
+    pub connected_since: Option<u64>,
+    /// How many times the connection was lost since startup.
+    pub reconnects: u64,
+    /// The remote port being forwarded, as assigned by the server.
+    pub remote_port: Option<u16>,
+    /// The Unix socket on the server being forwarded instead of a remote port.
+    pub remote_socket: Option<String>,
+    /// How many forwarded connections are open, when tunneling.
+    pub open_channels: Option<usize>,
+    /// The public URL that the tunnel server announced for the current connection, if any.
+    pub public_url: Option<String>,
+    /// What went through the tunnel since startup, when tunneling.
+    pub traffic: Option<TrafficTotals>,
+}
+
+/// Whether players can reach the application, as the activities see the tunnel.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelLinkState {
+    /// Connected, or served directly without a tunnel.
+    Connected,
+    /// The connection was lost unexpectedly, and is about to come back.
+    Reconnecting,
+    /// Not connected yet, or down for the tunnel server's maintenance.
+    Disconnected,
+}
+
+impl From<TunnelState> for TunnelLinkState {
+    fn from(state: TunnelState) -> Self {
+        match state {
+            TunnelState::Local | TunnelState::Connected => TunnelLinkState::Connected,
+            TunnelState::Reconnecting => TunnelLinkState::Reconnecting,
+            TunnelState::Connecting | TunnelState::Maintenance => TunnelLinkState::Disconnected,
+        }
+    }
+}
+
+/// A change of the tunnel's [`TunnelLinkState`].
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TunnelEvent {
+    pub state: TunnelLinkState,
+    /// When the tunnel switched to `state`, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl TunnelEvent {
+    fn now(state: TunnelLinkState) -> Self {
+        TunnelEvent {
+            state,
+            timestamp: unix_timestamp(),
+        }
+    }
+}
+
+/// Follows the main tunnel's lifecycle, for activities that want to go easy on players while they can't reach us.
+///
+/// The default is always connected, as when serving locally.
+#[derive(Clone, Debug)]
+pub struct TunnelLink {
+    events: watch::Receiver<TunnelEvent>,
+}
+
+impl Default for TunnelLink {
+    fn default() -> Self {
+        TunnelLink {
+            events: watch::Sender::new(TunnelEvent::now(TunnelLinkState::Connected)).subscribe(),
+        }
+    }
+}
+
+impl TunnelLink {
+    pub fn current(&self) -> TunnelEvent {
+        *self.events.borrow()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.current().state == TunnelLinkState::Connected
+    }
+
+    /// Waits for the next change, or returns `None` once the tunnel is gone for good.
+    pub async fn changed(&mut self) -> Option<TunnelEvent> {
+        self.events.changed().await.ok()?;
+        Some(*self.events.borrow_and_update())
+    }
+}
+
+/// Counts what goes through a tunnel's forwarded connections. Kept across reconnections, until the process restarts.
+#[derive(Debug, Default)]
+pub struct TunnelStats {
+    connections: AtomicU64,
+    requests: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+/// A reading of [`TunnelStats`].
+#[derive(Serialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrafficTotals {
+    /// Forwarded connections that were served.
+    pub connections: u64,
+    pub requests: u64,
+    /// Bytes from the clients.
+    pub bytes_received: u64,
+    /// Bytes to the clients.
+    pub bytes_sent: u64,
+}
+
+impl TunnelStats {
+    pub fn connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> TrafficTotals {
+        TrafficTotals {
+            connections: self.connections.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// How this instance is being served, as shown to operators.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "mode")]
+pub enum DeploymentMode {
+    Local {
+        hostname: String,
+        port: u16,
+    },
+    /// Listening on a Unix socket with `--unix-socket`, instead of a TCP port.
+    UnixSocket {
+        path: PathBuf,
+    },
+    Ssh {
+        hostname: String,
+        port: u16,
+        remote_host: String,
+        remote_port: u16,
+        /// The Unix socket on the server that's forwarded instead of the remote port, if any.
+        remote_socket: Option<String>,
+    },
+    /// Serving the terminal UI with `serve-ssh`.
+    SshServer {
+        hostname: String,
+        port: u16,
+        http_port: Option<u16>,
+    },
+}
+
+/// Static details about this instance, set once in `main`.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeploymentInfo {
+    pub mode: DeploymentMode,
+    /// Where puzzles are fetched from, if the activity uses any.
+    pub puzzle_source: Option<String>,
+    pub version: &'static str,
+}
+
+impl Display for DeploymentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.mode {
+            DeploymentMode::Local { hostname, port } => write!(f, "local on {hostname}:{port}")?,
+            DeploymentMode::UnixSocket { path } => write!(f, "local on {}", path.display())?,
+            DeploymentMode::SshServer {
+                hostname,
+                port,
+                http_port,
+            } => {
+                write!(f, "terminal UI on {hostname}:{port}")?;
+                if let Some(http_port) = http_port {
+                    write!(f, ", HTTP on port {http_port}")?;
+                }
+            }
+            DeploymentMode::Ssh {
+                hostname,
+                port,
+                remote_host,
+                remote_port,
+                remote_socket,
+            } => match remote_socket {
+                Some(remote_socket) => {
+                    write!(f, "ssh to {hostname}:{port}, forwarding {remote_socket}")?
+                }
+                None => write!(
+                    f,
+                    "ssh to {hostname}:{port}, forwarding {}:{remote_port}",
+                    if remote_host.is_empty() {
+                        "*"
+                    } else {
+                        remote_host
+                    }
+                )?,
+            },
+        }
+        if let Some(puzzle_source) = &self.puzzle_source {
+            write!(f, " | puzzles from {puzzle_source}")?;
+        }
+        write!(f, " | v{}", self.version)
+    }
+}
+
+/// Tunnel status shared between the SSH client and the served pages.
+#[derive(Clone, Debug)]
+pub struct TunnelStatusCell {
+    status: Arc<RwLock<TunnelStatus>>,
+    maintenance_reasons: Arc<[String]>,
+    deployment: Option<Arc<DeploymentInfo>>,
+    /// Counts the forwarded connections.
+    drain: Option<Drain>,
+    /// Picks the public URL out of the tunnel server's output.
+    public_url_pattern: Option<Regex>,
+    stats: Arc<TunnelStats>,
+    /// Publishes the changes of state to every [`TunnelLink`].
+    events: Arc<watch::Sender<TunnelEvent>>,
+}
+
+impl TunnelStatusCell {
+    /// Creates the cell. Disconnect messages containing any of `maintenance_reasons` (case-insensitively) are
+    /// treated as planned maintenance.
+    pub fn new(state: TunnelState, maintenance_reasons: Vec<String>) -> Self {
+        TunnelStatusCell {
+            status: Arc::new(RwLock::new(TunnelStatus {
+                state,
+                last_disconnect: None,
+                connected_since: None,
+                reconnects: 0,
+                remote_port: None,
+                remote_socket: None,
+                open_channels: None,
+                public_url: None,
+                traffic: None,
+            })),
+            maintenance_reasons: maintenance_reasons
+                .into_iter()
+                .map(|reason| reason.to_lowercase())
+                .filter(|reason| !reason.is_empty())
+                .collect(),
+            deployment: None,
+            drain: None,
+            public_url_pattern: None,
+            stats: Arc::default(),
+            events: Arc::new(watch::Sender::new(TunnelEvent::now(state.into()))),
+        }
+    }
+
+    pub fn with_deployment(mut self, deployment: DeploymentInfo) -> Self {
+        self.deployment = Some(Arc::new(deployment));
+        self
+    }
+
+    pub fn deployment(&self) -> Option<&DeploymentInfo> {
+        self.deployment.as_deref()
+    }
+
+    /// Reports the forwarded connections counted by `drain`.
+    pub fn with_drain(mut self, drain: Drain) -> Self {
+        self.drain = Some(drain);
+        self
+    }
+
+    /// Looks for the public URL in the tunnel server's output with `pattern`.
+    pub fn with_public_url_pattern(mut self, pattern: Regex) -> Self {
+        self.public_url_pattern = Some(pattern);
+        self
+    }
+
+    /// A cell for another tunnel of this instance, as with `--ssh-target`: the same settings and forwarded
+    /// connections, with a status of its own. The deployment details stay with this one.
+    pub fn sibling(&self) -> Self {
+        TunnelStatusCell {
+            status: Arc::new(RwLock::new(TunnelStatus {
+                state: TunnelState::Connecting,
+                last_disconnect: None,
+                connected_since: None,
+                reconnects: 0,
+                remote_port: None,
+                remote_socket: None,
+                open_channels: None,
+                public_url: None,
+                traffic: None,
+            })),
+            maintenance_reasons: Arc::clone(&self.maintenance_reasons),
+            deployment: None,
+            drain: self.drain.clone(),
+            public_url_pattern: self.public_url_pattern.clone(),
+            stats: Arc::default(),
+            events: Arc::new(watch::Sender::new(TunnelEvent::now(
+                TunnelLinkState::Disconnected,
+            ))),
+        }
+    }
+
+    pub fn get(&self) -> TunnelStatus {
+        let mut status = self.status.read().unwrap().clone();
+        status.open_channels = self.drain.as_ref().map(Drain::connections);
+        status.traffic = self.drain.as_ref().map(|_| self.stats.totals());
+        status
+    }
+
+    /// Where the forwarded connections of this tunnel are counted.
+    pub fn stats(&self) -> &Arc<TunnelStats> {
+        &self.stats
+    }
+
+    pub fn set_state(&self, state: TunnelState) {
+        self.status.write().unwrap().state = state;
+        self.publish(state);
+    }
+
+    /// Follows the changes of this tunnel's state.
+    pub fn link(&self) -> TunnelLink {
+        TunnelLink {
+            events: self.events.subscribe(),
+        }
+    }
+
+    /// Tells the links about `state`, unless they already know.
+    fn publish(&self, state: TunnelState) {
+        let state = TunnelLinkState::from(state);
+        self.events.send_if_modified(|event| {
+            if event.state == state {
+                return false;
+            }
+            *event = TunnelEvent::now(state);
+            true
+        });
+    }
+
+    pub fn classify(&self, message: &str) -> DisconnectKind {
+        let message = message.to_lowercase();
+        if self
+            .maintenance_reasons
+            .iter()
+            .any(|reason| message.contains(reason.as_str()))
+        {
+            DisconnectKind::Maintenance
+        } else {
+            DisconnectKind::Other
+        }
+    }
+
+    /// Records a disconnect message from the tunnel server.
+    pub fn record_disconnect(&self, reason_code: &str, message: &str) -> DisconnectKind {
+        let kind = self.classify(message);
+        match kind {
+            DisconnectKind::Maintenance => {
+                info!(
+                    reason_code = reason_code,
+                    message = message,
+                    "Tunnel server is going down for maintenance."
+                )
+            }
+            DisconnectKind::Other => warn!(reason_code, message, "Tunnel server disconnected."),
+        }
+        self.status.write().unwrap().last_disconnect = Some(DisconnectInfo {
+            reason_code: String::from(reason_code),
+            message: String::from(message),
+            kind,
+            timestamp: unix_timestamp(),
+        });
+        kind
+    }
+
+    /// Marks the tunnel as down, picking the state from the last disconnect message.
+    /// Returns how we should go about reconnecting.
+    pub fn connection_lost(&self) -> ReconnectPolicy {
+        let mut status = self.status.write().unwrap();
+        let policy = match status.last_disconnect.take() {
+            Some(info) if info.kind == DisconnectKind::Maintenance => {
+                status.last_disconnect = Some(info);
+                ReconnectPolicy::Patient
+            }
+            info => {
+                status.last_disconnect = info;
+                ReconnectPolicy::Eager
+            }
+        };
+        status.state = match policy {
+            ReconnectPolicy::Eager => TunnelState::Reconnecting,
+            ReconnectPolicy::Patient => TunnelState::Maintenance,
+        };
+        status.connected_since = None;
+        status.remote_port = None;
+        status.remote_socket = None;
+        status.public_url = None;
+        status.reconnects += 1;
+        self.publish(status.state);
+        policy
+    }
+
+    /// Marks the tunnel as up again, forgetting the last disconnect's kind for the next reconnection.
+    pub fn connected(&self) {
+        let mut status = self.status.write().unwrap();
+        status.state = TunnelState::Connected;
+        status.connected_since = Some(unix_timestamp());
+        if let Some(info) = status.last_disconnect.as_mut() {
+            info.kind = DisconnectKind::Other;
+        }
+        self.publish(status.state);
+    }
+
+    /// Records the remote port that the server is forwarding.
+    pub fn forwarding(&self, remote_port: u16) {
+        self.status.write().unwrap().remote_port = Some(remote_port);
+    }
+
+    /// Records the Unix socket that the server is forwarding instead of a remote port.
+    pub fn forwarding_socket(&self, remote_socket: &str) {
+        self.status.write().unwrap().remote_socket = Some(String::from(remote_socket));
+    }
+
+    /// Looks for the public URL in a line of the tunnel server's output, as printed by services like sish and
+    /// localhost.run. Only the first match of each connection is kept.
+    pub fn scan_public_url(&self, line: &str) {
+        let Some(pattern) = &self.public_url_pattern else {
+            return;
+        };
+        if self.status.read().unwrap().public_url.is_some() {
+            return;
+        }
+        let line = ANSI_ESCAPE.replace_all(line, "");
+        let Some(url) = pattern.find(&line) else {
+            return;
+        };
+        let url = url.as_str();
+        info!("Public URL: {url}");
+        self.status
+            .write()
+            .unwrap()
+            .public_url
+            .get_or_insert_with(|| String::from(url));
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// How long to wait between reconnection attempts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReconnectPolicy {
+    /// A handful of quick retries, for unexpected drops.
+    Eager,
+    /// Slower retries over a long period, while the tunnel server is under maintenance.
+    Patient,
+}
+
+impl ReconnectPolicy {
+    /// Eager reconnections follow `backoff`. Patient ones check back about every minute at most, since the server's
+    /// maintenance takes as long as it takes, but still give up after as many attempts and keep the same jitter.
+    pub fn delays(self, backoff: BackoffConfig) -> Backoff {
+        match self {
+            ReconnectPolicy::Eager => Backoff::new(backoff),
+            ReconnectPolicy::Patient => Backoff::new(BackoffConfig {
+                strategy: BackoffStrategy::Linear,
+                initial_delay: Duration::from_secs(15),
+                max_delay: Duration::from_secs(60),
+                max_attempts: backoff.max_attempts,
+                jitter_percent: backoff.jitter_percent,
+            }),
+        }
+    }
+}
+
+/// When to give up on the tunnel, as set by `--reconnect-max-attempts`, `--max-session-restarts` and
+/// `--retry-forever`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delays between attempts that didn't get as far as forwarding the remote port, whether connecting failed or the
+    /// session ended before forwarding. Its `max_attempts` counts them in a row.
+    pub backoff: BackoffConfig,
+    /// How many times a tunnel that was forwarding can be connected again, or `None` for no limit.
+    pub max_session_restarts: Option<u64>,
+}
+
+/// Keeps count of attempts and restarts for a [`RetryPolicy`]. As an iterator, yields the delay before each
+/// connection attempt after a failed one, until they run out.
+#[derive(Clone, Debug)]
+pub struct Retries {
+    policy: RetryPolicy,
+    reconnect: ReconnectPolicy,
+    delays: Backoff,
+    /// Whether the current session got to forward the remote port.
+    forwarding: bool,
+    restarts: u64,
+}
+
+impl Retries {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Retries {
+            policy,
+            reconnect: ReconnectPolicy::Eager,
+            delays: ReconnectPolicy::Eager.delays(policy.backoff),
+            forwarding: false,
+            restarts: 0,
+        }
+    }
+
+    /// The remote port is forwarded, so that failed attempts are counted from zero again.
+    pub fn forwarding(&mut self) {
+        self.forwarding = true;
+        self.delays = self.reconnect.delays(self.policy.backoff);
+    }
+
+    /// The session ended, and `reconnect` says how to go about the next one. Returns how long to wait before
+    /// connecting again: not at all after a session that was forwarding, and the next delay otherwise. Fails once the
+    /// restarts or the attempts run out.
+    pub fn session_ended(&mut self, reconnect: ReconnectPolicy) -> Result<Option<Duration>> {
+        if reconnect != self.reconnect {
+            self.reconnect = reconnect;
+            self.delays = reconnect.delays(self.policy.backoff);
+        }
+        if !std::mem::take(&mut self.forwarding) {
+            return match self.delays.next() {
+                Some(delay) => Ok(Some(delay)),
+                None => bail!("Gave up graceful reconnection."),
+            };
+        }
+        if self
+            .policy
+            .max_session_restarts
+            .is_some_and(|max_restarts| self.restarts >= max_restarts)
+        {
+            bail!("Gave up after {} session restarts.", self.restarts);
+        }
+        self.restarts += 1;
+        Ok(None)
+    }
+}
+
+impl Iterator for Retries {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        self.delays.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell() -> TunnelStatusCell {
+        TunnelStatusCell::new(
+            TunnelState::Connected,
+            vec![
+                String::from(DEFAULT_MAINTENANCE_REASON),
+                String::from("restarting"),
+            ],
+        )
+    }
+
+    #[test]
+    fn it_classifies_disconnect_reasons() {
+        let cell = cell();
+        assert_eq!(
+            cell.classify("Server going down for MAINTENANCE at 03:00"),
+            DisconnectKind::Maintenance
+        );
+        assert_eq!(
+            cell.classify("sish is restarting, be right back"),
+            DisconnectKind::Maintenance
+        );
+        assert_eq!(cell.classify("Too many connections"), DisconnectKind::Other);
+        assert_eq!(cell.classify(""), DisconnectKind::Other);
+        let cell = TunnelStatusCell::new(TunnelState::Connected, vec![String::new()]);
+        assert_eq!(cell.classify("anything"), DisconnectKind::Other);
+    }
+
+    #[test]
+    fn it_switches_to_a_patient_policy_for_maintenance() {
+        let cell = cell();
+        cell.record_disconnect("ByApplication", "Scheduled maintenance");
+        assert_eq!(cell.connection_lost(), ReconnectPolicy::Patient);
+        let status = cell.get();
+        assert_eq!(status.state, TunnelState::Maintenance);
+        assert_eq!(
+            status.last_disconnect.unwrap().message,
+            "Scheduled maintenance"
+        );
+
+        cell.connected();
+        assert_eq!(cell.get().state, TunnelState::Connected);
+        assert!(cell.get().connected_since.is_some());
+        // A plain connection drop afterwards goes back to eager reconnections.
+        assert_eq!(cell.connection_lost(), ReconnectPolicy::Eager);
+        assert_eq!(cell.get().state, TunnelState::Reconnecting);
+
+        cell.record_disconnect("TooManyConnections", "Too many connections");
+        assert_eq!(cell.connection_lost(), ReconnectPolicy::Eager);
+    }
+
+    #[test]
+    fn forwarded_sockets_are_shown_instead_of_ports() {
+        let deployment = |remote_socket: Option<&str>| DeploymentInfo {
+            mode: DeploymentMode::Ssh {
+                hostname: String::from("sish.top"),
+                port: 2222,
+                remote_host: String::new(),
+                remote_port: 80,
+                remote_socket: remote_socket.map(String::from),
+            },
+            puzzle_source: None,
+            version: "1.2.3",
+        };
+        assert_eq!(
+            deployment(None).to_string(),
+            "ssh to sish.top:2222, forwarding *:80 | v1.2.3"
+        );
+        assert_eq!(
+            deployment(Some("/run/apps/games.sock")).to_string(),
+            "ssh to sish.top:2222, forwarding /run/apps/games.sock | v1.2.3"
+        );
+    }
+
+    #[test]
+    fn it_counts_reconnects_and_channels() {
+        assert_eq!(cell().get().open_channels, None);
+        let drain = Drain::default();
+        let cell = cell().with_drain(drain.clone());
+        cell.connected();
+        cell.forwarding(49152);
+        let _connection = drain.begin_connection().unwrap();
+        let status = cell.get();
+        assert_eq!(status.reconnects, 0);
+        assert_eq!(status.remote_port, Some(49152));
+        assert_eq!(status.open_channels, Some(1));
+
+        cell.connection_lost();
+        let status = cell.get();
+        assert_eq!(status.reconnects, 1);
+        assert_eq!(status.connected_since, None);
+        assert_eq!(status.remote_port, None);
+    }
+
+    #[test]
+    fn forwarded_sockets_are_reported_until_the_connection_is_lost() {
+        let cell = cell();
+        cell.connected();
+        cell.forwarding_socket("/run/apps/games.sock");
+        let status = cell.get();
+        assert_eq!(
+            status.remote_socket.as_deref(),
+            Some("/run/apps/games.sock")
+        );
+        assert_eq!(status.remote_port, None);
+
+        cell.connection_lost();
+        assert_eq!(cell.get().remote_socket, None);
+    }
+
+    #[test]
+    fn traffic_is_counted_across_reconnections() {
+        assert_eq!(cell().get().traffic, None);
+        let cell = cell().with_drain(Drain::default());
+        cell.stats().connection();
+        cell.stats().request();
+        cell.stats().received(120);
+        cell.connection_lost();
+        cell.connected();
+        cell.stats().request();
+        cell.stats().sent(4096);
+        assert_eq!(
+            cell.get().traffic,
+            Some(TrafficTotals {
+                connections: 1,
+                requests: 2,
+                bytes_received: 120,
+                bytes_sent: 4096,
+            })
+        );
+        assert_eq!(cell.sibling().get().traffic, Some(TrafficTotals::default()));
+    }
+
+    #[test]
+    fn the_first_public_url_of_a_connection_is_kept() {
+        let cell = cell();
+        cell.scan_public_url("https://ignored.example.com");
+        assert_eq!(cell.get().public_url, None);
+
+        assert!(parse_public_url_pattern(r"\S*").is_err());
+        let cell = cell
+            .with_public_url_pattern(parse_public_url_pattern(DEFAULT_PUBLIC_URL_PATTERN).unwrap());
+        cell.scan_public_url("Press Ctrl-C to close the session.");
+        assert_eq!(cell.get().public_url, None);
+        cell.scan_public_url("HTTP: \x1b[32mhttp://game.tuns.sh\x1b[0m");
+        cell.scan_public_url("HTTPS: https://game.tuns.sh");
+        assert_eq!(
+            cell.get().public_url.as_deref(),
+            Some("http://game.tuns.sh")
+        );
+
+        cell.connection_lost();
+        assert_eq!(cell.get().public_url, None);
+        cell.scan_public_url(
+            "abc123.lhr.life tunneled with tls termination, https://abc123.lhr.life",
+        );
+        assert_eq!(
+            cell.get().public_url.as_deref(),
+            Some("https://abc123.lhr.life")
+        );
+    }
+
+    #[test]
+    fn siblings_have_a_status_of_their_own() {
+        let cell = cell();
+        cell.connected();
+        let sibling = cell.sibling();
+        assert_eq!(sibling.get().state, TunnelState::Connecting);
+        assert_eq!(sibling.classify("Restarting"), DisconnectKind::Maintenance);
+        sibling.record_disconnect("ByApplication", "Restarting");
+        sibling.connection_lost();
+        assert_eq!(sibling.get().state, TunnelState::Maintenance);
+        assert_eq!(cell.get().state, TunnelState::Connected);
+        assert_eq!(cell.get().last_disconnect, None);
+    }
+
+    #[tokio::test]
+    async fn links_follow_the_tunnel_lifecycle() {
+        assert!(TunnelLink::default().is_connected());
+        assert!(TunnelStatusCell::new(TunnelState::Local, vec![])
+            .link()
+            .is_connected());
+        let cell = TunnelStatusCell::new(TunnelState::Connecting, vec![String::from("restarting")]);
+        let mut link = cell.link();
+        assert_eq!(link.current().state, TunnelLinkState::Disconnected);
+        cell.set_state(TunnelState::Connecting);
+        cell.connected();
+        assert_eq!(
+            link.changed().await.unwrap().state,
+            TunnelLinkState::Connected
+        );
+        cell.connection_lost();
+        assert_eq!(
+            link.changed().await.unwrap().state,
+            TunnelLinkState::Reconnecting
+        );
+        cell.connected();
+        cell.record_disconnect("ByApplication", "Restarting");
+        cell.connection_lost();
+        // Only the latest state is kept for links that fall behind.
+        assert_eq!(
+            link.changed().await.unwrap().state,
+            TunnelLinkState::Disconnected
+        );
+        assert!(!cell.sibling().link().is_connected());
+        drop(cell);
+        assert_eq!(link.changed().await, None);
+    }
+
+    #[test]
+    fn patient_reconnections_wait_longer() {
+        let backoff = BackoffConfig {
+            max_attempts: 5,
+            jitter_percent: 0,
+            ..Default::default()
+        };
+        let eager = ReconnectPolicy::Eager.delays(backoff).collect::<Vec<_>>();
+        let patient = ReconnectPolicy::Patient.delays(backoff).collect::<Vec<_>>();
+        assert_eq!(eager.len(), 5);
+        assert_eq!(patient.len(), 5);
+        assert!(patient.iter().sum::<Duration>() > eager.iter().sum::<Duration>() * 3);
+        assert!(ReconnectPolicy::Patient
+            .delays(BackoffConfig::default())
+            .take(1000)
+            .all(|delay| delay <= Duration::from_secs(78)));
+    }
+
+    fn linear(max_attempts: u64) -> BackoffConfig {
+        BackoffConfig {
+            strategy: BackoffStrategy::Linear,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(5),
+            max_attempts,
+            jitter_percent: 0,
+        }
+    }
+
+    #[test]
+    fn failed_attempts_share_one_budget_until_forwarding() {
+        let mut retries = Retries::new(RetryPolicy {
+            backoff: linear(3),
+            max_session_restarts: None,
+        });
+        // Connecting fails twice, then the session ends before forwarding, as when the port is refused.
+        assert_eq!(retries.next(), Some(Duration::from_secs(2)));
+        assert_eq!(retries.next(), Some(Duration::from_secs(4)));
+        assert_eq!(
+            retries.session_ended(ReconnectPolicy::Eager).unwrap(),
+            Some(Duration::from_secs(5))
+        );
+        assert!(retries.next().is_none());
+
+        // Forwarding starts the count over.
+        retries.forwarding();
+        assert_eq!(retries.session_ended(ReconnectPolicy::Eager).unwrap(), None);
+        assert_eq!(retries.next(), Some(Duration::from_secs(2)));
+        assert_eq!(
+            retries.session_ended(ReconnectPolicy::Eager).unwrap(),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(
+            retries.session_ended(ReconnectPolicy::Eager).unwrap(),
+            Some(Duration::from_secs(5))
+        );
+        let error = retries.session_ended(ReconnectPolicy::Eager).unwrap_err();
+        assert_eq!(error.to_string(), "Gave up graceful reconnection.");
+    }
+
+    #[test]
+    fn session_restarts_are_limited_separately() {
+        let mut retries = Retries::new(RetryPolicy {
+            backoff: linear(1),
+            max_session_restarts: Some(2),
+        });
+        for _ in 0..2 {
+            retries.forwarding();
+            assert_eq!(retries.session_ended(ReconnectPolicy::Eager).unwrap(), None);
+            // Each restart still gets the full budget of attempts.
+            assert_eq!(retries.next(), Some(Duration::from_secs(2)));
+        }
+        retries.forwarding();
+        let error = retries.session_ended(ReconnectPolicy::Eager).unwrap_err();
+        assert_eq!(error.to_string(), "Gave up after 2 session restarts.");
+
+        let mut never = Retries::new(RetryPolicy {
+            backoff: linear(1),
+            max_session_restarts: Some(0),
+        });
+        never.forwarding();
+        assert!(never.session_ended(ReconnectPolicy::Eager).is_err());
+    }
+
+    #[test]
+    fn retrying_forever_never_gives_up() {
+        let mut retries = Retries::new(RetryPolicy::default());
+        for _ in 0..1000 {
+            retries.forwarding();
+            assert_eq!(retries.session_ended(ReconnectPolicy::Eager).unwrap(), None);
+            assert!(retries
+                .session_ended(ReconnectPolicy::Eager)
+                .unwrap()
+                .is_some());
+        }
+        assert!(retries
+            .take(1000)
+            .all(|delay| delay <= Duration::from_secs(5 * 60).mul_f64(1.3)));
+    }
+
+    #[test]
+    fn maintenance_switches_to_patient_delays() {
+        let mut retries = Retries::new(RetryPolicy {
+            backoff: linear(0),
+            max_session_restarts: None,
+        });
+        retries.forwarding();
+        assert_eq!(
+            retries.session_ended(ReconnectPolicy::Patient).unwrap(),
+            None
+        );
+        assert_eq!(retries.next(), Some(Duration::from_secs(15)));
+        assert_eq!(retries.next(), Some(Duration::from_secs(30)));
+        // Still in maintenance: the delays keep growing instead of starting over.
+        assert_eq!(
+            retries.session_ended(ReconnectPolicy::Patient).unwrap(),
+            Some(Duration::from_secs(45))
+        );
+    }
+}